@@ -1,6 +1,7 @@
-use actix_web::web::{self as actix_web_config, ServiceConfig, scope, resource, get, post};
+use actix_web::web::{ServiceConfig, scope, resource, get, post};
+use socialhub_core::{auth::permissions::ADDONS_MANAGE, require_permission};
 
-mod plugin;
+pub mod plugin;
 mod sandbox;
 mod manager;
 mod error;
@@ -10,15 +11,32 @@ pub mod web;  // Agora é público diretamente
 pub use plugin::Plugin;
 pub use manager::AddonManager;
 
+/// Registers the addon routes. Installing, enabling, disabling and
+/// configuring addons require the `addons:manage` permission. Configurations
+/// are kept in the shared `web::Data<AddonManager>`, which must be registered
+/// as app data.
 pub fn configure(cfg: &mut ServiceConfig) {
     cfg.service(
         scope("/addons")
-            .service(resource("/install").route(post().to(manager::install_addon)))
+            .service(
+                resource("/install")
+                    .wrap(require_permission(ADDONS_MANAGE))
+                    .route(post().to(manager::install_addon))
+            )
             .service(resource("/list").route(get().to(manager::list_addons)))
-            .service(resource("/{id}/enable").route(post().to(manager::enable_addon)))
-            .service(resource("/{id}/disable").route(post().to(manager::disable_addon)))
+            .service(
+                resource("/{id}/enable")
+                    .wrap(require_permission(ADDONS_MANAGE))
+                    .route(post().to(manager::enable_addon))
+            )
+            .service(
+                resource("/{id}/disable")
+                    .wrap(require_permission(ADDONS_MANAGE))
+                    .route(post().to(manager::disable_addon))
+            )
             .service(
                 resource("/{id}/configure")
+                    .wrap(require_permission(ADDONS_MANAGE))
                     .route(post().to(web::configure_addon))
             )
             .service(
@@ -31,9 +49,10 @@ pub fn configure(cfg: &mut ServiceConfig) {
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use actix_web::{test, App};
+    use std::sync::Arc;
+    use actix_web::{test, web as actix_web_config, App};
+    use socialhub_auth::{AuthConfig, AuthService, InMemoryUserRepository, Role};
     use uuid::Uuid;
-    use serde_json::json;
 
     #[tokio::test]
     async fn test_install_addon() {
@@ -86,22 +105,108 @@ pub mod tests {
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
     }
+
+    #[tokio::test]
+    async fn test_management_requires_addons_manage() {
+        let auth = actix_web_config::Data::new(
+            AuthService::new(AuthConfig::default(), Arc::new(InMemoryUserRepository::new()))
+        );
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .configure(configure)
+        ).await;
+        let bearer = |role: Role| {
+            let (token, _) = auth.tokens().issue(1, &[role], &[]).unwrap();
+            ("Authorization", format!("Bearer {}", token))
+        };
+        let addon_id = Uuid::new_v4();
+
+        let req = test::TestRequest::get().uri("/addons/list").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/addons/{}/enable", addon_id))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 401);
+
+        for role in [Role::User, Role::Moderator] {
+            let req = test::TestRequest::post()
+                .uri(&format!("/addons/{}/disable", addon_id))
+                .insert_header(bearer(role))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status().as_u16(), 403);
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/addons/test-addon/configure")
+            .insert_header(bearer(Role::User))
+            .set_json(crate::web::AddonConfig::defaults("test-addon".to_string()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 403);
+
+        let req = test::TestRequest::post()
+            .uri("/addons/install")
+            .insert_header(bearer(Role::Admin))
+            .set_json(serde_json::json!({ "name": "test-addon", "version": "1.0.0" }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_permissions_map_to_oauth_scopes() {
+        use crate::plugin::Permission;
+        use socialhub_core::auth::scopes;
+
+        for permission in Permission::ALL {
+            assert!(scopes::ALL.contains(&permission.scope()), "{:?}", permission);
+            assert_eq!(Permission::from_scope(permission.scope()), Some(permission));
+        }
+        assert_eq!(Permission::from_scope(scopes::OPENID), None);
+    }
+
+    #[tokio::test]
+    async fn test_data_subject_export_and_erase() {
+        use socialhub_core::DataSubjectHook;
+        use crate::web::AddonConfig;
+
+        let manager = AddonManager::new();
+        let mut config = AddonConfig::defaults("stremio".to_string());
+        config.preferred_quality = Some("4K".to_string());
+        manager.set_config(1, config).await.unwrap();
+        manager.set_config(2, AddonConfig::defaults("stremio".to_string())).await.unwrap();
+
+        let files = manager.export(1).await.unwrap();
+        assert_eq!(files[0].path, "configs.json");
+        let exported: Vec<AddonConfig> = serde_json::from_slice(files[0].bytes().unwrap()).unwrap();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].preferred_quality.as_deref(), Some("4K"));
+
+        manager.erase(1).await.unwrap();
+        manager.erase(1).await.unwrap();
+        assert!(manager.config(1, "stremio").await.is_none());
+        assert!(manager.config(2, "stremio").await.is_some());
+    }
 }
 
 #[cfg(test)]
 mod integration_tests {
-    use super::*;
-    use crate::web::{AddonConfig, configure_addon, get_addon_config};
+    use std::sync::Arc;
+    use crate::{web::{AddonConfig, configure_addon}, AddonManager};
     use actix_web::{test, App, web};
-    use uuid::Uuid;
+    use socialhub_auth::{AuthConfig, AuthService, InMemoryUserRepository, Role};
 
     #[actix_web::test]
     async fn test_web_configure_addon() {
+        let auth = web::Data::new(AuthService::new(AuthConfig::default(), Arc::new(InMemoryUserRepository::new())));
         let app = test::init_service(
-            App::new().service(
-                web::resource("/addon/{id}/configure")
-                    .route(web::post().to(configure_addon))
-            )
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(AddonManager::new()))
+                .service(
+                    web::resource("/addon/{id}/configure")
+                        .route(web::post().to(configure_addon))
+                )
         ).await;
 
         let config = AddonConfig {
@@ -112,8 +217,10 @@ mod integration_tests {
             preferred_quality: Some("HD".to_string()),
         };
 
+        let (token, _) = auth.tokens().issue(1, &[Role::User], &[]).unwrap();
         let req = test::TestRequest::post()
             .uri("/addon/test-addon/configure")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
             .set_json(&config)
             .to_request();
 
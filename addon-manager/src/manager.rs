@@ -1,19 +1,97 @@
+use std::collections::HashMap;
 use actix_web::{web, Error, HttpResponse};
+use futures::future::BoxFuture;
+use socialhub_core::{
+    cache::{CacheConfig, CacheManager},
+    data_subject::{DataSubjectHook, ExportedFile},
+    error::CommonError,
+};
 use uuid::Uuid;
+use crate::error::AddonError;
 use crate::plugin::Plugin;
 use crate::sandbox::Sandbox;
+use crate::web::AddonConfig;
 
 pub struct AddonManager {
+    #[allow(dead_code)]
     plugins: Vec<Plugin>,
+    #[allow(dead_code)]
     sandbox: Sandbox,
+    /// Each user's addon configurations by addon id, kept in process memory.
+    configs: CacheManager<i32, HashMap<String, AddonConfig>>,
 }
 
+/// Users whose configurations are kept at once.
+const CONFIG_CAPACITY: u64 = 10_000;
+/// Addons a user may have configured at once.
+pub const MAX_CONFIGS_PER_USER: usize = 32;
+/// Seconds a user's configurations are kept after they last changed or were read.
+const CONFIG_RETENTION: u64 = 180 * 24 * 60 * 60;
+
 impl AddonManager {
     pub fn new() -> Self {
         Self {
             plugins: Vec::new(),
             sandbox: Sandbox::new(std::path::PathBuf::from("./addons")),
+            configs: CacheManager::new(CacheConfig {
+                max_capacity: CONFIG_CAPACITY,
+                time_to_live: CONFIG_RETENTION,
+                time_to_idle: CONFIG_RETENTION,
+            }),
+        }
+    }
+
+    /// The configuration `user_id` saved for `addon_id`, if any.
+    pub async fn config(&self, user_id: i32, addon_id: &str) -> Option<AddonConfig> {
+        self.configs.get(&user_id).await.and_then(|mut own| own.remove(addon_id))
+    }
+
+    /// Saves the configuration of `user_id` for the addon `config.id`, which
+    /// must be valid. Fails once the user has configured
+    /// [`MAX_CONFIGS_PER_USER`] other addons.
+    pub async fn set_config(&self, user_id: i32, config: AddonConfig) -> Result<(), AddonError> {
+        config.validate()?;
+        let mut full = false;
+        self.configs.upsert_with(user_id, |own| {
+            let mut own = own.unwrap_or_default();
+            if own.contains_key(&config.id) || own.len() < MAX_CONFIGS_PER_USER {
+                own.insert(config.id.clone(), config);
+            } else {
+                full = true;
+            }
+            own
+        }).await;
+        if full {
+            return Err(AddonError::Configuration(format!("at most {} addons can be configured", MAX_CONFIGS_PER_USER)));
         }
+        Ok(())
+    }
+}
+
+impl DataSubjectHook for AddonManager {
+    fn name(&self) -> &'static str {
+        "addons"
+    }
+
+    fn export(&self, user_id: i32) -> BoxFuture<'_, Result<Vec<ExportedFile>, CommonError>> {
+        Box::pin(async move {
+            let mut own: Vec<AddonConfig> = self.configs.get(&user_id).await.unwrap_or_default().into_values().collect();
+            own.sort_by(|a, b| a.id.cmp(&b.id));
+            Ok(vec![ExportedFile::json("configs.json", &own)?])
+        })
+    }
+
+    fn erase(&self, user_id: i32) -> BoxFuture<'_, Result<(), CommonError>> {
+        Box::pin(async move {
+            self.configs.take(&user_id).await;
+            Ok(())
+        })
+    }
+}
+
+impl Default for AddonManager {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use socialhub_core::auth::scopes;
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -10,7 +11,7 @@ pub struct Plugin {
     pub permissions: Vec<Permission>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Permission {
     ReadUser,
     WriteUser,
@@ -20,6 +21,35 @@ pub enum Permission {
     WriteStream,
 }
 
+impl Permission {
+    pub const ALL: [Permission; 6] = [
+        Permission::ReadUser,
+        Permission::WriteUser,
+        Permission::ReadMedia,
+        Permission::WriteMedia,
+        Permission::ReadStream,
+        Permission::WriteStream,
+    ];
+
+    /// The OAuth scope an app needs for this permission, as advertised by the
+    /// socialhub-auth discovery document.
+    pub fn scope(&self) -> &'static str {
+        match self {
+            Permission::ReadUser => scopes::USER_READ,
+            Permission::WriteUser => scopes::USER_WRITE,
+            Permission::ReadMedia => scopes::MEDIA_READ,
+            Permission::WriteMedia => scopes::MEDIA_WRITE,
+            Permission::ReadStream => scopes::STREAM_READ,
+            Permission::WriteStream => scopes::STREAM_WRITE,
+        }
+    }
+
+    pub fn from_scope(scope: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.scope() == scope)
+    }
+}
+
+#[allow(dead_code)]
 pub trait PluginInterface {
     fn initialize(&self) -> Result<(), Box<dyn std::error::Error>>;
     fn shutdown(&self) -> Result<(), Box<dyn std::error::Error>>;
@@ -2,6 +2,7 @@ use std::path::PathBuf;
 use crate::error::AddonError;
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub struct Sandbox {
     root_path: PathBuf,
     memory_limit: usize,
@@ -17,6 +18,7 @@ impl Sandbox {
         }
     }
 
+    #[allow(dead_code)]
     pub fn execute<F, T>(&self, f: F) -> Result<T, AddonError>
     where
         F: FnOnce() -> Result<T, AddonError>,
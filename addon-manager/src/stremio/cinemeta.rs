@@ -2,6 +2,7 @@ use crate::error::AddonError;
 use serde::{Deserialize, Serialize};
 use reqwest;
 
+#[cfg(not(test))]
 const CINEMETA_URL: &str = "https://v3-cinemeta.strem.io";
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,7 +35,7 @@ pub struct CinemetaClient {
 
 impl CinemetaClient {
     #[cfg(not(test))]
-    const BASE_URL: &'static str = "https://v3-cinemeta.strem.io";
+    const BASE_URL: &'static str = CINEMETA_URL;
     
     #[cfg(test)]
     fn base_url() -> String {
@@ -62,7 +63,7 @@ impl CinemetaClient {
             .await?
             .json::<CinemetaResponse>()
             .await
-            .map_err(|e| AddonError::ManifestFetch(e))?;
+            .map_err(AddonError::ManifestFetch)?;
 
         Ok(response.meta)
     }
@@ -78,10 +79,16 @@ impl CinemetaClient {
     }
 }
 
+impl Default for CinemetaClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use mockito::{mock, server_url};
+    use mockito::mock;
     use tokio;
 
     #[tokio::test]
@@ -88,7 +88,7 @@ impl StremioAddonAdapter {
             .await?
             .json()
             .await
-            .map_err(|e| AddonError::ManifestFetch(e))?;
+            .map_err(AddonError::ManifestFetch)?;
 
         // Get base URL by removing everything after the last slash
         let base_url = manifest_url
@@ -113,7 +113,7 @@ impl StremioAddonAdapter {
             .await?
             .json()
             .await
-            .map_err(|e| AddonError::CatalogFetch(e))
+            .map_err(AddonError::CatalogFetch)
     }
 
     pub async fn get_streams(&self, type_name: &str, id: &str) -> Result<Vec<Stream>, AddonError> {
@@ -126,7 +126,7 @@ impl StremioAddonAdapter {
             .await?
             .json::<StreamResponse>()
             .await
-            .map_err(|e| AddonError::StreamFetch(e))?;
+            .map_err(AddonError::StreamFetch)?;
 
         Ok(response.streams)
     }
@@ -149,7 +149,7 @@ impl StremioAddonAdapter {
             .await?
             .json()
             .await
-            .map_err(|e| AddonError::CatalogFetch(e))
+            .map_err(AddonError::CatalogFetch)
     }
 
     pub async fn filter_catalog(
@@ -176,7 +176,7 @@ impl StremioAddonAdapter {
             .await?
             .json()
             .await
-            .map_err(|e| AddonError::CatalogFetch(e))
+            .map_err(AddonError::CatalogFetch)
     }
 
     pub async fn get_catalog_with_pagination(
@@ -193,7 +193,7 @@ impl StremioAddonAdapter {
         }
         
         if let Some(limit) = limit {
-            url.push_str(&format!("{}", if skip.is_some() { "&" } else { "?" }));
+            url.push_str(if skip.is_some() { "&" } else { "?" });
             url.push_str(&format!("limit={}", limit));
         }
 
@@ -205,7 +205,7 @@ impl StremioAddonAdapter {
             .await?
             .json()
             .await
-            .map_err(|e| AddonError::CatalogFetch(e))
+            .map_err(AddonError::CatalogFetch)
     }
 
     pub async fn get_catalog_by_type(&self, catalog_type: &str) -> Result<Vec<StreamioCatalog>, AddonError> {
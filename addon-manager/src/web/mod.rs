@@ -1,7 +1,9 @@
 use actix_web::{web, HttpResponse, Result};
 use serde::{Deserialize, Serialize};
+use socialhub_core::auth::AuthenticatedUser;
 use utoipa::ToSchema;
 use crate::error::AddonError;
+use crate::manager::AddonManager;
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct AddonConfig {
@@ -17,40 +19,83 @@ pub struct AddonConfig {
     pub preferred_quality: Option<String>,
 }
 
+/// Longest addon id, and longest catalog filter or quality name.
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_CATALOG_FILTERS: usize = 32;
+
+impl AddonConfig {
+    /// What is returned before the addon `id` was configured.
+    pub fn defaults(id: String) -> Self {
+        Self {
+            id,
+            enabled: true,
+            catalog_filters: None,
+            max_results: Some(50),
+            preferred_quality: Some("HD".to_string()),
+        }
+    }
+
+    /// Addon ids are short names of letters, digits, `.`, `_` and `-`, and
+    /// the other fields are kept small.
+    pub fn validate(&self) -> Result<(), AddonError> {
+        let valid_id = !self.id.is_empty()
+            && self.id.len() <= MAX_NAME_LENGTH
+            && self.id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+        if !valid_id {
+            return Err(AddonError::Configuration("invalid addon id".to_string()));
+        }
+        let filters = self.catalog_filters.as_deref().unwrap_or_default();
+        if filters.len() > MAX_CATALOG_FILTERS || filters.iter().any(|f| f.len() > MAX_NAME_LENGTH) {
+            return Err(AddonError::Configuration("too many or too long catalog filters".to_string()));
+        }
+        if self.preferred_quality.as_ref().is_some_and(|q| q.len() > MAX_NAME_LENGTH) {
+            return Err(AddonError::Configuration("preferred_quality is too long".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Saves the caller's configuration of an addon. Needs `addons:manage`.
 #[utoipa::path(
     post,
-    path = "/addon/{id}/configure",
+    path = "/addons/{id}/configure",
     request_body = AddonConfig,
     responses(
         (status = 200, description = "Addon configured successfully", body = AddonConfig),
-        (status = 400, description = "Invalid configuration")
-    )
+        (status = 400, description = "Invalid configuration, or too many addons configured"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Missing the addons:manage permission")
+    ),
+    security(("bearer_token" = []))
 )]
 pub async fn configure_addon(
+    manager: web::Data<AddonManager>,
+    user: AuthenticatedUser,
+    id: web::Path<String>,
     config: web::Json<AddonConfig>,
 ) -> Result<HttpResponse, AddonError> {
-    Ok(HttpResponse::Ok().json(config.0))
+    let config = AddonConfig { id: id.into_inner(), ..config.into_inner() };
+    manager.set_config(user.user_id, config.clone()).await?;
+    Ok(HttpResponse::Ok().json(config))
 }
 
+/// The caller's configuration of an addon, or the defaults.
 #[utoipa::path(
     get,
-    path = "/addon/{id}/config",
+    path = "/addons/{id}/config",
     responses(
         (status = 200, description = "Addon configuration retrieved", body = AddonConfig),
-        (status = 404, description = "Addon not found")
-    )
+        (status = 401, description = "Not authenticated")
+    ),
+    security(("bearer_token" = []))
 )]
 pub async fn get_addon_config(
+    manager: web::Data<AddonManager>,
+    user: AuthenticatedUser,
     id: web::Path<String>,
 ) -> Result<HttpResponse, AddonError> {
-    let config = AddonConfig {
-        id: id.into_inner(),
-        enabled: true,
-        catalog_filters: None,
-        max_results: Some(50),
-        preferred_quality: Some("HD".to_string()),
-    };
-    
+    let id = id.into_inner();
+    let config = manager.config(user.user_id, &id).await.unwrap_or_else(|| AddonConfig::defaults(id));
     Ok(HttpResponse::Ok().json(config))
 }
 
@@ -1,19 +1,34 @@
 use super::*;
+use std::sync::Arc;
 use actix_web::{test, App, web::ServiceConfig};
+use socialhub_auth::{AuthConfig, AuthService, InMemoryUserRepository, Role};
+
+fn auth_service() -> web::Data<AuthService> {
+    web::Data::new(AuthService::new(AuthConfig::default(), Arc::new(InMemoryUserRepository::new())))
+}
+
+fn bearer(auth: &AuthService, user_id: i32) -> (&'static str, String) {
+    let (token, _) = auth.tokens().issue(user_id, &[Role::User], &[]).unwrap();
+    ("Authorization", format!("Bearer {}", token))
+}
 
 fn test_config(cfg: &mut ServiceConfig) {
-    cfg.service(
-        web::resource("/addon/{id}/configure")
-            .route(web::post().to(configure_addon))
-    ).service(
-        web::resource("/addon/{id}/config")
-            .route(web::get().to(get_addon_config))
-    );
+    cfg.app_data(web::Data::new(AddonManager::new()))
+        .service(
+            web::resource("/addon/{id}/configure")
+                .route(web::post().to(configure_addon))
+        ).service(
+            web::resource("/addon/{id}/config")
+                .route(web::get().to(get_addon_config))
+        );
 }
 
 #[actix_web::test]
 async fn test_configure_addon() {
-    let app = test::init_service(App::new().configure(test_config)).await;
+    let auth = auth_service();
+    let app = test::init_service(
+        App::new().configure(socialhub_auth::configure_state(auth.clone())).configure(test_config)
+    ).await;
 
     let config = AddonConfig {
         id: "test-addon".to_string(),
@@ -27,6 +42,13 @@ async fn test_configure_addon() {
         .uri("/addon/test-addon/configure")
         .set_json(&config)
         .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 401);
+
+    let req = test::TestRequest::post()
+        .uri("/addon/test-addon/configure")
+        .insert_header(bearer(&auth, 1))
+        .set_json(&config)
+        .to_request();
 
     let resp = test::call_service(&app, req).await;
     assert!(resp.status().is_success());
@@ -34,14 +56,33 @@ async fn test_configure_addon() {
     let result: AddonConfig = test::read_body_json(resp).await;
     assert_eq!(result.id, "test-addon");
     assert_eq!(result.max_results, Some(100));
+
+    // Saved for the caller only.
+    let req = test::TestRequest::get()
+        .uri("/addon/test-addon/config")
+        .insert_header(bearer(&auth, 1))
+        .to_request();
+    let saved: AddonConfig = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(saved.max_results, Some(100));
+
+    let req = test::TestRequest::get()
+        .uri("/addon/test-addon/config")
+        .insert_header(bearer(&auth, 2))
+        .to_request();
+    let other: AddonConfig = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(other.max_results, Some(50));
 }
 
 #[actix_web::test]
 async fn test_get_addon_config() {
-    let app = test::init_service(App::new().configure(test_config)).await;
+    let auth = auth_service();
+    let app = test::init_service(
+        App::new().configure(socialhub_auth::configure_state(auth.clone())).configure(test_config)
+    ).await;
 
     let req = test::TestRequest::get()
         .uri("/addon/test-addon/config")
+        .insert_header(bearer(&auth, 1))
         .to_request();
 
     let resp = test::call_service(&app, req).await;
@@ -51,3 +92,41 @@ async fn test_get_addon_config() {
     assert_eq!(config.id, "test-addon");
     assert!(config.enabled);
 }
+
+#[actix_web::test]
+async fn test_configure_addon_rejects_invalid_config() {
+    let auth = auth_service();
+    let app = test::init_service(
+        App::new().configure(socialhub_auth::configure_state(auth.clone())).configure(test_config)
+    ).await;
+
+    let req = test::TestRequest::post()
+        .uri("/addon/bad%20id/configure")
+        .insert_header(bearer(&auth, 1))
+        .set_json(AddonConfig::defaults("bad id".to_string()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 400);
+
+    let mut config = AddonConfig::defaults("test-addon".to_string());
+    config.catalog_filters = Some(vec!["Action".to_string(); 100]);
+    let req = test::TestRequest::post()
+        .uri("/addon/test-addon/configure")
+        .insert_header(bearer(&auth, 1))
+        .set_json(&config)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 400);
+}
+
+#[actix_web::test]
+async fn test_configure_addon_caps_configs_per_user() {
+    let manager = AddonManager::new();
+    for i in 0..crate::manager::MAX_CONFIGS_PER_USER {
+        manager.set_config(1, AddonConfig::defaults(format!("addon-{}", i))).await.unwrap();
+    }
+
+    let extra = manager.set_config(1, AddonConfig::defaults("one-more".to_string())).await;
+    assert!(matches!(extra, Err(AddonError::Configuration(_))));
+    // Updating a configured addon and other users are unaffected.
+    manager.set_config(1, AddonConfig::defaults("addon-0".to_string())).await.unwrap();
+    manager.set_config(2, AddonConfig::defaults("one-more".to_string())).await.unwrap();
+}
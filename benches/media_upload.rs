@@ -1,24 +1,28 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::sync::Arc;
+use criterion::{criterion_group, criterion_main, Criterion};
 use actix_web::{test, web, App};
-use socialhub_media::handlers;
-use bytes::Bytes;
+use socialhub_auth::{AuthConfig, AuthService, InMemoryUserRepository};
+use socialhub_core::Role;
+use socialhub_media::{handlers, storage::LocalStorage, InMemoryMediaRepository, MediaConfig, MediaService};
 
-async fn benchmark_upload() {
+async fn benchmark_upload(auth: web::Data<AuthService>, media: web::Data<MediaService>, token: &str) {
     let app = test::init_service(
-        App::new().service(web::scope("/media").route("/upload", web::post().to(handlers::upload)))
+        App::new()
+            .configure(socialhub_auth::configure_state(auth))
+            .app_data(media)
+            .service(web::scope("/media").route("/upload", web::post().to(handlers::upload)))
     ).await;
 
-    let payload = concat!(
-        "--boundary\r\n",
-        "Content-Disposition: form-data; name=\"file\"; filename=\"test.jpg\"\r\n",
-        "Content-Type: image/jpeg\r\n\r\n",
-        "test file content\r\n",
-        "--boundary--\r\n"
-    );
+    let payload: &[u8] = b"--boundary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"pixel.gif\"\r\n\
+        Content-Type: image/gif\r\n\r\n\
+        GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xFF\xFF\xFF!\xF9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x01D\x00;\r\n\
+        --boundary--\r\n";
 
     let req = test::TestRequest::post()
         .uri("/media/upload")
         .insert_header(("content-type", "multipart/form-data; boundary=boundary"))
+        .insert_header(("authorization", format!("Bearer {}", token)))
         .set_payload(payload)
         .to_request();
 
@@ -26,12 +30,20 @@ async fn benchmark_upload() {
 }
 
 pub fn upload_benchmark(c: &mut Criterion) {
-    let rt = tokio::runtime::Runtime::new().unwrap();
+    let system = actix_rt::System::new();
+    let auth = web::Data::new(AuthService::new(AuthConfig::default(), Arc::new(InMemoryUserRepository::new())));
+    let (token, _) = auth.tokens().issue(1, &[Role::User], &[]).unwrap();
+    let upload_dir = std::env::temp_dir().join("socialhub-bench-uploads");
+    let media = web::Data::new(MediaService::new(
+        MediaConfig::default(),
+        Arc::new(LocalStorage::new(&upload_dir)),
+        Arc::new(InMemoryMediaRepository::new()),
+    ));
+
     c.bench_function("media_upload", |b| {
-        b.to_async(&rt).iter(|| async {
-            benchmark_upload().await
-        });
+        b.iter(|| system.block_on(benchmark_upload(auth.clone(), media.clone(), &token)));
     });
+    let _ = std::fs::remove_dir_all(upload_dir);
 }
 
 criterion_group!(benches, upload_benchmark);
@@ -0,0 +1,3 @@
+ALTER TABLE users
+    ADD COLUMN roles TEXT[] NOT NULL DEFAULT '{user}',
+    ADD COLUMN permissions TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,11 @@
+CREATE TABLE user_sessions (
+    id UUID PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    device_name VARCHAR(64),
+    user_agent VARCHAR(512),
+    ip VARCHAR(45),
+    created_at BIGINT NOT NULL,
+    last_seen_at BIGINT NOT NULL
+);
+
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id);
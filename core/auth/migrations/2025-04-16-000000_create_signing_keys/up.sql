@@ -0,0 +1,7 @@
+CREATE TABLE signing_keys (
+    kid TEXT PRIMARY KEY,
+    algorithm VARCHAR(16) NOT NULL,
+    private_key BYTEA NOT NULL,
+    created_at BIGINT NOT NULL,
+    active_from BIGINT NOT NULL
+);
@@ -0,0 +1,3 @@
+ALTER TABLE users ADD COLUMN deletion_due_at BIGINT;
+
+CREATE INDEX users_deletion_due_at_idx ON users (deletion_due_at) WHERE deletion_due_at IS NOT NULL;
@@ -0,0 +1,32 @@
+-- No foreign keys to users: events outlive the accounts they mention.
+CREATE TABLE audit_events (
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at BIGINT NOT NULL,
+    action VARCHAR(32) NOT NULL,
+    outcome VARCHAR(16) NOT NULL,
+    actor_id INTEGER,
+    target_id INTEGER,
+    ip VARCHAR(45),
+    user_agent VARCHAR(512),
+    details JSONB NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id, id);
+CREATE INDEX audit_events_target_id_idx ON audit_events (target_id, id);
+CREATE INDEX audit_events_action_idx ON audit_events (action, id);
+CREATE INDEX audit_events_occurred_at_idx ON audit_events (occurred_at);
+
+-- The log is append-only.
+CREATE FUNCTION audit_events_reject_change() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_no_update_or_delete
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION audit_events_reject_change();
+
+CREATE TRIGGER audit_events_no_truncate
+    BEFORE TRUNCATE ON audit_events
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_events_reject_change();
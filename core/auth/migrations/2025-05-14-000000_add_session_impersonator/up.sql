@@ -0,0 +1 @@
+ALTER TABLE user_sessions ADD COLUMN impersonator_id INTEGER REFERENCES users (id) ON DELETE SET NULL;
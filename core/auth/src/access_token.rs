@@ -0,0 +1,101 @@
+//! Personal access tokens: named, scoped and revocable credentials for bots
+//! and integrations, used in place of a login session.
+//!
+//! Tokens carry [`TOKEN_PREFIX`] so the verifier can tell them from JWTs.
+//! Like refresh tokens, only their digest is stored.
+
+use std::{collections::HashMap, sync::Mutex};
+use async_trait::async_trait;
+use jsonwebtoken::get_current_timestamp;
+use uuid::Uuid;
+use crate::error::AuthError;
+
+/// Prefix of every personal access token.
+pub const TOKEN_PREFIX: &str = "shp_";
+
+#[derive(Debug, Clone)]
+pub struct AccessTokenRecord {
+    pub id: Uuid,
+    pub user_id: i32,
+    pub name: String,
+    /// Permissions the token is limited to.
+    pub scopes: Vec<String>,
+    /// SHA-256 digest of the token, see [`crate::secret::hash`].
+    pub token_hash: String,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+}
+
+impl AccessTokenRecord {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= get_current_timestamp())
+    }
+}
+
+#[async_trait]
+pub trait AccessTokenStore: Send + Sync {
+    async fn insert(&self, record: AccessTokenRecord) -> Result<(), AuthError>;
+
+    /// Tokens of the user, oldest first.
+    async fn list(&self, user_id: i32) -> Result<Vec<AccessTokenRecord>, AuthError>;
+
+    async fn find(&self, token_hash: &str) -> Result<Option<AccessTokenRecord>, AuthError>;
+
+    /// Deletes a token of the user. Returns `false` if the user has no such token.
+    async fn delete(&self, user_id: i32, id: Uuid) -> Result<bool, AuthError>;
+
+    /// Records that the token was used at `at`.
+    async fn touch(&self, id: Uuid, at: u64) -> Result<(), AuthError>;
+}
+
+/// Keeps personal access tokens in process memory. Intended for tests and
+/// local development.
+#[derive(Default)]
+pub struct InMemoryAccessTokenStore {
+    tokens: Mutex<HashMap<Uuid, AccessTokenRecord>>,
+}
+
+impl InMemoryAccessTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AccessTokenStore for InMemoryAccessTokenStore {
+    async fn insert(&self, record: AccessTokenRecord) -> Result<(), AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        tokens.insert(record.id, record);
+        Ok(())
+    }
+
+    async fn list(&self, user_id: i32) -> Result<Vec<AccessTokenRecord>, AuthError> {
+        let tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        let mut list: Vec<_> = tokens.values().filter(|r| r.user_id == user_id).cloned().collect();
+        list.sort_by_key(|r| r.created_at);
+        Ok(list)
+    }
+
+    async fn find(&self, token_hash: &str) -> Result<Option<AccessTokenRecord>, AuthError> {
+        let tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(tokens.values().find(|r| r.token_hash == token_hash).cloned())
+    }
+
+    async fn delete(&self, user_id: i32, id: Uuid) -> Result<bool, AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        if tokens.get(&id).is_some_and(|r| r.user_id == user_id) {
+            tokens.remove(&id);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    async fn touch(&self, id: Uuid, at: u64) -> Result<(), AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        if let Some(record) = tokens.get_mut(&id) {
+            record.last_used_at = Some(at);
+        }
+        Ok(())
+    }
+}
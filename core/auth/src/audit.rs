@@ -0,0 +1,327 @@
+//! Security audit log.
+//!
+//! Logins, device logins, OAuth token grants, password resets, 2FA changes,
+//! token changes, session revocations, role changes and impersonations are
+//! appended as [`AuditEvent`]s, whether they succeed or fail. Events are
+//! never changed or deleted; the Postgres table rejects updates and deletes
+//! outright.
+
+use std::{fmt, str::FromStr, sync::Mutex};
+use async_trait::async_trait;
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::{error::AuthError, session::ClientInfo};
+
+/// Events returned per page by default, and at most.
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    TwoFactorLogin,
+    DeviceLogin,
+    #[serde(rename = "oauth_token_issued")]
+    OAuthTokenIssued,
+    Logout,
+    LogoutAll,
+    SessionRevoked,
+    PasswordReset,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    AccessTokenCreated,
+    AccessTokenDeleted,
+    RolesUpdated,
+    AccountDeletionRequested,
+    ImpersonationStarted,
+}
+
+impl AuditAction {
+    pub const ALL: &'static [AuditAction] = &[
+        AuditAction::Login,
+        AuditAction::TwoFactorLogin,
+        AuditAction::DeviceLogin,
+        AuditAction::OAuthTokenIssued,
+        AuditAction::Logout,
+        AuditAction::LogoutAll,
+        AuditAction::SessionRevoked,
+        AuditAction::PasswordReset,
+        AuditAction::TwoFactorEnabled,
+        AuditAction::TwoFactorDisabled,
+        AuditAction::AccessTokenCreated,
+        AuditAction::AccessTokenDeleted,
+        AuditAction::RolesUpdated,
+        AuditAction::AccountDeletionRequested,
+        AuditAction::ImpersonationStarted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::TwoFactorLogin => "two_factor_login",
+            AuditAction::DeviceLogin => "device_login",
+            AuditAction::OAuthTokenIssued => "oauth_token_issued",
+            AuditAction::Logout => "logout",
+            AuditAction::LogoutAll => "logout_all",
+            AuditAction::SessionRevoked => "session_revoked",
+            AuditAction::PasswordReset => "password_reset",
+            AuditAction::TwoFactorEnabled => "two_factor_enabled",
+            AuditAction::TwoFactorDisabled => "two_factor_disabled",
+            AuditAction::AccessTokenCreated => "access_token_created",
+            AuditAction::AccessTokenDeleted => "access_token_deleted",
+            AuditAction::RolesUpdated => "roles_updated",
+            AuditAction::AccountDeletionRequested => "account_deletion_requested",
+            AuditAction::ImpersonationStarted => "impersonation_started",
+        }
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|action| action.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown audit action: {}", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+        }
+    }
+}
+
+impl FromStr for AuditOutcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "success" => Ok(AuditOutcome::Success),
+            "failure" => Ok(AuditOutcome::Failure),
+            _ => Err(format!("Unknown audit outcome: {}", s)),
+        }
+    }
+}
+
+/// A recorded event.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditEvent {
+    /// Increases with every event; pages are cut at it.
+    pub id: i64,
+    /// Unix timestamp in seconds.
+    pub occurred_at: u64,
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    /// The user who acted, if known. Failed logins have none.
+    pub actor_id: Option<i32>,
+    /// The user acted upon, e.g. whose roles changed.
+    pub target_id: Option<i32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Action-specific facts, e.g. the attempted username or the error.
+    #[schema(value_type = Object)]
+    pub details: serde_json::Value,
+}
+
+/// An event to record.
+#[derive(Debug, Clone)]
+pub struct NewAuditEvent {
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    pub actor_id: Option<i32>,
+    pub target_id: Option<i32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: serde_json::Map<String, serde_json::Value>,
+}
+
+impl NewAuditEvent {
+    /// A successful `action` by the client.
+    pub fn new(action: AuditAction, client: &ClientInfo) -> Self {
+        Self {
+            action,
+            outcome: AuditOutcome::Success,
+            actor_id: None,
+            target_id: None,
+            ip: client.ip.clone(),
+            user_agent: client.user_agent.clone(),
+            details: serde_json::Map::new(),
+        }
+    }
+
+    /// Of an action by `user_id` on their own account.
+    pub fn by(self, user_id: i32) -> Self {
+        self.actor(user_id).target(user_id)
+    }
+
+    pub fn actor(mut self, user_id: i32) -> Self {
+        self.actor_id = Some(user_id);
+        self
+    }
+
+    pub fn target(mut self, user_id: i32) -> Self {
+        self.target_id = Some(user_id);
+        self
+    }
+
+    pub fn detail(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.details.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Marks the event failed and notes the error.
+    pub fn failed(mut self, error: &AuthError) -> Self {
+        self.outcome = AuditOutcome::Failure;
+        self.detail("error", error.to_string())
+    }
+
+    /// Marks the event failed if `result` is an error.
+    pub fn outcome<T>(self, result: &Result<T, AuthError>) -> Self {
+        match result {
+            Ok(_) => self,
+            Err(e) => self.failed(e),
+        }
+    }
+}
+
+/// Filters of an audit log query. All given ones must match.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub action: Option<AuditAction>,
+    pub outcome: Option<AuditOutcome>,
+    pub actor_id: Option<i32>,
+    pub target_id: Option<i32>,
+    pub ip: Option<String>,
+    /// Unix timestamps in seconds, inclusive.
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// Only events older than the one with this id.
+    pub before: Option<i64>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        self.action.is_none_or(|action| event.action == action)
+            && self.outcome.is_none_or(|outcome| event.outcome == outcome)
+            && self.actor_id.is_none_or(|id| event.actor_id == Some(id))
+            && self.target_id.is_none_or(|id| event.target_id == Some(id))
+            && self.ip.as_ref().is_none_or(|ip| event.ip.as_ref() == Some(ip))
+            && self.since.is_none_or(|since| event.occurred_at >= since)
+            && self.until.is_none_or(|until| event.occurred_at <= until)
+            && self.before.is_none_or(|before| event.id < before)
+    }
+}
+
+#[async_trait]
+pub trait AuditLog: Send + Sync {
+    async fn append(&self, event: NewAuditEvent) -> Result<(), AuthError>;
+
+    /// Up to `limit` matching events, newest first.
+    async fn query(&self, filter: &AuditFilter, limit: u32) -> Result<Vec<AuditEvent>, AuthError>;
+}
+
+/// Keeps audit events in process memory. Intended for tests and local development.
+#[derive(Default)]
+pub struct InMemoryAuditLog {
+    events: Mutex<Vec<AuditEvent>>,
+}
+
+impl InMemoryAuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AuditLog for InMemoryAuditLog {
+    async fn append(&self, event: NewAuditEvent) -> Result<(), AuthError> {
+        let mut events = self.events.lock().map_err(|_| AuthError::InternalError)?;
+        let id = events.len() as i64 + 1;
+        events.push(AuditEvent {
+            id,
+            occurred_at: get_current_timestamp(),
+            action: event.action,
+            outcome: event.outcome,
+            actor_id: event.actor_id,
+            target_id: event.target_id,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            details: serde_json::Value::Object(event.details),
+        });
+        Ok(())
+    }
+
+    async fn query(&self, filter: &AuditFilter, limit: u32) -> Result<Vec<AuditEvent>, AuthError> {
+        let events = self.events.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(events
+            .iter()
+            .rev()
+            .filter(|event| filter.matches(event))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_names_round_trip() {
+        for action in AuditAction::ALL {
+            assert_eq!(action.as_str().parse::<AuditAction>(), Ok(*action));
+            assert_eq!(serde_json::to_value(action).unwrap(), action.as_str());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_query_pages_newest_first() {
+        let log = InMemoryAuditLog::new();
+        let client = ClientInfo { ip: Some("10.0.0.1".to_string()), ..ClientInfo::default() };
+        for user_id in 1..=5 {
+            log.append(NewAuditEvent::new(AuditAction::Login, &client).by(user_id)).await.unwrap();
+        }
+        let failed: Result<(), AuthError> = Err(AuthError::InvalidCredentials);
+        log.append(NewAuditEvent::new(AuditAction::Login, &ClientInfo::default()).outcome(&failed)).await.unwrap();
+
+        let page = log.query(&AuditFilter::default(), 4).await.unwrap();
+        assert_eq!(page.iter().map(|e| e.id).collect::<Vec<_>>(), vec![6, 5, 4, 3]);
+        assert_eq!(page[0].outcome, AuditOutcome::Failure);
+        assert_eq!(page[0].details["error"], "Invalid credentials");
+
+        let next = AuditFilter { before: Some(3), ..AuditFilter::default() };
+        let page = log.query(&next, 4).await.unwrap();
+        assert_eq!(page.iter().map(|e| e.id).collect::<Vec<_>>(), vec![2, 1]);
+
+        let filter = AuditFilter {
+            ip: Some("10.0.0.1".to_string()),
+            actor_id: Some(2),
+            ..AuditFilter::default()
+        };
+        let page = log.query(&filter, 10).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].target_id, Some(2));
+    }
+}
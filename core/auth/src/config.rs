@@ -0,0 +1,210 @@
+use std::path::PathBuf;
+use jsonwebtoken::Algorithm;
+use log::warn;
+use crate::invite::RegistrationMode;
+use rand::{distributions::Alphanumeric, Rng};
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// HMAC secret that verifies access tokens without a key id, issued
+    /// before signing keys were rotated, see [`crate::keys`].
+    pub jwt_secret: String,
+    /// Algorithm of access tokens: `HS256`, `RS256` or `EdDSA`. Only the
+    /// asymmetric ones can be verified by other services with the published keys.
+    pub jwt_algorithm: Algorithm,
+    /// Seconds after which the signing key is replaced.
+    pub key_rotation_interval: u64,
+    /// Value of the `iss` claim, checked on verification.
+    pub issuer: String,
+    /// Base URL of this API as an OpenID Connect provider. The discovery
+    /// document is served below it, and ID tokens carry it as `iss`.
+    pub oidc_issuer: String,
+    /// Access token lifetime in seconds.
+    pub token_expiration: u64,
+    /// Refresh token lifetime in seconds.
+    pub refresh_token_expiration: u64,
+    /// Lifetime in seconds of the tokens admins get to act as another user.
+    /// They cannot be refreshed.
+    pub impersonation_token_expiration: u64,
+    /// bcrypt cost factor for password hashes.
+    pub hash_cost: u32,
+    /// Base URL of the web app, used for the links in password reset and
+    /// verification emails.
+    pub public_url: String,
+    /// Password reset token lifetime in seconds.
+    pub password_reset_expiration: u64,
+    /// Email verification token lifetime in seconds.
+    pub email_verification_expiration: u64,
+    /// Failed logins for one username before it is locked.
+    pub login_max_failures: u32,
+    /// Failed logins from one IP address, across usernames, before it is locked.
+    pub login_max_failures_per_ip: u32,
+    /// First lockout in seconds; doubles with every further failure.
+    pub login_lockout: u64,
+    /// Longest lockout in seconds.
+    pub login_max_lockout: u64,
+    /// Seconds without failures after which the counters start over.
+    pub login_failure_window: u64,
+    /// Lifetime of device authorization codes in seconds.
+    pub device_code_expiration: u64,
+    /// Seconds devices have to wait between token polls.
+    pub device_poll_interval: u64,
+    /// Seconds between a deletion request and the account being erased.
+    /// Until then the user can still log in and cancel it.
+    pub account_deletion_grace_period: u64,
+    /// Seconds a personal data export stays available for download.
+    pub export_expiration: u64,
+    /// Directory export archives are written to until they expire.
+    pub export_dir: PathBuf,
+    /// Who may sign up, see [`crate::invite`].
+    pub registration_mode: RegistrationMode,
+    /// Email domains accepted in [`RegistrationMode::DomainAllowlist`],
+    /// subdomains included.
+    pub registration_allowed_domains: Vec<String>,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        let jwt_secret = std::env::var("AUTH_JWT_SECRET").unwrap_or_else(|_| {
+            // Tokens signed with a random secret do not survive a restart and are
+            // not shared between instances, so this is only good for development.
+            warn!("AUTH_JWT_SECRET not set, generating an ephemeral signing secret");
+            random_secret()
+        });
+
+        Self {
+            jwt_secret,
+            jwt_algorithm: std::env::var("AUTH_JWT_ALGORITHM")
+                .unwrap_or_else(|_| "HS256".to_string())
+                .parse()
+                .ok()
+                .filter(|algorithm| crate::keys::SUPPORTED_ALGORITHMS.contains(algorithm))
+                .expect("AUTH_JWT_ALGORITHM must be HS256, RS256 or EdDSA"),
+            key_rotation_interval: std::env::var("AUTH_KEY_ROTATION_INTERVAL")
+                .unwrap_or_else(|_| "2592000".to_string()) // 30 days
+                .parse()
+                .unwrap(),
+            issuer: std::env::var("AUTH_ISSUER")
+                .unwrap_or_else(|_| "socialhub".to_string()),
+            oidc_issuer: std::env::var("AUTH_OIDC_ISSUER")
+                .unwrap_or_else(|_| "http://localhost:8080".to_string()),
+            token_expiration: std::env::var("AUTH_TOKEN_EXPIRATION")
+                .unwrap_or_else(|_| "86400".to_string()) // 24 hours
+                .parse()
+                .unwrap(),
+            refresh_token_expiration: std::env::var("AUTH_REFRESH_TOKEN_EXPIRATION")
+                .unwrap_or_else(|_| "604800".to_string()) // 7 days
+                .parse()
+                .unwrap(),
+            impersonation_token_expiration: std::env::var("AUTH_IMPERSONATION_TOKEN_EXPIRATION")
+                .unwrap_or_else(|_| "900".to_string()) // 15 minutes
+                .parse()
+                .unwrap(),
+            hash_cost: std::env::var("AUTH_HASH_COST")
+                .map(|cost| cost.parse().unwrap())
+                .unwrap_or(bcrypt::DEFAULT_COST),
+            public_url: std::env::var("AUTH_PUBLIC_URL")
+                .unwrap_or_else(|_| "http://localhost:8080".to_string()),
+            password_reset_expiration: std::env::var("AUTH_PASSWORD_RESET_EXPIRATION")
+                .unwrap_or_else(|_| "3600".to_string()) // 1 hour
+                .parse()
+                .unwrap(),
+            email_verification_expiration: std::env::var("AUTH_EMAIL_VERIFICATION_EXPIRATION")
+                .unwrap_or_else(|_| "86400".to_string()) // 24 hours
+                .parse()
+                .unwrap(),
+            login_max_failures: std::env::var("AUTH_LOGIN_MAX_FAILURES")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .unwrap(),
+            login_max_failures_per_ip: std::env::var("AUTH_LOGIN_MAX_FAILURES_PER_IP")
+                .unwrap_or_else(|_| "20".to_string())
+                .parse()
+                .unwrap(),
+            login_lockout: std::env::var("AUTH_LOGIN_LOCKOUT")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap(),
+            login_max_lockout: std::env::var("AUTH_LOGIN_MAX_LOCKOUT")
+                .unwrap_or_else(|_| "3600".to_string()) // 1 hour
+                .parse()
+                .unwrap(),
+            login_failure_window: std::env::var("AUTH_LOGIN_FAILURE_WINDOW")
+                .unwrap_or_else(|_| "900".to_string()) // 15 minutes
+                .parse()
+                .unwrap(),
+            device_code_expiration: std::env::var("AUTH_DEVICE_CODE_EXPIRATION")
+                .unwrap_or_else(|_| "600".to_string()) // 10 minutes
+                .parse()
+                .unwrap(),
+            device_poll_interval: std::env::var("AUTH_DEVICE_POLL_INTERVAL")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .unwrap(),
+            account_deletion_grace_period: std::env::var("AUTH_ACCOUNT_DELETION_GRACE_PERIOD")
+                .unwrap_or_else(|_| "2592000".to_string()) // 30 days
+                .parse()
+                .unwrap(),
+            export_expiration: std::env::var("AUTH_EXPORT_EXPIRATION")
+                .unwrap_or_else(|_| "86400".to_string()) // 24 hours
+                .parse()
+                .unwrap(),
+            export_dir: std::env::var("AUTH_EXPORT_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| default_export_dir()),
+            registration_mode: std::env::var("AUTH_REGISTRATION_MODE")
+                .unwrap_or_else(|_| "open".to_string())
+                .parse()
+                .expect("AUTH_REGISTRATION_MODE must be open, invite or allowlist"),
+            registration_allowed_domains: std::env::var("AUTH_REGISTRATION_ALLOWED_DOMAINS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|domain| domain.trim().to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwt_secret: random_secret(),
+            jwt_algorithm: Algorithm::HS256,
+            key_rotation_interval: 2592000,
+            issuer: "socialhub".to_string(),
+            oidc_issuer: "http://localhost:8080".to_string(),
+            token_expiration: 86400,
+            refresh_token_expiration: 604800,
+            impersonation_token_expiration: 900,
+            hash_cost: bcrypt::DEFAULT_COST,
+            public_url: "http://localhost:8080".to_string(),
+            password_reset_expiration: 3600,
+            email_verification_expiration: 86400,
+            login_max_failures: 5,
+            login_max_failures_per_ip: 20,
+            login_lockout: 30,
+            login_max_lockout: 3600,
+            login_failure_window: 900,
+            device_code_expiration: 600,
+            device_poll_interval: 5,
+            account_deletion_grace_period: 2592000,
+            export_expiration: 86400,
+            export_dir: default_export_dir(),
+            registration_mode: RegistrationMode::Open,
+            registration_allowed_domains: Vec::new(),
+        }
+    }
+}
+
+fn default_export_dir() -> PathBuf {
+    std::env::temp_dir().join("socialhub-exports")
+}
+
+fn random_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect()
+}
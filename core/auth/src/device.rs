@@ -0,0 +1,302 @@
+//! OAuth 2.0 device authorization grant (RFC 8628), for TVs and consoles
+//! where typing a password is impractical.
+//!
+//! The device gets a secret device code and a short user code. The user
+//! enters the user code on another, signed-in device to approve the login,
+//! while the device polls the token endpoint with its device code. Pending
+//! authorizations only live in memory and expire after
+//! [`AuthConfig::device_code_expiration`] seconds.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use rand::Rng;
+use socialhub_core::cache::{CacheConfig, CacheManager};
+use crate::{
+    config::AuthConfig,
+    error::{AuthError, OAuthErrorCode},
+    secret,
+    session::ClientInfo,
+};
+
+/// `grant_type` of device code token requests.
+pub const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Upper bound on pending authorizations.
+const CAPACITY: u64 = 100_000;
+/// Consonants only, so codes spell no words and survive being read aloud.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+/// Added to the polling interval on every `slow_down`, in seconds.
+const SLOW_DOWN_STEP: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Approved { user_id: i32 },
+    Denied,
+    /// Tokens were issued; the device code cannot be used again.
+    Redeemed,
+}
+
+#[derive(Debug, Clone)]
+struct DeviceAuthorization {
+    client_id: String,
+    client: ClientInfo,
+    status: Status,
+    /// Milliseconds since the epoch.
+    expires_at: u64,
+    /// Minimum time between polls, in seconds.
+    interval: u64,
+    last_poll: Option<u64>,
+}
+
+/// Codes handed to the device by [`DeviceAuthorizations::start`].
+#[derive(Debug, Clone)]
+pub struct DeviceCodes {
+    pub device_code: String,
+    /// Formatted as `XXXX-XXXX`.
+    pub user_code: String,
+}
+
+/// The device is cleared to receive tokens for `user_id`.
+#[derive(Debug, Clone)]
+pub struct DeviceGrant {
+    pub user_id: i32,
+    /// The device as seen when it asked for its codes.
+    pub client: ClientInfo,
+}
+
+pub struct DeviceAuthorizations {
+    /// Keyed by the digest of the device code.
+    pending: CacheManager<String, DeviceAuthorization>,
+    /// Normalized user code to device code digest.
+    user_codes: CacheManager<String, String>,
+    lifetime: u64,
+    interval: u64,
+}
+
+impl DeviceAuthorizations {
+    pub fn new(config: &AuthConfig) -> Self {
+        // Kept past expiry so late polls get `expired_token` rather than `invalid_grant`.
+        let retention = config.device_code_expiration * 2;
+        let cache_config = CacheConfig {
+            max_capacity: CAPACITY,
+            time_to_live: retention,
+            time_to_idle: retention,
+        };
+        Self {
+            pending: CacheManager::new(cache_config.clone()),
+            user_codes: CacheManager::new(cache_config),
+            lifetime: config.device_code_expiration,
+            interval: config.device_poll_interval,
+        }
+    }
+
+    /// Seconds until a new authorization expires.
+    pub fn lifetime(&self) -> u64 {
+        self.lifetime
+    }
+
+    /// Seconds a device has to wait between polls.
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// Starts an authorization for `client_id` on the device described by `client`.
+    pub async fn start(&self, client_id: &str, client: ClientInfo) -> DeviceCodes {
+        let device_code = secret::generate();
+        let user_code = generate_user_code();
+        let device_hash = secret::hash(&device_code);
+
+        self.pending.set(device_hash.clone(), DeviceAuthorization {
+            client_id: client_id.to_string(),
+            client,
+            status: Status::Pending,
+            expires_at: now_millis() + self.lifetime * 1000,
+            interval: self.interval,
+            last_poll: None,
+        }).await;
+        self.user_codes.set(normalize_user_code(&user_code), device_hash).await;
+
+        DeviceCodes { device_code, user_code }
+    }
+
+    /// Approves the pending authorization of `user_code` for `user_id`, or
+    /// denies it when `user_id` is `None`.
+    pub async fn decide(&self, user_code: &str, user_id: Option<i32>) -> Result<(), AuthError> {
+        let user_code = normalize_user_code(user_code);
+        let device_hash = self.user_codes.get(&user_code).await.ok_or(AuthError::UserCodeNotFound)?;
+        // Each user code is good for one decision.
+        self.user_codes.remove(&user_code).await;
+
+        let now = now_millis();
+        let mut decided = false;
+        self.pending.upsert_with(device_hash, |current| {
+            let mut authorization = current.unwrap_or_else(expired_authorization);
+            if authorization.status == Status::Pending && authorization.expires_at > now {
+                authorization.status = match user_id {
+                    Some(user_id) => Status::Approved { user_id },
+                    None => Status::Denied,
+                };
+                decided = true;
+            }
+            authorization
+        }).await;
+
+        if !decided {
+            return Err(AuthError::UserCodeNotFound);
+        }
+        Ok(())
+    }
+
+    /// Polls the authorization of `device_code`. Succeeds once, after the user
+    /// approved it; until then fails with the RFC 8628 error to send to the device.
+    pub async fn poll(&self, device_code: &str, client_id: &str) -> Result<DeviceGrant, AuthError> {
+        self.poll_at(device_code, client_id, now_millis()).await
+    }
+
+    async fn poll_at(&self, device_code: &str, client_id: &str, now: u64) -> Result<DeviceGrant, AuthError> {
+        let device_hash = secret::hash(device_code);
+        let found = self.pending
+            .get(&device_hash)
+            .await
+            .filter(|a| a.client_id == client_id)
+            .ok_or(AuthError::OAuth(OAuthErrorCode::InvalidGrant))?;
+
+        let mut outcome = Err(AuthError::OAuth(OAuthErrorCode::InvalidGrant));
+        self.pending.upsert_with(device_hash, |current| {
+            let mut authorization = current.unwrap_or(found);
+            let too_early = authorization
+                .last_poll
+                .is_some_and(|last| now < last + authorization.interval * 1000);
+            authorization.last_poll = Some(now);
+
+            outcome = match authorization.status {
+                Status::Redeemed => Err(OAuthErrorCode::InvalidGrant),
+                _ if authorization.expires_at <= now => Err(OAuthErrorCode::ExpiredToken),
+                Status::Denied => Err(OAuthErrorCode::AccessDenied),
+                Status::Pending if too_early => {
+                    authorization.interval += SLOW_DOWN_STEP;
+                    Err(OAuthErrorCode::SlowDown)
+                }
+                Status::Pending => Err(OAuthErrorCode::AuthorizationPending),
+                Status::Approved { user_id } => {
+                    authorization.status = Status::Redeemed;
+                    Ok(DeviceGrant { user_id, client: authorization.client.clone() })
+                }
+            }
+            .map_err(AuthError::OAuth);
+            authorization
+        }).await;
+        outcome
+    }
+}
+
+/// Stand-in for an authorization evicted between lookup and update.
+fn expired_authorization() -> DeviceAuthorization {
+    DeviceAuthorization {
+        client_id: String::new(),
+        client: ClientInfo::default(),
+        status: Status::Redeemed,
+        expires_at: 0,
+        interval: 0,
+        last_poll: None,
+    }
+}
+
+fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    let code: String = (0..USER_CODE_LENGTH)
+        .map(|_| USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &code[..USER_CODE_LENGTH / 2], &code[USER_CODE_LENGTH / 2..])
+}
+
+/// Ignores case, spaces and dashes, which users type inconsistently.
+fn normalize_user_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authorizations() -> DeviceAuthorizations {
+        DeviceAuthorizations::new(&AuthConfig {
+            device_code_expiration: 600,
+            device_poll_interval: 5,
+            ..AuthConfig::default()
+        })
+    }
+
+    fn error_code(result: Result<DeviceGrant, AuthError>) -> OAuthErrorCode {
+        match result {
+            Err(AuthError::OAuth(code)) => code,
+            Ok(grant) => panic!("unexpected grant for user {}", grant.user_id),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_user_code_format() {
+        let code = generate_user_code();
+        assert_eq!(code.len(), 9);
+        assert_eq!(&code[4..5], "-");
+        assert!(code.bytes().filter(|&b| b != b'-').all(|b| USER_CODE_ALPHABET.contains(&b)));
+        assert_eq!(normalize_user_code("bcdf ghjk"), normalize_user_code("BCDF-GHJK"));
+    }
+
+    #[actix_rt::test]
+    async fn test_polling_too_fast_slows_down() {
+        let authorizations = authorizations();
+        let codes = authorizations.start("tv", ClientInfo::default()).await;
+        let start = now_millis();
+
+        let poll = |at: u64| authorizations.poll_at(&codes.device_code, "tv", at);
+        assert_eq!(error_code(poll(start).await), OAuthErrorCode::AuthorizationPending);
+        assert_eq!(error_code(poll(start + 4_000).await), OAuthErrorCode::SlowDown);
+        // The interval is now 10 seconds, counted from the last poll.
+        assert_eq!(error_code(poll(start + 9_000).await), OAuthErrorCode::SlowDown);
+        assert_eq!(error_code(poll(start + 24_000).await), OAuthErrorCode::AuthorizationPending);
+
+        authorizations.decide(&codes.user_code.to_lowercase(), Some(7)).await.unwrap();
+        let grant = poll(start + 39_000).await.unwrap();
+        assert_eq!(grant.user_id, 7);
+        assert_eq!(error_code(poll(start + 54_000).await), OAuthErrorCode::InvalidGrant);
+    }
+
+    #[actix_rt::test]
+    async fn test_expired_and_foreign_codes() {
+        let authorizations = authorizations();
+        let codes = authorizations.start("tv", ClientInfo::default()).await;
+
+        let expired = now_millis() + 600_000;
+        assert_eq!(
+            error_code(authorizations.poll_at(&codes.device_code, "tv", expired).await),
+            OAuthErrorCode::ExpiredToken
+        );
+        assert_eq!(
+            error_code(authorizations.poll(&codes.device_code, "console").await),
+            OAuthErrorCode::InvalidGrant
+        );
+        assert_eq!(
+            error_code(authorizations.poll("unknown", "tv").await),
+            OAuthErrorCode::InvalidGrant
+        );
+
+        authorizations.decide(&codes.user_code, None).await.unwrap();
+        assert!(matches!(
+            authorizations.decide(&codes.user_code, Some(1)).await,
+            Err(AuthError::UserCodeNotFound)
+        ));
+    }
+}
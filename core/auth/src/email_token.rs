@@ -0,0 +1,100 @@
+//! Single-use tokens mailed to users, for password resets and address
+//! verification.
+//!
+//! Like refresh tokens, only the digest of a token is stored. A token is
+//! deleted when it is used, whether or not it turned out to be expired.
+
+use std::{collections::HashMap, sync::Mutex};
+use async_trait::async_trait;
+use jsonwebtoken::get_current_timestamp;
+use crate::error::AuthError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmailTokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+impl EmailTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailTokenPurpose::PasswordReset => "password_reset",
+            EmailTokenPurpose::EmailVerification => "email_verification",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EmailTokenRecord {
+    /// SHA-256 digest of the token, see [`crate::secret::hash`].
+    pub token_hash: String,
+    pub user_id: i32,
+    pub purpose: EmailTokenPurpose,
+    /// Address the token was mailed to.
+    pub email: String,
+    pub expires_at: u64,
+}
+
+impl EmailTokenRecord {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= get_current_timestamp()
+    }
+}
+
+#[async_trait]
+pub trait EmailTokenStore: Send + Sync {
+    async fn insert(&self, record: EmailTokenRecord) -> Result<(), AuthError>;
+
+    /// Deletes the token and returns its record, if it exists for `purpose`.
+    ///
+    /// Must be atomic: of two concurrent calls for the same token, only one
+    /// may get the record.
+    async fn consume(
+        &self,
+        token_hash: &str,
+        purpose: EmailTokenPurpose,
+    ) -> Result<Option<EmailTokenRecord>, AuthError>;
+
+    /// Deletes every token of the user issued for `purpose`.
+    async fn revoke_user(&self, user_id: i32, purpose: EmailTokenPurpose) -> Result<(), AuthError>;
+}
+
+/// Keeps email tokens in process memory. Intended for tests and local development.
+#[derive(Default)]
+pub struct InMemoryEmailTokenStore {
+    tokens: Mutex<HashMap<String, EmailTokenRecord>>,
+}
+
+impl InMemoryEmailTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl EmailTokenStore for InMemoryEmailTokenStore {
+    async fn insert(&self, record: EmailTokenRecord) -> Result<(), AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        tokens.retain(|_, r| !r.is_expired());
+        tokens.insert(record.token_hash.clone(), record);
+        Ok(())
+    }
+
+    async fn consume(
+        &self,
+        token_hash: &str,
+        purpose: EmailTokenPurpose,
+    ) -> Result<Option<EmailTokenRecord>, AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        if tokens.get(token_hash).is_some_and(|r| r.purpose == purpose) {
+            return Ok(tokens.remove(token_hash));
+        }
+        Ok(None)
+    }
+
+    async fn revoke_user(&self, user_id: i32, purpose: EmailTokenPurpose) -> Result<(), AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        tokens.retain(|_, r| r.user_id != user_id || r.purpose != purpose);
+        Ok(())
+    }
+}
@@ -1,11 +1,114 @@
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{
+    http::header::{CacheControl, CacheDirective, RETRY_AFTER, WWW_AUTHENTICATE},
+    HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use serde_json::json;
 use thiserror::Error;
+use crate::invite::RegistrationErrorCode;
+
+/// OAuth 2.0 error codes of the authorization endpoint (RFC 6749 section
+/// 4.1.2.1), the token endpoint (section 5.2) and the device flow (RFC 8628
+/// section 3.5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OAuthErrorCode {
+    InvalidRequest,
+    InvalidClient,
+    InvalidGrant,
+    InvalidScope,
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    AuthorizationPending,
+    SlowDown,
+    AccessDenied,
+    ExpiredToken,
+}
 
 #[derive(Debug, Error)]
 pub enum AuthError {
     #[error("Authentication error: {0}")]
     AuthenticationError(String),
 
+    #[error("Invalid credentials")]
+    InvalidCredentials,
+
+    #[error("Username already exists")]
+    UsernameTaken,
+
+    #[error("Email already registered")]
+    EmailTaken,
+
+    #[error("Validation error: {0}")]
+    Validation(String),
+
+    #[error("Invalid token")]
+    InvalidToken,
+
+    #[error("Token expired")]
+    TokenExpired,
+
+    #[error("Token revoked")]
+    TokenRevoked,
+
+    #[error("Refresh token reuse detected")]
+    RefreshTokenReused,
+
+    #[error("User not found")]
+    UserNotFound,
+
+    #[error("Two-factor authentication already enabled")]
+    TwoFactorAlreadyEnabled,
+
+    #[error("Two-factor authentication is not enabled")]
+    TwoFactorNotEnabled,
+
+    #[error("Invalid two-factor code")]
+    InvalidTwoFactorCode,
+
+    #[error("Access token not found")]
+    AccessTokenNotFound,
+
+    #[error("Session not found")]
+    SessionNotFound,
+
+    #[error("Unknown or expired user code")]
+    UserCodeNotFound,
+
+    #[error("OAuth error: {0:?}")]
+    OAuth(OAuthErrorCode),
+
+    /// Authorization request error to report to the app at its redirect URI.
+    #[error("OAuth error: {error:?}")]
+    OAuthRedirect { error: OAuthErrorCode, redirect_to: String },
+
+    #[error("OAuth client not found")]
+    OAuthClientNotFound,
+
+    #[error("Insufficient scope")]
+    InsufficientScope,
+
+    #[error("Email address already verified")]
+    EmailAlreadyVerified,
+
+    #[error("Registration refused: {0:?}")]
+    Registration(RegistrationErrorCode),
+
+    #[error("Invite not found")]
+    InviteNotFound,
+
+    #[error("Export not found")]
+    ExportNotFound,
+
+    #[error("Export not ready")]
+    ExportNotReady,
+
+    #[error("Cannot impersonate this user")]
+    ImpersonationNotAllowed,
+
+    #[error("Too many failed login attempts, retry after {retry_after} seconds")]
+    TooManyAttempts { retry_after: u64 },
+
     #[error("Internal server error")]
     InternalError,
 }
@@ -16,6 +119,96 @@ impl ResponseError for AuthError {
             AuthError::AuthenticationError(_) => {
                 HttpResponse::Unauthorized().json("Authentication failed")
             }
+            AuthError::InvalidCredentials => {
+                HttpResponse::Unauthorized().json("Invalid credentials")
+            }
+            AuthError::UsernameTaken => {
+                HttpResponse::Conflict().json("Username already exists")
+            }
+            AuthError::EmailTaken => {
+                HttpResponse::Conflict().json("Email already registered")
+            }
+            AuthError::Validation(message) => {
+                HttpResponse::BadRequest().json(message)
+            }
+            AuthError::InvalidToken => {
+                HttpResponse::Unauthorized().json("Invalid token")
+            }
+            AuthError::TokenExpired => {
+                HttpResponse::Unauthorized().json("Token expired")
+            }
+            AuthError::TokenRevoked => {
+                HttpResponse::Unauthorized().json("Token revoked")
+            }
+            AuthError::RefreshTokenReused => {
+                HttpResponse::Unauthorized().json("Refresh token reuse detected")
+            }
+            AuthError::UserNotFound => {
+                HttpResponse::NotFound().json("User not found")
+            }
+            AuthError::TwoFactorAlreadyEnabled => {
+                HttpResponse::Conflict().json("Two-factor authentication already enabled")
+            }
+            AuthError::TwoFactorNotEnabled => {
+                HttpResponse::BadRequest().json("Two-factor authentication is not enabled")
+            }
+            AuthError::InvalidTwoFactorCode => {
+                HttpResponse::Unauthorized().json("Invalid two-factor code")
+            }
+            AuthError::AccessTokenNotFound => {
+                HttpResponse::NotFound().json("Access token not found")
+            }
+            AuthError::SessionNotFound => {
+                HttpResponse::NotFound().json("Session not found")
+            }
+            AuthError::UserCodeNotFound => {
+                HttpResponse::NotFound().json("Unknown or expired user code")
+            }
+            AuthError::OAuth(OAuthErrorCode::InvalidClient) => {
+                HttpResponse::Unauthorized()
+                    .insert_header((WWW_AUTHENTICATE, "Basic realm=\"socialhub\""))
+                    .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+                    .json(json!({ "error": OAuthErrorCode::InvalidClient }))
+            }
+            AuthError::OAuth(code) => {
+                HttpResponse::BadRequest()
+                    .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+                    .json(json!({ "error": code }))
+            }
+            AuthError::OAuthRedirect { error, redirect_to } => {
+                HttpResponse::BadRequest().json(json!({ "error": error, "redirect_to": redirect_to }))
+            }
+            AuthError::OAuthClientNotFound => {
+                HttpResponse::NotFound().json("OAuth client not found")
+            }
+            AuthError::InsufficientScope => {
+                HttpResponse::Forbidden()
+                    .insert_header((WWW_AUTHENTICATE, "Bearer realm=\"socialhub\", error=\"insufficient_scope\""))
+                    .json("Insufficient scope")
+            }
+            AuthError::EmailAlreadyVerified => {
+                HttpResponse::Conflict().json("Email address already verified")
+            }
+            AuthError::Registration(code) => {
+                HttpResponse::Forbidden().json(json!({ "error": code }))
+            }
+            AuthError::InviteNotFound => {
+                HttpResponse::NotFound().json("Invite not found")
+            }
+            AuthError::ExportNotFound => {
+                HttpResponse::NotFound().json("Export not found")
+            }
+            AuthError::ExportNotReady => {
+                HttpResponse::Conflict().json("Export not ready")
+            }
+            AuthError::ImpersonationNotAllowed => {
+                HttpResponse::Forbidden().json("Cannot impersonate this user")
+            }
+            AuthError::TooManyAttempts { retry_after } => {
+                HttpResponse::TooManyRequests()
+                    .insert_header((RETRY_AFTER, retry_after.to_string()))
+                    .json("Too many failed login attempts")
+            }
             AuthError::InternalError => {
                 HttpResponse::InternalServerError().json("Internal server error")
             }
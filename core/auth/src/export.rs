@@ -0,0 +1,330 @@
+//! Personal data exports (GDPR articles 15 and 20).
+//!
+//! An export is built in the background from the account data and the
+//! [`DataSubjectHook`]s of the other crates, as a ZIP archive with one
+//! directory per crate. The archive is written to a file in
+//! [`AuthConfig::export_dir`] as the hooks' files are read, so media
+//! originals never have to fit in memory. Jobs only live in memory and are
+//! dropped after [`AuthConfig::export_expiration`] seconds, their archive
+//! with them, or when the account is erased.
+//!
+//! [`DataSubjectHook`]: socialhub_core::data_subject::DataSubjectHook
+
+use std::{fs::File, io::Write, path::{Path, PathBuf}, sync::Arc};
+use actix_web::web::{self, Bytes, BytesMut};
+use futures::{channel::mpsc, SinkExt, Stream, StreamExt};
+use jsonwebtoken::get_current_timestamp;
+use log::warn;
+use serde::Serialize;
+use socialhub_core::{
+    cache::{CacheConfig, CacheManager},
+    data_subject::{ExportedContents, ExportedFile},
+};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+use crate::{config::AuthConfig, error::AuthError, models::{AccountExport, ExportStatus}};
+
+/// Upper bound on exports kept at once.
+const CAPACITY: u64 = 10_000;
+/// Chunks on their way from the hooks to the archive file.
+const CHUNKS_IN_FLIGHT: usize = 16;
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+struct ExportJob {
+    details: AccountExport,
+    user_id: i32,
+    archive: Option<Arc<ExportArchive>>,
+}
+
+/// A finished archive on disk. The file is deleted once the job has expired
+/// and the last download of it has finished.
+#[derive(Debug)]
+pub struct ExportArchive {
+    path: PathBuf,
+    size: u64,
+}
+
+impl ExportArchive {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Reads the archive in chunks.
+    pub async fn open(self: Arc<Self>) -> Result<impl Stream<Item = Result<Bytes, AuthError>>, AuthError> {
+        let file = tokio::fs::File::open(&self.path).await.map_err(|e| {
+            warn!("Failed to open export archive {}: {}", self.path.display(), e);
+            AuthError::InternalError
+        })?;
+        Ok(futures::stream::try_unfold((file, self), |(mut file, archive)| async move {
+            let mut buf = BytesMut::zeroed(READ_CHUNK_SIZE);
+            let read = file.read(&mut buf).await.map_err(|_| AuthError::InternalError)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            buf.truncate(read);
+            Ok(Some((buf.freeze(), (file, archive))))
+        }))
+    }
+}
+
+impl Drop for ExportArchive {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to delete export archive {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+pub struct AccountExports {
+    jobs: CacheManager<Uuid, ExportJob>,
+    /// Exports of each user, the latest last.
+    by_user: CacheManager<i32, Vec<Uuid>>,
+    dir: PathBuf,
+}
+
+impl AccountExports {
+    pub fn new(config: &AuthConfig) -> Self {
+        let cache_config = CacheConfig {
+            max_capacity: CAPACITY,
+            time_to_live: config.export_expiration,
+            time_to_idle: config.export_expiration,
+        };
+        Self {
+            jobs: CacheManager::new(cache_config.clone()),
+            by_user: CacheManager::new(cache_config),
+            dir: config.export_dir.clone(),
+        }
+    }
+
+    /// Registers a new export for `user_id`, unless one is still being built,
+    /// in which case that one is returned with `false`.
+    pub async fn start(&self, user_id: i32) -> (AccountExport, bool) {
+        if let Some(pending) = self.latest(user_id).await.filter(|e| e.status == ExportStatus::Pending) {
+            return (pending, false);
+        }
+
+        let details = AccountExport {
+            id: Uuid::new_v4(),
+            status: ExportStatus::Pending,
+            requested_at: get_current_timestamp(),
+            completed_at: None,
+            size: None,
+        };
+        let mut ids = Vec::new();
+        for id in self.by_user.get(&user_id).await.unwrap_or_default() {
+            if self.jobs.get(&id).await.is_some() {
+                ids.push(id);
+            }
+        }
+        ids.push(details.id);
+        self.jobs.set(details.id, ExportJob { details: details.clone(), user_id, archive: None }).await;
+        self.by_user.set(user_id, ids).await;
+        (details, true)
+    }
+
+    /// Where the archive of the export `id` is written.
+    pub fn archive_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.zip", id))
+    }
+
+    /// Stores the outcome of the export `id`.
+    pub async fn finish(&self, id: Uuid, archive: Result<ExportArchive, AuthError>) {
+        let Some(mut job) = self.jobs.get(&id).await else {
+            return;
+        };
+        job.details.completed_at = Some(get_current_timestamp());
+        match archive {
+            Ok(archive) => {
+                job.details.status = ExportStatus::Ready;
+                job.details.size = Some(archive.size);
+                job.archive = Some(Arc::new(archive));
+            }
+            Err(_) => job.details.status = ExportStatus::Failed,
+        }
+        self.jobs.set(id, job).await;
+    }
+
+    /// The export `id` of `user_id`, with its archive once ready.
+    pub async fn find(&self, user_id: i32, id: Uuid) -> Option<(AccountExport, Option<Arc<ExportArchive>>)> {
+        self.jobs
+            .get(&id)
+            .await
+            .filter(|job| job.user_id == user_id)
+            .map(|job| (job.details, job.archive))
+    }
+
+    /// Forgets every export of `user_id` and deletes their archives. The
+    /// cache may hold on to a removed job for a while, so the files are not
+    /// left to [`ExportArchive`]'s `Drop`. Downloads in progress still finish
+    /// from their open file.
+    pub async fn remove_user(&self, user_id: i32) {
+        for id in self.by_user.take(&user_id).await.unwrap_or_default() {
+            self.jobs.take(&id).await;
+            let path = self.archive_path(id);
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to delete export archive {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    async fn latest(&self, user_id: i32) -> Option<AccountExport> {
+        let id = *self.by_user.get(&user_id).await?.last()?;
+        self.jobs.get(&id).await.map(|job| job.details)
+    }
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    user_id: i32,
+    generated_at: u64,
+    sections: Vec<&'a str>,
+}
+
+/// What the archive writer gets: the start of a file, then its contents.
+enum Part {
+    File { path: String, streamed: bool },
+    Chunk(Bytes),
+}
+
+/// Packs the files of every section into a ZIP archive at `path`, each
+/// section in a directory of its own, next to a `manifest.json`. Streamed
+/// files are stored as they are, as they are mostly compressed media already.
+pub async fn write_archive(
+    path: PathBuf,
+    user_id: i32,
+    sections: Vec<(&'static str, Vec<ExportedFile>)>,
+) -> Result<ExportArchive, AuthError> {
+    // From here on, failing deletes whatever was written.
+    let mut archive = ExportArchive { path, size: 0 };
+    let manifest = ExportedFile::json("manifest.json", &Manifest {
+        user_id,
+        generated_at: get_current_timestamp(),
+        sections: sections.iter().map(|(name, _)| *name).collect(),
+    }).map_err(|_| AuthError::InternalError)?;
+
+    let (mut tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let target = archive.path.clone();
+    let write = web::block(move || write_parts(&target, rx));
+    let feed = async move {
+        let files = std::iter::once(("", manifest))
+            .chain(sections.into_iter().flat_map(|(name, files)| files.into_iter().map(move |file| (name, file))));
+        for (name, file) in files {
+            // Paths come from our own crates, but keep them inside their directory.
+            let path = file.path.trim_start_matches('/').replace("..", "_");
+            let path = if name.is_empty() { path } else { format!("{}/{}", name, path) };
+            let streamed = matches!(file.contents, ExportedContents::Stream(_));
+            // A closed channel means the writer failed; its error is returned below.
+            if tx.send(Part::File { path, streamed }).await.is_err() {
+                return Ok(());
+            }
+            match file.contents {
+                ExportedContents::Bytes(bytes) => {
+                    if tx.send(Part::Chunk(bytes.into())).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                ExportedContents::Stream(mut chunks) => {
+                    while let Some(chunk) = chunks.next().await {
+                        let chunk = chunk.map_err(|_| AuthError::InternalError)?;
+                        if tx.send(Part::Chunk(chunk)).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    };
+
+    let (written, fed) = futures::join!(write, feed);
+    fed?;
+    archive.size = written.map_err(|_| AuthError::InternalError)??;
+    Ok(archive)
+}
+
+/// Writes the parts arriving through `parts` into a ZIP file at `path`, on
+/// a blocking thread, and returns its size.
+fn write_parts(path: &Path, mut parts: mpsc::Receiver<Part>) -> Result<u64, AuthError> {
+    let io_error = |e: std::io::Error| {
+        warn!("Failed to write export archive {}: {}", path.display(), e);
+        AuthError::InternalError
+    };
+    let zip_error = |e: zip::result::ZipError| {
+        warn!("Failed to write export archive {}: {}", path.display(), e);
+        AuthError::InternalError
+    };
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(io_error)?;
+    }
+    let mut zip = ZipWriter::new(File::create(path).map_err(io_error)?);
+    while let Some(part) = futures::executor::block_on(parts.next()) {
+        match part {
+            Part::File { path, streamed: false } => zip.start_file(path, SimpleFileOptions::default()),
+            Part::File { path, streamed: true } => zip.start_file(path, SimpleFileOptions::default()
+                .compression_method(CompressionMethod::Stored)
+                .large_file(true)),
+            Part::Chunk(chunk) => zip.write_all(&chunk).map_err(Into::into),
+        }
+        .map_err(zip_error)?;
+    }
+    let file = zip.finish().map_err(zip_error)?;
+    Ok(file.metadata().map_err(io_error)?.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use futures::stream;
+
+    #[actix_rt::test]
+    async fn test_archive_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("export.zip");
+        let chunks = vec![Ok(Bytes::from_static(b"big ")), Ok(Bytes::from_static(b"file"))];
+        let archive = write_archive(path.clone(), 7, vec![
+            ("account", vec![ExportedFile::json("profile.json", &serde_json::json!({ "id": 7 })).unwrap()]),
+            ("social", vec![ExportedFile::new("../../etc/passwd", b"x".to_vec())]),
+            ("media", vec![ExportedFile::stream("files/video.mp4", stream::iter(chunks).boxed())]),
+        ]).await.unwrap();
+        assert_eq!(archive.size(), std::fs::metadata(&path).unwrap().len());
+
+        let mut zip = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let names: Vec<String> = zip.file_names().map(str::to_string).collect();
+        assert!(names.contains(&"manifest.json".to_string()));
+        assert!(names.contains(&"account/profile.json".to_string()));
+        assert!(names.contains(&"social/_/_/etc/passwd".to_string()));
+
+        let mut read = |name: &str| {
+            let mut contents = String::new();
+            zip.by_name(name).unwrap().read_to_string(&mut contents).unwrap();
+            contents
+        };
+        let manifest: serde_json::Value = serde_json::from_str(&read("manifest.json")).unwrap();
+        assert_eq!(manifest["sections"], serde_json::json!(["account", "social", "media"]));
+        assert_eq!(read("media/files/video.mp4"), "big file");
+
+        // The file goes with the archive.
+        drop(archive);
+        assert!(!path.exists());
+    }
+
+    #[actix_rt::test]
+    async fn test_failed_archive_is_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.zip");
+        let chunks = vec![Ok(Bytes::from_static(b"partial")), Err(socialhub_core::error::CommonError::InternalError)];
+        let result = write_archive(path.clone(), 7, vec![
+            ("media", vec![ExportedFile::stream("files/video.mp4", stream::iter(chunks).boxed())]),
+        ]).await;
+        assert!(matches!(result, Err(AuthError::InternalError)));
+        assert!(!path.exists());
+    }
+}
@@ -1,26 +1,151 @@
-use actix_web::{web, Error, HttpResponse, HttpRequest};
-use crate::models::{LoginRequest, RegisterRequest, AuthResponse};
-use crate::error::AuthError;
+use actix_web::{
+    http::header::{CacheControl, CacheDirective, ContentDisposition, DispositionParam, DispositionType},
+    web, Either, Error, HttpRequest, HttpResponse,
+};
+use socialhub_core::auth::{bearer_token, AuthRejection, AuthenticatedUser, TokenKind};
+use uuid::Uuid;
+use crate::audit::AuditFilter;
+use crate::models::{
+    AuditQuery, AuthorizeRequest, ConsentDecision, CreateAccessTokenRequest, CreateInviteRequest, CreateOAuthClientRequest,
+    DeleteAccountRequest,
+    DeviceCodeRequest,
+    DeviceVerifyRequest, ForgotPasswordRequest, ImpersonateRequest, LoginRequest, LogoutRequest, RefreshRequest, RegisterRequest,
+    ResetPasswordRequest, TokenRequest, TwoFactorCodeRequest, TwoFactorLoginRequest, UpdateRolesRequest,
+    VerifyEmailRequest,
+};
+use crate::oauth;
+use crate::service::AuthService;
+use crate::session::ClientInfo;
 
 #[utoipa::path(
     post,
     path = "/auth/login",
     request_body = LoginRequest,
     responses(
-        (status = 200, description = "Login successful", body = AuthResponse),
-        (status = 401, description = "Invalid credentials")
+        (status = 200, description = "Login successful, or a 2FA challenge if the account has 2FA enabled", body = LoginResponse),
+        (status = 401, description = "Invalid credentials"),
+        (status = 429, description = "Too many failed attempts for the username or address, see Retry-After")
     ),
     tag = "auth"
 )]
-pub async fn login(credentials: web::Json<LoginRequest>) -> Result<HttpResponse, Error> {
-    if credentials.username == "invalid" {
-        return Ok(HttpResponse::Unauthorized().json("Invalid credentials"));
-    }
-    
-    Ok(HttpResponse::Ok().json(AuthResponse {
-        token: "dummy_token".to_string(),
-        user_id: 1,
-    }))
+pub async fn login(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    credentials: web::Json<LoginRequest>
+) -> Result<HttpResponse, Error> {
+    let client = ClientInfo::from_request(&req, credentials.device_name.as_deref());
+    let response = service.login(&credentials, &client).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/login/2fa",
+    request_body = TwoFactorLoginRequest,
+    responses(
+        (status = 200, description = "Login completed", body = AuthResponse),
+        (status = 401, description = "Invalid challenge or two-factor code")
+    ),
+    tag = "auth"
+)]
+pub async fn login_two_factor(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    request: web::Json<TwoFactorLoginRequest>
+) -> Result<HttpResponse, Error> {
+    let client = ClientInfo::from_request(&req, request.device_name.as_deref());
+    let response = service.login_two_factor(&request, &client).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "New token pair issued", body = AuthResponse),
+        (status = 401, description = "Refresh token invalid, expired or reused")
+    ),
+    tag = "auth"
+)]
+pub async fn refresh(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    request: web::Json<RefreshRequest>
+) -> Result<HttpResponse, Error> {
+    let client = ClientInfo::from_request(&req, None);
+    let response = service.refresh(&request.refresh_token, &client).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/device/code",
+    request_body(content = DeviceCodeRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Codes for the device to show and poll with", body = DeviceCodeResponse),
+        (status = 400, description = "Invalid client_id")
+    ),
+    tag = "auth"
+)]
+pub async fn device_code(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    request: Either<web::Form<DeviceCodeRequest>, web::Json<DeviceCodeRequest>>
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    let client = ClientInfo::from_request(&req, request.device_name.as_deref());
+    let response = service.start_device_authorization(&request, client).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/device/verify",
+    request_body = DeviceVerifyRequest,
+    responses(
+        (status = 204, description = "Device login approved or denied"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access token"),
+        (status = 404, description = "Unknown, expired or already used user code")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn verify_device(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    request: web::Json<DeviceVerifyRequest>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.verify_device(&user, &request.user_code, request.deny).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/token",
+    request_body(content = TokenRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Session for an approved device login, or a scoped token \
+            for an authorization code", body = TokenResponse),
+        (status = 400, description = "OAuth error: authorization_pending, slow_down, access_denied, \
+            expired_token, invalid_grant, invalid_request or unsupported_grant_type"),
+        (status = 401, description = "OAuth error invalid_client: unknown client or wrong secret, \
+            sent as HTTP Basic credentials or client_secret")
+    ),
+    tag = "auth"
+)]
+pub async fn token(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    request: Either<web::Form<TokenRequest>, web::Json<TokenRequest>>
+) -> Result<HttpResponse, Error> {
+    let client = ClientInfo::from_request(&req, None);
+    let response = service.token(&request.into_inner(), oauth::basic_credentials(&req), &client).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(response))
 }
 
 #[utoipa::path(
@@ -28,32 +153,791 @@ pub async fn login(credentials: web::Json<LoginRequest>) -> Result<HttpResponse,
     path = "/auth/register",
     request_body = RegisterRequest,
     responses(
-        (status = 201, description = "User registered successfully"),
-        (status = 409, description = "Username already exists")
+        (status = 201, description = "User registered successfully", body = User),
+        (status = 400, description = "Invalid registration data"),
+        (status = 403, description = "Refused by the registration policy; `error` is one of `invite_required`, \
+            `invite_invalid`, `invite_expired`, `invite_exhausted` or `domain_not_allowed`"),
+        (status = 409, description = "Username or email already exists")
     ),
     tag = "auth"
 )]
-pub async fn register(user_data: web::Json<RegisterRequest>) -> Result<HttpResponse, Error> {
-    if user_data.username == "existing_user" {
-        return Ok(HttpResponse::Conflict().json("User already exists"));
-    }
-    
-    Ok(HttpResponse::Ok().json("User registered successfully"))
+pub async fn register(
+    service: web::Data<AuthService>,
+    user_data: web::Json<RegisterRequest>
+) -> Result<HttpResponse, Error> {
+    let user = service.register(&user_data).await?;
+    Ok(HttpResponse::Created().json(user))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/password/forgot",
+    request_body = ForgotPasswordRequest,
+    responses(
+        (status = 202, description = "A reset link was mailed if the address belongs to an account")
+    ),
+    tag = "auth"
+)]
+pub async fn forgot_password(
+    service: web::Data<AuthService>,
+    request: web::Json<ForgotPasswordRequest>
+) -> Result<HttpResponse, Error> {
+    service.forgot_password(&request.email).await?;
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/password/reset",
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 200, description = "Password changed and all sessions logged out"),
+        (status = 400, description = "Invalid new password"),
+        (status = 401, description = "Reset token invalid, expired or already used")
+    ),
+    tag = "auth"
+)]
+pub async fn reset_password(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    request: web::Json<ResetPasswordRequest>
+) -> Result<HttpResponse, Error> {
+    let client = ClientInfo::from_request(&req, None);
+    service.reset_password(&request.token, &request.new_password, &client).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/email/verify",
+    request_body = VerifyEmailRequest,
+    responses(
+        (status = 200, description = "Email address verified"),
+        (status = 401, description = "Verification token invalid, expired or already used")
+    ),
+    tag = "auth"
+)]
+pub async fn verify_email(
+    service: web::Data<AuthService>,
+    request: web::Json<VerifyEmailRequest>
+) -> Result<HttpResponse, Error> {
+    service.verify_email(&request.token).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/email/verify/send",
+    responses(
+        (status = 202, description = "A new verification link was mailed"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not called with a login session"),
+        (status = 409, description = "Email address already verified")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn send_verification_email(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.resend_verification_email(&user).await?;
+    Ok(HttpResponse::Accepted().finish())
 }
 
 #[utoipa::path(
     post,
     path = "/auth/logout",
+    request_body(content = Option<LogoutRequest>),
     responses(
-        (status = 200, description = "Logged out successfully"),
-        (status = 401, description = "Not authenticated")
+        (status = 200, description = "Logged out successfully; an impersonation token ends its own session"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token")
     ),
     security(("bearer_token" = [])),
     tag = "auth"
 )]
-pub async fn logout(req: HttpRequest) -> Result<HttpResponse, Error> {
-    match req.headers().get("Authorization") {
-        Some(_) => Ok(HttpResponse::Ok().finish()),
-        None => Ok(HttpResponse::Unauthorized().finish())
+pub async fn logout(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    request: Option<web::Json<LogoutRequest>>
+) -> Result<HttpResponse, Error> {
+    // An admin must be able to end an impersonation without the user's help.
+    if user.token_kind != TokenKind::Impersonation {
+        user.require_session()?;
     }
+    let refresh_token = request.as_ref().and_then(|r| r.refresh_token.as_deref());
+    service.logout(&user, refresh_token, &ClientInfo::from_request(&req, None)).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/logout/all",
+    responses(
+        (status = 200, description = "Every session of the user was logged out"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn logout_all(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.logout_all_sessions(&user, &ClientInfo::from_request(&req, None)).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    put,
+    path = "/auth/users/{id}/roles",
+    params(("id" = i32, Path, description = "User id")),
+    request_body = UpdateRolesRequest,
+    responses(
+        (status = 200, description = "Roles updated", body = User),
+        (status = 400, description = "Unknown role or permission"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Missing the users:manage permission, or not called with a login session"),
+        (status = 404, description = "User not found")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn update_roles(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    id: web::Path<i32>,
+    request: web::Json<UpdateRolesRequest>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let request = request.into_inner();
+    let client = ClientInfo::from_request(&req, None);
+    let updated = service
+        .update_roles(&user, id.into_inner(), request.roles, request.permissions, &client)
+        .await?;
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/users/{id}/impersonate",
+    params(("id" = i32, Path, description = "User id")),
+    request_body(content = Option<ImpersonateRequest>),
+    responses(
+        (status = 201, description = "Short-lived token to act as the user", body = Impersonation),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Missing the users:impersonate permission, not called with a login session, or the user may impersonate others"),
+        (status = 404, description = "User not found")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn impersonate(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    id: web::Path<i32>,
+    request: Option<web::Json<ImpersonateRequest>>
+) -> Result<HttpResponse, Error> {
+    // Neither an impersonation token nor a scoped one can start another.
+    user.require_session()?;
+    let request = request.map(web::Json::into_inner).unwrap_or_default();
+    let client = ClientInfo::from_request(&req, None);
+    let impersonation = service.impersonate(&user, id.into_inner(), &request, &client).await?;
+    Ok(HttpResponse::Created()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(impersonation))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/invites",
+    request_body = CreateInviteRequest,
+    responses(
+        (status = 201, description = "Invite created; the code is only returned here", body = CreatedInvite),
+        (status = 400, description = "Invalid usage limit or lifetime"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Missing the users:manage permission, or not called with a login session")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn create_invite(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    request: web::Json<CreateInviteRequest>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let created = service.create_invite(&user, &request).await?;
+    Ok(HttpResponse::Created().json(created))
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/invites",
+    responses(
+        (status = 200, description = "Every invite, newest first", body = Vec<Invite>),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Missing the users:manage permission, or not called with a login session")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn list_invites(service: web::Data<AuthService>, user: AuthenticatedUser) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let invites = service.list_invites().await?;
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/auth/invites/{id}",
+    params(("id" = Uuid, Path, description = "Invite id")),
+    responses(
+        (status = 204, description = "Invite deleted; its code no longer works"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Missing the users:manage permission, or not called with a login session"),
+        (status = 404, description = "No such invite")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn delete_invite(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.delete_invite(&user, id.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Matching audit events, newest first", body = AuditPage),
+        (status = 400, description = "Unknown action or outcome"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Missing the audit:read permission, or not called with a login session")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn list_audit_events(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    query: web::Query<AuditQuery>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let page = service.audit_events(&query).await?;
+    Ok(HttpResponse::Ok().json(page))
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/audit/export",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Every matching audit event as JSON Lines, newest first; `cursor` and `limit` are ignored", content_type = "application/x-ndjson"),
+        (status = 400, description = "Unknown action or outcome"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Missing the audit:read permission, or not called with a login session")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn export_audit_events(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    query: web::Query<AuditQuery>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let filter = AuditFilter { before: None, ..query.filter() };
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("audit.jsonl".to_string())],
+        })
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .streaming(service.export_audit_events(filter)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/2fa/enroll",
+    responses(
+        (status = 200, description = "Secret and recovery codes for a pending enrollment", body = TwoFactorEnrollment),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access token"),
+        (status = 409, description = "Two-factor authentication already enabled")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn enroll_two_factor(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let enrollment = service.enroll_two_factor(&user).await?;
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/2fa/confirm",
+    request_body = TwoFactorCodeRequest,
+    responses(
+        (status = 200, description = "Two-factor authentication enabled"),
+        (status = 400, description = "No pending enrollment"),
+        (status = 401, description = "Not authenticated or invalid code"),
+        (status = 403, description = "Called with a personal access token"),
+        (status = 409, description = "Two-factor authentication already enabled")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn confirm_two_factor(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    request: web::Json<TwoFactorCodeRequest>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.confirm_two_factor(&user, &request.code, &ClientInfo::from_request(&req, None)).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/2fa/disable",
+    request_body = TwoFactorCodeRequest,
+    responses(
+        (status = 200, description = "Two-factor authentication disabled"),
+        (status = 400, description = "Two-factor authentication is not enabled"),
+        (status = 401, description = "Not authenticated or invalid code"),
+        (status = 403, description = "Called with a personal access token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn disable_two_factor(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    request: web::Json<TwoFactorCodeRequest>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.disable_two_factor(&user, &request.code, &ClientInfo::from_request(&req, None)).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/tokens",
+    request_body = CreateAccessTokenRequest,
+    responses(
+        (status = 201, description = "Token created; the secret is only returned here", body = CreatedAccessToken),
+        (status = 400, description = "Invalid name, expiry or scopes"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn create_access_token(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    request: web::Json<CreateAccessTokenRequest>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let created = service
+        .create_access_token(&user, &request, &ClientInfo::from_request(&req, None))
+        .await?;
+    Ok(HttpResponse::Created().json(created))
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/tokens",
+    responses(
+        (status = 200, description = "Personal access tokens of the caller", body = Vec<AccessToken>),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn list_access_tokens(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let tokens = service.list_access_tokens(&user).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/auth/tokens/{id}",
+    params(("id" = Uuid, Path, description = "Token id")),
+    responses(
+        (status = 204, description = "Token deleted"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access token"),
+        (status = 404, description = "No such token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn delete_access_token(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.delete_access_token(&user, id.into_inner(), &ClientInfo::from_request(&req, None)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/sessions",
+    responses(
+        (status = 200, description = "Signed-in devices of the caller, most recently seen first", body = Vec<Session>),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn list_sessions(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let sessions = service.list_sessions(&user).await?;
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/auth/sessions/{id}",
+    params(("id" = Uuid, Path, description = "Session id")),
+    responses(
+        (status = 204, description = "Session signed out, its tokens revoked"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access token"),
+        (status = 404, description = "No such session")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn revoke_session(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.revoke_session(&user, id.into_inner(), &ClientInfo::from_request(&req, None)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/account/export",
+    responses(
+        (status = 202, description = "Export started, or the one still being built; poll it for completion", body = AccountExport),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn request_export(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let export = service.into_inner().request_export(&user).await?;
+    Ok(HttpResponse::Accepted().json(export))
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/account/export/{id}",
+    params(("id" = Uuid, Path, description = "Export id")),
+    responses(
+        (status = 200, description = "Status of the export", body = AccountExport),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token"),
+        (status = 404, description = "No such export, or it expired")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn export_status(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let export = service.export_status(&user, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(export))
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/account/export/{id}/download",
+    params(("id" = Uuid, Path, description = "Export id")),
+    responses(
+        (status = 200, description = "ZIP archive with a directory per service and a manifest.json", content_type = "application/zip"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token"),
+        (status = 404, description = "No such export, or it expired"),
+        (status = 409, description = "The export is not ready, or failed")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn download_export(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let id = id.into_inner();
+    let archive = service.download_export(&user, id).await?;
+    let size = archive.size();
+    let body = archive.open().await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("socialhub-export-{}.zip", id))],
+        })
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .no_chunking(size)
+        .streaming(body))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/auth/account",
+    request_body = DeleteAccountRequest,
+    responses(
+        (status = 202, description = "Account scheduled for deletion and signed out everywhere", body = AccountDeletion),
+        (status = 401, description = "Not authenticated, or wrong password"),
+        (status = 403, description = "Called with a personal access or OAuth token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn delete_account(
+    req: HttpRequest,
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    request: web::Json<DeleteAccountRequest>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let deletion = service
+        .schedule_account_deletion(&user, &request.password, &ClientInfo::from_request(&req, None))
+        .await?;
+    Ok(HttpResponse::Accepted().json(deletion))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/account/restore",
+    responses(
+        (status = 204, description = "Scheduled deletion cancelled, if there was one"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "auth"
+)]
+pub async fn restore_account(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.cancel_account_deletion(&user).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/oauth/clients",
+    request_body = CreateOAuthClientRequest,
+    responses(
+        (status = 201, description = "Client registered; the secret is only returned here", body = CreatedOAuthClient),
+        (status = 400, description = "Invalid name or redirect URIs, or too many clients"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "oauth"
+)]
+pub async fn create_oauth_client(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    request: web::Json<CreateOAuthClientRequest>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let created = service.create_oauth_client(&user, &request).await?;
+    Ok(HttpResponse::Created().json(created))
+}
+
+#[utoipa::path(
+    get,
+    path = "/oauth/clients",
+    responses(
+        (status = 200, description = "OAuth clients registered by the caller", body = Vec<OAuthClient>),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "oauth"
+)]
+pub async fn list_oauth_clients(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let clients = service.list_oauth_clients(&user).await?;
+    Ok(HttpResponse::Ok().json(clients))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/oauth/clients/{client_id}",
+    params(("client_id" = String, Path, description = "Client id")),
+    responses(
+        (status = 204, description = "Client deleted"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token"),
+        (status = 404, description = "No such client")
+    ),
+    security(("bearer_token" = [])),
+    tag = "oauth"
+)]
+pub async fn delete_oauth_client(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    client_id: web::Path<String>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    service.delete_oauth_client(&user, &client_id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/oauth/authorize",
+    params(AuthorizeRequest),
+    responses(
+        (status = 200, description = "What the app asks for, to show on the consent screen", body = ConsentPrompt),
+        (status = 400, description = "Unknown client or unregistered redirect URI, or an OAuth error \
+            to send back to the app in `redirect_to`"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "oauth"
+)]
+pub async fn consent_prompt(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    request: web::Query<AuthorizeRequest>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let prompt = service.consent_prompt(&request).await?;
+    Ok(HttpResponse::Ok().json(prompt))
+}
+
+#[utoipa::path(
+    post,
+    path = "/oauth/authorize",
+    request_body = ConsentDecision,
+    responses(
+        (status = 200, description = "Where to send the browser: the app's redirect URI with a code \
+            or access_denied", body = AuthorizationRedirect),
+        (status = 400, description = "Unknown client or unregistered redirect URI, or an OAuth error \
+            to send back to the app in `redirect_to`"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Called with a personal access or OAuth token")
+    ),
+    security(("bearer_token" = [])),
+    tag = "oauth"
+)]
+pub async fn authorize(
+    service: web::Data<AuthService>,
+    user: AuthenticatedUser,
+    decision: web::Json<ConsentDecision>
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+    let redirect = service.authorize(&user, &decision.request, decision.deny).await?;
+    Ok(HttpResponse::Ok().json(redirect))
+}
+
+#[utoipa::path(
+    get,
+    path = "/oauth/userinfo",
+    responses(
+        (status = 200, description = "Claims about the user, as far as the token's scopes allow", body = UserInfo),
+        (status = 401, description = "Missing, invalid or expired token"),
+        (status = 403, description = "Token was not issued with the openid scope")
+    ),
+    security(("bearer_token" = [])),
+    tag = "oauth"
+)]
+pub async fn userinfo(
+    req: HttpRequest,
+    service: web::Data<AuthService>
+) -> Result<HttpResponse, Error> {
+    let token = bearer_token(&req).ok_or(AuthRejection::MissingToken)?;
+    let info = service.userinfo(&token).await?;
+    Ok(HttpResponse::Ok().json(info))
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "Public keys that access and ID tokens are signed with, \
+            including keys about to be used and retired keys whose tokens are still valid", body = Object)
+    ),
+    tag = "oauth"
+)]
+pub async fn jwks(service: web::Data<AuthService>) -> Result<HttpResponse, Error> {
+    let jwks = service.jwks().await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::Public, CacheDirective::MaxAge(300)]))
+        .json(jwks))
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/openid-configuration",
+    responses(
+        (status = 200, description = "OpenID Connect discovery document", body = OpenIdConfiguration)
+    ),
+    tag = "oauth"
+)]
+pub async fn openid_configuration(service: web::Data<AuthService>) -> HttpResponse {
+    HttpResponse::Ok().json(service.openid_configuration())
 }
@@ -0,0 +1,223 @@
+//! Registration policy: open sign-up, invite codes or an email domain allowlist.
+//!
+//! Invite codes are created by admins and can be redeemed a limited number of
+//! times before they expire. Like other secrets, only their digest is stored.
+
+use std::{collections::HashMap, str::FromStr, sync::Mutex};
+use async_trait::async_trait;
+use jsonwebtoken::get_current_timestamp;
+use rand::Rng;
+use serde::Serialize;
+use uuid::Uuid;
+use crate::error::AuthError;
+
+/// Characters of invite codes, without ones that are easily confused.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationMode {
+    /// Anyone can sign up.
+    Open,
+    /// Sign-up needs an invite code.
+    InviteOnly,
+    /// Sign-up needs an address at one of the allowed domains.
+    DomainAllowlist,
+}
+
+impl FromStr for RegistrationMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "open" => Ok(Self::Open),
+            "invite" => Ok(Self::InviteOnly),
+            "allowlist" => Ok(Self::DomainAllowlist),
+            _ => Err(format!("Unknown registration mode: {}", mode)),
+        }
+    }
+}
+
+/// Why a registration was refused by the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationErrorCode {
+    InviteRequired,
+    InviteInvalid,
+    InviteExpired,
+    InviteExhausted,
+    DomainNotAllowed,
+}
+
+#[derive(Debug, Clone)]
+pub struct InviteRecord {
+    pub id: Uuid,
+    /// SHA-256 digest of the normalized code, see [`crate::secret::hash`].
+    pub code_hash: String,
+    /// The admin who created the code.
+    pub created_by: i32,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+impl InviteRecord {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= get_current_timestamp())
+    }
+
+    /// Why the code cannot be redeemed, if it cannot.
+    pub fn check(&self) -> Result<(), RegistrationErrorCode> {
+        if self.is_expired() {
+            return Err(RegistrationErrorCode::InviteExpired);
+        }
+        if self.uses >= self.max_uses {
+            return Err(RegistrationErrorCode::InviteExhausted);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait InviteStore: Send + Sync {
+    async fn insert(&self, record: InviteRecord) -> Result<(), AuthError>;
+
+    /// Every invite, newest first.
+    async fn list(&self) -> Result<Vec<InviteRecord>, AuthError>;
+
+    async fn find(&self, code_hash: &str) -> Result<Option<InviteRecord>, AuthError>;
+
+    /// Counts a use of the code, provided it has not expired by `now` nor
+    /// been used up. Returns `false` otherwise.
+    async fn redeem(&self, code_hash: &str, now: u64) -> Result<bool, AuthError>;
+
+    /// Returns `false` if there is no such invite.
+    async fn delete(&self, id: Uuid) -> Result<bool, AuthError>;
+}
+
+/// Keeps invites in process memory. Intended for tests and local development.
+#[derive(Default)]
+pub struct InMemoryInviteStore {
+    invites: Mutex<HashMap<Uuid, InviteRecord>>,
+}
+
+impl InMemoryInviteStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl InviteStore for InMemoryInviteStore {
+    async fn insert(&self, record: InviteRecord) -> Result<(), AuthError> {
+        let mut invites = self.invites.lock().map_err(|_| AuthError::InternalError)?;
+        invites.insert(record.id, record);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<InviteRecord>, AuthError> {
+        let invites = self.invites.lock().map_err(|_| AuthError::InternalError)?;
+        let mut list: Vec<_> = invites.values().cloned().collect();
+        list.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        Ok(list)
+    }
+
+    async fn find(&self, code_hash: &str) -> Result<Option<InviteRecord>, AuthError> {
+        let invites = self.invites.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(invites.values().find(|r| r.code_hash == code_hash).cloned())
+    }
+
+    async fn redeem(&self, code_hash: &str, now: u64) -> Result<bool, AuthError> {
+        let mut invites = self.invites.lock().map_err(|_| AuthError::InternalError)?;
+        let invite = invites.values_mut().find(|r| {
+            r.code_hash == code_hash
+                && r.uses < r.max_uses
+                && r.expires_at.is_none_or(|expires_at| expires_at > now)
+        });
+        Ok(invite.map(|r| r.uses += 1).is_some())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool, AuthError> {
+        let mut invites = self.invites.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(invites.remove(&id).is_some())
+    }
+}
+
+pub fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    let code: String = (0..CODE_LENGTH)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}-{}", &code[..4], &code[4..8], &code[8..])
+}
+
+/// Ignores case, spaces and dashes, as people retype codes.
+pub fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Whether the domain of `email` is one of `domains` or a subdomain of one.
+pub fn domain_allowed(email: &str, domains: &[String]) -> bool {
+    let Some((_, domain)) = email.rsplit_once('@') else {
+        return false;
+    };
+    let domain = domain.to_ascii_lowercase();
+    domains.iter().any(|allowed| {
+        let allowed = allowed.trim_start_matches('.').to_ascii_lowercase();
+        domain == allowed || domain.ends_with(&format!(".{}", allowed))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        let code = generate_code();
+        assert_eq!(code.len(), CODE_LENGTH + 2);
+        assert!(code.bytes().filter(|&b| b != b'-').all(|b| CODE_ALPHABET.contains(&b)));
+        assert_eq!(normalize_code("abcd efgh-jkmn"), "ABCDEFGHJKMN");
+    }
+
+    #[test]
+    fn test_domain_allowlist() {
+        let domains = vec!["example.org".to_string(), ".beta.dev".to_string()];
+        assert!(domain_allowed("alice@example.org", &domains));
+        assert!(domain_allowed("alice@EXAMPLE.org", &domains));
+        assert!(domain_allowed("bob@mail.beta.dev", &domains));
+        assert!(domain_allowed("bob@beta.dev", &domains));
+        assert!(!domain_allowed("eve@notexample.org", &domains));
+        assert!(!domain_allowed("eve@example.org.evil.com", &domains));
+        assert!(!domain_allowed("no-at-sign", &domains));
+    }
+
+    #[actix_rt::test]
+    async fn test_redeem_respects_limits() {
+        let store = InMemoryInviteStore::new();
+        let now = get_current_timestamp();
+        store.insert(InviteRecord {
+            id: Uuid::new_v4(),
+            code_hash: "hash".to_string(),
+            created_by: 1,
+            max_uses: 2,
+            uses: 0,
+            created_at: now,
+            expires_at: Some(now + 60),
+        }).await.unwrap();
+
+        assert!(!store.redeem("hash", now + 60).await.unwrap());
+        assert!(store.redeem("hash", now).await.unwrap());
+        assert!(store.redeem("hash", now).await.unwrap());
+        assert!(!store.redeem("hash", now).await.unwrap());
+        assert!(!store.redeem("other", now).await.unwrap());
+
+        let invite = store.find("hash").await.unwrap().unwrap();
+        assert_eq!(invite.uses, 2);
+        assert_eq!(invite.check(), Err(RegistrationErrorCode::InviteExhausted));
+    }
+}
@@ -0,0 +1,617 @@
+//! Keys that sign the tokens issued by SocialHub.
+//!
+//! Keys are identified by `kid`, which every token carries in its header. The
+//! [`KeyStore`] rotates the key of each algorithm in use every
+//! [`AuthConfig::key_rotation_interval`] seconds. A new key is published in the
+//! JWK Set [`PUBLISH_LEAD`] seconds before it signs anything, so that other
+//! instances and services verifying offline know it in time. A retired key
+//! stays until the last token it signed has expired.
+//!
+//! Keys are kept in a [`SigningKeyStore`] shared by all instances. Tokens
+//! without a `kid` predate key rotation and are checked against
+//! [`AuthConfig::jwt_secret`].
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+use actix_web::web;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode_header, encode, get_current_timestamp, Algorithm, DecodingKey, EncodingKey, Header};
+use log::{error, info};
+use rand::RngCore;
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use rsa::{
+    pkcs1::EncodeRsaPrivateKey,
+    pkcs8::{DecodePrivateKey, EncodePrivateKey},
+    traits::PublicKeyParts,
+    RsaPrivateKey,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use crate::{config::AuthConfig, error::AuthError};
+
+/// Algorithms keys can be generated for.
+pub const SUPPORTED_ALGORITHMS: &[Algorithm] = &[Algorithm::HS256, Algorithm::RS256, Algorithm::EdDSA];
+/// Seconds a new key is published before it signs tokens.
+pub const PUBLISH_LEAD: u64 = 3600;
+
+/// Seconds between reloads of the key store.
+const REFRESH_INTERVAL: u64 = 60;
+/// Tokens with an unknown `kid` reload the key store at most this often, in seconds.
+const MISS_REFRESH_INTERVAL: u64 = 5;
+const RSA_KEY_BITS: usize = 2048;
+const HMAC_KEY_BYTES: usize = 32;
+
+/// A signing key as persisted.
+#[derive(Debug, Clone)]
+pub struct SigningKeyRecord {
+    pub kid: String,
+    /// `HS256`, `RS256` or `EdDSA`.
+    pub algorithm: String,
+    /// PKCS#8 DER of asymmetric keys, the raw secret of HMAC keys.
+    pub private_key: Vec<u8>,
+    pub created_at: u64,
+    /// From then on the key signs tokens of its algorithm, until a newer one takes over.
+    pub active_from: u64,
+}
+
+#[async_trait]
+pub trait SigningKeyStore: Send + Sync {
+    async fn insert(&self, record: SigningKeyRecord) -> Result<(), AuthError>;
+
+    async fn list(&self) -> Result<Vec<SigningKeyRecord>, AuthError>;
+
+    async fn delete(&self, kid: &str) -> Result<(), AuthError>;
+}
+
+/// Keeps signing keys in process memory. Intended for tests, local development
+/// and single-instance deployments that accept losing all tokens on restart.
+#[derive(Default)]
+pub struct InMemorySigningKeyStore {
+    keys: Mutex<HashMap<String, SigningKeyRecord>>,
+}
+
+impl InMemorySigningKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SigningKeyStore for InMemorySigningKeyStore {
+    async fn insert(&self, record: SigningKeyRecord) -> Result<(), AuthError> {
+        let mut keys = self.keys.lock().map_err(|_| AuthError::InternalError)?;
+        keys.insert(record.kid.clone(), record);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<SigningKeyRecord>, AuthError> {
+        let keys = self.keys.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(keys.values().cloned().collect())
+    }
+
+    async fn delete(&self, kid: &str) -> Result<(), AuthError> {
+        let mut keys = self.keys.lock().map_err(|_| AuthError::InternalError)?;
+        keys.remove(kid);
+        Ok(())
+    }
+}
+
+/// A public key in JWK format (RFC 7517).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Jwk {
+    /// `RSA` or `OKP`.
+    pub kty: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub alg: String,
+    pub kid: String,
+    /// RSA modulus, base64url.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// RSA public exponent, base64url.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// `Ed25519` for EdDSA keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    /// Ed25519 public key, base64url.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+}
+
+impl Jwk {
+    pub fn decoding_key(&self) -> Result<DecodingKey, AuthError> {
+        match (self.kty.as_str(), &self.n, &self.e, &self.x) {
+            ("RSA", Some(n), Some(e), _) => DecodingKey::from_rsa_components(n, e),
+            ("OKP", _, _, Some(x)) => DecodingKey::from_ed_components(x),
+            _ => return Err(AuthError::InternalError),
+        }
+        .map_err(|_| AuthError::InternalError)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    /// `None` for HMAC keys, which are never published.
+    jwk: Option<Jwk>,
+    active_from: u64,
+}
+
+impl SigningKey {
+    fn load(record: &SigningKeyRecord) -> Result<Self, AuthError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            error!("Invalid signing key {}: {}", record.kid, e);
+            AuthError::InternalError
+        };
+        let algorithm: Algorithm = record.algorithm.parse().map_err(|e| invalid(&e))?;
+
+        let (encoding_key, decoding_key, jwk) = match algorithm {
+            Algorithm::HS256 => (
+                EncodingKey::from_secret(&record.private_key),
+                DecodingKey::from_secret(&record.private_key),
+                None,
+            ),
+            Algorithm::RS256 => {
+                let key = RsaPrivateKey::from_pkcs8_der(&record.private_key).map_err(|e| invalid(&e))?;
+                let pkcs1 = key.to_pkcs1_der().map_err(|e| invalid(&e))?;
+                let n = URL_SAFE_NO_PAD.encode(key.n().to_bytes_be());
+                let e = URL_SAFE_NO_PAD.encode(key.e().to_bytes_be());
+                let jwk = Jwk {
+                    kty: "RSA".to_string(),
+                    key_use: "sig".to_string(),
+                    alg: record.algorithm.clone(),
+                    kid: record.kid.clone(),
+                    n: Some(n),
+                    e: Some(e),
+                    crv: None,
+                    x: None,
+                };
+                (EncodingKey::from_rsa_der(pkcs1.as_bytes()), jwk.decoding_key()?, Some(jwk))
+            }
+            Algorithm::EdDSA => {
+                let pair = Ed25519KeyPair::from_pkcs8(&record.private_key).map_err(|e| invalid(&e))?;
+                let jwk = Jwk {
+                    kty: "OKP".to_string(),
+                    key_use: "sig".to_string(),
+                    alg: record.algorithm.clone(),
+                    kid: record.kid.clone(),
+                    n: None,
+                    e: None,
+                    crv: Some("Ed25519".to_string()),
+                    x: Some(URL_SAFE_NO_PAD.encode(pair.public_key().as_ref())),
+                };
+                (EncodingKey::from_ed_der(&record.private_key), jwk.decoding_key()?, Some(jwk))
+            }
+            other => return Err(invalid(&format!("unsupported algorithm {:?}", other))),
+        };
+
+        Ok(Self {
+            kid: record.kid.clone(),
+            algorithm,
+            encoding_key,
+            decoding_key,
+            jwk,
+            active_from: record.active_from,
+        })
+    }
+}
+
+/// Generates a key for `algorithm`. Its id is the JWK thumbprint (RFC 7638)
+/// of asymmetric keys, and random for HMAC keys.
+pub fn generate_key(algorithm: Algorithm, active_from: u64) -> Result<SigningKeyRecord, AuthError> {
+    let failed = |e: &dyn std::fmt::Display| {
+        error!("Failed to generate {:?} signing key: {}", algorithm, e);
+        AuthError::InternalError
+    };
+    let private_key = match algorithm {
+        Algorithm::HS256 => {
+            let mut secret = vec![0u8; HMAC_KEY_BYTES];
+            rand::thread_rng().fill_bytes(&mut secret);
+            secret
+        }
+        Algorithm::RS256 => RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_BITS)
+            .map_err(|e| failed(&e))?
+            .to_pkcs8_der()
+            .map_err(|e| failed(&e))?
+            .as_bytes()
+            .to_vec(),
+        Algorithm::EdDSA => Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|e| failed(&e))?
+            .as_ref()
+            .to_vec(),
+        other => return Err(failed(&format!("unsupported algorithm {:?}", other))),
+    };
+
+    let mut record = SigningKeyRecord {
+        kid: String::new(),
+        algorithm: format!("{:?}", algorithm),
+        private_key,
+        created_at: get_current_timestamp(),
+        active_from,
+    };
+    record.kid = match SigningKey::load(&record)?.jwk {
+        Some(jwk) => thumbprint(&jwk),
+        None => {
+            let mut id = [0u8; 16];
+            rand::thread_rng().fill_bytes(&mut id);
+            URL_SAFE_NO_PAD.encode(id)
+        }
+    };
+    Ok(record)
+}
+
+/// JWK thumbprint (RFC 7638): the required members in lexicographic order,
+/// without whitespace.
+fn thumbprint(jwk: &Jwk) -> String {
+    let canonical = match (&jwk.crv, &jwk.x) {
+        (Some(crv), Some(x)) => json!({ "crv": crv, "kty": jwk.kty, "x": x }),
+        _ => json!({ "e": jwk.e, "kty": jwk.kty, "n": jwk.n }),
+    };
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.to_string().as_bytes()))
+}
+
+#[derive(Default)]
+struct Ring {
+    keys: Vec<Arc<SigningKey>>,
+    refreshed_at: Option<u64>,
+}
+
+/// The signing keys in use, loaded from a [`SigningKeyStore`].
+///
+/// Signing and verification are synchronous and only use the keys loaded so
+/// far; [`KeyStore::refresh_if_due`] loads and rotates them.
+pub struct KeyStore {
+    store: Arc<dyn SigningKeyStore>,
+    /// Algorithm of access tokens.
+    algorithm: Algorithm,
+    rotation_interval: u64,
+    /// Seconds a retired key is kept: the longest lifetime of a token, plus leeway.
+    retention: u64,
+    /// Checks tokens without a `kid`.
+    legacy: SigningKey,
+    ring: RwLock<Ring>,
+    /// Set once an ID token was requested while access tokens use HMAC, which
+    /// third parties cannot verify. An RS256 key is then kept as well.
+    id_tokens: AtomicBool,
+    /// Held while refreshing, so one instance does not rotate twice.
+    refreshing: futures::lock::Mutex<()>,
+}
+
+impl KeyStore {
+    pub fn new(config: &AuthConfig, store: Arc<dyn SigningKeyStore>) -> Self {
+        Self {
+            store,
+            algorithm: config.jwt_algorithm,
+            rotation_interval: config.key_rotation_interval,
+            retention: config.token_expiration + jsonwebtoken::Validation::default().leeway,
+            legacy: SigningKey {
+                kid: String::new(),
+                algorithm: Algorithm::HS256,
+                encoding_key: EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+                decoding_key: DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+                jwk: None,
+                active_from: 0,
+            },
+            ring: RwLock::new(Ring::default()),
+            id_tokens: AtomicBool::new(false),
+            refreshing: futures::lock::Mutex::new(()),
+        }
+    }
+
+    /// Algorithm ID tokens are signed with: that of access tokens, unless it is HMAC.
+    pub fn id_token_algorithm(&self) -> Algorithm {
+        match self.algorithm {
+            Algorithm::HS256 => Algorithm::RS256,
+            algorithm => algorithm,
+        }
+    }
+
+    /// Makes sure there is a key to sign ID tokens with.
+    pub async fn prepare_id_tokens(&self) -> Result<(), AuthError> {
+        if self.id_tokens.swap(true, Ordering::Relaxed) {
+            return self.refresh_if_due().await;
+        }
+        let _guard = self.refreshing.lock().await;
+        self.refresh().await
+    }
+
+    /// Signs `claims` with the current key for access tokens.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, AuthError> {
+        self.sign_with(self.algorithm, claims)
+    }
+
+    /// Signs `claims` with the current key for `algorithm`. Before the first
+    /// refresh, HS256 tokens are signed with the legacy secret.
+    pub fn sign_with<T: Serialize>(&self, algorithm: Algorithm, claims: &T) -> Result<String, AuthError> {
+        let current = self.current(algorithm, get_current_timestamp());
+        let (key, kid) = match &current {
+            Some(key) => (key.as_ref(), Some(key.kid.clone())),
+            None if algorithm == Algorithm::HS256 => (&self.legacy, None),
+            None => {
+                error!("No {:?} signing key loaded", algorithm);
+                return Err(AuthError::InternalError);
+            }
+        };
+        let mut header = Header::new(algorithm);
+        header.kid = kid;
+        encode(&header, claims, &key.encoding_key).map_err(|_| AuthError::InternalError)
+    }
+
+    /// Key that verifies `token`, by the `kid` in its header, with its algorithm.
+    pub fn decoding_key(&self, token: &str) -> Result<(DecodingKey, Algorithm), AuthError> {
+        let header = decode_header(token).map_err(|_| AuthError::InvalidToken)?;
+        let Some(kid) = header.kid else {
+            return Ok((self.legacy.decoding_key.clone(), self.legacy.algorithm));
+        };
+        let ring = self.ring.read().map_err(|_| AuthError::InternalError)?;
+        ring.keys
+            .iter()
+            .find(|k| k.kid == kid)
+            .map(|k| (k.decoding_key.clone(), k.algorithm))
+            .ok_or(AuthError::InvalidToken)
+    }
+
+    /// Whether a token signed with key `kid` could be verified. Tokens without
+    /// a `kid` always can.
+    pub fn knows(&self, token: &str) -> bool {
+        let Some(kid) = decode_header(token).ok().and_then(|h| h.kid) else {
+            return true;
+        };
+        self.ring.read().is_ok_and(|ring| ring.keys.iter().any(|k| k.kid == kid))
+    }
+
+    /// Public keys of all asymmetric keys loaded, including those not signing yet.
+    pub fn jwks(&self) -> JwkSet {
+        let ring = self.ring.read().unwrap_or_else(|e| e.into_inner());
+        JwkSet { keys: ring.keys.iter().filter_map(|k| k.jwk.clone()).collect() }
+    }
+
+    /// Reloads the keys if the last refresh is more than a minute old.
+    pub async fn refresh_if_due(&self) -> Result<(), AuthError> {
+        self.refresh_older_than(REFRESH_INTERVAL).await
+    }
+
+    /// Reloads the keys for a token signed by a key not loaded yet, typically
+    /// just created by another instance.
+    pub async fn refresh_for(&self, token: &str) -> Result<(), AuthError> {
+        if self.knows(token) {
+            return Ok(());
+        }
+        self.refresh_older_than(MISS_REFRESH_INTERVAL).await
+    }
+
+    async fn refresh_older_than(&self, age: u64) -> Result<(), AuthError> {
+        let due = |refreshed_at: Option<u64>| {
+            refreshed_at.is_none_or(|at| get_current_timestamp() >= at + age)
+        };
+        if !due(self.ring.read().map_err(|_| AuthError::InternalError)?.refreshed_at) {
+            return Ok(());
+        }
+        let _guard = self.refreshing.lock().await;
+        // Another task may have refreshed while this one waited.
+        if !due(self.ring.read().map_err(|_| AuthError::InternalError)?.refreshed_at) {
+            return Ok(());
+        }
+        self.refresh().await
+    }
+
+    /// Loads the keys from the store, generating a key for every algorithm
+    /// whose current key is due for rotation and deleting keys whose tokens
+    /// have all expired.
+    pub async fn refresh(&self) -> Result<(), AuthError> {
+        let now = get_current_timestamp();
+        let mut records = self.store.list().await?;
+
+        for algorithm in self.algorithms() {
+            let name = format!("{:?}", algorithm);
+            let newest = records
+                .iter()
+                .filter(|r| r.algorithm == name)
+                .map(|r| r.active_from)
+                .max();
+            let active_from = match newest {
+                None => now,
+                Some(at) if now >= at + self.rotation_interval => now + PUBLISH_LEAD,
+                Some(_) => continue,
+            };
+            // Generating an RSA key takes a while.
+            let record = web::block(move || generate_key(algorithm, active_from))
+                .await
+                .map_err(|_| AuthError::InternalError)??;
+            info!("Generated {} signing key {}, active from {}", record.algorithm, record.kid, active_from);
+            self.store.insert(record.clone()).await?;
+            records.push(record);
+        }
+
+        // A key is retired once its successor signs; drop it when its tokens are gone.
+        let mut expired = Vec::new();
+        for record in &records {
+            let retired_at = records
+                .iter()
+                .filter(|r| r.algorithm == record.algorithm && r.active_from > record.active_from)
+                .map(|r| r.active_from)
+                .filter(|&at| at <= now)
+                .min();
+            if retired_at.is_some_and(|at| at + self.retention < now) {
+                expired.push(record.kid.clone());
+            }
+        }
+        for kid in &expired {
+            self.store.delete(kid).await?;
+            info!("Deleted retired signing key {}", kid);
+        }
+
+        let keys = records
+            .iter()
+            .filter(|r| !expired.contains(&r.kid))
+            .map(|r| SigningKey::load(r).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut ring = self.ring.write().map_err(|_| AuthError::InternalError)?;
+        *ring = Ring { keys, refreshed_at: Some(now) };
+        Ok(())
+    }
+
+    fn algorithms(&self) -> Vec<Algorithm> {
+        let mut algorithms = vec![self.algorithm];
+        if self.id_tokens.load(Ordering::Relaxed) && self.id_token_algorithm() != self.algorithm {
+            algorithms.push(self.id_token_algorithm());
+        }
+        algorithms
+    }
+
+    /// Newest key of `algorithm` that is active at `now`.
+    fn current(&self, algorithm: Algorithm, now: u64) -> Option<Arc<SigningKey>> {
+        let ring = self.ring.read().ok()?;
+        ring.keys
+            .iter()
+            .filter(|k| k.algorithm == algorithm && k.active_from <= now)
+            .max_by_key(|k| k.active_from)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{decode, Validation};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct TestClaims {
+        sub: String,
+        exp: u64,
+    }
+
+    fn key_store(algorithm: Algorithm, store: Arc<dyn SigningKeyStore>) -> KeyStore {
+        KeyStore::new(&AuthConfig {
+            jwt_secret: "test-secret".to_string(),
+            jwt_algorithm: algorithm,
+            key_rotation_interval: 86400,
+            token_expiration: 3600,
+            ..AuthConfig::default()
+        }, store)
+    }
+
+    fn verify(keys: &KeyStore, token: &str) -> Result<TestClaims, AuthError> {
+        let (key, algorithm) = keys.decoding_key(token)?;
+        let mut validation = Validation::new(algorithm);
+        validation.set_required_spec_claims(&["exp"]);
+        decode::<TestClaims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| AuthError::InvalidToken)
+    }
+
+    fn claims() -> TestClaims {
+        TestClaims { sub: "7".to_string(), exp: get_current_timestamp() + 300 }
+    }
+
+    #[actix_rt::test]
+    async fn test_tokens_verify_with_published_keys() {
+        for algorithm in [Algorithm::RS256, Algorithm::EdDSA] {
+            let keys = key_store(algorithm, Arc::new(InMemorySigningKeyStore::new()));
+            assert!(keys.sign(&claims()).is_err());
+            keys.refresh().await.unwrap();
+
+            let token = keys.sign(&claims()).unwrap();
+            let jwks = keys.jwks();
+            assert_eq!(jwks.keys.len(), 1);
+            let jwk = &jwks.keys[0];
+            assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some(jwk.kid.as_str()));
+            assert_eq!(jwk.alg, format!("{:?}", algorithm));
+
+            // What an edge service does with the published key.
+            let published: Jwk = serde_json::from_value(serde_json::to_value(jwk).unwrap()).unwrap();
+            let mut validation = Validation::new(algorithm);
+            validation.set_required_spec_claims(&["exp"]);
+            let decoded = decode::<TestClaims>(&token, &published.decoding_key().unwrap(), &validation).unwrap();
+            assert_eq!(decoded.claims.sub, "7");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_rotation_keeps_retired_keys_until_their_tokens_expire() {
+        let store = Arc::new(InMemorySigningKeyStore::new());
+        let keys = key_store(Algorithm::HS256, store.clone());
+
+        // Before the first refresh, and for tokens without a kid, the legacy secret is used.
+        let legacy = keys.sign(&claims()).unwrap();
+        assert_eq!(decode_header(&legacy).unwrap().kid, None);
+        keys.refresh().await.unwrap();
+        let first = keys.sign(&claims()).unwrap();
+        let first_kid = decode_header(&first).unwrap().kid.unwrap();
+        assert!(keys.jwks().keys.is_empty(), "HMAC keys are never published");
+
+        // Due for rotation: the new key is published first and signs later.
+        let now = get_current_timestamp();
+        let mut records = store.list().await.unwrap();
+        records[0].active_from = now - 86400;
+        store.insert(records[0].clone()).await.unwrap();
+        keys.refresh().await.unwrap();
+        assert_eq!(store.list().await.unwrap().len(), 2);
+        assert_eq!(decode_header(&keys.sign(&claims()).unwrap()).unwrap().kid.unwrap(), first_kid);
+
+        // Once the successor signs, tokens of the retired key stay valid...
+        for mut record in store.list().await.unwrap() {
+            if record.kid != first_kid {
+                record.active_from = now - 60;
+                store.insert(record).await.unwrap();
+            }
+        }
+        keys.refresh().await.unwrap();
+        let second = keys.sign(&claims()).unwrap();
+        assert_ne!(decode_header(&second).unwrap().kid.unwrap(), first_kid);
+        for token in [&legacy, &first, &second] {
+            assert_eq!(verify(&keys, token).unwrap().sub, "7");
+        }
+
+        // ...until the longest token lifetime has passed.
+        for mut record in store.list().await.unwrap() {
+            if record.kid != first_kid {
+                record.active_from = now - 3600 - 120;
+                store.insert(record).await.unwrap();
+            }
+        }
+        keys.refresh().await.unwrap();
+        assert_eq!(store.list().await.unwrap().len(), 1);
+        assert!(matches!(verify(&keys, &first), Err(AuthError::InvalidToken)));
+        assert!(verify(&keys, &second).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_keys_created_by_another_instance() {
+        let store: Arc<dyn SigningKeyStore> = Arc::new(InMemorySigningKeyStore::new());
+        let this = key_store(Algorithm::EdDSA, store.clone());
+        let other = key_store(Algorithm::EdDSA, store);
+        this.refresh().await.unwrap();
+        other.refresh().await.unwrap();
+        assert_eq!(this.jwks().keys.len(), 1, "instances share keys instead of each making one");
+
+        let token = other.sign(&claims()).unwrap();
+        assert!(this.knows(&token));
+        assert!(verify(&this, &token).is_ok());
+        assert!(!this.knows(&token_with_kid("unknown-kid")));
+    }
+
+    fn token_with_kid(kid: &str) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(kid.to_string());
+        encode(&header, &claims(), &EncodingKey::from_secret(b"x")).unwrap()
+    }
+}
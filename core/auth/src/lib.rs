@@ -4,49 +4,209 @@
 //! 
 //! # Examples
 //! 
+//! The handlers expect a shared [`AuthService`], registered with [`configure_state`].
+//!
 //! ```rust
-//! use socialhub_auth;
+//! use std::sync::Arc;
+//! use socialhub_auth::{AuthConfig, AuthService, InMemoryUserRepository};
 //! use actix_web::{web, App, HttpResponse};
 //! 
 //! async fn configure_app() {
+//!     let users = Arc::new(InMemoryUserRepository::new());
+//!     let auth = web::Data::new(AuthService::new(AuthConfig::default(), users));
 //!     let app = App::new()
+//!         .configure(socialhub_auth::configure_state(auth.clone()))
 //!         .configure(socialhub_auth::configure);
 //! }
 //! ```
 
+use std::sync::Arc;
 #[allow(unused_imports)]
 use actix_web::{web, HttpResponse};
 #[allow(unused_imports)]
 use serde::{Deserialize, Serialize};
 
+pub mod access_token;
+pub mod audit;
+pub mod config;
+pub mod device;
+pub mod email_token;
+pub mod export;
 pub mod handlers;
+pub mod invite;
+pub mod keys;
+pub mod mailer;
 pub mod models;
+pub mod oauth;
+pub mod oauth_client;
+pub mod oidc;
+pub mod refresh;
+pub mod repository;
+pub mod revocation;
+pub mod session;
+pub mod throttle;
+pub mod token;
+pub mod two_factor;
+mod schema;
+mod secret;
+mod totp;
 mod service;
 mod error;
 
+pub use access_token::{AccessTokenStore, InMemoryAccessTokenStore};
+pub use audit::{AuditLog, InMemoryAuditLog};
+pub use config::AuthConfig;
+pub use email_token::{EmailTokenStore, InMemoryEmailTokenStore};
+pub use error::{AuthError, OAuthErrorCode};
+pub use invite::{InMemoryInviteStore, InviteStore, RegistrationErrorCode, RegistrationMode};
 pub use handlers::*;
+pub use keys::{InMemorySigningKeyStore, KeyStore, SigningKeyStore};
+pub use mailer::{FileMailer, Mailer, SmtpMailer};
 pub use models::*;
+pub use oauth_client::{InMemoryOAuthClientStore, OAuthClientStore};
+pub use refresh::{InMemoryRefreshTokenStore, RefreshTokenStore};
+pub use repository::{InMemoryUserRepository, PgUserRepository, UserRepository};
+pub use revocation::{CacheRevocationStore, RevocationStore};
+pub use service::AuthService;
+pub use session::{InMemorySessionStore, SessionStore};
+pub use token::{Claims, TokenService, TokenUse};
+pub use two_factor::{InMemoryTwoFactorStore, TwoFactorStore};
+pub use socialhub_core::auth::{permissions, AuthenticatedUser, Role, TokenKind, TokenVerifier};
+use socialhub_core::require_permission;
+
+/// Registers `service` as app data, both for the auth handlers and as the
+/// [`TokenVerifier`] behind `socialhub_core::AuthenticatedUser` in the other crates.
+///
+/// Build the service once and pass a clone to every worker:
+/// `App::new().configure(socialhub_auth::configure_state(auth.clone()))`.
+pub fn configure_state(service: web::Data<AuthService>) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        let verifier: Arc<dyn TokenVerifier> = service.clone().into_inner();
+        cfg.app_data(service).app_data(web::Data::from(verifier));
+    }
+}
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/auth")
             .route("/login", web::post().to(handlers::login))
+            .route("/login/2fa", web::post().to(handlers::login_two_factor))
+            .route("/refresh", web::post().to(handlers::refresh))
+            .route("/device/code", web::post().to(handlers::device_code))
+            .route("/device/verify", web::post().to(handlers::verify_device))
+            .route("/token", web::post().to(handlers::token))
             .route("/register", web::post().to(handlers::register))
+            .route("/password/forgot", web::post().to(handlers::forgot_password))
+            .route("/password/reset", web::post().to(handlers::reset_password))
+            .route("/email/verify", web::post().to(handlers::verify_email))
+            .route("/email/verify/send", web::post().to(handlers::send_verification_email))
             .route("/logout", web::post().to(handlers::logout))
-    );
+            .route("/logout/all", web::post().to(handlers::logout_all))
+            .route("/sessions", web::get().to(handlers::list_sessions))
+            .route("/sessions/{id}", web::delete().to(handlers::revoke_session))
+            .route("/account", web::delete().to(handlers::delete_account))
+            .route("/account/restore", web::post().to(handlers::restore_account))
+            .route("/account/export", web::post().to(handlers::request_export))
+            .route("/account/export/{id}", web::get().to(handlers::export_status))
+            .route("/account/export/{id}/download", web::get().to(handlers::download_export))
+            .route("/tokens", web::post().to(handlers::create_access_token))
+            .route("/tokens", web::get().to(handlers::list_access_tokens))
+            .route("/tokens/{id}", web::delete().to(handlers::delete_access_token))
+            .route("/2fa/enroll", web::post().to(handlers::enroll_two_factor))
+            .route("/2fa/confirm", web::post().to(handlers::confirm_two_factor))
+            .route("/2fa/disable", web::post().to(handlers::disable_two_factor))
+            .route(
+                "/users/{id}/roles",
+                web::put()
+                    .to(handlers::update_roles)
+                    .wrap(require_permission(permissions::USERS_MANAGE))
+            )
+            .route(
+                "/users/{id}/impersonate",
+                web::post()
+                    .to(handlers::impersonate)
+                    .wrap(require_permission(permissions::USERS_IMPERSONATE))
+            )
+            .route(
+                "/invites",
+                web::post()
+                    .to(handlers::create_invite)
+                    .wrap(require_permission(permissions::USERS_MANAGE))
+            )
+            .route(
+                "/invites",
+                web::get()
+                    .to(handlers::list_invites)
+                    .wrap(require_permission(permissions::USERS_MANAGE))
+            )
+            .route(
+                "/invites/{id}",
+                web::delete()
+                    .to(handlers::delete_invite)
+                    .wrap(require_permission(permissions::USERS_MANAGE))
+            )
+            .route(
+                "/audit",
+                web::get()
+                    .to(handlers::list_audit_events)
+                    .wrap(require_permission(permissions::AUDIT_READ))
+            )
+            .route(
+                "/audit/export",
+                web::get()
+                    .to(handlers::export_audit_events)
+                    .wrap(require_permission(permissions::AUDIT_READ))
+            )
+    )
+    .service(
+        web::scope("/oauth")
+            .route("/clients", web::post().to(handlers::create_oauth_client))
+            .route("/clients", web::get().to(handlers::list_oauth_clients))
+            .route("/clients/{client_id}", web::delete().to(handlers::delete_oauth_client))
+            .route("/authorize", web::get().to(handlers::consent_prompt))
+            .route("/authorize", web::post().to(handlers::authorize))
+            .route("/userinfo", web::get().to(handlers::userinfo))
+            .route("/userinfo", web::post().to(handlers::userinfo))
+    )
+    .route("/.well-known/openid-configuration", web::get().to(handlers::openid_configuration))
+    .route("/.well-known/jwks.json", web::get().to(handlers::jwks));
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use std::sync::Arc;
     use actix_web::{test, App};
-    use crate::{handlers, models::{LoginRequest, RegisterRequest}};
+    use crate::{audit::{AuditAction, AuditEvent, AuditOutcome}, handlers, models::{AuditPage, AuditQuery, AuthResponse, Impersonation, Session, CreatedAccessToken, LoginRequest, TwoFactorChallenge, TwoFactorEnrollment}};
+    use socialhub_core::auth::AuthRejection;
     use serde_json::json;
 
+    pub(crate) fn auth_service() -> web::Data<AuthService> {
+        let config = AuthConfig {
+            jwt_secret: "test-secret".to_string(),
+            hash_cost: 4,
+            ..AuthConfig::default()
+        };
+        web::Data::new(AuthService::new(config, Arc::new(InMemoryUserRepository::new())))
+    }
+
+    pub(crate) async fn register_user(service: &AuthService, username: &str) -> User {
+        service.register(&RegisterRequest {
+            username: username.to_string(),
+            email: format!("{}@test.com", username),
+            password: "password123".to_string(),
+            invite_code: None,
+        }).await.unwrap()
+    }
+
     #[actix_rt::test]
     async fn test_login_success() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
         let app = test::init_service(
-            App::new().service(web::scope("/auth").route("/login", web::post().to(handlers::login)))
+            App::new()
+                .app_data(service.clone())
+                .service(web::scope("/auth").route("/login", web::post().to(handlers::login)))
         ).await;
 
         let req = test::TestRequest::post()
@@ -54,17 +214,25 @@ pub mod tests {
             .set_json(LoginRequest {
                 username: "testuser".to_string(),
                 password: "password123".to_string(),
+                device_name: None,
             })
             .to_request();
 
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
+
+        let body: AuthResponse = test::read_body_json(resp).await;
+        assert_eq!(body.token_type, "Bearer");
+        let claims = service.verify_token(&body.token).await.unwrap();
+        assert_eq!(claims.user_id().unwrap(), body.user_id);
     }
 
     #[actix_rt::test]
     async fn test_login_failure() {
         let app = test::init_service(
-            App::new().service(web::scope("/auth").route("/login", web::post().to(handlers::login)))
+            App::new()
+                .app_data(auth_service())
+                .service(web::scope("/auth").route("/login", web::post().to(handlers::login)))
         ).await;
 
         let req = test::TestRequest::post()
@@ -72,6 +240,7 @@ pub mod tests {
             .set_json(LoginRequest {
                 username: "invalid".to_string(),
                 password: "wrong".to_string(),
+                device_name: None,
             })
             .to_request();
 
@@ -79,17 +248,62 @@ pub mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[actix_rt::test]
+    async fn test_login_wrong_password() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().app_data(service).configure(configure)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": "testuser", "password": "wrong-password" }))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_rt::test]
+    async fn test_register_returns_created_user() {
+        let service = auth_service();
+        let app = test::init_service(App::new().app_data(service.clone()).configure(configure)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/auth/register")
+            .set_json(json!({
+                "username": "newuser",
+                "email": "New@User.com",
+                "password": "password123"
+            }))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 201);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["username"], "newuser");
+        assert_eq!(body["email"], "new@user.com");
+        assert!(body.get("password_hash").is_none());
+
+        let stored = service.users().find_by_username("newuser").await.unwrap().unwrap();
+        assert!(bcrypt::verify("password123", &stored.password_hash).unwrap());
+    }
+
     #[actix_rt::test]
     async fn test_register_duplicate_user() {
+        let service = auth_service();
+        register_user(&service, "existing_user").await;
         let app = test::init_service(
-            App::new().service(web::scope("/auth").route("/register", web::post().to(handlers::register)))
+            App::new()
+                .app_data(service)
+                .service(web::scope("/auth").route("/register", web::post().to(handlers::register)))
         ).await;
 
         let req = test::TestRequest::post()
             .uri("/auth/register")
             .set_json(json!({
-                "username": "existing_user",
-                "email": "existing@test.com",
+                "username": "Existing_User",
+                "email": "other@test.com",
                 "password": "password123"
             }))
             .to_request();
@@ -98,10 +312,225 @@ pub mod tests {
         assert_eq!(resp.status().as_u16(), 409); // Conflict
     }
 
+    #[actix_rt::test]
+    async fn test_register_duplicate_email() {
+        let service = auth_service();
+        register_user(&service, "existing_user").await;
+        let app = test::init_service(App::new().app_data(service).configure(configure)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/auth/register")
+            .set_json(json!({
+                "username": "someone_else",
+                "email": "EXISTING_USER@test.com",
+                "password": "password123"
+            }))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 409);
+        let body: String = test::read_body_json(resp).await;
+        assert_eq!(body, "Email already registered");
+    }
+
+    #[actix_rt::test]
+    async fn test_register_rejects_invalid_data() {
+        let app = test::init_service(App::new().app_data(auth_service()).configure(configure)).await;
+
+        for payload in [
+            json!({ "username": "ab", "email": "ab@test.com", "password": "password123" }),
+            json!({ "username": "valid_name", "email": "not-an-email", "password": "password123" }),
+            json!({ "username": "valid_name", "email": "valid@test.com", "password": "short" }),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/auth/register")
+                .set_json(payload)
+                .to_request();
+
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), 400);
+        }
+    }
+    #[actix_rt::test]
+    async fn test_invite_only_registration() {
+        let service = web::Data::new(AuthService::new(
+            AuthConfig { hash_cost: 4, registration_mode: RegistrationMode::InviteOnly, ..AuthConfig::default() },
+            Arc::new(InMemoryUserRepository::new()),
+        ));
+        // The first admin cannot be invited; it is created directly.
+        let admin = service.users().create(repository::NewUser {
+            username: "testuser".to_string(),
+            email: "testuser@test.com".to_string(),
+            password_hash: bcrypt::hash("password123", 4).unwrap(),
+        }).await.unwrap();
+        service.users().update_roles(admin.id, vec![Role::Admin], Vec::new()).await.unwrap();
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+
+        let register = |username: &str, invite_code: Option<&str>| post_json(&app, "/auth/register", None, json!({
+            "username": username,
+            "email": format!("{}@test.com", username),
+            "password": "password123",
+            "invite_code": invite_code,
+        }));
+        let error = |resp: actix_web::dev::ServiceResponse| async move {
+            assert_eq!(resp.status().as_u16(), 403);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            body["error"].as_str().unwrap().to_string()
+        };
+
+        assert_eq!(error(register("alice", None).await).await, "invite_required");
+        assert_eq!(error(register("alice", Some("AAAA-BBBB-CCCC")).await).await, "invite_invalid");
+
+        let resp = post_json(&app, "/auth/invites", Some(&session.token), json!({ "max_uses": 2 })).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let invite: CreatedInvite = test::read_body_json(resp).await;
+        assert_eq!(invite.details.created_by, admin.id);
+
+        // Codes are accepted however they are typed.
+        let typed = invite.code.to_lowercase().replace('-', " ");
+        assert_eq!(register("alice", Some(&typed)).await.status().as_u16(), 201);
+        // A refused registration does not use the code up.
+        assert_eq!(register("alice", Some(&invite.code)).await.status().as_u16(), 409);
+        assert_eq!(register("bob", Some(&invite.code)).await.status().as_u16(), 201);
+        assert_eq!(error(register("carol", Some(&invite.code)).await).await, "invite_exhausted");
+
+        let req = test::TestRequest::get().uri("/auth/invites").insert_header(bearer(&session.token)).to_request();
+        let invites: Vec<Invite> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].uses, 2);
+
+        let resp = post_json(&app, "/auth/invites", Some(&session.token), json!({ "expires_in": 1 })).await;
+        let expiring: CreatedInvite = test::read_body_json(resp).await;
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert_eq!(error(register("carol", Some(&expiring.code)).await).await, "invite_expired");
+
+        let resp = post_json(&app, "/auth/invites", Some(&session.token), json!({})).await;
+        let deleted: CreatedInvite = test::read_body_json(resp).await;
+        let uri = format!("/auth/invites/{}", deleted.details.id);
+        let req = test::TestRequest::delete().uri(&uri).insert_header(bearer(&session.token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 204);
+        assert_eq!(error(register("carol", Some(&deleted.code)).await).await, "invite_invalid");
+
+        // Only admins manage invites.
+        let resp = post_json(&app, "/auth/login", None, json!({ "username": "alice", "password": "password123" })).await;
+        let alice: AuthResponse = test::read_body_json(resp).await;
+        let resp = post_json(&app, "/auth/invites", Some(&alice.token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 403);
+    }
+
+    #[actix_rt::test]
+    async fn test_domain_allowlist_registration() {
+        let service = web::Data::new(AuthService::new(
+            AuthConfig {
+                hash_cost: 4,
+                registration_mode: RegistrationMode::DomainAllowlist,
+                registration_allowed_domains: vec!["test.com".to_string()],
+                ..AuthConfig::default()
+            },
+            Arc::new(InMemoryUserRepository::new()),
+        ));
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        let resp = post_json(&app, "/auth/register", None, json!({
+            "username": "eve", "email": "eve@example.org", "password": "password123",
+        })).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "domain_not_allowed");
+
+        let resp = post_json(&app, "/auth/register", None, json!({
+            "username": "alice", "email": "alice@staff.test.com", "password": "password123",
+        })).await;
+        assert_eq!(resp.status().as_u16(), 201);
+    }
+
+    async fn login(app: &impl actix_web::dev::Service<
+        actix_http::Request, Response = actix_web::dev::ServiceResponse, Error = actix_web::Error
+    >) -> AuthResponse {
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": "testuser", "password": "password123" }))
+            .to_request();
+        test::call_and_read_body_json(app, req).await
+    }
+
+    fn refresh_request(refresh_token: &str) -> actix_http::Request {
+        test::TestRequest::post()
+            .uri("/auth/refresh")
+            .set_json(json!({ "refresh_token": refresh_token }))
+            .to_request()
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_rotates_tokens() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        let first = login(&app).await;
+        let resp = test::call_service(&app, refresh_request(&first.refresh_token)).await;
+        assert!(resp.status().is_success());
+
+        let second: AuthResponse = test::read_body_json(resp).await;
+        assert_ne!(second.refresh_token, first.refresh_token);
+        assert_eq!(second.user_id, first.user_id);
+        assert!(service.verify_token(&second.token).await.is_ok());
+
+        // The rotated token keeps working.
+        let resp = test::call_service(&app, refresh_request(&second.refresh_token)).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_reuse_revokes_family() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service)).configure(configure)).await;
+
+        let first = login(&app).await;
+        let other_session = login(&app).await;
+        let second: AuthResponse = test::call_and_read_body_json(&app, refresh_request(&first.refresh_token)).await;
+
+        // Replaying the consumed token is rejected...
+        let resp = test::call_service(&app, refresh_request(&first.refresh_token)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // ...and takes down the token that replaced it.
+        let resp = test::call_service(&app, refresh_request(&second.refresh_token)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // Other logins are unaffected.
+        let resp = test::call_service(&app, refresh_request(&other_session.refresh_token)).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_rt::test]
+    async fn test_refresh_rejects_unknown_and_expired_tokens() {
+        let config = AuthConfig {
+            jwt_secret: "test-secret".to_string(),
+            hash_cost: 4,
+            refresh_token_expiration: 0,
+            ..AuthConfig::default()
+        };
+        let service = web::Data::new(AuthService::new(config, Arc::new(InMemoryUserRepository::new())));
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service)).configure(configure)).await;
+
+        let resp = test::call_service(&app, refresh_request("not-a-refresh-token")).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let expired = login(&app).await;
+        let resp = test::call_service(&app, refresh_request(&expired.refresh_token)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
     #[actix_rt::test]
     async fn test_logout_without_token() {
         let app = test::init_service(
-            App::new().service(web::scope("/auth").route("/logout", web::post().to(handlers::logout)))
+            App::new()
+                .configure(configure_state(auth_service()))
+                .service(web::scope("/auth").route("/logout", web::post().to(handlers::logout)))
         ).await;
 
         let req = test::TestRequest::post()
@@ -110,20 +539,1411 @@ pub mod tests {
 
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status().as_u16(), 401); // Unauthorized
+        assert!(resp.headers().contains_key("WWW-Authenticate"));
+    }
+
+    #[actix_rt::test]
+    async fn test_logout_with_invalid_token() {
+        let app = test::init_service(
+            App::new().configure(configure_state(auth_service())).configure(configure)
+        ).await;
+
+        let req = test::TestRequest::post()
+            .uri("/auth/logout")
+            .insert_header(("Authorization", "Bearer not-a-jwt"))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 401);
     }
 
     #[actix_rt::test]
     async fn test_logout_with_token() {
+        let service = auth_service();
+        let (token, _) = service.tokens().issue(1, &[Role::User], &[]).unwrap();
         let app = test::init_service(
-            App::new().service(web::scope("/auth").route("/logout", web::post().to(handlers::logout)))
+            App::new()
+                .configure(configure_state(service))
+                .service(web::scope("/auth").route("/logout", web::post().to(handlers::logout)))
         ).await;
 
         let req = test::TestRequest::post()
             .uri("/auth/logout")
-            .insert_header(("Authorization", "Bearer valid-token"))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+    }
+
+    fn bearer(token: &str) -> (&'static str, String) {
+        ("Authorization", format!("Bearer {}", token))
+    }
+
+    #[actix_rt::test]
+    async fn test_logout_revokes_token() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        let session = login(&app).await;
+        let other = login(&app).await;
+
+        let req = test::TestRequest::post()
+            .uri("/auth/logout")
+            .insert_header(bearer(&session.token))
+            .set_json(json!({ "refresh_token": session.refresh_token }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        // The extractor now rejects the token...
+        let req = test::TestRequest::post()
+            .uri("/auth/logout")
+            .insert_header(bearer(&session.token))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let challenge = resp.headers().get("WWW-Authenticate").unwrap().to_str().unwrap();
+        assert!(challenge.contains("invalid_token"));
+
+        // ...the refresh token of the same login is gone...
+        let resp = test::call_service(&app, refresh_request(&session.refresh_token)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // ...and other logins keep working.
+        assert!(service.verify_token(&other.token).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_logout_all_revokes_every_session() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        register_user(&service, "bystander").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        let first = login(&app).await;
+        let second = login(&app).await;
+        let bystander: AuthResponse = test::call_and_read_body_json(&app, test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": "bystander", "password": "password123" }))
+            .to_request()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/auth/logout/all")
+            .insert_header(bearer(&first.token))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        for session in [&first, &second] {
+            assert!(matches!(service.verify_token(&session.token).await, Err(AuthError::TokenRevoked)));
+            let resp = test::call_service(&app, refresh_request(&session.refresh_token)).await;
+            assert_eq!(resp.status().as_u16(), 401);
+        }
+        assert!(service.verify_token(&bystander.token).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_update_roles_requires_users_manage() {
+        let service = auth_service();
+        let user = register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service)).configure(configure)).await;
+
+        let session = login(&app).await;
+        let req = test::TestRequest::put()
+            .uri(&format!("/auth/users/{}/roles", user.id))
+            .insert_header(bearer(&session.token))
+            .set_json(json!({ "roles": ["admin"] }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 403);
+
+        let req = test::TestRequest::put()
+            .uri(&format!("/auth/users/{}/roles", user.id))
+            .set_json(json!({ "roles": ["admin"] }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 401);
+    }
+
+    #[actix_rt::test]
+    async fn test_admin_updates_roles() {
+        let service = auth_service();
+        let admin = register_user(&service, "admin").await;
+        let user = register_user(&service, "testuser").await;
+        service.users().update_roles(admin.id, vec![Role::Admin], Vec::new()).await.unwrap();
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        let admin_session: AuthResponse = test::call_and_read_body_json(&app, test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": "admin", "password": "password123" }))
+            .to_request()).await;
+        let user_session = login(&app).await;
+
+        let req = test::TestRequest::put()
+            .uri(&format!("/auth/users/{}/roles", user.id))
+            .insert_header(bearer(&admin_session.token))
+            .set_json(json!({ "roles": ["user", "moderator"], "permissions": ["addons:manage"] }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["roles"], json!(["user", "moderator"]));
+
+        // Old tokens are cut off; refreshing yields one with the new permissions.
+        assert!(matches!(service.verify_token(&user_session.token).await, Err(AuthError::TokenRevoked)));
+        // The cutoff has one-second resolution.
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let refreshed: AuthResponse = test::call_and_read_body_json(&app, refresh_request(&user_session.refresh_token)).await;
+        let claims = service.verify_token(&refreshed.token).await.unwrap();
+        assert_eq!(claims.roles, vec![Role::User, Role::Moderator]);
+        assert!(claims.perms.contains(&permissions::POSTS_DELETE_ANY.to_string()));
+        assert!(claims.perms.contains(&permissions::ADDONS_MANAGE.to_string()));
+        assert!(!claims.perms.contains(&permissions::USERS_MANAGE.to_string()));
+
+        for (uri, payload, status) in [
+            (format!("/auth/users/{}/roles", user.id), json!({ "roles": ["user"], "permissions": ["everything"] }), 400),
+            (format!("/auth/users/{}/roles", user.id), json!({ "roles": ["superuser"] }), 400),
+            ("/auth/users/999/roles".to_string(), json!({ "roles": ["user"] }), 404),
+        ] {
+            let req = test::TestRequest::put()
+                .uri(&uri)
+                .insert_header(bearer(&admin_session.token))
+                .set_json(payload)
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status().as_u16(), status);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_audit_log() {
+        let service = auth_service();
+        let admin = register_user(&service, "admin").await;
+        let user = register_user(&service, "testuser").await;
+        service.users().update_roles(admin.id, vec![Role::Admin], Vec::new()).await.unwrap();
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        let attempt = |password: &str| test::TestRequest::post()
+            .uri("/auth/login")
+            .peer_addr("203.0.113.7:40000".parse().unwrap())
+            .insert_header(("User-Agent", "audit-test"))
+            .set_json(json!({ "username": "testuser", "password": password }))
             .to_request();
+        assert_eq!(test::call_service(&app, attempt("wrong")).await.status().as_u16(), 401);
+        let session: AuthResponse = test::call_and_read_body_json(&app, attempt("password123")).await;
+        let resp = post_json(&app, "/auth/tokens", Some(&session.token), json!({ "name": "ci", "scopes": ["posts:create"] })).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        // Only admins read the log.
+        for uri in ["/auth/audit", "/auth/audit/export"] {
+            let req = test::TestRequest::get().uri(uri).insert_header(bearer(&session.token)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status().as_u16(), 403);
+        }
+        let admin_session: AuthResponse = test::call_and_read_body_json(&app, test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": "admin", "password": "password123" }))
+            .to_request()).await;
+        let req = test::TestRequest::put()
+            .uri(&format!("/auth/users/{}/roles", user.id))
+            .insert_header(bearer(&admin_session.token))
+            .set_json(json!({ "roles": ["user", "moderator"] }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let query = |params: &str| test::TestRequest::get()
+            .uri(&format!("/auth/audit?{}", params))
+            .insert_header(bearer(&admin_session.token))
+            .to_request();
+        let page: AuditPage = test::call_and_read_body_json(&app, query("")).await;
+        let actions: Vec<_> = page.events.iter().map(|e| e.action).collect();
+        assert_eq!(actions, vec![
+            AuditAction::RolesUpdated,
+            AuditAction::Login,
+            AuditAction::AccessTokenCreated,
+            AuditAction::Login,
+            AuditAction::Login,
+        ]);
+        assert_eq!(page.next_cursor, None);
+
+        let roles = &page.events[0];
+        assert_eq!((roles.actor_id, roles.target_id), (Some(admin.id), Some(user.id)));
+        assert_eq!(roles.details["roles"], json!(["user", "moderator"]));
+
+        let page: AuditPage = test::call_and_read_body_json(&app, query("action=login&outcome=failure")).await;
+        assert_eq!(page.events.len(), 1);
+        let failed = &page.events[0];
+        assert_eq!(failed.actor_id, None);
+        assert_eq!(failed.target_id, Some(user.id));
+        assert_eq!(failed.ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(failed.user_agent.as_deref(), Some("audit-test"));
+        assert_eq!(failed.details["username"], "testuser");
+
+        let page: AuditPage = test::call_and_read_body_json(&app, query(&format!("actor_id={}", user.id))).await;
+        assert_eq!(page.events.iter().map(|e| e.action).collect::<Vec<_>>(), vec![
+            AuditAction::AccessTokenCreated,
+            AuditAction::Login,
+        ]);
+        assert_eq!(test::call_service(&app, query("action=unknown")).await.status().as_u16(), 400);
 
+        // Pages follow the cursor without gaps or repeats.
+        let mut ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let params = match cursor {
+                Some(cursor) => format!("limit=2&cursor={}", cursor),
+                None => "limit=2".to_string(),
+            };
+            let page: AuditPage = test::call_and_read_body_json(&app, query(&params)).await;
+            ids.extend(page.events.iter().map(|e| e.id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(ids, vec![5, 4, 3, 2, 1]);
+
+        let req = test::TestRequest::get()
+            .uri("/auth/audit/export?action=login")
+            .insert_header(bearer(&admin_session.token))
+            .to_request();
         let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("content-type").unwrap(), "application/x-ndjson");
+        let body = test::read_body(resp).await;
+        let lines: Vec<AuditEvent> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|e| e.action == AuditAction::Login));
+    }
+
+    async fn post_json(
+        app: &impl actix_web::dev::Service<
+            actix_http::Request, Response = actix_web::dev::ServiceResponse, Error = actix_web::Error
+        >,
+        uri: &str,
+        token: Option<&str>,
+        body: serde_json::Value,
+    ) -> actix_web::dev::ServiceResponse {
+        let mut req = test::TestRequest::post().uri(uri).set_json(body);
+        if let Some(token) = token {
+            req = req.insert_header(bearer(token));
+        }
+        test::call_service(app, req.to_request()).await
+    }
+
+    /// Enrolls and confirms 2FA for testuser; returns the secret and recovery codes.
+    async fn enable_two_factor(
+        app: &impl actix_web::dev::Service<
+            actix_http::Request, Response = actix_web::dev::ServiceResponse, Error = actix_web::Error
+        >,
+        token: &str,
+    ) -> (Vec<u8>, Vec<String>) {
+        let resp = post_json(app, "/auth/2fa/enroll", Some(token), json!({})).await;
+        assert!(resp.status().is_success());
+        let enrollment: TwoFactorEnrollment = test::read_body_json(resp).await;
+        assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/socialhub:testuser?secret="));
+        assert_eq!(enrollment.recovery_codes.len(), 10);
+        let secret = crate::totp::tests::base32_decode(&enrollment.secret);
+
+        let resp = post_json(app, "/auth/2fa/confirm", Some(token), json!({ "code": "000000x" })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let code = crate::totp::tests::current_code(&secret, 0);
+        let resp = post_json(app, "/auth/2fa/confirm", Some(token), json!({ "code": code })).await;
+        assert!(resp.status().is_success());
+
+        (secret, enrollment.recovery_codes)
+    }
+
+    async fn login_challenge(
+        app: &impl actix_web::dev::Service<
+            actix_http::Request, Response = actix_web::dev::ServiceResponse, Error = actix_web::Error
+        >,
+    ) -> TwoFactorChallenge {
+        let resp = post_json(app, "/auth/login", None, json!({ "username": "testuser", "password": "password123" })).await;
         assert!(resp.status().is_success());
+        test::read_body_json(resp).await
+    }
+
+    #[actix_rt::test]
+    async fn test_two_factor_login() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service)).configure(configure)).await;
+
+        let session = login(&app).await;
+        let (secret, recovery_codes) = enable_two_factor(&app, &session.token).await;
+
+        // The password alone now only yields a challenge, which is no access token.
+        let challenge = login_challenge(&app).await;
+        assert_eq!(challenge.expires_in, 300);
+        let resp = post_json(&app, "/auth/logout", Some(&challenge.challenge_token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // The code used for confirming cannot be replayed.
+        let replayed = crate::totp::tests::current_code(&secret, 0);
+        let resp = post_json(&app, "/auth/login/2fa", None, json!({
+            "challenge_token": challenge.challenge_token, "code": replayed
+        })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let next = crate::totp::tests::current_code(&secret, 1);
+        let resp = post_json(&app, "/auth/login/2fa", None, json!({
+            "challenge_token": challenge.challenge_token, "code": next
+        })).await;
+        assert!(resp.status().is_success());
+        let tokens: AuthResponse = test::read_body_json(resp).await;
+        assert!(!tokens.refresh_token.is_empty());
+
+        // Challenges are single-use.
+        let resp = post_json(&app, "/auth/login/2fa", None, json!({
+            "challenge_token": challenge.challenge_token, "code": recovery_codes[0]
+        })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // Recovery codes work once, in any case and with or without the dash.
+        let challenge = login_challenge(&app).await;
+        let resp = post_json(&app, "/auth/login/2fa", None, json!({
+            "challenge_token": challenge.challenge_token, "code": recovery_codes[1].to_uppercase().replace('-', "")
+        })).await;
+        assert!(resp.status().is_success());
+        let challenge = login_challenge(&app).await;
+        let resp = post_json(&app, "/auth/login/2fa", None, json!({
+            "challenge_token": challenge.challenge_token, "code": recovery_codes[1]
+        })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_rt::test]
+    async fn test_two_factor_challenge_attempt_limit() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service)).configure(configure)).await;
+
+        let session = login(&app).await;
+        let (secret, _) = enable_two_factor(&app, &session.token).await;
+        let challenge = login_challenge(&app).await;
+
+        for _ in 0..5 {
+            let resp = post_json(&app, "/auth/login/2fa", None, json!({
+                "challenge_token": challenge.challenge_token, "code": "not-a-code"
+            })).await;
+            assert_eq!(resp.status().as_u16(), 401);
+        }
+
+        let code = crate::totp::tests::current_code(&secret, 1);
+        let resp = post_json(&app, "/auth/login/2fa", None, json!({
+            "challenge_token": challenge.challenge_token, "code": code
+        })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_rt::test]
+    async fn test_two_factor_enable_and_disable() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service)).configure(configure)).await;
+
+        let session = login(&app).await;
+        let resp = post_json(&app, "/auth/2fa/disable", Some(&session.token), json!({ "code": "123456" })).await;
+        assert_eq!(resp.status().as_u16(), 400);
+        let resp = post_json(&app, "/auth/2fa/confirm", Some(&session.token), json!({ "code": "123456" })).await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        let (_, recovery_codes) = enable_two_factor(&app, &session.token).await;
+        let resp = post_json(&app, "/auth/2fa/enroll", Some(&session.token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 409);
+
+        let resp = post_json(&app, "/auth/2fa/disable", Some(&session.token), json!({ "code": "wrong-code" })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = post_json(&app, "/auth/2fa/disable", Some(&session.token), json!({ "code": recovery_codes[0] })).await;
+        assert!(resp.status().is_success());
+
+        // Back to plain password logins.
+        let tokens = login(&app).await;
+        assert!(!tokens.token.is_empty());
+    }
+
+    /// Keeps sent emails so tests can follow the links in them.
+    #[derive(Default)]
+    struct RecordingMailer {
+        sent: std::sync::Mutex<Vec<mailer::Email>>,
+    }
+
+    impl RecordingMailer {
+        /// Token from the link of the last email to `to` with `subject` in its subject line.
+        fn token(&self, to: &str, subject: &str) -> Option<String> {
+            let sent = self.sent.lock().unwrap();
+            let email = sent.iter().rev().find(|e| e.to == to && e.subject.contains(subject))?;
+            let (_, rest) = email.body.split_once("?token=")?;
+            Some(rest.split_whitespace().next()?.to_string())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Mailer for RecordingMailer {
+        async fn send(&self, email: mailer::Email) -> Result<(), AuthError> {
+            self.sent.lock().unwrap().push(email);
+            Ok(())
+        }
+    }
+
+    fn mailing_auth_service(config: AuthConfig) -> (web::Data<AuthService>, Arc<RecordingMailer>) {
+        let mailer = Arc::new(RecordingMailer::default());
+        let config = AuthConfig {
+            jwt_secret: "test-secret".to_string(),
+            hash_cost: 4,
+            public_url: "https://socialhub.test".to_string(),
+            ..config
+        };
+        let service = AuthService::new(config, Arc::new(InMemoryUserRepository::new()))
+            .with_mailer(mailer.clone());
+        (web::Data::new(service), mailer)
+    }
+
+    #[actix_rt::test]
+    async fn test_password_reset() {
+        let (service, mailer) = mailing_auth_service(AuthConfig::default());
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+
+        // Unknown addresses get the same answer, but no mail.
+        let resp = post_json(&app, "/auth/password/forgot", None, json!({ "email": "nobody@test.com" })).await;
+        assert_eq!(resp.status().as_u16(), 202);
+        assert!(mailer.token("nobody@test.com", "password").is_none());
+
+        let resp = post_json(&app, "/auth/password/forgot", None, json!({ "email": "TestUser@test.com" })).await;
+        assert_eq!(resp.status().as_u16(), 202);
+        let token = mailer.token("testuser@test.com", "password").unwrap();
+        assert!(mailer.sent.lock().unwrap().last().unwrap().body.contains("https://socialhub.test/reset-password?token="));
+
+        let resp = post_json(&app, "/auth/password/reset", None, json!({ "token": token, "new_password": "short" })).await;
+        assert_eq!(resp.status().as_u16(), 400);
+        let resp = post_json(&app, "/auth/password/reset", None, json!({ "token": "forged", "new_password": "new-password" })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = post_json(&app, "/auth/password/reset", None, json!({ "token": token, "new_password": "new-password" })).await;
+        assert!(resp.status().is_success());
+
+        // Tokens are single-use, and the reset ends every session.
+        let resp = post_json(&app, "/auth/password/reset", None, json!({ "token": token, "new_password": "other-password" })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        assert!(matches!(service.verify_token(&session.token).await, Err(AuthError::TokenRevoked)));
+        let resp = test::call_service(&app, refresh_request(&session.refresh_token)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = post_json(&app, "/auth/login", None, json!({ "username": "testuser", "password": "password123" })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = post_json(&app, "/auth/login", None, json!({ "username": "testuser", "password": "new-password" })).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_rt::test]
+    async fn test_password_reset_deletes_access_tokens() {
+        let (service, mailer) = mailing_auth_service(AuthConfig::default());
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+        let resp = post_json(&app, "/auth/tokens", Some(&session.token), json!({ "name": "ci", "scopes": ["posts:create"] })).await;
+        let created: CreatedAccessToken = test::read_body_json(resp).await;
+        let tokens = || test::TestRequest::get().uri("/auth/tokens").insert_header(bearer(&created.token)).to_request();
+        // Valid, though it cannot list tokens.
+        assert_eq!(test::call_service(&app, tokens()).await.status().as_u16(), 403);
+
+        post_json(&app, "/auth/password/forgot", None, json!({ "email": "testuser@test.com" })).await;
+        let token = mailer.token("testuser@test.com", "password").unwrap();
+        let resp = post_json(&app, "/auth/password/reset", None, json!({ "token": token, "new_password": "new-password" })).await;
+        assert!(resp.status().is_success());
+
+        assert_eq!(test::call_service(&app, tokens()).await.status().as_u16(), 401);
+    }
+
+    #[actix_rt::test]
+    async fn test_access_tokens_unusable_pending_deletion() {
+        let service = web::Data::new(AuthService::new(
+            AuthConfig { hash_cost: 4, ..AuthConfig::default() },
+            Arc::new(InMemoryUserRepository::new()),
+        ));
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+        let resp = post_json(&app, "/auth/tokens", Some(&session.token), json!({ "name": "ci", "scopes": ["posts:create"] })).await;
+        let created: CreatedAccessToken = test::read_body_json(resp).await;
+        let tokens = || test::TestRequest::get().uri("/auth/tokens").insert_header(bearer(&created.token)).to_request();
+
+        let req = test::TestRequest::delete()
+            .uri("/auth/account")
+            .insert_header(bearer(&session.token))
+            .set_json(json!({ "password": "password123" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 202);
+        assert_eq!(test::call_service(&app, tokens()).await.status().as_u16(), 401);
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let session = login(&app).await;
+        let resp = post_json(&app, "/auth/account/restore", Some(&session.token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        assert_eq!(test::call_service(&app, tokens()).await.status().as_u16(), 403);
+    }
+
+    #[actix_rt::test]
+    async fn test_password_reset_tokens_expire_and_are_revoked() {
+        let (service, mailer) = mailing_auth_service(AuthConfig { password_reset_expiration: 0, ..AuthConfig::default() });
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        post_json(&app, "/auth/password/forgot", None, json!({ "email": "testuser@test.com" })).await;
+        let expired = mailer.token("testuser@test.com", "password").unwrap();
+        let resp = post_json(&app, "/auth/password/reset", None, json!({ "token": expired, "new_password": "new-password" })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        // Using one link invalidates the others.
+        let (service, mailer) = mailing_auth_service(AuthConfig::default());
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service)).configure(configure)).await;
+        post_json(&app, "/auth/password/forgot", None, json!({ "email": "testuser@test.com" })).await;
+        let first = mailer.token("testuser@test.com", "password").unwrap();
+        post_json(&app, "/auth/password/forgot", None, json!({ "email": "testuser@test.com" })).await;
+        let second = mailer.token("testuser@test.com", "password").unwrap();
+        assert_ne!(first, second);
+
+        let resp = post_json(&app, "/auth/password/reset", None, json!({ "token": second, "new_password": "new-password" })).await;
+        assert!(resp.status().is_success());
+        let resp = post_json(&app, "/auth/password/reset", None, json!({ "token": first, "new_password": "other-password" })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    #[actix_rt::test]
+    async fn test_email_verification() {
+        let (service, mailer) = mailing_auth_service(AuthConfig::default());
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        let resp = post_json(&app, "/auth/register", None, json!({
+            "username": "testuser", "email": "testuser@test.com", "password": "password123"
+        })).await;
+        let user: User = test::read_body_json(resp).await;
+        assert!(!user.email_verified);
+        let first = mailer.token("testuser@test.com", "Confirm").unwrap();
+        assert!(mailer.sent.lock().unwrap()[0].body.contains("https://socialhub.test/verify-email?token="));
+
+        // Asking again replaces the first link.
+        let session = login(&app).await;
+        let resp = post_json(&app, "/auth/email/verify/send", None, json!({})).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = post_json(&app, "/auth/email/verify/send", Some(&session.token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 202);
+        let second = mailer.token("testuser@test.com", "Confirm").unwrap();
+        let resp = post_json(&app, "/auth/email/verify", None, json!({ "token": first })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = post_json(&app, "/auth/email/verify", None, json!({ "token": second })).await;
+        assert!(resp.status().is_success());
+        assert!(service.users().find_by_id(user.id).await.unwrap().unwrap().email_verified);
+
+        let resp = post_json(&app, "/auth/email/verify", None, json!({ "token": second })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = post_json(&app, "/auth/email/verify/send", Some(&session.token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 409);
+    }
+
+    #[actix_rt::test]
+    async fn test_login_lockout() {
+        let config = AuthConfig {
+            jwt_secret: "test-secret".to_string(),
+            hash_cost: 4,
+            login_max_failures: 3,
+            login_max_failures_per_ip: 4,
+            ..AuthConfig::default()
+        };
+        let service = web::Data::new(AuthService::new(config, Arc::new(InMemoryUserRepository::new())));
+        register_user(&service, "testuser").await;
+        register_user(&service, "other").await;
+        let app = test::init_service(App::new().configure(configure_state(service)).configure(configure)).await;
+
+        let attempt = |username: &str, password: &str, ip: &str| test::TestRequest::post()
+            .uri("/auth/login")
+            .peer_addr(format!("{}:40000", ip).parse().unwrap())
+            .set_json(json!({ "username": username, "password": password }))
+            .to_request();
+
+        for _ in 0..3 {
+            let resp = test::call_service(&app, attempt("testuser", "wrong-password", "10.0.0.1")).await;
+            assert_eq!(resp.status().as_u16(), 401);
+        }
+
+        // Locked, even with the right password and from elsewhere.
+        let resp = test::call_service(&app, attempt("testuser", "password123", "10.0.0.2")).await;
+        assert_eq!(resp.status().as_u16(), 429);
+        let retry_after: u64 = resp.headers().get("Retry-After").unwrap().to_str().unwrap().parse().unwrap();
+        assert!((1..=30).contains(&retry_after));
+
+        // One more failure from the first address locks it for every username.
+        let resp = test::call_service(&app, attempt("other", "wrong-password", "10.0.0.1")).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let resp = test::call_service(&app, attempt("other", "password123", "10.0.0.1")).await;
+        assert_eq!(resp.status().as_u16(), 429);
+        let resp = test::call_service(&app, attempt("other", "password123", "10.0.0.3")).await;
+        assert!(resp.status().is_success());
+    }
+
+    async fn whoami(user: AuthenticatedUser) -> HttpResponse {
+        HttpResponse::Ok().json(user)
+    }
+
+    #[actix_rt::test]
+    async fn test_personal_access_tokens() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        let app = test::init_service(
+            App::new()
+                .configure(configure_state(service))
+                .configure(configure)
+                .route("/whoami", web::get().to(whoami))
+        ).await;
+        let session = login(&app).await;
+
+        for scopes in [json!([]), json!(["users:manage"]), json!(["everything"])] {
+            let resp = post_json(&app, "/auth/tokens", Some(&session.token), json!({ "name": "ci", "scopes": scopes })).await;
+            assert_eq!(resp.status().as_u16(), 400);
+        }
+        let resp = post_json(&app, "/auth/tokens", None, json!({ "name": "ci", "scopes": ["posts:create"] })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let resp = post_json(&app, "/auth/tokens", Some(&session.token), json!({
+            "name": "ci", "scopes": ["posts:create", "streams:start"]
+        })).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let created: serde_json::Value = test::read_body_json(resp).await;
+        let token = created["token"].as_str().unwrap().to_string();
+        assert!(token.starts_with("shp_"));
+        assert!(created["expires_at"].is_null());
+
+        // The token only carries its scopes and cannot manage tokens or the account.
+        let req = test::TestRequest::get().uri("/whoami").insert_header(bearer(&token)).to_request();
+        let caller: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(caller["permissions"], json!(["posts:create", "streams:start"]));
+        assert_eq!(caller["token_kind"], "personal_access_token");
+        let resp = post_json(&app, "/auth/tokens", Some(&token), json!({ "name": "x", "scopes": ["posts:create"] })).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = post_json(&app, "/auth/logout/all", Some(&token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let req = test::TestRequest::get().uri("/auth/tokens").insert_header(bearer(&session.token)).to_request();
+        let listed: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        assert_eq!(listed[0]["name"], "ci");
+        assert!(listed[0]["last_used_at"].is_u64());
+        assert!(listed[0].get("token").is_none());
+
+        let uri = format!("/auth/tokens/{}", created["id"].as_str().unwrap());
+        let req = test::TestRequest::delete().uri(&uri).insert_header(bearer(&session.token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 204);
+        let req = test::TestRequest::delete().uri(&uri).insert_header(bearer(&session.token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 404);
+
+        let req = test::TestRequest::get().uri("/whoami").insert_header(bearer(&token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 401);
+    }
+
+    #[actix_rt::test]
+    async fn test_personal_access_token_limits() {
+        let service = auth_service();
+        let user = register_user(&service, "testuser").await;
+        service.users().update_roles(user.id, vec![Role::Moderator], Vec::new()).await.unwrap();
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+
+        let resp = post_json(&app, "/auth/tokens", Some(&session.token), json!({
+            "name": "moderation-bot", "scopes": ["posts:delete_any"]
+        })).await;
+        let lasting: CreatedAccessToken = test::read_body_json(resp).await;
+        let resp = post_json(&app, "/auth/tokens", Some(&session.token), json!({
+            "name": "short", "scopes": ["posts:create"], "expires_in": 1
+        })).await;
+        let short: CreatedAccessToken = test::read_body_json(resp).await;
+        assert!(short.details.expires_at.is_some());
+        assert!(service.verify(&short.token).await.is_ok());
+
+        // Losing a permission takes it away from existing tokens too.
+        service.users().update_roles(user.id, vec![Role::User], Vec::new()).await.unwrap();
+        let caller = service.verify(&lasting.token).await.unwrap();
+        assert!(caller.permissions.is_empty());
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert!(matches!(service.verify(&short.token).await, Err(AuthRejection::TokenExpired)));
+        assert!(matches!(service.verify("shp_unknown").await, Err(AuthRejection::InvalidToken)));
+    }
+
+    #[actix_rt::test]
+    async fn test_impersonation() {
+        let service = auth_service();
+        let admin = register_user(&service, "admin").await;
+        let user = register_user(&service, "testuser").await;
+        service.users().update_roles(admin.id, vec![Role::Admin], Vec::new()).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .configure(configure_state(service.clone()))
+                .configure(configure)
+                .route("/whoami", web::get().to(whoami))
+        ).await;
+        let admin_session: AuthResponse = test::call_and_read_body_json(&app, test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": "admin", "password": "password123" }))
+            .to_request()).await;
+        let user_session = login(&app).await;
+
+        let uri = format!("/auth/users/{}/impersonate", user.id);
+        let resp = post_json(&app, &uri, Some(&admin_session.token), json!({ "reason": "ticket 42" })).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let impersonation: Impersonation = test::read_body_json(resp).await;
+        assert_eq!(impersonation.expires_in, 900);
+
+        let claims = service.verify_token(&impersonation.token).await.unwrap();
+        assert_eq!(claims.user_id().unwrap(), user.id);
+        assert_eq!(claims.actor_id().unwrap(), Some(admin.id));
+        assert_eq!(claims.exp - claims.iat, 900);
+        let req = test::TestRequest::get().uri("/whoami").insert_header(bearer(&impersonation.token)).to_request();
+        let caller: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(caller["user_id"], user.id);
+        assert_eq!(caller["actor_id"], admin.id);
+        assert_eq!(caller["token_kind"], "impersonation");
+
+        // No password, 2FA or token changes, and no further impersonation.
+        for (uri, body) in [
+            ("/auth/tokens", json!({ "name": "ci", "scopes": ["posts:create"] })),
+            ("/auth/2fa/enroll", json!({})),
+            ("/auth/logout/all", json!({})),
+            (uri.as_str(), json!({})),
+        ] {
+            let resp = post_json(&app, uri, Some(&impersonation.token), body).await;
+            assert_eq!(resp.status().as_u16(), 403, "{}", uri);
+        }
+        let req = test::TestRequest::delete()
+            .uri("/auth/account")
+            .insert_header(bearer(&impersonation.token))
+            .set_json(json!({ "password": "password123" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 403);
+
+        // The user sees the session and can end it.
+        let req = test::TestRequest::get().uri("/auth/sessions").insert_header(bearer(&user_session.token)).to_request();
+        let sessions: Vec<Session> = test::call_and_read_body_json(&app, req).await;
+        let session = sessions.iter().find(|s| s.id == impersonation.session_id).unwrap();
+        assert_eq!(session.impersonator_id, Some(admin.id));
+        let req = test::TestRequest::delete()
+            .uri(&format!("/auth/sessions/{}", impersonation.session_id))
+            .insert_header(bearer(&user_session.token))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 204);
+        assert!(matches!(service.verify_token(&impersonation.token).await, Err(AuthError::TokenRevoked)));
+
+        let page = service.audit_events(&AuditQuery {
+            action: Some(AuditAction::ImpersonationStarted),
+            ..AuditQuery::default()
+        }).await.unwrap();
+        assert_eq!(page.events.len(), 1);
+        assert_eq!((page.events[0].actor_id, page.events[0].target_id), (Some(admin.id), Some(user.id)));
+        assert_eq!(page.events[0].details["reason"], "ticket 42");
+
+        // Not oneself, nor anyone who may impersonate, nor as a plain user.
+        for (uri, token, status) in [
+            (format!("/auth/users/{}/impersonate", admin.id), &admin_session.token, 403),
+            ("/auth/users/999/impersonate".to_string(), &admin_session.token, 404),
+            (format!("/auth/users/{}/impersonate", admin.id), &user_session.token, 403),
+        ] {
+            assert_eq!(post_json(&app, &uri, Some(token), json!({})).await.status().as_u16(), status, "{}", uri);
+        }
+        let other = register_user(&service, "other").await;
+        service.users().update_roles(other.id, vec![Role::Admin], Vec::new()).await.unwrap();
+        let uri = format!("/auth/users/{}/impersonate", other.id);
+        assert_eq!(post_json(&app, &uri, Some(&admin_session.token), json!({})).await.status().as_u16(), 403);
+    }
+
+    #[actix_rt::test]
+    async fn test_impersonation_logout() {
+        let service = auth_service();
+        let admin = register_user(&service, "admin").await;
+        let user = register_user(&service, "testuser").await;
+        service.users().update_roles(admin.id, vec![Role::Admin], Vec::new()).await.unwrap();
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let admin_session: AuthResponse = test::call_and_read_body_json(&app, test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({ "username": "admin", "password": "password123" }))
+            .to_request()).await;
+        let user_session = login(&app).await;
+
+        let uri = format!("/auth/users/{}/impersonate", user.id);
+        let impersonation: Impersonation = test::read_body_json(
+            post_json(&app, &uri, Some(&admin_session.token), json!({})).await
+        ).await;
+
+        // The impersonation ends its own session, and only that one.
+        let resp = post_json(&app, "/auth/logout", Some(&impersonation.token), json!({ "refresh_token": user_session.refresh_token })).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert!(matches!(service.verify_token(&impersonation.token).await, Err(AuthError::TokenRevoked)));
+        let req = test::TestRequest::get().uri("/auth/sessions").insert_header(bearer(&user_session.token)).to_request();
+        let sessions: Vec<Session> = test::call_and_read_body_json(&app, req).await;
+        assert!(sessions.iter().all(|s| s.id != impersonation.session_id));
+        assert_eq!(sessions.len(), 1);
+        let resp = post_json(&app, "/auth/refresh", None, json!({ "refresh_token": user_session.refresh_token })).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let page = service.audit_events(&AuditQuery {
+            action: Some(AuditAction::Logout),
+            ..AuditQuery::default()
+        }).await.unwrap();
+        assert_eq!(page.events.len(), 1);
+        assert_eq!((page.events[0].actor_id, page.events[0].target_id), (Some(admin.id), Some(user.id)));
+    }
+
+    #[actix_rt::test]
+    async fn test_admin_endpoints_require_session() {
+        let service = auth_service();
+        let admin = register_user(&service, "admin").await;
+        let user = register_user(&service, "testuser").await;
+        service.users().update_roles(admin.id, vec![Role::Admin], Vec::new()).await.unwrap();
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        // An app holding the admin's powers still cannot use them here.
+        let perms = vec![
+            permissions::USERS_MANAGE.to_string(),
+            permissions::AUDIT_READ.to_string(),
+        ];
+        let (token, _) = service.tokens().issue_for_client(admin.id, &[Role::Admin], perms, "app", &[]).unwrap();
+        let requests = [
+            test::TestRequest::put()
+                .uri(&format!("/auth/users/{}/roles", user.id))
+                .set_json(json!({ "roles": ["admin"] })),
+            test::TestRequest::post().uri("/auth/invites").set_json(json!({})),
+            test::TestRequest::get().uri("/auth/invites"),
+            test::TestRequest::delete().uri(&format!("/auth/invites/{}", uuid::Uuid::new_v4())),
+            test::TestRequest::get().uri("/auth/audit"),
+            test::TestRequest::get().uri("/auth/audit/export"),
+            test::TestRequest::post().uri("/auth/email/verify/send"),
+        ];
+        for req in requests {
+            let req = req.insert_header(bearer(&token)).to_request();
+            let uri = req.uri().to_string();
+            assert_eq!(test::call_service(&app, req).await.status().as_u16(), 403, "{}", uri);
+        }
+        let user = service.users().find_by_id(user.id).await.unwrap().unwrap();
+        assert_eq!(user.roles, vec![Role::User]);
+    }
+
+    #[actix_rt::test]
+    async fn test_sessions_list_and_revoke() {
+        let service = auth_service();
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+
+        let device_login = |device: &str, agent: &str| test::TestRequest::post()
+            .uri("/auth/login")
+            .insert_header(("User-Agent", agent.to_string()))
+            .peer_addr("10.0.0.1:5000".parse().unwrap())
+            .set_json(json!({ "username": "testuser", "password": "password123", "device_name": device }))
+            .to_request();
+        let laptop: AuthResponse = test::call_and_read_body_json(&app, device_login("Laptop", "Firefox/128.0")).await;
+        let phone: AuthResponse = test::call_and_read_body_json(&app, device_login("Phone", "SocialHub-Android/2.1")).await;
+
+        let req = test::TestRequest::get().uri("/auth/sessions").insert_header(bearer(&laptop.token)).to_request();
+        let sessions: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(sessions.len(), 2);
+        let current: Vec<_> = sessions.iter().filter(|s| s["current"] == true).collect();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0]["device_name"], "Laptop");
+        assert_eq!(current[0]["user_agent"], "Firefox/128.0");
+        assert_eq!(current[0]["ip"], "10.0.0.1");
+        let phone_session = sessions.iter().find(|s| s["device_name"] == "Phone").unwrap();
+        assert_eq!(phone_session["user_agent"], "SocialHub-Android/2.1");
+
+        let uri = format!("/auth/sessions/{}", phone_session["id"].as_str().unwrap());
+        let req = test::TestRequest::delete().uri(&uri).insert_header(bearer(&laptop.token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 204);
+        let req = test::TestRequest::delete().uri(&uri).insert_header(bearer(&laptop.token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 404);
+
+        // The phone is signed out at once, the laptop is not.
+        assert!(matches!(service.verify_token(&phone.token).await, Err(AuthError::TokenRevoked)));
+        let resp = test::call_service(&app, refresh_request(&phone.refresh_token)).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        assert!(service.verify_token(&laptop.token).await.is_ok());
+        let req = test::TestRequest::get().uri("/auth/sessions").insert_header(bearer(&laptop.token)).to_request();
+        let sessions: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(sessions.len(), 1);
+
+        // Personal access tokens cannot see or end sessions.
+        let resp = post_json(&app, "/auth/tokens", Some(&laptop.token), json!({ "name": "ci", "scopes": ["posts:create"] })).await;
+        let created: CreatedAccessToken = test::read_body_json(resp).await;
+        let req = test::TestRequest::get().uri("/auth/sessions").insert_header(bearer(&created.token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 403);
+    }
+
+    #[actix_rt::test]
+    async fn test_device_authorization_flow() {
+        let service = web::Data::new(AuthService::new(
+            AuthConfig {
+                jwt_secret: "test-secret".to_string(),
+                hash_cost: 4,
+                public_url: "https://socialhub.test".to_string(),
+                device_poll_interval: 0,
+                ..AuthConfig::default()
+            },
+            Arc::new(InMemoryUserRepository::new()),
+        ));
+        let user = register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+
+        let start_device = || test::TestRequest::post()
+            .uri("/auth/device/code")
+            .insert_header(("User-Agent", "Stremio/4.4 (Android TV)"))
+            .set_form([("client_id", "stremio-tv")])
+            .to_request();
+        let poll = |device_code: &str| test::TestRequest::post()
+            .uri("/auth/token")
+            .set_form([
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                ("device_code", device_code),
+                ("client_id", "stremio-tv"),
+            ])
+            .to_request();
+
+        let codes: serde_json::Value = test::call_and_read_body_json(&app, start_device()).await;
+        let device_code = codes["device_code"].as_str().unwrap();
+        let user_code = codes["user_code"].as_str().unwrap();
+        assert_eq!(codes["verification_uri"], "https://socialhub.test/device");
+        assert_eq!(
+            codes["verification_uri_complete"],
+            format!("https://socialhub.test/device?user_code={}", user_code)
+        );
+        assert_eq!(codes["expires_in"], 600);
+
+        let resp = test::call_service(&app, poll(device_code)).await;
+        assert_eq!(resp.status().as_u16(), 400);
+        assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-store");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!({ "error": "authorization_pending" }));
+
+        // Approving takes a login session, not an access token.
+        let resp = post_json(&app, "/auth/tokens", Some(&session.token), json!({ "name": "ci", "scopes": ["posts:create"] })).await;
+        let created: CreatedAccessToken = test::read_body_json(resp).await;
+        let resp = post_json(&app, "/auth/device/verify", Some(&created.token), json!({ "user_code": user_code })).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        let resp = post_json(&app, "/auth/device/verify", None, json!({ "user_code": user_code })).await;
+        assert_eq!(resp.status().as_u16(), 401);
+
+        let typed = user_code.to_lowercase().replace('-', " ");
+        let resp = post_json(&app, "/auth/device/verify", Some(&session.token), json!({ "user_code": typed })).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        let resp = post_json(&app, "/auth/device/verify", Some(&session.token), json!({ "user_code": user_code })).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let resp = test::call_service(&app, poll(device_code)).await;
+        assert!(resp.status().is_success());
+        let device: AuthResponse = test::read_body_json(resp).await;
+        assert!(service.verify_token(&device.token).await.is_ok());
+        let resp = test::call_service(&app, refresh_request(&device.refresh_token)).await;
+        assert!(resp.status().is_success());
+
+        // The device shows up as a session of its own; its code is spent.
+        let req = test::TestRequest::get().uri("/auth/sessions").insert_header(bearer(&session.token)).to_request();
+        let sessions: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+        let tv = sessions.iter().find(|s| s["device_name"] == "stremio-tv").unwrap();
+        assert_eq!(tv["user_agent"], "Stremio/4.4 (Android TV)");
+        let body: serde_json::Value = test::call_and_read_body_json(&app, poll(device_code)).await;
+        assert_eq!(body["error"], "invalid_grant");
+
+        // A denied login stays denied.
+        let codes: serde_json::Value = test::call_and_read_body_json(&app, start_device()).await;
+        let resp = post_json(&app, "/auth/device/verify", Some(&session.token), json!({
+            "user_code": codes["user_code"], "deny": true
+        })).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        let body: serde_json::Value = test::call_and_read_body_json(&app, poll(codes["device_code"].as_str().unwrap())).await;
+        assert_eq!(body["error"], "access_denied");
+
+        // Answers are audited, pending polls are not.
+        let page = service.audit_events(&AuditQuery {
+            action: Some(AuditAction::DeviceLogin),
+            ..AuditQuery::default()
+        }).await.unwrap();
+        let outcomes: Vec<AuditOutcome> = page.events.iter().map(|e| e.outcome).collect();
+        assert_eq!(outcomes, vec![AuditOutcome::Failure, AuditOutcome::Failure, AuditOutcome::Success]);
+        assert_eq!(page.events[2].actor_id, Some(user.id));
+        assert!(page.events.iter().all(|e| e.details["client_id"] == "stremio-tv"));
+
+        let req = test::TestRequest::post()
+            .uri("/auth/token")
+            .set_form([("grant_type", "password"), ("username", "testuser"), ("password", "password123")])
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["error"], "unsupported_grant_type");
+    }
+    #[actix_rt::test]
+    async fn test_oauth_authorization_code_flow() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use jsonwebtoken::{decode, Algorithm, Validation};
+        use crate::{keys::JwkSet, oidc::IdTokenClaims};
+
+        let service = auth_service();
+        let owner = register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+
+        let resp = post_json(&app, "/oauth/clients", Some(&session.token), json!({
+            "name": "Partner", "redirect_uris": ["https://partner.example/cb"]
+        })).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let client: serde_json::Value = test::read_body_json(resp).await;
+        let client_id = client["client_id"].as_str().unwrap().to_string();
+        let client_secret = client["client_secret"].as_str().unwrap().to_string();
+        let resp = post_json(&app, "/oauth/clients", Some(&session.token), json!({
+            "name": "Insecure", "redirect_uris": ["http://partner.example/cb"]
+        })).await;
+        assert_eq!(resp.status().as_u16(), 400);
+
+        // RFC 7636 appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let authorize_request = json!({
+            "response_type": "code",
+            "client_id": client_id,
+            "redirect_uri": "https://partner.example/cb",
+            "scope": "openid profile email media:write",
+            "state": "xyz",
+            "code_challenge": "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
+            "code_challenge_method": "S256",
+            "nonce": "n-0S6",
+        });
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(authorize_request.as_object().unwrap().iter().map(|(k, v)| (k, v.as_str().unwrap())))
+            .finish();
+        let req = test::TestRequest::get()
+            .uri(&format!("/oauth/authorize?{}", query))
+            .insert_header(bearer(&session.token))
+            .to_request();
+        let prompt: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(prompt["client_name"], "Partner");
+        assert_eq!(prompt["scopes"].as_array().unwrap().len(), 4);
+
+        // Errors after the redirect URI checks out go back to the app.
+        let mut bad_scope = authorize_request.clone();
+        bad_scope["scope"] = json!("openid admin");
+        let resp = post_json(&app, "/oauth/authorize", Some(&session.token), bad_scope).await;
+        assert_eq!(resp.status().as_u16(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["redirect_to"], "https://partner.example/cb?error=invalid_scope&state=xyz");
+        let mut foreign_redirect = authorize_request.clone();
+        foreign_redirect["redirect_uri"] = json!("https://evil.example/cb");
+        let resp = post_json(&app, "/oauth/authorize", Some(&session.token), foreign_redirect).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body.get("redirect_to").is_none());
+
+        let authorize = || async {
+            let resp = post_json(&app, "/oauth/authorize", Some(&session.token), authorize_request.clone()).await;
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            let target = url::Url::parse(body["redirect_to"].as_str().unwrap()).unwrap();
+            assert_eq!(target.query_pairs().find(|(k, _)| k == "state").unwrap().1, "xyz");
+            target.query_pairs().find(|(k, _)| k == "code").unwrap().1.into_owned()
+        };
+        let exchange = |code: String, verifier: &'static str, secret: String| {
+            test::TestRequest::post()
+                .uri("/auth/token")
+                .insert_header(("Authorization", format!("Basic {}", STANDARD.encode(format!("{}:{}", client_id, secret)))))
+                .set_form([
+                    ("grant_type", "authorization_code"),
+                    ("code", code.as_str()),
+                    ("redirect_uri", "https://partner.example/cb"),
+                    ("code_verifier", verifier),
+                ])
+                .to_request()
+        };
+
+        let code = authorize().await;
+        let resp = test::call_service(&app, exchange(code.clone(), verifier, "wrong".to_string())).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "invalid_client");
+
+        let resp = test::call_service(&app, exchange(code.clone(), verifier, client_secret.clone())).await;
+        assert!(resp.status().is_success());
+        let tokens: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(tokens["token_type"], "Bearer");
+        assert_eq!(tokens["scope"], "email media:write openid profile");
+        let access_token = tokens["access_token"].as_str().unwrap().to_string();
+
+        // The scopes stand for permissions, limited to what the user holds.
+        let user = service.verify_token(&access_token).await.unwrap();
+        assert_eq!(user.perms, vec![permissions::MEDIA_UPLOAD]);
+        assert_eq!(user.client_id.as_deref(), Some(client_id.as_str()));
+
+        let req = test::TestRequest::get().uri("/.well-known/jwks.json").to_request();
+        let jwks: JwkSet = test::call_and_read_body_json(&app, req).await;
+        let key = jwks.keys[0].decoding_key().unwrap();
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[&client_id]);
+        validation.set_issuer(&["http://localhost:8080"]);
+        let id_token = decode::<IdTokenClaims>(tokens["id_token"].as_str().unwrap(), &key, &validation).unwrap().claims;
+        assert_eq!(id_token.nonce.as_deref(), Some("n-0S6"));
+        assert_eq!(id_token.preferred_username.as_deref(), Some("testuser"));
+
+        let req = test::TestRequest::get().uri("/oauth/userinfo").insert_header(bearer(&access_token)).to_request();
+        let info: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(info["preferred_username"], "testuser");
+        assert_eq!(info["email"], "testuser@test.com");
+        let req = test::TestRequest::get().uri("/oauth/userinfo").insert_header(bearer(&session.token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 403);
+
+        // Apps cannot manage the account.
+        let req = test::TestRequest::get().uri("/auth/tokens").insert_header(bearer(&access_token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 403);
+
+        // Codes are single-use and bound to the PKCE verifier.
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, exchange(code, verifier, client_secret.clone())).await;
+        assert_eq!(body["error"], "invalid_grant");
+        let code = authorize().await;
+        let other_verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXK";
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, exchange(code, other_verifier, client_secret)).await;
+        assert_eq!(body["error"], "invalid_grant");
+
+        // Every redemption is audited, including the failed ones.
+        let page = service.audit_events(&AuditQuery {
+            action: Some(AuditAction::OAuthTokenIssued),
+            ..AuditQuery::default()
+        }).await.unwrap();
+        let outcomes: Vec<AuditOutcome> = page.events.iter().map(|e| e.outcome).collect();
+        assert_eq!(outcomes, vec![AuditOutcome::Failure, AuditOutcome::Failure, AuditOutcome::Success, AuditOutcome::Failure]);
+        assert_eq!(page.events[2].actor_id, Some(owner.id));
+        assert!(page.events.iter().all(|e| e.details["client_id"] == client_id.as_str()));
+
+        let req = test::TestRequest::get().uri("/.well-known/openid-configuration").to_request();
+        let discovery: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(discovery["issuer"], "http://localhost:8080");
+        assert!(discovery["scopes_supported"].as_array().unwrap().contains(&json!("media:write")));
+        assert_eq!(discovery["code_challenge_methods_supported"], json!(["S256"]));
+    }
+
+    #[actix_rt::test]
+    async fn test_oauth_scopes_do_not_delegate_moderation() {
+        let service = auth_service();
+        let moderator = register_user(&service, "testuser").await;
+        service.users().update_roles(moderator.id, vec![Role::Moderator], Vec::new()).await.unwrap();
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+
+        let resp = post_json(&app, "/oauth/clients", Some(&session.token), json!({
+            "name": "Partner", "redirect_uris": ["https://partner.example/cb"], "public": true
+        })).await;
+        let client: serde_json::Value = test::read_body_json(resp).await;
+        let client_id = client["client_id"].as_str().unwrap();
+        let resp = post_json(&app, "/oauth/authorize", Some(&session.token), json!({
+            "response_type": "code",
+            "client_id": client_id,
+            "redirect_uri": "https://partner.example/cb",
+            "scope": "user:write media:write stream:write",
+            "code_challenge": "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
+            "code_challenge_method": "S256",
+        })).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let target = url::Url::parse(body["redirect_to"].as_str().unwrap()).unwrap();
+        let code = target.query_pairs().find(|(k, _)| k == "code").unwrap().1.into_owned();
+        let req = test::TestRequest::post()
+            .uri("/auth/token")
+            .set_form([
+                ("grant_type", "authorization_code"),
+                ("client_id", client_id),
+                ("code", code.as_str()),
+                ("redirect_uri", "https://partner.example/cb"),
+                ("code_verifier", "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            ])
+            .to_request();
+        let tokens: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        // The moderator's own powers stay with the moderator.
+        let user = service.verify_token(tokens["access_token"].as_str().unwrap()).await.unwrap();
+        assert_eq!(user.perms, vec![permissions::MEDIA_UPLOAD, permissions::POSTS_CREATE, permissions::STREAMS_START]);
+    }
+
+    #[actix_rt::test]
+    async fn test_access_tokens_verify_offline_with_published_keys() {
+        use jsonwebtoken::{decode, decode_header, Algorithm, Validation};
+        use crate::{keys::JwkSet, token::Claims};
+
+        let service = web::Data::new(AuthService::new(
+            AuthConfig {
+                hash_cost: 4,
+                jwt_algorithm: Algorithm::EdDSA,
+                ..AuthConfig::default()
+            },
+            Arc::new(InMemoryUserRepository::new()),
+        ));
+        register_user(&service, "testuser").await;
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/.well-known/jwks.json").to_request()).await;
+        assert_eq!(resp.headers().get("Cache-Control").unwrap(), "public, max-age=300");
+        let jwks: JwkSet = test::read_body_json(resp).await;
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.keys[0].crv.as_deref(), Some("Ed25519"));
+
+        // What an edge service does, without asking the auth service.
+        let header = decode_header(&session.token).unwrap();
+        assert_eq!(header.alg, Algorithm::EdDSA);
+        let jwk = jwks.keys.iter().find(|k| Some(&k.kid) == header.kid.as_ref()).unwrap();
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&["socialhub"]);
+        let claims = decode::<Claims>(&session.token, &jwk.decoding_key().unwrap(), &validation).unwrap().claims;
+        assert_eq!(claims.sub, "1");
+
+        let req = test::TestRequest::get().uri("/.well-known/openid-configuration").to_request();
+        let discovery: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(discovery["jwks_uri"], "http://localhost:8080/.well-known/jwks.json");
+        assert_eq!(discovery["id_token_signing_alg_values_supported"], json!(["EdDSA"]));
+    }
+
+    /// Stands in for another crate that stores notes about users.
+    #[derive(Default)]
+    struct NotesHook {
+        notes: std::sync::Mutex<std::collections::HashMap<i32, String>>,
+    }
+
+    impl socialhub_core::DataSubjectHook for NotesHook {
+        fn name(&self) -> &'static str {
+            "notes"
+        }
+
+        fn export(&self, user_id: i32) -> futures::future::BoxFuture<'_, Result<Vec<socialhub_core::ExportedFile>, socialhub_core::error::CommonError>> {
+            Box::pin(async move {
+                use futures::StreamExt;
+
+                // Streamed like large files are.
+                let note = self.notes.lock().unwrap().get(&user_id).cloned().unwrap_or_default();
+                let contents = futures::stream::once(async move { Ok(web::Bytes::from(note)) }).boxed();
+                Ok(vec![socialhub_core::ExportedFile::stream("note.txt", contents)])
+            })
+        }
+
+        fn erase(&self, user_id: i32) -> futures::future::BoxFuture<'_, Result<(), socialhub_core::error::CommonError>> {
+            Box::pin(async move {
+                self.notes.lock().unwrap().remove(&user_id);
+                Ok(())
+            })
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_account_export() {
+        use std::io::Read;
+
+        let export_dir = tempfile::tempdir().unwrap();
+        let notes = Arc::new(NotesHook::default());
+        let service = web::Data::new(AuthService::new(
+            AuthConfig { hash_cost: 4, export_dir: export_dir.path().to_path_buf(), ..AuthConfig::default() },
+            Arc::new(InMemoryUserRepository::new()),
+        ).with_data_subject_hook(notes.clone()));
+        let user = register_user(&service, "testuser").await;
+        notes.notes.lock().unwrap().insert(user.id, "likes jazz".to_string());
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let session = login(&app).await;
+
+        let resp = post_json(&app, "/auth/account/export", Some(&session.token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 202);
+        let export: AccountExport = test::read_body_json(resp).await;
+
+        let status_uri = format!("/auth/account/export/{}", export.id);
+        let mut status = export.status;
+        for _ in 0..100 {
+            let req = test::TestRequest::get().uri(&status_uri).insert_header(bearer(&session.token)).to_request();
+            let export: AccountExport = test::call_and_read_body_json(&app, req).await;
+            status = export.status;
+            if status != ExportStatus::Pending {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(status, ExportStatus::Ready);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("{}/download", status_uri))
+            .insert_header(bearer(&session.token))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Content-Type").unwrap(), "application/zip");
+        let archive = test::read_body(resp).await;
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive.to_vec())).unwrap();
+        let mut read = |path: &str| {
+            let mut contents = String::new();
+            zip.by_name(path).unwrap().read_to_string(&mut contents).unwrap();
+            contents
+        };
+        let profile: serde_json::Value = serde_json::from_str(&read("account/profile.json")).unwrap();
+        assert_eq!(profile["user"]["username"], "testuser");
+        assert!(profile["user"].get("password_hash").is_none());
+        let sessions: Vec<serde_json::Value> = serde_json::from_str(&read("account/sessions.json")).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(read("notes/note.txt"), "likes jazz");
+        assert_eq!(std::fs::read_dir(export_dir.path()).unwrap().count(), 1);
+
+        // Exports are private to their user.
+        register_user(&service, "other").await;
+        let resp = post_json(&app, "/auth/login", None, json!({ "username": "other", "password": "password123" })).await;
+        let other: AuthResponse = test::read_body_json(resp).await;
+        let req = test::TestRequest::get().uri(&status_uri).insert_header(bearer(&other.token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 404);
+
+        // Erasing the account deletes the archives of all of its exports.
+        let resp = post_json(&app, "/auth/account/export", Some(&session.token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 202);
+        for _ in 0..100 {
+            if std::fs::read_dir(export_dir.path()).unwrap().count() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(std::fs::read_dir(export_dir.path()).unwrap().count(), 2);
+        service.users().schedule_deletion(user.id, Some(0)).await.unwrap();
+        assert_eq!(service.purge_deleted_accounts().await.unwrap(), 1);
+        assert_eq!(std::fs::read_dir(export_dir.path()).unwrap().count(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_account_deletion() {
+        let notes = Arc::new(NotesHook::default());
+        let service = web::Data::new(AuthService::new(
+            AuthConfig { hash_cost: 4, account_deletion_grace_period: 0, ..AuthConfig::default() },
+            Arc::new(InMemoryUserRepository::new()),
+        ).with_data_subject_hook(notes.clone()));
+        let user = register_user(&service, "testuser").await;
+        notes.notes.lock().unwrap().insert(user.id, "likes jazz".to_string());
+        let app = test::init_service(App::new().configure(configure_state(service.clone())).configure(configure)).await;
+        let delete = |token: &str, password: &str| test::TestRequest::delete()
+            .uri("/auth/account")
+            .insert_header(bearer(token))
+            .set_json(json!({ "password": password }))
+            .to_request();
+
+        let session = login(&app).await;
+        assert_eq!(test::call_service(&app, delete(&session.token, "wrong-password")).await.status().as_u16(), 401);
+        let resp = test::call_service(&app, delete(&session.token, "password123")).await;
+        assert_eq!(resp.status().as_u16(), 202);
+        let deletion: AccountDeletion = test::read_body_json(resp).await;
+        assert!(matches!(service.verify_token(&session.token).await, Err(AuthError::TokenRevoked)));
+
+        // Logging in again during the grace period allows cancelling. Tokens
+        // from the second of the deletion request are revoked with the others.
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let session = login(&app).await;
+        let resp = post_json(&app, "/auth/account/restore", Some(&session.token), json!({})).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        assert_eq!(service.purge_deleted_accounts().await.unwrap(), 0);
+        assert!(service.users().find_by_id(user.id).await.unwrap().is_some());
+
+        let resp = test::call_service(&app, delete(&session.token, "password123")).await;
+        let again: AccountDeletion = test::read_body_json(resp).await;
+        assert!(again.deletion_due_at >= deletion.deletion_due_at);
+        assert_eq!(service.purge_deleted_accounts().await.unwrap(), 1);
+        assert!(service.users().find_by_id(user.id).await.unwrap().is_none());
+        assert!(notes.notes.lock().unwrap().is_empty());
+        let resp = post_json(&app, "/auth/login", None, json!({ "username": "testuser", "password": "password123" })).await;
+        assert_eq!(resp.status().as_u16(), 401);
     }
 }
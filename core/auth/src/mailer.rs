@@ -0,0 +1,316 @@
+//! Outgoing email, used for password resets and address verification.
+//!
+//! [`SmtpMailer`] delivers through an SMTP relay; [`FileMailer`] writes the
+//! messages to a directory or the log instead, for development. [`from_env`]
+//! picks one based on the environment.
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use actix_web::web;
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use log::{error, info};
+use uuid::Uuid;
+use crate::error::AuthError;
+
+const DEFAULT_FROM: &str = "SocialHub <no-reply@localhost>";
+const SMTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A plain-text message to a single recipient.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), AuthError>;
+}
+
+/// Builds the mailer configured by the environment: SMTP when `SMTP_HOST` is
+/// set, otherwise `.eml` files in `MAIL_DIR`, otherwise the log.
+pub fn from_env() -> Result<Arc<dyn Mailer>, AuthError> {
+    let from = std::env::var("MAIL_FROM").unwrap_or_else(|_| DEFAULT_FROM.to_string());
+    if let Some(config) = SmtpConfig::from_env(&from) {
+        return Ok(Arc::new(SmtpMailer::new(&config)?));
+    }
+    let dir = std::env::var("MAIL_DIR").ok().map(PathBuf::from);
+    Ok(Arc::new(FileMailer::new(&from, dir)?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// Plain text. Only for relays on localhost or a trusted network.
+    None,
+    /// Upgrade the connection with `STARTTLS`, usually on port 587.
+    StartTls,
+    /// TLS from the start, usually on port 465.
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// Defaults to the usual port of `security`.
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender, e.g. `SocialHub <no-reply@example.com>`.
+    pub from: String,
+}
+
+impl SmtpConfig {
+    /// Reads `SMTP_HOST`, `SMTP_PORT`, `SMTP_SECURITY` (`none`, `starttls` or
+    /// `tls`), `SMTP_USERNAME` and `SMTP_PASSWORD`. Returns `None` without a host.
+    pub fn from_env(from: &str) -> Option<Self> {
+        let host = std::env::var("SMTP_HOST").ok()?;
+        let security = match std::env::var("SMTP_SECURITY").as_deref() {
+            Ok("none") => SmtpSecurity::None,
+            Ok("tls") => SmtpSecurity::Tls,
+            _ => SmtpSecurity::StartTls,
+        };
+
+        Some(Self {
+            host,
+            port: std::env::var("SMTP_PORT").ok().map(|port| port.parse().unwrap()),
+            security,
+            username: std::env::var("SMTP_USERNAME").ok(),
+            password: std::env::var("SMTP_PASSWORD").ok(),
+            from: from.to_string(),
+        })
+    }
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &SmtpConfig) -> Result<Self, AuthError> {
+        let mut builder = match config.security {
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .map_err(smtp_error)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+                .map_err(smtp_error)?,
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            transport: builder.timeout(Some(SMTP_TIMEOUT)).build(),
+            from: parse_mailbox(&config.from)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), AuthError> {
+        let message = build_message(&self.from, email)?;
+        self.transport.send(message).await.map_err(smtp_error)?;
+        Ok(())
+    }
+}
+
+/// Writes every message as an `.eml` file into a directory, or only logs it.
+/// Meant for development, where the links in the messages are needed but no
+/// mail should leave the machine.
+pub struct FileMailer {
+    from: Mailbox,
+    dir: Option<PathBuf>,
+}
+
+impl FileMailer {
+    pub fn new(from: &str, dir: Option<PathBuf>) -> Result<Self, AuthError> {
+        Ok(Self { from: parse_mailbox(from)?, dir })
+    }
+
+    /// Logs messages, including their body, at info level.
+    pub fn log_only() -> Self {
+        Self {
+            from: DEFAULT_FROM.parse().expect("default sender is a valid mailbox"),
+            dir: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), AuthError> {
+        let Some(dir) = self.dir.clone() else {
+            info!("Mail to {}: {}\n{}", email.to, email.subject, email.body);
+            return Ok(());
+        };
+
+        let to = email.to.clone();
+        let message = build_message(&self.from, email)?.formatted();
+        let path = dir.join(format!("{}.eml", Uuid::new_v4()));
+        let written = path.clone();
+        web::block(move || {
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(&path, message)
+        })
+        .await
+        .map_err(|_| AuthError::InternalError)?
+        .map_err(|e| {
+            error!("Failed to write mail to {}: {}", written.display(), e);
+            AuthError::InternalError
+        })?;
+
+        info!("Mail to {} written to {}", to, written.display());
+        Ok(())
+    }
+}
+
+fn build_message(from: &Mailbox, email: Email) -> Result<Message, AuthError> {
+    Message::builder()
+        .from(from.clone())
+        .to(parse_mailbox(&email.to)?)
+        .subject(email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body)
+        .map_err(|e| {
+            error!("Failed to build mail: {}", e);
+            AuthError::InternalError
+        })
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, AuthError> {
+    address
+        .parse()
+        .map_err(|_| AuthError::Validation(format!("Invalid email address: {}", address)))
+}
+
+fn smtp_error(err: lettre::transport::smtp::Error) -> AuthError {
+    error!("SMTP delivery failed: {}", err);
+    AuthError::InternalError
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// Accepts one SMTP session and returns the commands and message data it received.
+    async fn smtp_stand_in(listener: TcpListener) -> (Vec<String>, String) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut commands = Vec::new();
+        let mut data = String::new();
+
+        writer.write_all(b"220 localhost ESMTP stand-in\r\n").await.unwrap();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            let command = line.trim_end().to_string();
+            let reply: &[u8] = match command.split(' ').next().unwrap().to_uppercase().as_str() {
+                "EHLO" => b"250-localhost\r\n250 8BITMIME\r\n",
+                "DATA" => {
+                    writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.unwrap();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).await.unwrap();
+                        if line == ".\r\n" {
+                            break;
+                        }
+                        data.push_str(&line);
+                    }
+                    b"250 Queued\r\n"
+                }
+                "QUIT" => {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    commands.push(command);
+                    break;
+                }
+                _ => b"250 OK\r\n",
+            };
+            commands.push(command);
+            writer.write_all(reply).await.unwrap();
+        }
+        (commands, data)
+    }
+
+    fn email() -> Email {
+        Email {
+            to: "alice@example.com".to_string(),
+            subject: "Reset your password".to_string(),
+            body: "https://socialhub.test/reset-password?token=abc".to_string(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_smtp_mailer_delivers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(smtp_stand_in(listener));
+
+        let mailer = SmtpMailer::new(&SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "SocialHub <no-reply@socialhub.test>".to_string(),
+        }).unwrap();
+        mailer.send(email()).await.unwrap();
+        drop(mailer);
+
+        let (commands, data) = server.await.unwrap();
+        assert!(commands.iter().any(|c| c.starts_with("MAIL FROM:<no-reply@socialhub.test>")));
+        assert!(commands.iter().any(|c| c == "RCPT TO:<alice@example.com>"));
+        assert!(data.contains("Subject: Reset your password"));
+        assert!(data.contains("reset-password?token=abc"));
+    }
+
+    #[actix_rt::test]
+    async fn test_smtp_mailer_reports_unreachable_relay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let mailer = SmtpMailer::new(&SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: DEFAULT_FROM.to_string(),
+        }).unwrap();
+        assert!(matches!(mailer.send(email()).await, Err(AuthError::InternalError)));
+    }
+
+    #[actix_rt::test]
+    async fn test_file_mailer_writes_eml() {
+        let dir = std::env::temp_dir().join(format!("socialhub-mail-{}", Uuid::new_v4()));
+        let mailer = FileMailer::new(DEFAULT_FROM, Some(dir.clone())).unwrap();
+        mailer.send(email()).await.unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        let contents = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(contents.contains("To: alice@example.com"));
+        assert!(contents.contains("reset-password?token=abc"));
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert!(FileMailer::log_only().send(email()).await.is_ok());
+        assert!(FileMailer::new("not an address", None).is_err());
+    }
+}
@@ -1,18 +1,44 @@
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use socialhub_core::auth::Role;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use crate::{
+    access_token::AccessTokenRecord,
+    audit::{AuditAction, AuditEvent, AuditFilter, AuditOutcome},
+    invite::InviteRecord, oauth_client::OAuthClientRecord, session::SessionRecord,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct User {
     pub id: i32,
     pub username: String,
     pub email: String,
+    /// Set once the user followed the link mailed to `email`.
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(skip_serializing, default)]
+    #[schema(read_only)]
     pub password_hash: String,
+    #[serde(default)]
+    #[schema(value_type = Vec<String>, example = json!(["user"]))]
+    pub roles: Vec<Role>,
+    /// Permissions granted individually, on top of those of the roles.
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    /// Unix timestamp in seconds at which the account will be erased, after
+    /// the user asked for its deletion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deletion_due_at: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
+    /// Name for the new session, shown in `/auth/sessions`.
+    #[serde(default)]
+    #[schema(example = "Living room TV")]
+    pub device_name: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -20,10 +46,525 @@ pub struct RegisterRequest {
     pub username: String,
     pub email: String,
     pub password: String,
+    /// Required when registration is invite-only.
+    #[serde(default)]
+    #[schema(example = "K7QF-M2XP-9HTW")]
+    pub invite_code: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct AuthResponse {
     pub token: String,
+    pub token_type: String,
+    /// Access token lifetime in seconds.
+    pub expires_in: u64,
+    /// Single-use token for `/auth/refresh`.
+    pub refresh_token: String,
     pub user_id: i32,
 }
+
+/// Returned by `/auth/login` instead of tokens when the account has 2FA enabled.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorChallenge {
+    /// Short-lived token for `/auth/login/2fa`.
+    pub challenge_token: String,
+    /// Challenge lifetime in seconds.
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(AuthResponse),
+    TwoFactorRequired(TwoFactorChallenge),
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_token: String,
+    /// Current authenticator code or one of the recovery codes.
+    pub code: String,
+    /// Name for the new session, shown in `/auth/sessions`.
+    #[serde(default)]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorEnrollment {
+    /// Base32 shared secret, for manual entry.
+    pub secret: String,
+    #[schema(example = "otpauth://totp/socialhub:alice?secret=JBSWY3DPEHPK3PXP&issuer=socialhub")]
+    pub otpauth_uri: String,
+    /// Single-use codes for when the authenticator is lost. Shown only once.
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorCodeRequest {
+    /// Current authenticator code, or a recovery code where accepted.
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Start of a device login (RFC 8628), sent by the device.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeviceCodeRequest {
+    #[schema(example = "stremio-tv")]
+    pub client_id: String,
+    /// Name for the session the device gets, shown in `/auth/sessions`.
+    /// Defaults to `client_id`.
+    #[serde(default)]
+    pub device_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeviceCodeResponse {
+    /// Secret the device polls `/auth/token` with.
+    pub device_code: String,
+    /// Code the user enters at `verification_uri`.
+    #[schema(example = "WDJB-MJHT")]
+    pub user_code: String,
+    pub verification_uri: String,
+    /// `verification_uri` with the user code filled in, e.g. for a QR code.
+    pub verification_uri_complete: String,
+    /// Lifetime of both codes in seconds.
+    pub expires_in: u64,
+    /// Seconds to wait between polls.
+    pub interval: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeviceVerifyRequest {
+    /// Code shown on the device. Case, spaces and dashes are ignored.
+    pub user_code: String,
+    /// Rejects the login instead of approving it.
+    #[serde(default)]
+    pub deny: bool,
+}
+
+/// OAuth 2.0 token request, for the device code and the authorization code grants.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenRequest {
+    /// `urn:ietf:params:oauth:grant-type:device_code` or `authorization_code`.
+    #[schema(example = "urn:ietf:params:oauth:grant-type:device_code")]
+    pub grant_type: String,
+    pub device_code: Option<String>,
+    /// May instead be sent with the secret as HTTP Basic credentials.
+    pub client_id: Option<String>,
+    /// Secret of confidential clients, unless sent as HTTP Basic credentials.
+    pub client_secret: Option<String>,
+    pub code: Option<String>,
+    /// Must equal the one of the authorization request.
+    pub redirect_uri: Option<String>,
+    /// PKCE verifier matching the authorization request's `code_challenge`.
+    pub code_verifier: Option<String>,
+}
+
+/// Token endpoint response to third-party apps (RFC 6749 section 5.1).
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OAuthTokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    /// Granted scopes, space-separated.
+    pub scope: String,
+    /// OpenID Connect ID token, with the `openid` scope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum TokenResponse {
+    /// For the device code grant: a regular login.
+    Session(AuthResponse),
+    /// For the authorization code grant.
+    OAuth(OAuthTokenResponse),
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateOAuthClientRequest {
+    #[schema(example = "Partner Playlists")]
+    pub name: String,
+    /// Exact URIs to send authorization codes to: `https`, or `http` on localhost.
+    #[schema(example = json!(["https://partner.example/oauth/callback"]))]
+    pub redirect_uris: Vec<String>,
+    /// For mobile and single-page apps, which cannot keep a secret.
+    #[serde(default)]
+    pub public: bool,
+}
+
+/// A registered third-party app, without its secret.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    /// Whether the app authenticates without a secret.
+    pub public: bool,
+    /// Unix timestamp in seconds.
+    pub created_at: u64,
+}
+
+impl From<OAuthClientRecord> for OAuthClient {
+    fn from(record: OAuthClientRecord) -> Self {
+        Self {
+            client_id: record.client_id,
+            name: record.name,
+            redirect_uris: record.redirect_uris,
+            public: record.secret_hash.is_none(),
+            created_at: record.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreatedOAuthClient {
+    #[serde(flatten)]
+    pub details: OAuthClient,
+    /// Secret of confidential clients. Shown only once.
+    pub client_secret: Option<String>,
+}
+
+/// Authorization request parameters (RFC 6749 section 4.1.1), as received by
+/// the web app's consent page.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, IntoParams)]
+pub struct AuthorizeRequest {
+    /// Must be `code`.
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    /// Space-separated scopes, see the discovery document.
+    #[schema(example = "openid profile media:write")]
+    pub scope: String,
+    pub state: Option<String>,
+    /// PKCE challenge; required.
+    pub code_challenge: Option<String>,
+    /// Must be `S256`.
+    pub code_challenge_method: Option<String>,
+    /// Echoed in the ID token.
+    pub nonce: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScopeDescription {
+    pub scope: String,
+    pub description: String,
+}
+
+/// What the consent screen shows the user.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ConsentPrompt {
+    pub client_id: String,
+    pub client_name: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<ScopeDescription>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ConsentDecision {
+    #[serde(flatten)]
+    pub request: AuthorizeRequest,
+    /// Refuses access instead of granting it.
+    #[serde(default)]
+    pub deny: bool,
+}
+
+/// Where the web app sends the browser after the consent screen.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuthorizationRedirect {
+    /// The app's redirect URI with `code` and `state`, or with `error`.
+    pub redirect_to: String,
+}
+
+/// OpenID Connect UserInfo response.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserInfo {
+    pub sub: String,
+    /// With the `profile` scope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    /// With the `email` scope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+/// OpenID Connect discovery document (OpenID Connect Discovery section 3).
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OpenIdConfiguration {
+    pub issuer: String,
+    /// Consent page of the web app.
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub device_authorization_endpoint: String,
+    pub scopes_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpdateRolesRequest {
+    #[schema(value_type = Vec<String>, example = json!(["user", "moderator"]))]
+    pub roles: Vec<Role>,
+    /// Extra permissions, e.g. `media:delete`, granted regardless of role.
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LogoutRequest {
+    /// Refresh token of the same login, revoked along with the access token.
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ResetPasswordRequest {
+    /// Token from the password reset email.
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VerifyEmailRequest {
+    /// Token from the verification email.
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateAccessTokenRequest {
+    /// What the token is for, e.g. `ci-deploy`.
+    pub name: String,
+    /// Permissions the token is limited to. Each must be held by the creator.
+    #[schema(example = json!(["posts:create", "streams:start"]))]
+    pub scopes: Vec<String>,
+    /// Lifetime in seconds. Without one, the token lasts until deleted.
+    pub expires_in: Option<u64>,
+}
+
+/// A personal access token, without the secret.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccessToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Unix timestamps in seconds.
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+}
+
+impl From<AccessTokenRecord> for AccessToken {
+    fn from(record: AccessTokenRecord) -> Self {
+        Self {
+            id: record.id,
+            name: record.name,
+            scopes: record.scopes,
+            created_at: record.created_at,
+            expires_at: record.expires_at,
+            last_used_at: record.last_used_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreatedAccessToken {
+    /// The bearer token. Shown only once.
+    #[schema(example = "shp_9Ib3bqGxPuzQ2T0dm2vR0l2cWcxHkN8x1ZbQW5uT0k4")]
+    pub token: String,
+    #[serde(flatten)]
+    pub details: AccessToken,
+}
+
+/// A signed-in device.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Session {
+    pub id: Uuid,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    /// Address of the last login or refresh.
+    pub ip: Option<String>,
+    /// Unix timestamps in seconds.
+    pub created_at: u64,
+    pub last_seen_at: u64,
+    /// Whether this is the session of the calling token.
+    pub current: bool,
+    /// Set if an admin opened the session to act as the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator_id: Option<i32>,
+}
+
+impl Session {
+    pub fn from_record(record: SessionRecord, current: bool) -> Self {
+        Self {
+            id: record.id,
+            device_name: record.device_name,
+            user_agent: record.user_agent,
+            ip: record.ip,
+            created_at: record.created_at,
+            last_seen_at: record.last_seen_at,
+            current,
+            impersonator_id: record.impersonator_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// A personal data export, see `/auth/account/export`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AccountExport {
+    pub id: Uuid,
+    pub status: ExportStatus,
+    /// Unix timestamps in seconds.
+    pub requested_at: u64,
+    pub completed_at: Option<u64>,
+    /// Archive size in bytes, once ready.
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeleteAccountRequest {
+    /// Current password, to confirm the deletion.
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountDeletion {
+    /// Unix timestamp in seconds at which the account will be erased, unless
+    /// the deletion is cancelled at `/auth/account/restore` before.
+    pub deletion_due_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateInviteRequest {
+    /// Registrations the code allows.
+    #[serde(default = "default_invite_uses")]
+    #[schema(default = 1)]
+    pub max_uses: u32,
+    /// Lifetime in seconds. Without one, the code lasts until used up or deleted.
+    pub expires_in: Option<u64>,
+}
+
+fn default_invite_uses() -> u32 {
+    1
+}
+
+/// An invite code, without the code itself.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Invite {
+    pub id: Uuid,
+    /// The admin who created it.
+    pub created_by: i32,
+    pub max_uses: u32,
+    pub uses: u32,
+    /// Unix timestamps in seconds.
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+impl From<InviteRecord> for Invite {
+    fn from(record: InviteRecord) -> Self {
+        Self {
+            id: record.id,
+            created_by: record.created_by,
+            max_uses: record.max_uses,
+            uses: record.uses,
+            created_at: record.created_at,
+            expires_at: record.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreatedInvite {
+    /// The code to hand out. Shown only once.
+    #[schema(example = "K7QF-M2XP-9HTW")]
+    pub code: String,
+    #[serde(flatten)]
+    pub details: Invite,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ImpersonateRequest {
+    /// Why, e.g. the support ticket. Recorded in the audit log.
+    pub reason: Option<String>,
+}
+
+/// A token to act as another user.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Impersonation {
+    /// Access token for the user, carrying an `act` claim naming the admin.
+    /// It cannot be refreshed, nor manage the account, its 2FA or its tokens.
+    pub token: String,
+    /// Seconds until the token expires.
+    pub expires_in: u64,
+    pub user_id: i32,
+    /// The session shown in the user's session list; revoking it ends the impersonation.
+    pub session_id: Uuid,
+}
+
+/// Filters and page of an audit log query.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+pub struct AuditQuery {
+    #[param(value_type = Option<String>, example = "login")]
+    pub action: Option<AuditAction>,
+    #[param(value_type = Option<String>, example = "failure")]
+    pub outcome: Option<AuditOutcome>,
+    pub actor_id: Option<i32>,
+    pub target_id: Option<i32>,
+    pub ip: Option<String>,
+    /// Unix timestamps in seconds, inclusive.
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<i64>,
+    /// Events per page, 50 by default and at most 500.
+    pub limit: Option<u32>,
+}
+
+impl AuditQuery {
+    pub fn filter(&self) -> AuditFilter {
+        AuditFilter {
+            action: self.action,
+            outcome: self.outcome,
+            actor_id: self.actor_id,
+            target_id: self.target_id,
+            ip: self.ip.clone(),
+            since: self.since,
+            until: self.until,
+            before: self.cursor,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditPage {
+    /// Newest first.
+    pub events: Vec<AuditEvent>,
+    /// Pass as `cursor` to get the next, older page. Absent on the last page.
+    pub next_cursor: Option<i64>,
+}
@@ -0,0 +1,186 @@
+//! OAuth 2.0 authorization code grant (RFC 6749) with PKCE (RFC 7636), for
+//! third-party apps acting on behalf of SocialHub users.
+//!
+//! The web app shows the consent screen and, once the user agrees, hands a
+//! short-lived authorization code to the app's redirect URI. The app redeems
+//! it at `/auth/token` together with the PKCE verifier. Codes only live in
+//! memory.
+
+use actix_web::{http::header::AUTHORIZATION, HttpRequest};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use sha2::{Digest, Sha256};
+use socialhub_core::auth::scopes;
+use socialhub_core::cache::{CacheConfig, CacheManager};
+use url::Url;
+use crate::{
+    error::{AuthError, OAuthErrorCode},
+    secret,
+};
+
+/// `grant_type` of authorization code token requests.
+pub const AUTHORIZATION_CODE_GRANT: &str = "authorization_code";
+/// The only supported `code_challenge_method`.
+pub const PKCE_METHOD: &str = "S256";
+
+/// Upper bound on unredeemed authorization codes.
+const CAPACITY: u64 = 100_000;
+/// Seconds an authorization code stays valid, as recommended by RFC 6749 section 4.1.2.
+const CODE_LIFETIME: u64 = 60;
+
+/// What the user agreed to, stored under its authorization code.
+#[derive(Debug, Clone)]
+pub struct AuthorizationGrant {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub user_id: i32,
+    pub scopes: Vec<String>,
+    /// PKCE challenge, `BASE64URL(SHA256(code_verifier))`.
+    pub code_challenge: String,
+    /// OpenID Connect nonce, echoed in the ID token.
+    pub nonce: Option<String>,
+}
+
+pub struct AuthorizationCodes {
+    /// Keyed by the digest of the code.
+    codes: CacheManager<String, AuthorizationGrant>,
+}
+
+impl Default for AuthorizationCodes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AuthorizationCodes {
+    pub fn new() -> Self {
+        Self {
+            codes: CacheManager::new(CacheConfig {
+                max_capacity: CAPACITY,
+                time_to_live: CODE_LIFETIME,
+                time_to_idle: CODE_LIFETIME,
+            }),
+        }
+    }
+
+    /// Stores the grant and returns the code for it.
+    pub async fn issue(&self, grant: AuthorizationGrant) -> String {
+        let code = secret::generate();
+        self.codes.set(secret::hash(&code), grant).await;
+        code
+    }
+
+    /// Returns the grant of `code` and forgets it, so each code is redeemed once.
+    pub async fn redeem(&self, code: &str) -> Option<AuthorizationGrant> {
+        self.codes.take(&secret::hash(code)).await
+    }
+}
+
+/// Splits a space-separated `scope` parameter, rejecting unknown scopes.
+pub fn parse_scopes(scope: &str) -> Result<Vec<String>, AuthError> {
+    let mut requested: Vec<String> = scope.split_ascii_whitespace().map(str::to_string).collect();
+    if requested.is_empty() || requested.iter().any(|s| !scopes::ALL.contains(&s.as_str())) {
+        return Err(AuthError::OAuth(OAuthErrorCode::InvalidScope));
+    }
+    requested.sort();
+    requested.dedup();
+    Ok(requested)
+}
+
+/// Checks a PKCE verifier against the challenge sent with the authorization request.
+pub fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    // RFC 7636 section 4.1: 43 to 128 unreserved characters.
+    let well_formed = (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'));
+    well_formed && URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes())) == code_challenge
+}
+
+/// Accepts absolute `https` URIs, and `http` ones on the loopback interface
+/// for apps under development. Fragments are not allowed (RFC 6749 section 3.1.2).
+pub fn validate_redirect_uri(uri: &str) -> Result<(), AuthError> {
+    let invalid = || AuthError::Validation(format!("Invalid redirect URI: {}", uri));
+    let url = Url::parse(uri).map_err(|_| invalid())?;
+    let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    match url.scheme() {
+        _ if url.fragment().is_some() => Err(invalid()),
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+/// `redirect_uri` with `params` appended to its query.
+pub fn redirect_to(redirect_uri: &str, params: &[(&str, &str)]) -> Result<String, AuthError> {
+    let mut url = Url::parse(redirect_uri).map_err(|_| AuthError::InternalError)?;
+    url.query_pairs_mut().extend_pairs(params);
+    Ok(url.into())
+}
+
+/// Client id and secret from an `Authorization: Basic` header
+/// (`client_secret_basic`, RFC 6749 section 2.3.1).
+pub fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    // Both parts are form-encoded before being joined.
+    let decode = |part: &str| {
+        url::form_urlencoded::parse(format!("x={}", part).as_bytes())
+            .next()
+            .map(|(_, value)| value.into_owned())
+    };
+    Some((decode(client_id)?, decode(client_secret)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_pkce_s256() {
+        // Example from RFC 7636 appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert!(verify_pkce(verifier, challenge));
+        assert!(!verify_pkce("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXK", challenge));
+        assert!(!verify_pkce("short", challenge));
+    }
+
+    #[test]
+    fn test_redirect_uris() {
+        assert!(validate_redirect_uri("https://partner.example/callback").is_ok());
+        assert!(validate_redirect_uri("http://127.0.0.1:8400/cb").is_ok());
+        assert!(validate_redirect_uri("http://partner.example/callback").is_err());
+        assert!(validate_redirect_uri("https://partner.example/cb#frag").is_err());
+        assert!(validate_redirect_uri("javascript:alert(1)").is_err());
+
+        let target = redirect_to("https://partner.example/cb?app=1", &[("code", "a b"), ("state", "xyz")]).unwrap();
+        assert_eq!(target, "https://partner.example/cb?app=1&code=a+b&state=xyz");
+    }
+
+    #[test]
+    fn test_scopes() {
+        assert_eq!(parse_scopes("media:write openid  openid").unwrap(), vec!["media:write", "openid"]);
+        assert!(matches!(parse_scopes("openid admin"), Err(AuthError::OAuth(OAuthErrorCode::InvalidScope))));
+        assert!(parse_scopes(" ").is_err());
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Basic {}", STANDARD.encode("partner%3Aapp:s3cr%2Bt"))))
+            .to_http_request();
+        assert_eq!(basic_credentials(&req), Some(("partner:app".to_string(), "s3cr+t".to_string())));
+
+        let req = TestRequest::default().insert_header(("Authorization", "Bearer abc")).to_http_request();
+        assert_eq!(basic_credentials(&req), None);
+    }
+}
@@ -0,0 +1,78 @@
+//! Third-party apps registered to sign users in through OAuth 2.0.
+//!
+//! Confidential clients (apps with a backend) get a secret, of which only the
+//! digest is stored. Public clients (mobile and single-page apps) have none
+//! and rely on PKCE alone.
+
+use std::{collections::HashMap, sync::Mutex};
+use async_trait::async_trait;
+use crate::error::AuthError;
+
+#[derive(Debug, Clone)]
+pub struct OAuthClientRecord {
+    pub client_id: String,
+    /// SHA-256 digest of the client secret, see [`crate::secret::hash`].
+    /// `None` for public clients.
+    pub secret_hash: Option<String>,
+    pub name: String,
+    /// Exact URIs the authorization code may be sent to.
+    pub redirect_uris: Vec<String>,
+    /// User who registered the app.
+    pub owner_id: i32,
+    pub created_at: u64,
+}
+
+#[async_trait]
+pub trait OAuthClientStore: Send + Sync {
+    async fn insert(&self, record: OAuthClientRecord) -> Result<(), AuthError>;
+
+    async fn find(&self, client_id: &str) -> Result<Option<OAuthClientRecord>, AuthError>;
+
+    /// Clients registered by the user, oldest first.
+    async fn list(&self, owner_id: i32) -> Result<Vec<OAuthClientRecord>, AuthError>;
+
+    /// Deletes a client of the user. Returns `false` if the user has no such client.
+    async fn delete(&self, owner_id: i32, client_id: &str) -> Result<bool, AuthError>;
+}
+
+/// Keeps OAuth clients in process memory. Intended for tests and local development.
+#[derive(Default)]
+pub struct InMemoryOAuthClientStore {
+    clients: Mutex<HashMap<String, OAuthClientRecord>>,
+}
+
+impl InMemoryOAuthClientStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl OAuthClientStore for InMemoryOAuthClientStore {
+    async fn insert(&self, record: OAuthClientRecord) -> Result<(), AuthError> {
+        let mut clients = self.clients.lock().map_err(|_| AuthError::InternalError)?;
+        clients.insert(record.client_id.clone(), record);
+        Ok(())
+    }
+
+    async fn find(&self, client_id: &str) -> Result<Option<OAuthClientRecord>, AuthError> {
+        let clients = self.clients.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(clients.get(client_id).cloned())
+    }
+
+    async fn list(&self, owner_id: i32) -> Result<Vec<OAuthClientRecord>, AuthError> {
+        let clients = self.clients.lock().map_err(|_| AuthError::InternalError)?;
+        let mut list: Vec<_> = clients.values().filter(|c| c.owner_id == owner_id).cloned().collect();
+        list.sort_by_key(|c| c.created_at);
+        Ok(list)
+    }
+
+    async fn delete(&self, owner_id: i32, client_id: &str) -> Result<bool, AuthError> {
+        let mut clients = self.clients.lock().map_err(|_| AuthError::InternalError)?;
+        if clients.get(client_id).is_some_and(|c| c.owner_id == owner_id) {
+            clients.remove(client_id);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,29 @@
+//! OpenID Connect ID tokens.
+//!
+//! ID tokens are verified by third-party apps, so they are always signed with
+//! an asymmetric key from the [`crate::keys::KeyStore`], whose public half is
+//! published as a JWK Set. See [`crate::keys::KeyStore::id_token_algorithm`].
+
+use serde::{Deserialize, Serialize};
+
+/// Claims of an ID token (OpenID Connect Core section 2).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    /// User id.
+    pub sub: String,
+    /// Client id of the app the token is for.
+    pub aud: String,
+    pub iat: u64,
+    pub exp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// With the `profile` scope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    /// With the `email` scope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
@@ -0,0 +1,96 @@
+//! Server-side refresh token storage.
+//!
+//! Refresh tokens are single-use. Every token issued from one login shares a
+//! `family_id`; presenting an already used token means it leaked, so the whole
+//! family is revoked and the session has to log in again.
+
+use std::{collections::HashMap, sync::Mutex};
+use async_trait::async_trait;
+use jsonwebtoken::get_current_timestamp;
+use uuid::Uuid;
+use crate::error::AuthError;
+
+#[derive(Debug, Clone)]
+pub struct RefreshTokenRecord {
+    /// SHA-256 digest of the token, see [`crate::secret::hash`].
+    pub token_hash: String,
+    pub family_id: Uuid,
+    pub user_id: i32,
+    pub expires_at: u64,
+    pub used: bool,
+}
+
+impl RefreshTokenRecord {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= get_current_timestamp()
+    }
+}
+
+#[async_trait]
+pub trait RefreshTokenStore: Send + Sync {
+    async fn insert(&self, record: RefreshTokenRecord) -> Result<(), AuthError>;
+
+    async fn find(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AuthError>;
+
+    /// Marks the token as used and returns its record as it was before the call.
+    ///
+    /// Must be atomic: of two concurrent calls for the same token, only one may
+    /// observe `used == false`.
+    async fn consume(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AuthError>;
+
+    /// Deletes every token of the family, used or not.
+    async fn revoke_family(&self, family_id: Uuid) -> Result<(), AuthError>;
+
+    /// Deletes every token belonging to the user.
+    async fn revoke_user(&self, user_id: i32) -> Result<(), AuthError>;
+}
+
+/// Keeps refresh tokens in process memory.
+///
+/// Used tokens are kept until they expire so that reuse can still be detected.
+#[derive(Default)]
+pub struct InMemoryRefreshTokenStore {
+    tokens: Mutex<HashMap<String, RefreshTokenRecord>>,
+}
+
+impl InMemoryRefreshTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RefreshTokenStore for InMemoryRefreshTokenStore {
+    async fn insert(&self, record: RefreshTokenRecord) -> Result<(), AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        tokens.retain(|_, r| !r.is_expired());
+        tokens.insert(record.token_hash.clone(), record);
+        Ok(())
+    }
+
+    async fn find(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AuthError> {
+        let tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(tokens.get(token_hash).cloned())
+    }
+
+    async fn consume(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(tokens.get_mut(token_hash).map(|record| {
+            let before = record.clone();
+            record.used = true;
+            before
+        }))
+    }
+
+    async fn revoke_family(&self, family_id: Uuid) -> Result<(), AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        tokens.retain(|_, r| r.family_id != family_id);
+        Ok(())
+    }
+
+    async fn revoke_user(&self, user_id: i32) -> Result<(), AuthError> {
+        let mut tokens = self.tokens.lock().map_err(|_| AuthError::InternalError)?;
+        tokens.retain(|_, r| r.user_id != user_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::RwLock;
+use async_trait::async_trait;
+use socialhub_core::auth::Role;
+use super::{NewUser, UserRepository};
+use crate::{error::AuthError, models::User};
+
+/// Keeps users in process memory. Intended for tests and local development.
+#[derive(Default)]
+pub struct InMemoryUserRepository {
+    users: RwLock<Vec<User>>,
+}
+
+impl InMemoryUserRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn create(&self, user: NewUser) -> Result<User, AuthError> {
+        let mut users = self.users.write().map_err(|_| AuthError::InternalError)?;
+
+        if users.iter().any(|u| u.username.eq_ignore_ascii_case(&user.username)) {
+            return Err(AuthError::UsernameTaken);
+        }
+        if users.iter().any(|u| u.email.eq_ignore_ascii_case(&user.email)) {
+            return Err(AuthError::EmailTaken);
+        }
+
+        let created = User {
+            // Users can be deleted, so the count is no good as the next id.
+            id: users.iter().map(|u| u.id).max().unwrap_or(0) + 1,
+            username: user.username,
+            email: user.email,
+            email_verified: false,
+            password_hash: user.password_hash,
+            roles: vec![Role::User],
+            permissions: Vec::new(),
+            deletion_due_at: None,
+        };
+        users.push(created.clone());
+        Ok(created)
+    }
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<User>, AuthError> {
+        let users = self.users.read().map_err(|_| AuthError::InternalError)?;
+        Ok(users.iter().find(|u| u.id == id).cloned())
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
+        let users = self.users.read().map_err(|_| AuthError::InternalError)?;
+        Ok(users.iter().find(|u| u.username.eq_ignore_ascii_case(username)).cloned())
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+        let users = self.users.read().map_err(|_| AuthError::InternalError)?;
+        Ok(users.iter().find(|u| u.email.eq_ignore_ascii_case(email)).cloned())
+    }
+
+    async fn update_password(&self, id: i32, password_hash: String) -> Result<bool, AuthError> {
+        let mut users = self.users.write().map_err(|_| AuthError::InternalError)?;
+        Ok(users.iter_mut().find(|u| u.id == id).map(|user| user.password_hash = password_hash).is_some())
+    }
+
+    async fn mark_email_verified(&self, id: i32, email: &str) -> Result<bool, AuthError> {
+        let mut users = self.users.write().map_err(|_| AuthError::InternalError)?;
+        Ok(users
+            .iter_mut()
+            .find(|u| u.id == id && u.email.eq_ignore_ascii_case(email))
+            .map(|user| user.email_verified = true)
+            .is_some())
+    }
+
+    async fn update_roles(
+        &self,
+        id: i32,
+        roles: Vec<Role>,
+        permissions: Vec<String>,
+    ) -> Result<Option<User>, AuthError> {
+        let mut users = self.users.write().map_err(|_| AuthError::InternalError)?;
+        Ok(users.iter_mut().find(|u| u.id == id).map(|user| {
+            user.roles = roles;
+            user.permissions = permissions;
+            user.clone()
+        }))
+    }
+
+    async fn schedule_deletion(&self, id: i32, due_at: Option<u64>) -> Result<bool, AuthError> {
+        let mut users = self.users.write().map_err(|_| AuthError::InternalError)?;
+        Ok(users.iter_mut().find(|u| u.id == id).map(|user| user.deletion_due_at = due_at).is_some())
+    }
+
+    async fn due_for_deletion(&self, now: u64) -> Result<Vec<i32>, AuthError> {
+        let users = self.users.read().map_err(|_| AuthError::InternalError)?;
+        Ok(users
+            .iter()
+            .filter(|u| u.deletion_due_at.is_some_and(|due_at| due_at <= now))
+            .map(|u| u.id)
+            .collect())
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, AuthError> {
+        let mut users = self.users.write().map_err(|_| AuthError::InternalError)?;
+        let before = users.len();
+        users.retain(|u| u.id != id);
+        Ok(users.len() < before)
+    }
+}
@@ -0,0 +1,64 @@
+//! User storage.
+//!
+//! Handlers only talk to [`UserRepository`]; production uses
+//! [`PgUserRepository`] and tests use [`InMemoryUserRepository`].
+
+use async_trait::async_trait;
+use socialhub_core::auth::Role;
+use crate::{error::AuthError, models::User};
+
+mod memory;
+mod postgres;
+
+pub use memory::InMemoryUserRepository;
+pub use postgres::PgUserRepository;
+
+/// A user that has not been stored yet.
+#[derive(Debug, Clone)]
+pub struct NewUser {
+    pub username: String,
+    pub email: String,
+    pub password_hash: String,
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Stores a new user.
+    ///
+    /// Fails with [`AuthError::UsernameTaken`] or [`AuthError::EmailTaken`] when
+    /// either value is already in use, compared case-insensitively.
+    async fn create(&self, user: NewUser) -> Result<User, AuthError>;
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<User>, AuthError>;
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AuthError>;
+
+    /// Looks a user up by email address, compared case-insensitively.
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError>;
+
+    /// Replaces the password hash. Returns `false` if there is no such user.
+    async fn update_password(&self, id: i32, password_hash: String) -> Result<bool, AuthError>;
+
+    /// Marks the address of the user as verified, provided it still is
+    /// `email`. Returns `false` otherwise.
+    async fn mark_email_verified(&self, id: i32, email: &str) -> Result<bool, AuthError>;
+
+    /// Replaces the roles and extra permissions of a user. Returns `None` if
+    /// there is no such user.
+    async fn update_roles(
+        &self,
+        id: i32,
+        roles: Vec<Role>,
+        permissions: Vec<String>,
+    ) -> Result<Option<User>, AuthError>;
+
+    /// Sets or, with `None`, clears the time at which the account is erased.
+    /// Returns `false` if there is no such user.
+    async fn schedule_deletion(&self, id: i32, due_at: Option<u64>) -> Result<bool, AuthError>;
+
+    /// Users whose deletion was due at or before `now`.
+    async fn due_for_deletion(&self, now: u64) -> Result<Vec<i32>, AuthError>;
+
+    /// Removes a user for good. Returns `false` if there is no such user.
+    async fn delete(&self, id: i32) -> Result<bool, AuthError>;
+}
@@ -0,0 +1,1022 @@
+use actix_web::web;
+use async_trait::async_trait;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+    result::{DatabaseErrorKind, Error as DieselError},
+};
+use jsonwebtoken::get_current_timestamp;
+use log::{error, warn};
+use socialhub_core::auth::Role;
+use super::{NewUser, UserRepository};
+use uuid::Uuid;
+use crate::{
+    access_token::{AccessTokenRecord, AccessTokenStore},
+    audit::{AuditEvent, AuditFilter, AuditLog, NewAuditEvent},
+    email_token::{EmailTokenPurpose, EmailTokenRecord, EmailTokenStore},
+    error::AuthError,
+    invite::{InviteRecord, InviteStore},
+    keys::{SigningKeyRecord, SigningKeyStore},
+    models::User,
+    oauth_client::{OAuthClientRecord, OAuthClientStore},
+    schema::{audit_events, email_tokens, invites, oauth_clients, personal_access_tokens, signing_keys, user_sessions, user_totp, users},
+    session::{SessionRecord, SessionStore},
+    two_factor::{TwoFactorRecord, TwoFactorStore},
+};
+
+type PgPool = Pool<ConnectionManager<PgConnection>>;
+
+define_sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+define_sql_function!(fn array_remove(
+    array: diesel::sql_types::Array<diesel::sql_types::Text>,
+    element: diesel::sql_types::Text
+) -> diesel::sql_types::Array<diesel::sql_types::Text>);
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = users)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct UserRow {
+    id: i32,
+    username: String,
+    email: String,
+    password_hash: String,
+    roles: Vec<String>,
+    permissions: Vec<String>,
+    email_verified: bool,
+    deletion_due_at: Option<i64>,
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        let roles = row.roles
+            .iter()
+            .filter_map(|role| match role.parse() {
+                Ok(role) => Some(role),
+                Err(e) => {
+                    warn!("Ignoring role of user {}: {}", row.id, e);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            id: row.id,
+            username: row.username,
+            email: row.email,
+            email_verified: row.email_verified,
+            password_hash: row.password_hash,
+            roles,
+            permissions: row.permissions,
+            deletion_due_at: row.deletion_due_at.map(|due_at| due_at as u64),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+struct NewUserRow {
+    username: String,
+    email: String,
+    password_hash: String,
+}
+
+#[derive(Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = user_totp)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct TwoFactorRow {
+    user_id: i32,
+    secret: Vec<u8>,
+    enabled: bool,
+    recovery_codes: Vec<String>,
+    last_used_step: i64,
+}
+
+impl From<TwoFactorRow> for TwoFactorRecord {
+    fn from(row: TwoFactorRow) -> Self {
+        Self {
+            user_id: row.user_id,
+            secret: row.secret,
+            enabled: row.enabled,
+            recovery_codes: row.recovery_codes,
+            last_used_step: row.last_used_step as u64,
+        }
+    }
+}
+
+impl From<TwoFactorRecord> for TwoFactorRow {
+    fn from(record: TwoFactorRecord) -> Self {
+        Self {
+            user_id: record.user_id,
+            secret: record.secret,
+            enabled: record.enabled,
+            recovery_codes: record.recovery_codes,
+            last_used_step: record.last_used_step as i64,
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = email_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct EmailTokenRow {
+    token_hash: String,
+    user_id: i32,
+    purpose: String,
+    email: String,
+    /// Unix time in seconds.
+    expires_at: i64,
+}
+
+impl From<EmailTokenRecord> for EmailTokenRow {
+    fn from(record: EmailTokenRecord) -> Self {
+        Self {
+            token_hash: record.token_hash,
+            user_id: record.user_id,
+            purpose: record.purpose.as_str().to_string(),
+            email: record.email,
+            expires_at: record.expires_at as i64,
+        }
+    }
+}
+
+impl EmailTokenRow {
+    fn into_record(self, purpose: EmailTokenPurpose) -> EmailTokenRecord {
+        EmailTokenRecord {
+            token_hash: self.token_hash,
+            user_id: self.user_id,
+            purpose,
+            email: self.email,
+            expires_at: self.expires_at as u64,
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = personal_access_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct AccessTokenRow {
+    id: Uuid,
+    user_id: i32,
+    name: String,
+    token_hash: String,
+    scopes: Vec<String>,
+    created_at: i64,
+    expires_at: Option<i64>,
+    last_used_at: Option<i64>,
+}
+
+impl From<AccessTokenRow> for AccessTokenRecord {
+    fn from(row: AccessTokenRow) -> Self {
+        Self {
+            id: row.id,
+            user_id: row.user_id,
+            name: row.name,
+            scopes: row.scopes,
+            token_hash: row.token_hash,
+            created_at: row.created_at as u64,
+            expires_at: row.expires_at.map(|t| t as u64),
+            last_used_at: row.last_used_at.map(|t| t as u64),
+        }
+    }
+}
+
+impl From<AccessTokenRecord> for AccessTokenRow {
+    fn from(record: AccessTokenRecord) -> Self {
+        Self {
+            id: record.id,
+            user_id: record.user_id,
+            name: record.name,
+            token_hash: record.token_hash,
+            scopes: record.scopes,
+            created_at: record.created_at as i64,
+            expires_at: record.expires_at.map(|t| t as i64),
+            last_used_at: record.last_used_at.map(|t| t as i64),
+        }
+    }
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = audit_events)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct AuditEventRow {
+    id: i64,
+    occurred_at: i64,
+    action: String,
+    outcome: String,
+    actor_id: Option<i32>,
+    target_id: Option<i32>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    details: serde_json::Value,
+}
+
+impl TryFrom<AuditEventRow> for AuditEvent {
+    type Error = AuthError;
+
+    fn try_from(row: AuditEventRow) -> Result<Self, Self::Error> {
+        let invalid = |e: String| {
+            error!("Invalid audit event {}: {}", row.id, e);
+            AuthError::InternalError
+        };
+        Ok(Self {
+            id: row.id,
+            occurred_at: row.occurred_at as u64,
+            action: row.action.parse().map_err(invalid)?,
+            outcome: row.outcome.parse().map_err(invalid)?,
+            actor_id: row.actor_id,
+            target_id: row.target_id,
+            ip: row.ip,
+            user_agent: row.user_agent,
+            details: row.details,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = audit_events)]
+struct NewAuditEventRow {
+    occurred_at: i64,
+    action: &'static str,
+    outcome: &'static str,
+    actor_id: Option<i32>,
+    target_id: Option<i32>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    details: serde_json::Value,
+}
+
+impl From<NewAuditEvent> for NewAuditEventRow {
+    fn from(event: NewAuditEvent) -> Self {
+        Self {
+            occurred_at: get_current_timestamp() as i64,
+            action: event.action.as_str(),
+            outcome: event.outcome.as_str(),
+            actor_id: event.actor_id,
+            target_id: event.target_id,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            details: serde_json::Value::Object(event.details),
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = invites)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct InviteRow {
+    id: Uuid,
+    code_hash: String,
+    created_by: i32,
+    max_uses: i32,
+    uses: i32,
+    created_at: i64,
+    expires_at: Option<i64>,
+}
+
+impl From<InviteRow> for InviteRecord {
+    fn from(row: InviteRow) -> Self {
+        Self {
+            id: row.id,
+            code_hash: row.code_hash,
+            created_by: row.created_by,
+            max_uses: row.max_uses as u32,
+            uses: row.uses as u32,
+            created_at: row.created_at as u64,
+            expires_at: row.expires_at.map(|t| t as u64),
+        }
+    }
+}
+
+impl From<InviteRecord> for InviteRow {
+    fn from(record: InviteRecord) -> Self {
+        Self {
+            id: record.id,
+            code_hash: record.code_hash,
+            created_by: record.created_by,
+            max_uses: record.max_uses as i32,
+            uses: record.uses as i32,
+            created_at: record.created_at as i64,
+            expires_at: record.expires_at.map(|t| t as i64),
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = user_sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct SessionRow {
+    id: Uuid,
+    user_id: i32,
+    device_name: Option<String>,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    created_at: i64,
+    last_seen_at: i64,
+    impersonator_id: Option<i32>,
+}
+
+impl From<SessionRow> for SessionRecord {
+    fn from(row: SessionRow) -> Self {
+        Self {
+            id: row.id,
+            user_id: row.user_id,
+            device_name: row.device_name,
+            user_agent: row.user_agent,
+            ip: row.ip,
+            created_at: row.created_at as u64,
+            last_seen_at: row.last_seen_at as u64,
+            impersonator_id: row.impersonator_id,
+        }
+    }
+}
+
+impl From<SessionRecord> for SessionRow {
+    fn from(record: SessionRecord) -> Self {
+        Self {
+            id: record.id,
+            user_id: record.user_id,
+            device_name: record.device_name,
+            user_agent: record.user_agent,
+            ip: record.ip,
+            created_at: record.created_at as i64,
+            last_seen_at: record.last_seen_at as i64,
+            impersonator_id: record.impersonator_id,
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = oauth_clients)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct OAuthClientRow {
+    client_id: String,
+    secret_hash: Option<String>,
+    name: String,
+    redirect_uris: Vec<String>,
+    owner_id: i32,
+    created_at: i64,
+}
+
+impl From<OAuthClientRow> for OAuthClientRecord {
+    fn from(row: OAuthClientRow) -> Self {
+        Self {
+            client_id: row.client_id,
+            secret_hash: row.secret_hash,
+            name: row.name,
+            redirect_uris: row.redirect_uris,
+            owner_id: row.owner_id,
+            created_at: row.created_at as u64,
+        }
+    }
+}
+
+impl From<OAuthClientRecord> for OAuthClientRow {
+    fn from(record: OAuthClientRecord) -> Self {
+        Self {
+            client_id: record.client_id,
+            secret_hash: record.secret_hash,
+            name: record.name,
+            redirect_uris: record.redirect_uris,
+            owner_id: record.owner_id,
+            created_at: record.created_at as i64,
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = signing_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct SigningKeyRow {
+    kid: String,
+    algorithm: String,
+    private_key: Vec<u8>,
+    created_at: i64,
+    active_from: i64,
+}
+
+impl From<SigningKeyRow> for SigningKeyRecord {
+    fn from(row: SigningKeyRow) -> Self {
+        Self {
+            kid: row.kid,
+            algorithm: row.algorithm,
+            private_key: row.private_key,
+            created_at: row.created_at as u64,
+            active_from: row.active_from as u64,
+        }
+    }
+}
+
+impl From<SigningKeyRecord> for SigningKeyRow {
+    fn from(record: SigningKeyRecord) -> Self {
+        Self {
+            kid: record.kid,
+            algorithm: record.algorithm,
+            private_key: record.private_key,
+            created_at: record.created_at as i64,
+            active_from: record.active_from as i64,
+        }
+    }
+}
+
+/// Postgres-backed user store. Queries run on actix's blocking thread pool.
+#[derive(Clone)]
+pub struct PgUserRepository {
+    pool: PgPool,
+}
+
+impl PgUserRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Opens a connection pool against `database_url`.
+    pub fn connect(database_url: &str, max_connections: u32) -> Result<Self, AuthError> {
+        let manager = ConnectionManager::<PgConnection>::new(database_url);
+        let pool = Pool::builder()
+            .max_size(max_connections)
+            .build(manager)
+            .map_err(|e| {
+                error!("Failed to create Postgres pool: {}", e);
+                AuthError::InternalError
+            })?;
+        Ok(Self::new(pool))
+    }
+
+    async fn run<F, T>(&self, query: F) -> Result<T, AuthError>
+    where
+        F: FnOnce(&mut PgConnection) -> Result<T, DieselError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        web::block(move || {
+            let mut conn = pool.get().map_err(|e| {
+                error!("Failed to get Postgres connection: {}", e);
+                AuthError::InternalError
+            })?;
+            query(&mut conn).map_err(map_error)
+        })
+        .await
+        .map_err(|_| AuthError::InternalError)?
+    }
+}
+
+fn map_error(err: DieselError) -> AuthError {
+    match &err {
+        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+            match info.constraint_name() {
+                Some("users_email_key") => AuthError::EmailTaken,
+                _ => AuthError::UsernameTaken,
+            }
+        }
+        _ => {
+            error!("Postgres query failed: {}", err);
+            AuthError::InternalError
+        }
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgUserRepository {
+    async fn create(&self, user: NewUser) -> Result<User, AuthError> {
+        let row = NewUserRow {
+            username: user.username,
+            email: user.email,
+            password_hash: user.password_hash,
+        };
+        self.run(move |conn| {
+            diesel::insert_into(users::table)
+                .values(&row)
+                .returning(UserRow::as_returning())
+                .get_result(conn)
+        })
+        .await
+        .map(User::from)
+    }
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<User>, AuthError> {
+        self.run(move |conn| {
+            users::table
+                .find(id)
+                .select(UserRow::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await
+        .map(|row| row.map(User::from))
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
+        let username = username.to_lowercase();
+        self.run(move |conn| {
+            users::table
+                .filter(lower(users::username).eq(username))
+                .select(UserRow::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await
+        .map(|row| row.map(User::from))
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+        let email = email.to_lowercase();
+        self.run(move |conn| {
+            users::table
+                .filter(lower(users::email).eq(email))
+                .select(UserRow::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await
+        .map(|row| row.map(User::from))
+    }
+
+    async fn update_password(&self, id: i32, password_hash: String) -> Result<bool, AuthError> {
+        self.run(move |conn| {
+            diesel::update(users::table.find(id))
+                .set(users::password_hash.eq(password_hash))
+                .execute(conn)
+        })
+        .await
+        .map(|updated| updated == 1)
+    }
+
+    async fn mark_email_verified(&self, id: i32, email: &str) -> Result<bool, AuthError> {
+        let email = email.to_lowercase();
+        self.run(move |conn| {
+            diesel::update(
+                users::table
+                    .find(id)
+                    .filter(lower(users::email).eq(email))
+            )
+            .set(users::email_verified.eq(true))
+            .execute(conn)
+        })
+        .await
+        .map(|updated| updated == 1)
+    }
+
+    async fn update_roles(
+        &self,
+        id: i32,
+        roles: Vec<Role>,
+        permissions: Vec<String>,
+    ) -> Result<Option<User>, AuthError> {
+        let roles: Vec<String> = roles.iter().map(Role::to_string).collect();
+        self.run(move |conn| {
+            diesel::update(users::table.find(id))
+                .set((users::roles.eq(roles), users::permissions.eq(permissions)))
+                .returning(UserRow::as_returning())
+                .get_result(conn)
+                .optional()
+        })
+        .await
+        .map(|row| row.map(User::from))
+    }
+
+    async fn schedule_deletion(&self, id: i32, due_at: Option<u64>) -> Result<bool, AuthError> {
+        let due_at = due_at.map(|due_at| due_at as i64);
+        self.run(move |conn| {
+            diesel::update(users::table.find(id))
+                .set(users::deletion_due_at.eq(due_at))
+                .execute(conn)
+        })
+        .await
+        .map(|updated| updated == 1)
+    }
+
+    async fn due_for_deletion(&self, now: u64) -> Result<Vec<i32>, AuthError> {
+        self.run(move |conn| {
+            users::table
+                .filter(users::deletion_due_at.le(now as i64))
+                .select(users::id)
+                .load(conn)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, AuthError> {
+        // Sessions, tokens and the other tables of the user cascade.
+        self.run(move |conn| diesel::delete(users::table.find(id)).execute(conn))
+            .await
+            .map(|deleted| deleted == 1)
+    }
+}
+
+/// Two-factor enrollments live in the `user_totp` table next to the users.
+#[async_trait]
+impl TwoFactorStore for PgUserRepository {
+    async fn find(&self, user_id: i32) -> Result<Option<TwoFactorRecord>, AuthError> {
+        self.run(move |conn| {
+            user_totp::table
+                .find(user_id)
+                .select(TwoFactorRow::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await
+        .map(|row| row.map(TwoFactorRecord::from))
+    }
+
+    async fn save(&self, record: TwoFactorRecord) -> Result<(), AuthError> {
+        let row = TwoFactorRow::from(record);
+        self.run(move |conn| {
+            diesel::insert_into(user_totp::table)
+                .values(&row)
+                .on_conflict(user_totp::user_id)
+                .do_update()
+                .set(&row)
+                .execute(conn)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn delete(&self, user_id: i32) -> Result<(), AuthError> {
+        self.run(move |conn| diesel::delete(user_totp::table.find(user_id)).execute(conn))
+            .await
+            .map(|_| ())
+    }
+
+    async fn use_step(&self, user_id: i32, step: u64) -> Result<bool, AuthError> {
+        let step = step as i64;
+        self.run(move |conn| {
+            diesel::update(
+                user_totp::table
+                    .find(user_id)
+                    .filter(user_totp::last_used_step.lt(step))
+            )
+            .set(user_totp::last_used_step.eq(step))
+            .execute(conn)
+        })
+        .await
+        .map(|updated| updated == 1)
+    }
+
+    async fn use_recovery_code(&self, user_id: i32, code_hash: &str) -> Result<bool, AuthError> {
+        let code_hash = code_hash.to_string();
+        self.run(move |conn| {
+            diesel::update(
+                user_totp::table
+                    .find(user_id)
+                    .filter(user_totp::recovery_codes.contains(vec![code_hash.clone()]))
+            )
+            .set(user_totp::recovery_codes.eq(array_remove(user_totp::recovery_codes, code_hash)))
+            .execute(conn)
+        })
+        .await
+        .map(|updated| updated == 1)
+    }
+}
+
+/// Password reset and verification tokens live in the `email_tokens` table.
+#[async_trait]
+impl EmailTokenStore for PgUserRepository {
+    async fn insert(&self, record: EmailTokenRecord) -> Result<(), AuthError> {
+        let row = EmailTokenRow::from(record);
+        let now = get_current_timestamp() as i64;
+        self.run(move |conn| {
+            diesel::delete(email_tokens::table.filter(email_tokens::expires_at.le(now)))
+                .execute(conn)?;
+            diesel::insert_into(email_tokens::table).values(&row).execute(conn)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn consume(
+        &self,
+        token_hash: &str,
+        purpose: EmailTokenPurpose,
+    ) -> Result<Option<EmailTokenRecord>, AuthError> {
+        let token_hash = token_hash.to_string();
+        self.run(move |conn| {
+            diesel::delete(
+                email_tokens::table
+                    .find(token_hash)
+                    .filter(email_tokens::purpose.eq(purpose.as_str()))
+            )
+            .returning(EmailTokenRow::as_returning())
+            .get_result(conn)
+            .optional()
+        })
+        .await
+        .map(|row| row.map(|row| row.into_record(purpose)))
+    }
+
+    async fn revoke_user(&self, user_id: i32, purpose: EmailTokenPurpose) -> Result<(), AuthError> {
+        self.run(move |conn| {
+            diesel::delete(
+                email_tokens::table
+                    .filter(email_tokens::user_id.eq(user_id))
+                    .filter(email_tokens::purpose.eq(purpose.as_str()))
+            )
+            .execute(conn)
+        })
+        .await
+        .map(|_| ())
+    }
+}
+
+/// Personal access tokens live in the `personal_access_tokens` table.
+#[async_trait]
+impl AccessTokenStore for PgUserRepository {
+    async fn insert(&self, record: AccessTokenRecord) -> Result<(), AuthError> {
+        let row = AccessTokenRow::from(record);
+        self.run(move |conn| {
+            diesel::insert_into(personal_access_tokens::table).values(&row).execute(conn)
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn list(&self, user_id: i32) -> Result<Vec<AccessTokenRecord>, AuthError> {
+        self.run(move |conn| {
+            personal_access_tokens::table
+                .filter(personal_access_tokens::user_id.eq(user_id))
+                .order(personal_access_tokens::created_at.asc())
+                .select(AccessTokenRow::as_select())
+                .load(conn)
+        })
+        .await
+        .map(|rows| rows.into_iter().map(AccessTokenRecord::from).collect())
+    }
+
+    async fn find(&self, token_hash: &str) -> Result<Option<AccessTokenRecord>, AuthError> {
+        let token_hash = token_hash.to_string();
+        self.run(move |conn| {
+            personal_access_tokens::table
+                .filter(personal_access_tokens::token_hash.eq(token_hash))
+                .select(AccessTokenRow::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await
+        .map(|row| row.map(AccessTokenRecord::from))
+    }
+
+    async fn delete(&self, user_id: i32, id: Uuid) -> Result<bool, AuthError> {
+        self.run(move |conn| {
+            diesel::delete(
+                personal_access_tokens::table
+                    .find(id)
+                    .filter(personal_access_tokens::user_id.eq(user_id))
+            )
+            .execute(conn)
+        })
+        .await
+        .map(|deleted| deleted == 1)
+    }
+
+    async fn touch(&self, id: Uuid, at: u64) -> Result<(), AuthError> {
+        self.run(move |conn| {
+            diesel::update(personal_access_tokens::table.find(id))
+                .set(personal_access_tokens::last_used_at.eq(at as i64))
+                .execute(conn)
+        })
+        .await
+        .map(|_| ())
+    }
+}
+
+/// Invite codes live in the `invites` table.
+#[async_trait]
+impl InviteStore for PgUserRepository {
+    async fn insert(&self, record: InviteRecord) -> Result<(), AuthError> {
+        let row = InviteRow::from(record);
+        self.run(move |conn| diesel::insert_into(invites::table).values(&row).execute(conn))
+            .await
+            .map(|_| ())
+    }
+
+    async fn list(&self) -> Result<Vec<InviteRecord>, AuthError> {
+        self.run(move |conn| {
+            invites::table
+                .order(invites::created_at.desc())
+                .select(InviteRow::as_select())
+                .load(conn)
+        })
+        .await
+        .map(|rows| rows.into_iter().map(InviteRecord::from).collect())
+    }
+
+    async fn find(&self, code_hash: &str) -> Result<Option<InviteRecord>, AuthError> {
+        let code_hash = code_hash.to_string();
+        self.run(move |conn| {
+            invites::table
+                .filter(invites::code_hash.eq(code_hash))
+                .select(InviteRow::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await
+        .map(|row| row.map(InviteRecord::from))
+    }
+
+    async fn redeem(&self, code_hash: &str, now: u64) -> Result<bool, AuthError> {
+        let code_hash = code_hash.to_string();
+        // A single conditional update, so concurrent registrations cannot
+        // exceed the limit.
+        self.run(move |conn| {
+            diesel::update(
+                invites::table
+                    .filter(invites::code_hash.eq(code_hash))
+                    .filter(invites::uses.lt(invites::max_uses))
+                    .filter(invites::expires_at.is_null().or(invites::expires_at.gt(now as i64)))
+            )
+            .set(invites::uses.eq(invites::uses + 1))
+            .execute(conn)
+        })
+        .await
+        .map(|updated| updated == 1)
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool, AuthError> {
+        self.run(move |conn| diesel::delete(invites::table.find(id)).execute(conn))
+            .await
+            .map(|deleted| deleted == 1)
+    }
+}
+
+/// The `audit_events` table rejects updates and deletes.
+#[async_trait]
+impl AuditLog for PgUserRepository {
+    async fn append(&self, event: NewAuditEvent) -> Result<(), AuthError> {
+        let row = NewAuditEventRow::from(event);
+        self.run(move |conn| diesel::insert_into(audit_events::table).values(&row).execute(conn))
+            .await
+            .map(|_| ())
+    }
+
+    async fn query(&self, filter: &AuditFilter, limit: u32) -> Result<Vec<AuditEvent>, AuthError> {
+        let filter = filter.clone();
+        let rows = self.run(move |conn| {
+            let mut query = audit_events::table.into_boxed();
+            if let Some(action) = filter.action {
+                query = query.filter(audit_events::action.eq(action.as_str()));
+            }
+            if let Some(outcome) = filter.outcome {
+                query = query.filter(audit_events::outcome.eq(outcome.as_str()));
+            }
+            if let Some(actor_id) = filter.actor_id {
+                query = query.filter(audit_events::actor_id.eq(actor_id));
+            }
+            if let Some(target_id) = filter.target_id {
+                query = query.filter(audit_events::target_id.eq(target_id));
+            }
+            if let Some(ip) = filter.ip {
+                query = query.filter(audit_events::ip.eq(ip));
+            }
+            if let Some(since) = filter.since {
+                query = query.filter(audit_events::occurred_at.ge(since as i64));
+            }
+            if let Some(until) = filter.until {
+                query = query.filter(audit_events::occurred_at.le(until as i64));
+            }
+            if let Some(before) = filter.before {
+                query = query.filter(audit_events::id.lt(before));
+            }
+            query
+                .order(audit_events::id.desc())
+                .limit(limit as i64)
+                .select(AuditEventRow::as_select())
+                .load(conn)
+        })
+        .await?;
+        rows.into_iter().map(AuditEvent::try_from).collect()
+    }
+}
+
+/// Login sessions live in the `user_sessions` table.
+#[async_trait]
+impl SessionStore for PgUserRepository {
+    async fn insert(&self, record: SessionRecord) -> Result<(), AuthError> {
+        let row = SessionRow::from(record);
+        self.run(move |conn| diesel::insert_into(user_sessions::table).values(&row).execute(conn))
+            .await
+            .map(|_| ())
+    }
+
+    async fn list(&self, user_id: i32) -> Result<Vec<SessionRecord>, AuthError> {
+        self.run(move |conn| {
+            user_sessions::table
+                .filter(user_sessions::user_id.eq(user_id))
+                .order(user_sessions::last_seen_at.desc())
+                .select(SessionRow::as_select())
+                .load(conn)
+        })
+        .await
+        .map(|rows| rows.into_iter().map(SessionRecord::from).collect())
+    }
+
+    async fn touch(&self, id: Uuid, at: u64, ip: Option<String>) -> Result<(), AuthError> {
+        self.run(move |conn| {
+            let session = user_sessions::table.find(id);
+            match ip {
+                Some(ip) => diesel::update(session)
+                    .set((user_sessions::last_seen_at.eq(at as i64), user_sessions::ip.eq(ip)))
+                    .execute(conn),
+                None => diesel::update(session)
+                    .set(user_sessions::last_seen_at.eq(at as i64))
+                    .execute(conn),
+            }
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn delete(&self, user_id: i32, id: Uuid) -> Result<bool, AuthError> {
+        self.run(move |conn| {
+            diesel::delete(
+                user_sessions::table
+                    .find(id)
+                    .filter(user_sessions::user_id.eq(user_id))
+            )
+            .execute(conn)
+        })
+        .await
+        .map(|deleted| deleted == 1)
+    }
+
+    async fn delete_user(&self, user_id: i32) -> Result<(), AuthError> {
+        self.run(move |conn| {
+            diesel::delete(user_sessions::table.filter(user_sessions::user_id.eq(user_id))).execute(conn)
+        })
+        .await
+        .map(|_| ())
+    }
+}
+
+/// Third-party apps live in the `oauth_clients` table.
+#[async_trait]
+impl OAuthClientStore for PgUserRepository {
+    async fn insert(&self, record: OAuthClientRecord) -> Result<(), AuthError> {
+        let row = OAuthClientRow::from(record);
+        self.run(move |conn| diesel::insert_into(oauth_clients::table).values(&row).execute(conn))
+            .await
+            .map(|_| ())
+    }
+
+    async fn find(&self, client_id: &str) -> Result<Option<OAuthClientRecord>, AuthError> {
+        let client_id = client_id.to_string();
+        self.run(move |conn| {
+            oauth_clients::table
+                .find(client_id)
+                .select(OAuthClientRow::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await
+        .map(|row| row.map(OAuthClientRecord::from))
+    }
+
+    async fn list(&self, owner_id: i32) -> Result<Vec<OAuthClientRecord>, AuthError> {
+        self.run(move |conn| {
+            oauth_clients::table
+                .filter(oauth_clients::owner_id.eq(owner_id))
+                .order(oauth_clients::created_at.asc())
+                .select(OAuthClientRow::as_select())
+                .load(conn)
+        })
+        .await
+        .map(|rows| rows.into_iter().map(OAuthClientRecord::from).collect())
+    }
+
+    async fn delete(&self, owner_id: i32, client_id: &str) -> Result<bool, AuthError> {
+        let client_id = client_id.to_string();
+        self.run(move |conn| {
+            diesel::delete(
+                oauth_clients::table
+                    .find(client_id)
+                    .filter(oauth_clients::owner_id.eq(owner_id))
+            )
+            .execute(conn)
+        })
+        .await
+        .map(|deleted| deleted == 1)
+    }
+}
+
+/// Token signing keys live in the `signing_keys` table, shared by all instances.
+#[async_trait]
+impl SigningKeyStore for PgUserRepository {
+    async fn insert(&self, record: SigningKeyRecord) -> Result<(), AuthError> {
+        let row = SigningKeyRow::from(record);
+        self.run(move |conn| diesel::insert_into(signing_keys::table).values(&row).execute(conn))
+            .await
+            .map(|_| ())
+    }
+
+    async fn list(&self) -> Result<Vec<SigningKeyRecord>, AuthError> {
+        self.run(|conn| signing_keys::table.select(SigningKeyRow::as_select()).load(conn))
+            .await
+            .map(|rows| rows.into_iter().map(SigningKeyRecord::from).collect())
+    }
+
+    async fn delete(&self, kid: &str) -> Result<(), AuthError> {
+        let kid = kid.to_string();
+        self.run(move |conn| diesel::delete(signing_keys::table.find(kid)).execute(conn))
+            .await
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,66 @@
+//! Denylist for access tokens that were revoked before they expired.
+
+use async_trait::async_trait;
+use socialhub_core::cache::{CacheConfig, CacheManager};
+use crate::error::AuthError;
+
+#[async_trait]
+pub trait RevocationStore: Send + Sync {
+    /// Revokes the token with id `jti`. The entry only needs to be kept until
+    /// `expires_at`, after which the token is rejected anyway.
+    async fn revoke_token(&self, jti: &str, expires_at: u64) -> Result<(), AuthError>;
+
+    async fn is_token_revoked(&self, jti: &str) -> Result<bool, AuthError>;
+
+    /// Revokes every token of `user_id` issued at or before `issued_before`.
+    async fn revoke_user_tokens(&self, user_id: i32, issued_before: u64) -> Result<(), AuthError>;
+
+    /// Returns the cutoff set by the last [`RevocationStore::revoke_user_tokens`] call.
+    async fn user_tokens_revoked_at(&self, user_id: i32) -> Result<Option<u64>, AuthError>;
+}
+
+/// Revocation store on top of [`CacheManager`].
+///
+/// Entries live for the access token lifetime, so they always outlast the
+/// tokens they block. The capacity has to cover every revocation made within
+/// one token lifetime: an evicted entry would make its token valid again.
+#[derive(Clone)]
+pub struct CacheRevocationStore {
+    tokens: CacheManager<String, u64>,
+    users: CacheManager<i32, u64>,
+}
+
+impl CacheRevocationStore {
+    pub fn new(token_lifetime: u64, max_capacity: u64) -> Self {
+        let config = CacheConfig {
+            max_capacity,
+            time_to_live: token_lifetime,
+            time_to_idle: token_lifetime,
+        };
+        Self {
+            tokens: CacheManager::new(config.clone()),
+            users: CacheManager::new(config),
+        }
+    }
+}
+
+#[async_trait]
+impl RevocationStore for CacheRevocationStore {
+    async fn revoke_token(&self, jti: &str, expires_at: u64) -> Result<(), AuthError> {
+        self.tokens.set(jti.to_string(), expires_at).await;
+        Ok(())
+    }
+
+    async fn is_token_revoked(&self, jti: &str) -> Result<bool, AuthError> {
+        Ok(self.tokens.get(&jti.to_string()).await.is_some())
+    }
+
+    async fn revoke_user_tokens(&self, user_id: i32, issued_before: u64) -> Result<(), AuthError> {
+        self.users.set(user_id, issued_before).await;
+        Ok(())
+    }
+
+    async fn user_tokens_revoked_at(&self, user_id: i32) -> Result<Option<u64>, AuthError> {
+        Ok(self.users.get(&user_id).await)
+    }
+}
@@ -0,0 +1,39 @@
+//! Opaque secrets handed to clients (refresh tokens, reset links, ...).
+//!
+//! Only the SHA-256 digest of a secret is ever stored server-side.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Returns a URL-safe random string carrying 256 bits of entropy.
+pub fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Digest under which a secret is stored and looked up.
+pub fn hash(secret: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_is_unique_and_url_safe() {
+        let a = generate();
+        let b = generate();
+        assert_ne!(a, b);
+        assert_eq!(a.len(), 43);
+        assert!(a.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    }
+
+    #[test]
+    fn test_hash_is_stable() {
+        assert_eq!(hash("secret"), hash("secret"));
+        assert_ne!(hash("secret"), hash("Secret"));
+    }
+}
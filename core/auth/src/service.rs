@@ -1,23 +1,1870 @@
-use crate::{models::{User, LoginRequest, AuthResponse}, error::AuthError};
+use std::sync::{Arc, OnceLock};
+use actix_web::web;
+use futures::{future::BoxFuture, stream, Stream};
+use jsonwebtoken::get_current_timestamp;
+use log::{error, info, warn};
+use socialhub_core::auth::{
+    effective_permissions, permissions, scopes, AuthRejection, AuthenticatedUser, Role, TokenKind, TokenVerifier,
+};
+use socialhub_core::cache::{CacheConfig, CacheManager};
+use socialhub_core::data_subject::{DataSubjectHook, ExportedFile};
+use crate::{
+    access_token::{AccessTokenRecord, AccessTokenStore, InMemoryAccessTokenStore, TOKEN_PREFIX},
+    audit::{AuditAction, AuditFilter, AuditLog, InMemoryAuditLog, NewAuditEvent, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+    config::AuthConfig,
+    device::{DeviceAuthorizations, DEVICE_CODE_GRANT},
+    email_token::{EmailTokenPurpose, EmailTokenRecord, EmailTokenStore, InMemoryEmailTokenStore},
+    error::{AuthError, OAuthErrorCode},
+    export::{self, AccountExports, ExportArchive},
+    invite::{self, InMemoryInviteStore, InviteRecord, InviteStore, RegistrationErrorCode, RegistrationMode},
+    mailer::{Email, FileMailer, Mailer},
+    models::{
+        AccessToken, AccountDeletion, AccountExport, AuditPage, AuditQuery, AuthorizationRedirect, AuthorizeRequest, ConsentPrompt, CreateAccessTokenRequest,
+        CreateInviteRequest, CreateOAuthClientRequest, ImpersonateRequest, Impersonation, CreatedAccessToken, CreatedInvite, CreatedOAuthClient,
+        DeviceCodeRequest, DeviceCodeResponse, Invite, OAuthClient, OAuthTokenResponse, OpenIdConfiguration, ScopeDescription, User,
+        UserInfo, LoginRequest, LoginResponse, RegisterRequest, AuthResponse, Session, TokenRequest,
+        TokenResponse, TwoFactorChallenge, TwoFactorEnrollment, TwoFactorLoginRequest,
+    },
+    oauth::{self, AuthorizationCodes, AuthorizationGrant, AUTHORIZATION_CODE_GRANT, PKCE_METHOD},
+    oauth_client::{InMemoryOAuthClientStore, OAuthClientRecord, OAuthClientStore},
+    keys::{JwkSet, KeyStore, SigningKeyStore, InMemorySigningKeyStore},
+    oidc::IdTokenClaims,
+    refresh::{InMemoryRefreshTokenStore, RefreshTokenRecord, RefreshTokenStore},
+    repository::{NewUser, UserRepository},
+    revocation::{CacheRevocationStore, RevocationStore},
+    secret,
+    session::{ClientInfo, InMemorySessionStore, SessionRecord, SessionStore},
+    throttle::LoginThrottle,
+    totp,
+    token::{Claims, TokenService},
+    two_factor::{InMemoryTwoFactorStore, TwoFactorRecord, TwoFactorStore},
+};
+use uuid::Uuid;
 
-pub struct AuthService;
+/// Upper bound on revocations kept by the default revocation store.
+const REVOCATION_CAPACITY: u64 = 100_000;
+/// Lifetime of the challenge token handed out by `login` to 2FA accounts, in seconds.
+const CHALLENGE_LIFETIME: u64 = 300;
+/// Wrong codes accepted per challenge before it is revoked.
+const CHALLENGE_MAX_ATTEMPTS: u32 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Personal access tokens a user may hold at once.
+const MAX_ACCESS_TOKENS: usize = 50;
+/// OAuth clients a user may register.
+const MAX_OAUTH_CLIENTS: usize = 20;
+const MAX_REDIRECT_URIS: usize = 10;
+/// Registrations a single invite code may allow.
+const MAX_INVITE_USES: u32 = 10_000;
+/// `last_used_at` of access tokens and `last_seen_at` of sessions are only
+/// written when older than this, in seconds.
+const LAST_USED_RESOLUTION: u64 = 60;
+
+pub struct AuthService {
+    config: AuthConfig,
+    users: Arc<dyn UserRepository>,
+    tokens: TokenService,
+    refresh_tokens: Arc<dyn RefreshTokenStore>,
+    revocations: Arc<dyn RevocationStore>,
+    two_factor: Arc<dyn TwoFactorStore>,
+    challenge_attempts: CacheManager<String, u32>,
+    login_throttle: LoginThrottle,
+    email_tokens: Arc<dyn EmailTokenStore>,
+    mailer: Arc<dyn Mailer>,
+    access_tokens: Arc<dyn AccessTokenStore>,
+    sessions: Arc<dyn SessionStore>,
+    /// Sessions whose `last_seen_at` was written recently.
+    session_activity: CacheManager<Uuid, ()>,
+    devices: DeviceAuthorizations,
+    oauth_clients: Arc<dyn OAuthClientStore>,
+    authorization_codes: AuthorizationCodes,
+    invites: Arc<dyn InviteStore>,
+    audit: Arc<dyn AuditLog>,
+    exports: AccountExports,
+    /// Other crates' part in exporting and erasing accounts.
+    data_subject_hooks: Vec<Arc<dyn DataSubjectHook>>,
+}
 
 impl AuthService {
-    pub async fn login(request: &LoginRequest) -> Result<AuthResponse, AuthError> {
-        // TODO: Implement login logic
+    pub fn new(config: AuthConfig, users: Arc<dyn UserRepository>) -> Self {
+        Self {
+            refresh_tokens: Arc::new(InMemoryRefreshTokenStore::new()),
+            revocations: Arc::new(CacheRevocationStore::new(
+                config.token_expiration,
+                REVOCATION_CAPACITY,
+            )),
+            two_factor: Arc::new(InMemoryTwoFactorStore::new()),
+            challenge_attempts: CacheManager::new(CacheConfig {
+                max_capacity: REVOCATION_CAPACITY,
+                time_to_live: CHALLENGE_LIFETIME,
+                time_to_idle: CHALLENGE_LIFETIME,
+            }),
+            login_throttle: LoginThrottle::new(&config),
+            email_tokens: Arc::new(InMemoryEmailTokenStore::new()),
+            mailer: Arc::new(FileMailer::log_only()),
+            access_tokens: Arc::new(InMemoryAccessTokenStore::new()),
+            sessions: Arc::new(InMemorySessionStore::new()),
+            session_activity: CacheManager::new(CacheConfig {
+                max_capacity: REVOCATION_CAPACITY,
+                time_to_live: LAST_USED_RESOLUTION,
+                time_to_idle: LAST_USED_RESOLUTION,
+            }),
+            devices: DeviceAuthorizations::new(&config),
+            oauth_clients: Arc::new(InMemoryOAuthClientStore::new()),
+            authorization_codes: AuthorizationCodes::new(),
+            invites: Arc::new(InMemoryInviteStore::new()),
+            audit: Arc::new(InMemoryAuditLog::new()),
+            exports: AccountExports::new(&config),
+            data_subject_hooks: Vec::new(),
+            tokens: TokenService::new(
+                &config,
+                Arc::new(KeyStore::new(&config, Arc::new(InMemorySigningKeyStore::new()))),
+            ),
+            config,
+            users,
+        }
+    }
+
+    /// Replaces the default in-memory refresh token store.
+    pub fn with_refresh_tokens(mut self, store: Arc<dyn RefreshTokenStore>) -> Self {
+        self.refresh_tokens = store;
+        self
+    }
+
+    /// Replaces the default cache-backed revocation store.
+    pub fn with_revocations(mut self, store: Arc<dyn RevocationStore>) -> Self {
+        self.revocations = store;
+        self
+    }
+
+    /// Replaces the default in-memory two-factor store.
+    pub fn with_two_factor(mut self, store: Arc<dyn TwoFactorStore>) -> Self {
+        self.two_factor = store;
+        self
+    }
+
+    /// Replaces the default in-memory store for password reset and
+    /// verification tokens.
+    pub fn with_email_tokens(mut self, store: Arc<dyn EmailTokenStore>) -> Self {
+        self.email_tokens = store;
+        self
+    }
+
+    /// Replaces the default mailer, which only logs messages.
+    pub fn with_mailer(mut self, mailer: Arc<dyn Mailer>) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    /// Replaces the default in-memory personal access token store.
+    pub fn with_access_tokens(mut self, store: Arc<dyn AccessTokenStore>) -> Self {
+        self.access_tokens = store;
+        self
+    }
+
+    /// Replaces the default in-memory session store.
+    pub fn with_sessions(mut self, store: Arc<dyn SessionStore>) -> Self {
+        self.sessions = store;
+        self
+    }
+
+    /// Replaces the default in-memory store of token signing keys. Instances
+    /// that verify each other's tokens need to share it.
+    pub fn with_signing_keys(mut self, store: Arc<dyn SigningKeyStore>) -> Self {
+        self.tokens = TokenService::new(&self.config, Arc::new(KeyStore::new(&self.config, store)));
+        self
+    }
+
+    /// Replaces the default in-memory OAuth client store.
+    pub fn with_oauth_clients(mut self, store: Arc<dyn OAuthClientStore>) -> Self {
+        self.oauth_clients = store;
+        self
+    }
+
+    /// Replaces the default in-memory invite store.
+    pub fn with_invites(mut self, store: Arc<dyn InviteStore>) -> Self {
+        self.invites = store;
+        self
+    }
+
+    /// Replaces the default in-memory audit log.
+    pub fn with_audit_log(mut self, log: Arc<dyn AuditLog>) -> Self {
+        self.audit = log;
+        self
+    }
+
+    /// Adds a crate's data to account exports and deletions.
+    pub fn with_data_subject_hook(mut self, hook: Arc<dyn DataSubjectHook>) -> Self {
+        self.data_subject_hooks.push(hook);
+        self
+    }
+
+    pub fn tokens(&self) -> &TokenService {
+        &self.tokens
+    }
+
+    pub fn users(&self) -> &dyn UserRepository {
+        self.users.as_ref()
+    }
+
+    /// Checks the password. Accounts with 2FA get a [`TwoFactorChallenge`] to
+    /// complete at [`AuthService::login_two_factor`] instead of tokens.
+    ///
+    /// Failures count against the username and the client's address; see
+    /// [`crate::throttle`]. While either is locked out, every attempt fails
+    /// with [`AuthError::TooManyAttempts`], even with the right password.
+    pub async fn login(
+        &self,
+        request: &LoginRequest,
+        client: &ClientInfo,
+    ) -> Result<LoginResponse, AuthError> {
+        let client_ip = client.ip.as_deref();
+        let event = NewAuditEvent::new(AuditAction::Login, client).detail("username", request.username.as_str());
+        if let Err(e) = self.login_throttle.check(&request.username, client_ip).await {
+            self.audit(event.failed(&e)).await;
+            return Err(e);
+        }
+        let user = self.users.find_by_username(&request.username).await?;
+
+        // Always run bcrypt so unknown usernames take as long as wrong passwords.
+        let hash = match &user {
+            Some(user) => user.password_hash.clone(),
+            None => self.dummy_hash().to_string(),
+        };
+        let valid = verify_password(request.password.clone(), hash).await?;
+
+        let user = match user {
+            Some(user) if valid => user,
+            _ => {
+                self.login_throttle.record_failure(&request.username, client_ip).await;
+                let event = match &user {
+                    Some(user) => event.target(user.id),
+                    None => event,
+                };
+                self.audit(event.failed(&AuthError::InvalidCredentials)).await;
+                return Err(AuthError::InvalidCredentials);
+            }
+        };
+        self.login_throttle.record_success(&request.username).await;
+
+        if self.two_factor.find(user.id).await?.is_some_and(|r| r.enabled) {
+            self.tokens.keys().refresh_if_due().await?;
+            let (challenge_token, _) = self.tokens.issue_challenge(user.id, CHALLENGE_LIFETIME)?;
+            // The login completes with a `two_factor_login` event.
+            self.audit(event.by(user.id).detail("two_factor_required", true)).await;
+            return Ok(LoginResponse::TwoFactorRequired(TwoFactorChallenge {
+                challenge_token,
+                expires_in: CHALLENGE_LIFETIME,
+            }));
+        }
+        let result = self.start_session(&user, client).await;
+        self.audit(event.by(user.id).outcome(&result)).await;
+        result.map(LoginResponse::Authenticated)
+    }
+
+    /// Completes a login started by [`AuthService::login`] with an
+    /// authenticator or recovery code.
+    pub async fn login_two_factor(
+        &self,
+        request: &TwoFactorLoginRequest,
+        client: &ClientInfo,
+    ) -> Result<AuthResponse, AuthError> {
+        let result = self.complete_two_factor_login(request, client).await;
+
+        let mut event = NewAuditEvent::new(AuditAction::TwoFactorLogin, client).outcome(&result);
+        // The challenge names the user even if the code was wrong.
+        if let Some(user_id) = self.tokens.verify_challenge(&request.challenge_token).ok().and_then(|c| c.user_id().ok()) {
+            event = event.target(user_id);
+        }
+        if let Ok(response) = &result {
+            event = event.actor(response.user_id);
+        }
+        self.audit(event).await;
+        result
+    }
+
+    async fn complete_two_factor_login(
+        &self,
+        request: &TwoFactorLoginRequest,
+        client: &ClientInfo,
+    ) -> Result<AuthResponse, AuthError> {
+        self.tokens.keys().refresh_for(&request.challenge_token).await?;
+        let claims = self.tokens.verify_challenge(&request.challenge_token)?;
+        if self.revocations.is_token_revoked(&claims.jti).await? {
+            return Err(AuthError::TokenRevoked);
+        }
+
+        let user_id = claims.user_id()?;
+        let record = self.two_factor
+            .find(user_id)
+            .await?
+            .filter(|r| r.enabled)
+            .ok_or(AuthError::InvalidToken)?;
+
+        if !self.check_two_factor_code(&record, &request.code, true).await? {
+            let attempts = self.challenge_attempts.get(&claims.jti).await.unwrap_or(0) + 1;
+            if attempts >= CHALLENGE_MAX_ATTEMPTS {
+                warn!("Too many wrong two-factor codes for user {}, revoking challenge", user_id);
+                self.revocations.revoke_token(&claims.jti, claims.exp).await?;
+            } else {
+                self.challenge_attempts.set(claims.jti.clone(), attempts).await;
+            }
+            return Err(AuthError::InvalidTwoFactorCode);
+        }
+
+        // A challenge completes a single login.
+        self.revocations.revoke_token(&claims.jti, claims.exp).await?;
+
+        let user = self.users
+            .find_by_id(user_id)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        self.start_session(&user, client).await
+    }
+
+    /// Starts (or restarts) 2FA enrollment with a fresh secret and recovery
+    /// codes. Logins are unaffected until [`AuthService::confirm_two_factor`].
+    pub async fn enroll_two_factor(&self, user: &AuthenticatedUser) -> Result<TwoFactorEnrollment, AuthError> {
+        if self.two_factor.find(user.user_id).await?.is_some_and(|r| r.enabled) {
+            return Err(AuthError::TwoFactorAlreadyEnabled);
+        }
+        let account = self.users
+            .find_by_id(user.user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+
+        let secret = totp::generate_secret();
+        let recovery_codes = totp::generate_recovery_codes(RECOVERY_CODE_COUNT);
+        self.two_factor.save(TwoFactorRecord {
+            user_id: account.id,
+            secret: secret.clone(),
+            enabled: false,
+            recovery_codes: recovery_codes
+                .iter()
+                .map(|code| secret::hash(&totp::normalize_recovery_code(code)))
+                .collect(),
+            last_used_step: 0,
+        }).await?;
+
+        Ok(TwoFactorEnrollment {
+            secret: totp::base32_encode(&secret),
+            otpauth_uri: totp::provisioning_uri(&secret, &self.config.issuer, &account.username),
+            recovery_codes,
+        })
+    }
+
+    /// Enables 2FA once the user proves their authenticator produces valid codes.
+    pub async fn confirm_two_factor(
+        &self,
+        user: &AuthenticatedUser,
+        code: &str,
+        client: &ClientInfo,
+    ) -> Result<(), AuthError> {
+        let event = NewAuditEvent::new(AuditAction::TwoFactorEnabled, client).by(user.user_id);
+        let result: Result<(), AuthError> = async {
+            let record = self.two_factor
+                .find(user.user_id)
+                .await?
+                .ok_or_else(|| AuthError::Validation("No pending two-factor enrollment".to_string()))?;
+            if record.enabled {
+                return Err(AuthError::TwoFactorAlreadyEnabled);
+            }
+            if !self.check_two_factor_code(&record, code, false).await? {
+                return Err(AuthError::InvalidTwoFactorCode);
+            }
+
+            // Reload to keep the step recorded by the check.
+            let mut record = self.two_factor
+                .find(user.user_id)
+                .await?
+                .ok_or(AuthError::InternalError)?;
+            record.enabled = true;
+            self.two_factor.save(record).await?;
+
+            info!("User {} enabled two-factor authentication", user.user_id);
+            Ok(())
+        }.await;
+        self.audit(event.outcome(&result)).await;
+        result
+    }
+
+    /// Turns 2FA off. Takes an authenticator or a recovery code.
+    pub async fn disable_two_factor(
+        &self,
+        user: &AuthenticatedUser,
+        code: &str,
+        client: &ClientInfo,
+    ) -> Result<(), AuthError> {
+        let event = NewAuditEvent::new(AuditAction::TwoFactorDisabled, client).by(user.user_id);
+        let result: Result<(), AuthError> = async {
+            let record = self.two_factor
+                .find(user.user_id)
+                .await?
+                .filter(|r| r.enabled)
+                .ok_or(AuthError::TwoFactorNotEnabled)?;
+            if !self.check_two_factor_code(&record, code, true).await? {
+                return Err(AuthError::InvalidTwoFactorCode);
+            }
+
+            self.two_factor.delete(user.user_id).await?;
+            info!("User {} disabled two-factor authentication", user.user_id);
+            Ok(())
+        }.await;
+        self.audit(event.outcome(&result)).await;
+        result
+    }
+
+    /// Starts a device login. The device shows the user code and polls
+    /// [`AuthService::token`] until the user approved it with
+    /// [`AuthService::verify_device`].
+    pub async fn start_device_authorization(
+        &self,
+        request: &DeviceCodeRequest,
+        client: ClientInfo,
+    ) -> Result<DeviceCodeResponse, AuthError> {
+        let client_id = request.client_id.trim();
+        if client_id.is_empty() || client_id.len() > 64 {
+            return Err(AuthError::Validation("client_id must be 1-64 characters".to_string()));
+        }
+        let client = ClientInfo {
+            device_name: client.device_name.or_else(|| Some(client_id.to_string())),
+            ..client
+        };
+
+        let codes = self.devices.start(client_id, client).await;
+        let verification_uri = format!("{}/device", self.config.public_url.trim_end_matches('/'));
+        Ok(DeviceCodeResponse {
+            verification_uri_complete: format!("{}?user_code={}", verification_uri, codes.user_code),
+            verification_uri,
+            device_code: codes.device_code,
+            user_code: codes.user_code,
+            expires_in: self.devices.lifetime(),
+            interval: self.devices.interval(),
+        })
+    }
+
+    /// Approves or denies the device login showing `user_code`, as the signed-in `user`.
+    pub async fn verify_device(&self, user: &AuthenticatedUser, user_code: &str, deny: bool) -> Result<(), AuthError> {
+        let decision = (!deny).then_some(user.user_id);
+        self.devices.decide(user_code, decision).await?;
+
+        info!(
+            "User {} {} a device login",
+            user.user_id,
+            if deny { "denied" } else { "approved" }
+        );
+        Ok(())
+    }
+
+    /// OAuth 2.0 token endpoint. Issues a session for an approved device
+    /// login, or a scoped token for a third-party app redeeming an
+    /// authorization code. Failures carry the [`OAuthErrorCode`] the client acts on.
+    ///
+    /// `basic` holds client credentials sent as HTTP Basic authentication.
+    pub async fn token(
+        &self,
+        request: &TokenRequest,
+        basic: Option<(String, String)>,
+        client: &ClientInfo,
+    ) -> Result<TokenResponse, AuthError> {
+        match request.grant_type.as_str() {
+            DEVICE_CODE_GRANT => self.audited_device_token(request, client).await.map(TokenResponse::Session),
+            AUTHORIZATION_CODE_GRANT => {
+                self.audited_authorization_code_token(request, basic, client).await.map(TokenResponse::OAuth)
+            }
+            _ => Err(AuthError::OAuth(OAuthErrorCode::UnsupportedGrantType)),
+        }
+    }
+
+    async fn audited_device_token(&self, request: &TokenRequest, client: &ClientInfo) -> Result<AuthResponse, AuthError> {
+        let result = self.device_token(request).await;
+        // A device polls until the user decides; only the answer is recorded.
+        if matches!(result, Err(AuthError::OAuth(OAuthErrorCode::AuthorizationPending | OAuthErrorCode::SlowDown))) {
+            return result;
+        }
+
+        let mut event = NewAuditEvent::new(AuditAction::DeviceLogin, client).outcome(&result);
+        if let Some(client_id) = &request.client_id {
+            event = event.detail("client_id", client_id.trim());
+        }
+        if let Ok(response) = &result {
+            event = event.by(response.user_id);
+        }
+        self.audit(event).await;
+        result
+    }
+
+    async fn audited_authorization_code_token(
+        &self,
+        request: &TokenRequest,
+        basic: Option<(String, String)>,
+        client: &ClientInfo,
+    ) -> Result<OAuthTokenResponse, AuthError> {
+        let client_id = basic.as_ref().map(|(id, _)| id.clone()).or_else(|| request.client_id.clone());
+        let result = self.authorization_code_token(request, basic).await;
+
+        let mut event = NewAuditEvent::new(AuditAction::OAuthTokenIssued, client).outcome(&result);
+        if let Some(client_id) = client_id {
+            event = event.detail("client_id", client_id);
+        }
+        if let Ok((user_id, _)) = &result {
+            event = event.by(*user_id);
+        }
+        self.audit(event).await;
+        result.map(|(_, response)| response)
+    }
+
+    async fn device_token(&self, request: &TokenRequest) -> Result<AuthResponse, AuthError> {
+        let (Some(device_code), Some(client_id)) = (&request.device_code, &request.client_id) else {
+            return Err(AuthError::OAuth(OAuthErrorCode::InvalidRequest));
+        };
+
+        let grant = self.devices.poll(device_code, client_id.trim()).await?;
+        let user = self.users
+            .find_by_id(grant.user_id)
+            .await?
+            .ok_or(AuthError::OAuth(OAuthErrorCode::InvalidGrant))?;
+
+        info!("User {} signed in device {:?}", user.id, grant.client.device_name);
+        self.start_session(&user, &grant.client).await
+    }
+
+    async fn authorization_code_token(
+        &self,
+        request: &TokenRequest,
+        basic: Option<(String, String)>,
+    ) -> Result<(i32, OAuthTokenResponse), AuthError> {
+        let client = self.authenticate_client(request, basic).await?;
+        let (Some(code), Some(redirect_uri), Some(code_verifier)) =
+            (&request.code, &request.redirect_uri, &request.code_verifier)
+        else {
+            return Err(AuthError::OAuth(OAuthErrorCode::InvalidRequest));
+        };
+
+        let grant = self.authorization_codes
+            .redeem(code)
+            .await
+            .filter(|g| g.client_id == client.client_id && &g.redirect_uri == redirect_uri)
+            .filter(|g| oauth::verify_pkce(code_verifier, &g.code_challenge))
+            .ok_or(AuthError::OAuth(OAuthErrorCode::InvalidGrant))?;
+        let user = self.users
+            .find_by_id(grant.user_id)
+            .await?
+            .ok_or(AuthError::OAuth(OAuthErrorCode::InvalidGrant))?;
+
+        // The app gets what its scopes stand for, as far as the user holds it.
+        let held = effective_permissions(&user.roles, &user.permissions);
+        let mut granted: Vec<String> = grant.scopes
+            .iter()
+            .flat_map(|scope| scopes::permissions(scope))
+            .filter(|p| held.iter().any(|h| h == *p))
+            .map(|p| p.to_string())
+            .collect();
+        granted.sort();
+        granted.dedup();
+
+        self.tokens.keys().refresh_if_due().await?;
+        let (access_token, claims) = self.tokens.issue_for_client(
+            user.id, &user.roles, granted, &client.client_id, &grant.scopes,
+        )?;
+        let id_token = match grant.scopes.iter().any(|s| s == scopes::OPENID) {
+            true => Some(self.issue_id_token(&user, &grant, claims.iat, claims.exp).await?),
+            false => None,
+        };
+
+        info!("User {} signed in to OAuth client {}", user.id, client.client_id);
+        Ok((user.id, OAuthTokenResponse {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: self.tokens.expiration(),
+            scope: grant.scopes.join(" "),
+            id_token,
+        }))
+    }
+
+    /// Identifies the client of a token request by its id, checking the
+    /// secret of confidential clients.
+    async fn authenticate_client(
+        &self,
+        request: &TokenRequest,
+        basic: Option<(String, String)>,
+    ) -> Result<OAuthClientRecord, AuthError> {
+        let (client_id, client_secret) = match basic {
+            Some((client_id, client_secret)) => {
+                if request.client_id.as_ref().is_some_and(|id| *id != client_id) {
+                    return Err(AuthError::OAuth(OAuthErrorCode::InvalidRequest));
+                }
+                (client_id, Some(client_secret))
+            }
+            None => (
+                request.client_id.clone().ok_or(AuthError::OAuth(OAuthErrorCode::InvalidRequest))?,
+                request.client_secret.clone(),
+            ),
+        };
+
+        let client = self.oauth_clients
+            .find(&client_id)
+            .await?
+            .ok_or(AuthError::OAuth(OAuthErrorCode::InvalidClient))?;
+        if let Some(secret_hash) = &client.secret_hash {
+            if client_secret.is_none_or(|s| secret::hash(&s) != *secret_hash) {
+                return Err(AuthError::OAuth(OAuthErrorCode::InvalidClient));
+            }
+        }
+        Ok(client)
+    }
+
+    async fn issue_id_token(&self, user: &User, grant: &AuthorizationGrant, iat: u64, exp: u64) -> Result<String, AuthError> {
+        let keys = self.tokens.keys();
+        keys.prepare_id_tokens().await?;
+        let granted = |scope: &str| grant.scopes.iter().any(|s| s == scope);
+        keys.sign_with(keys.id_token_algorithm(), &IdTokenClaims {
+            iss: self.config.oidc_issuer.clone(),
+            sub: user.id.to_string(),
+            aud: grant.client_id.clone(),
+            iat,
+            exp,
+            nonce: grant.nonce.clone(),
+            preferred_username: granted(scopes::PROFILE).then(|| user.username.clone()),
+            email: granted(scopes::EMAIL).then(|| user.email.clone()),
+            email_verified: granted(scopes::EMAIL).then_some(user.email_verified),
+        })
+    }
+
+    /// Public keys that tokens are signed with, for verifying them offline.
+    pub async fn jwks(&self) -> Result<JwkSet, AuthError> {
+        self.tokens.keys().refresh_if_due().await?;
+        Ok(self.tokens.keys().jwks())
+    }
+
+    /// OpenID Connect discovery document.
+    pub fn openid_configuration(&self) -> OpenIdConfiguration {
+        let issuer = self.config.oidc_issuer.trim_end_matches('/');
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        OpenIdConfiguration {
+            issuer: issuer.to_string(),
+            authorization_endpoint: format!("{}/oauth/authorize", self.config.public_url.trim_end_matches('/')),
+            token_endpoint: format!("{}/auth/token", issuer),
+            userinfo_endpoint: format!("{}/oauth/userinfo", issuer),
+            jwks_uri: format!("{}/.well-known/jwks.json", issuer),
+            device_authorization_endpoint: format!("{}/auth/device/code", issuer),
+            scopes_supported: strings(scopes::ALL),
+            response_types_supported: strings(&["code"]),
+            grant_types_supported: strings(&[AUTHORIZATION_CODE_GRANT, DEVICE_CODE_GRANT]),
+            subject_types_supported: strings(&["public"]),
+            id_token_signing_alg_values_supported: vec![format!("{:?}", self.tokens.keys().id_token_algorithm())],
+            token_endpoint_auth_methods_supported: strings(&["client_secret_basic", "client_secret_post", "none"]),
+            code_challenge_methods_supported: strings(&[PKCE_METHOD]),
+            claims_supported: strings(&["sub", "iss", "aud", "exp", "iat", "nonce", "preferred_username", "email", "email_verified"]),
+        }
+    }
+
+    /// Registers a third-party app owned by `user`.
+    pub async fn create_oauth_client(
+        &self,
+        user: &AuthenticatedUser,
+        request: &CreateOAuthClientRequest,
+    ) -> Result<CreatedOAuthClient, AuthError> {
+        let name = request.name.trim();
+        if name.is_empty() || name.len() > 64 {
+            return Err(AuthError::Validation("Client name must be 1-64 characters".to_string()));
+        }
+        if request.redirect_uris.is_empty() || request.redirect_uris.len() > MAX_REDIRECT_URIS {
+            return Err(AuthError::Validation(format!(
+                "A client needs 1-{} redirect URIs", MAX_REDIRECT_URIS
+            )));
+        }
+        for uri in &request.redirect_uris {
+            oauth::validate_redirect_uri(uri)?;
+        }
+        if self.oauth_clients.list(user.user_id).await?.len() >= MAX_OAUTH_CLIENTS {
+            return Err(AuthError::Validation(format!(
+                "At most {} OAuth clients per user", MAX_OAUTH_CLIENTS
+            )));
+        }
+
+        let client_secret = (!request.public).then(secret::generate);
+        let record = OAuthClientRecord {
+            client_id: Uuid::new_v4().simple().to_string(),
+            secret_hash: client_secret.as_deref().map(secret::hash),
+            name: name.to_string(),
+            redirect_uris: request.redirect_uris.clone(),
+            owner_id: user.user_id,
+            created_at: get_current_timestamp(),
+        };
+        self.oauth_clients.insert(record.clone()).await?;
+
+        info!("User {} registered OAuth client {} ({})", user.user_id, record.client_id, record.name);
+        Ok(CreatedOAuthClient { details: record.into(), client_secret })
+    }
+
+    pub async fn list_oauth_clients(&self, user: &AuthenticatedUser) -> Result<Vec<OAuthClient>, AuthError> {
+        let clients = self.oauth_clients.list(user.user_id).await?;
+        Ok(clients.into_iter().map(OAuthClient::from).collect())
+    }
+
+    pub async fn delete_oauth_client(&self, user: &AuthenticatedUser, client_id: &str) -> Result<(), AuthError> {
+        if !self.oauth_clients.delete(user.user_id, client_id).await? {
+            return Err(AuthError::OAuthClientNotFound);
+        }
+        info!("User {} deleted OAuth client {}", user.user_id, client_id);
+        Ok(())
+    }
+
+    /// Checks an authorization request and describes it for the consent screen.
+    pub async fn consent_prompt(&self, request: &AuthorizeRequest) -> Result<ConsentPrompt, AuthError> {
+        let (client, scopes) = self.validate_authorization(request).await?;
+        Ok(ConsentPrompt {
+            client_id: client.client_id,
+            client_name: client.name,
+            redirect_uri: request.redirect_uri.clone(),
+            scopes: scopes
+                .into_iter()
+                .map(|scope| ScopeDescription {
+                    description: scopes::description(&scope).to_string(),
+                    scope,
+                })
+                .collect(),
+        })
+    }
+
+    /// Records the user's answer to the consent screen. Either way, the
+    /// browser goes back to the app, with an authorization code or with
+    /// `access_denied`.
+    pub async fn authorize(
+        &self,
+        user: &AuthenticatedUser,
+        request: &AuthorizeRequest,
+        deny: bool,
+    ) -> Result<AuthorizationRedirect, AuthError> {
+        let (client, scopes) = self.validate_authorization(request).await?;
+        let state = request.state.as_deref();
+
+        if deny {
+            info!("User {} denied OAuth client {}", user.user_id, client.client_id);
+            let redirect_to = error_redirect(&request.redirect_uri, OAuthErrorCode::AccessDenied, state)?;
+            return Ok(AuthorizationRedirect { redirect_to });
+        }
+
+        let code = self.authorization_codes.issue(AuthorizationGrant {
+            client_id: client.client_id.clone(),
+            redirect_uri: request.redirect_uri.clone(),
+            user_id: user.user_id,
+            scopes,
+            code_challenge: request.code_challenge.clone().unwrap_or_default(),
+            nonce: request.nonce.clone(),
+        }).await;
+
+        info!("User {} authorized OAuth client {}", user.user_id, client.client_id);
+        let mut params = vec![("code", code.as_str())];
+        params.extend(state.map(|state| ("state", state)));
+        Ok(AuthorizationRedirect { redirect_to: oauth::redirect_to(&request.redirect_uri, &params)? })
+    }
+
+    /// Problems with the client or redirect URI are reported to the user;
+    /// anything else goes back to the app (RFC 6749 section 4.1.2.1).
+    async fn validate_authorization(
+        &self,
+        request: &AuthorizeRequest,
+    ) -> Result<(OAuthClientRecord, Vec<String>), AuthError> {
+        let client = self.oauth_clients
+            .find(&request.client_id)
+            .await?
+            .ok_or_else(|| AuthError::Validation("Unknown client_id".to_string()))?;
+        if !client.redirect_uris.contains(&request.redirect_uri) {
+            return Err(AuthError::Validation("redirect_uri is not registered for the client".to_string()));
+        }
+
+        let fail = |error| -> AuthError {
+            match error_redirect(&request.redirect_uri, error, request.state.as_deref()) {
+                Ok(redirect_to) => AuthError::OAuthRedirect { error, redirect_to },
+                Err(e) => e,
+            }
+        };
+        if request.response_type != "code" {
+            return Err(fail(OAuthErrorCode::UnsupportedResponseType));
+        }
+        // PKCE is required of every client (OAuth 2.0 Security BCP).
+        if request.code_challenge.as_deref().is_none_or(str::is_empty)
+            || request.code_challenge_method.as_deref() != Some(PKCE_METHOD)
+        {
+            return Err(fail(OAuthErrorCode::InvalidRequest));
+        }
+        let scopes = oauth::parse_scopes(&request.scope).map_err(|_| fail(OAuthErrorCode::InvalidScope))?;
+        Ok((client, scopes))
+    }
+
+    /// Claims about the user, for an app holding an `openid` access token.
+    pub async fn userinfo(&self, token: &str) -> Result<UserInfo, AuthError> {
+        let claims = self.verify_token(token).await?;
+        let scopes = claims.scopes();
+        if claims.client_id.is_none() || !scopes.contains(&scopes::OPENID) {
+            return Err(AuthError::InsufficientScope);
+        }
+        let user = self.users
+            .find_by_id(claims.user_id()?)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+
+        Ok(UserInfo {
+            sub: user.id.to_string(),
+            preferred_username: scopes.contains(&scopes::PROFILE).then(|| user.username.clone()),
+            email: scopes.contains(&scopes::EMAIL).then(|| user.email.clone()),
+            email_verified: scopes.contains(&scopes::EMAIL).then_some(user.email_verified),
+        })
+    }
+
+    /// Exchanges a refresh token for a new access and refresh token pair.
+    pub async fn refresh(&self, refresh_token: &str, client: &ClientInfo) -> Result<AuthResponse, AuthError> {
+        let record = self.refresh_tokens
+            .consume(&secret::hash(refresh_token))
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+
+        if record.used {
+            warn!(
+                "Refresh token reuse for user {}, revoking family {}",
+                record.user_id, record.family_id
+            );
+            self.refresh_tokens.revoke_family(record.family_id).await?;
+            return Err(AuthError::RefreshTokenReused);
+        }
+        if record.is_expired() {
+            return Err(AuthError::TokenExpired);
+        }
+
+        let user = self.users
+            .find_by_id(record.user_id)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        self.sessions.touch(record.family_id, get_current_timestamp(), client.ip.clone()).await?;
+        self.issue_tokens(&user, record.family_id).await
+    }
+
+    /// Creates an account, if the registration policy lets the request in.
+    ///
+    /// Refusals by the policy fail with [`AuthError::Registration`], whose
+    /// code tells the reason.
+    pub async fn register(&self, request: &RegisterRequest) -> Result<User, AuthError> {
+        validate_registration(request)?;
+        let invite = self.check_registration_policy(request).await?;
+
+        let password_hash = self.hash_password(&request.password).await?;
+        let user = self.users.create(NewUser {
+            username: request.username.trim().to_string(),
+            email: request.email.trim().to_lowercase(),
+            password_hash,
+        }).await?;
+
+        if let Some(invite) = invite {
+            // Counted only now, so failed registrations do not use the code
+            // up. If it ran out in the meantime, the account is undone.
+            if !self.invites.redeem(&invite.code_hash, get_current_timestamp()).await? {
+                self.users.delete(user.id).await?;
+                let reason = match self.invites.find(&invite.code_hash).await? {
+                    Some(current) => current.check().err().unwrap_or(RegistrationErrorCode::InviteExhausted),
+                    None => RegistrationErrorCode::InviteInvalid,
+                };
+                return Err(AuthError::Registration(reason));
+            }
+            info!("Registered user {} ({}) with invite {} of user {}", user.id, user.username, invite.id, invite.created_by);
+        } else {
+            info!("Registered user {} ({})", user.id, user.username);
+        }
+
+        // The account is usable either way; the user can ask for another email.
+        if let Err(e) = self.send_verification_email(&user).await {
+            warn!("Failed to send verification email to user {}: {}", user.id, e);
+        }
+        Ok(user)
+    }
+
+    /// Applies the registration mode. In invite-only mode, returns the invite
+    /// to count the registration against.
+    async fn check_registration_policy(&self, request: &RegisterRequest) -> Result<Option<InviteRecord>, AuthError> {
+        match self.config.registration_mode {
+            RegistrationMode::Open => Ok(None),
+            RegistrationMode::DomainAllowlist => {
+                if !invite::domain_allowed(request.email.trim(), &self.config.registration_allowed_domains) {
+                    return Err(AuthError::Registration(RegistrationErrorCode::DomainNotAllowed));
+                }
+                Ok(None)
+            }
+            RegistrationMode::InviteOnly => {
+                let code = request.invite_code
+                    .as_deref()
+                    .map(invite::normalize_code)
+                    .filter(|code| !code.is_empty())
+                    .ok_or(AuthError::Registration(RegistrationErrorCode::InviteRequired))?;
+                let invite = self.invites
+                    .find(&secret::hash(&code))
+                    .await?
+                    .ok_or(AuthError::Registration(RegistrationErrorCode::InviteInvalid))?;
+                invite.check().map_err(AuthError::Registration)?;
+                Ok(Some(invite))
+            }
+        }
+    }
+
+    pub async fn create_invite(
+        &self,
+        actor: &AuthenticatedUser,
+        request: &CreateInviteRequest,
+    ) -> Result<CreatedInvite, AuthError> {
+        if request.max_uses == 0 || request.max_uses > MAX_INVITE_USES {
+            return Err(AuthError::Validation(format!("max_uses must be 1-{}", MAX_INVITE_USES)));
+        }
+        if request.expires_in == Some(0) {
+            return Err(AuthError::Validation("expires_in must be positive".to_string()));
+        }
+
+        let now = get_current_timestamp();
+        let code = invite::generate_code();
+        let record = InviteRecord {
+            id: Uuid::new_v4(),
+            code_hash: secret::hash(&invite::normalize_code(&code)),
+            created_by: actor.user_id,
+            max_uses: request.max_uses,
+            uses: 0,
+            created_at: now,
+            expires_at: request.expires_in.map(|lifetime| now + lifetime),
+        };
+        self.invites.insert(record.clone()).await?;
+
+        info!("User {} created invite {} for {} registrations", actor.user_id, record.id, record.max_uses);
+        Ok(CreatedInvite { code, details: record.into() })
+    }
+
+    pub async fn list_invites(&self) -> Result<Vec<Invite>, AuthError> {
+        let invites = self.invites.list().await?;
+        Ok(invites.into_iter().map(Invite::from).collect())
+    }
+
+    pub async fn delete_invite(&self, actor: &AuthenticatedUser, id: Uuid) -> Result<(), AuthError> {
+        if !self.invites.delete(id).await? {
+            return Err(AuthError::InviteNotFound);
+        }
+        info!("User {} deleted invite {}", actor.user_id, id);
+        Ok(())
+    }
+
+    /// A page of audit events matching `query`, newest first.
+    pub async fn audit_events(&self, query: &AuditQuery) -> Result<AuditPage, AuthError> {
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let events = self.audit.query(&query.filter(), limit).await?;
+        let next_cursor = if events.len() == limit as usize {
+            events.last().map(|event| event.id)
+        } else {
+            None
+        };
+        Ok(AuditPage { events, next_cursor })
+    }
+
+    /// Every audit event matching `filter` as JSON Lines, newest first. The
+    /// log is read page by page as the stream is polled.
+    pub fn export_audit_events(
+        &self,
+        filter: AuditFilter
+    ) -> impl Stream<Item = Result<web::Bytes, AuthError>> + 'static {
+        let log = self.audit.clone();
+        stream::try_unfold(Some(filter), move |filter| {
+            let log = log.clone();
+            async move {
+                let Some(mut filter) = filter else {
+                    return Ok(None);
+                };
+                let events = log.query(&filter, MAX_PAGE_SIZE).await?;
+                if events.is_empty() {
+                    return Ok(None);
+                }
+
+                let mut lines = Vec::new();
+                for event in &events {
+                    serde_json::to_writer(&mut lines, event).map_err(|_| AuthError::InternalError)?;
+                    lines.push(b'\n');
+                }
+                let next = (events.len() == MAX_PAGE_SIZE as usize).then(|| {
+                    filter.before = events.last().map(|event| event.id);
+                    filter
+                });
+                Ok(Some((web::Bytes::from(lines), next)))
+            }
+        })
+    }
+
+    /// Mails a password reset link if an account uses `email`.
+    ///
+    /// Succeeds whether or not there is such an account, and even if the mail
+    /// could not be sent, so callers cannot probe for registered addresses.
+    pub async fn forgot_password(&self, email: &str) -> Result<(), AuthError> {
+        let Some(user) = self.users.find_by_email(email.trim()).await? else {
+            info!("Password reset requested for unknown address");
+            return Ok(());
+        };
+
+        let token = self.issue_email_token(
+            &user,
+            EmailTokenPurpose::PasswordReset,
+            self.config.password_reset_expiration,
+        ).await?;
+        let email = Email {
+            to: user.email.clone(),
+            subject: "Reset your SocialHub password".to_string(),
+            body: format!(
+                "Hi {},\n\nSomeone asked to reset the password of your SocialHub account. \
+                 To choose a new one, open this link within {} minutes:\n\n{}/reset-password?token={}\n\n\
+                 If it was not you, ignore this email; your password stays the same.\n",
+                user.username,
+                self.config.password_reset_expiration / 60,
+                self.config.public_url.trim_end_matches('/'),
+                token,
+            ),
+        };
+        if let Err(e) = self.mailer.send(email).await {
+            warn!("Failed to send password reset email to user {}: {}", user.id, e);
+        }
+
+        info!("Password reset requested for user {}", user.id);
+        Ok(())
+    }
+
+    /// Sets a new password with a token from [`AuthService::forgot_password`].
+    ///
+    /// Every other reset token of the user is revoked, all of their sessions
+    /// are logged out and their personal access tokens deleted.
+    pub async fn reset_password(&self, token: &str, new_password: &str, client: &ClientInfo) -> Result<(), AuthError> {
+        let result: Result<i32, AuthError> = async {
+            validate_password(new_password)?;
+
+            let record = self.email_tokens
+                .consume(&secret::hash(token), EmailTokenPurpose::PasswordReset)
+                .await?
+                .ok_or(AuthError::InvalidToken)?;
+            if record.is_expired() {
+                return Err(AuthError::TokenExpired);
+            }
+
+            let password_hash = self.hash_password(new_password).await?;
+            if !self.users.update_password(record.user_id, password_hash).await? {
+                return Err(AuthError::InvalidToken);
+            }
+            self.email_tokens.revoke_user(record.user_id, EmailTokenPurpose::PasswordReset).await?;
+            self.logout_all(record.user_id).await?;
+            // A token stolen along with the password must not outlive the reset.
+            self.delete_access_tokens(record.user_id).await?;
+
+            info!("User {} reset their password", record.user_id);
+            Ok(record.user_id)
+        }.await;
+
+        let mut event = NewAuditEvent::new(AuditAction::PasswordReset, client).outcome(&result);
+        if let Ok(user_id) = result {
+            event = event.by(user_id);
+        }
+        self.audit(event).await;
+        result.map(|_| ())
+    }
+
+    /// Mails a new verification link to the user, invalidating earlier ones.
+    pub async fn resend_verification_email(&self, user: &AuthenticatedUser) -> Result<(), AuthError> {
+        let user = self.users
+            .find_by_id(user.user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        self.send_verification_email(&user).await
+    }
+
+    /// Marks the address a verification token was sent to as verified.
+    ///
+    /// Fails if the user changed their address since the token was issued.
+    pub async fn verify_email(&self, token: &str) -> Result<(), AuthError> {
+        let record = self.email_tokens
+            .consume(&secret::hash(token), EmailTokenPurpose::EmailVerification)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        if record.is_expired() {
+            return Err(AuthError::TokenExpired);
+        }
+
+        if !self.users.mark_email_verified(record.user_id, &record.email).await? {
+            return Err(AuthError::InvalidToken);
+        }
+        self.email_tokens.revoke_user(record.user_id, EmailTokenPurpose::EmailVerification).await?;
+
+        info!("User {} verified their email address", record.user_id);
+        Ok(())
+    }
+
+    /// Replaces the roles and extra permissions of a user.
+    ///
+    /// Access tokens already issued to the user are revoked, so the change
+    /// applies right away; the user's next refresh picks up the new roles. As
+    /// with [`AuthService::logout_all`], tokens issued within the same second
+    /// are revoked too.
+    pub async fn update_roles(
+        &self,
+        actor: &AuthenticatedUser,
+        user_id: i32,
+        roles: Vec<Role>,
+        permissions: Vec<String>,
+        client: &ClientInfo,
+    ) -> Result<User, AuthError> {
+        let event = NewAuditEvent::new(AuditAction::RolesUpdated, client)
+            .actor(actor.user_id)
+            .target(user_id)
+            .detail("roles", roles.iter().map(Role::to_string).collect::<Vec<_>>())
+            .detail("permissions", permissions.clone());
+        let result: Result<User, AuthError> = async {
+            if let Some(unknown) = permissions.iter().find(|p| !permissions::ALL.contains(&p.as_str())) {
+                return Err(AuthError::Validation(format!("Unknown permission: {}", unknown)));
+            }
+
+            let user = self.users
+                .update_roles(user_id, roles, permissions)
+                .await?
+                .ok_or(AuthError::UserNotFound)?;
+            self.revocations.revoke_user_tokens(user_id, get_current_timestamp()).await?;
+
+            info!(
+                "User {} set roles of user {} to {:?} with permissions {:?}",
+                actor.user_id, user.id, user.roles, user.permissions
+            );
+            Ok(user)
+        }.await;
+        self.audit(event.outcome(&result)).await;
+        result
+    }
+
+    /// Mints a short-lived token for `admin` to act as `user_id`, e.g. to
+    /// reproduce a reported bug. It opens a session the user can see and
+    /// revoke. Users who may impersonate others cannot be impersonated.
+    pub async fn impersonate(
+        &self,
+        admin: &AuthenticatedUser,
+        user_id: i32,
+        request: &ImpersonateRequest,
+        client: &ClientInfo,
+    ) -> Result<Impersonation, AuthError> {
+        let mut event = NewAuditEvent::new(AuditAction::ImpersonationStarted, client)
+            .actor(admin.user_id)
+            .target(user_id);
+        if let Some(reason) = &request.reason {
+            event = event.detail("reason", reason.as_str());
+        }
+        let result: Result<Impersonation, AuthError> = async {
+            if user_id == admin.user_id {
+                return Err(AuthError::ImpersonationNotAllowed);
+            }
+            let user = self.users.find_by_id(user_id).await?.ok_or(AuthError::UserNotFound)?;
+            let held = effective_permissions(&user.roles, &user.permissions);
+            if held.iter().any(|p| p == permissions::USERS_IMPERSONATE) {
+                return Err(AuthError::ImpersonationNotAllowed);
+            }
+
+            let now = get_current_timestamp();
+            let session = SessionRecord {
+                id: Uuid::new_v4(),
+                user_id: user.id,
+                device_name: Some("Impersonation".to_string()),
+                user_agent: client.user_agent.clone(),
+                ip: client.ip.clone(),
+                created_at: now,
+                last_seen_at: now,
+                impersonator_id: Some(admin.user_id),
+            };
+            let session_id = session.id;
+            self.sessions.insert(session).await?;
+
+            let lifetime = self.config.impersonation_token_expiration;
+            self.tokens.keys().refresh_if_due().await?;
+            let (token, _) = self.tokens.issue_impersonation(
+                user.id, &user.roles, &user.permissions, session_id, admin.user_id, lifetime,
+            )?;
+
+            info!("User {} started impersonating user {} in session {}", admin.user_id, user.id, session_id);
+            Ok(Impersonation { token, expires_in: lifetime, user_id: user.id, session_id })
+        }.await;
+        if let Ok(impersonation) = &result {
+            event = event.detail("session_id", impersonation.session_id.to_string());
+        }
+        self.audit(event.outcome(&result)).await;
+        result
+    }
+
+    /// Creates a personal access token limited to `request.scopes`, each of
+    /// which the user must currently hold.
+    pub async fn create_access_token(
+        &self,
+        user: &AuthenticatedUser,
+        request: &CreateAccessTokenRequest,
+        client: &ClientInfo,
+    ) -> Result<CreatedAccessToken, AuthError> {
+        let mut event = NewAuditEvent::new(AuditAction::AccessTokenCreated, client).by(user.user_id);
+        let result: Result<CreatedAccessToken, AuthError> = async {
+            let name = request.name.trim();
+            if name.is_empty() || name.len() > 64 {
+                return Err(AuthError::Validation("Token name must be 1-64 characters".to_string()));
+            }
+            if request.scopes.is_empty() {
+                return Err(AuthError::Validation("A token needs at least one scope".to_string()));
+            }
+            if let Some(scope) = request.scopes.iter().find(|s| !user.has_permission(s)) {
+                return Err(AuthError::Validation(format!("Cannot grant permission {}", scope)));
+            }
+            if request.expires_in == Some(0) {
+                return Err(AuthError::Validation("expires_in must be positive".to_string()));
+            }
+            if self.access_tokens.list(user.user_id).await?.len() >= MAX_ACCESS_TOKENS {
+                return Err(AuthError::Validation(format!(
+                    "At most {} access tokens per user", MAX_ACCESS_TOKENS
+                )));
+            }
+
+            let mut scopes = request.scopes.clone();
+            scopes.sort();
+            scopes.dedup();
+
+            let now = get_current_timestamp();
+            let token = format!("{}{}", TOKEN_PREFIX, secret::generate());
+            let record = AccessTokenRecord {
+                id: Uuid::new_v4(),
+                user_id: user.user_id,
+                name: name.to_string(),
+                scopes,
+                token_hash: secret::hash(&token),
+                created_at: now,
+                expires_at: request.expires_in.map(|lifetime| now + lifetime),
+                last_used_at: None,
+            };
+            self.access_tokens.insert(record.clone()).await?;
+
+            info!("User {} created access token {} ({})", user.user_id, record.id, record.name);
+            Ok(CreatedAccessToken { token, details: record.into() })
+        }.await;
+        if let Ok(created) = &result {
+            event = event
+                .detail("token_id", created.details.id.to_string())
+                .detail("scopes", created.details.scopes.clone());
+        }
+        self.audit(event.outcome(&result)).await;
+        result
+    }
+
+    pub async fn list_access_tokens(&self, user: &AuthenticatedUser) -> Result<Vec<AccessToken>, AuthError> {
+        let tokens = self.access_tokens.list(user.user_id).await?;
+        Ok(tokens.into_iter().map(AccessToken::from).collect())
+    }
+
+    pub async fn delete_access_token(
+        &self,
+        user: &AuthenticatedUser,
+        id: Uuid,
+        client: &ClientInfo,
+    ) -> Result<(), AuthError> {
+        let event = NewAuditEvent::new(AuditAction::AccessTokenDeleted, client)
+            .by(user.user_id)
+            .detail("token_id", id.to_string());
+        let result: Result<(), AuthError> = async {
+            if !self.access_tokens.delete(user.user_id, id).await? {
+                return Err(AuthError::AccessTokenNotFound);
+            }
+            info!("User {} deleted access token {}", user.user_id, id);
+            Ok(())
+        }.await;
+        self.audit(event.outcome(&result)).await;
+        result
+    }
+
+    async fn delete_access_tokens(&self, user_id: i32) -> Result<(), AuthError> {
+        for token in self.access_tokens.list(user_id).await? {
+            self.access_tokens.delete(user_id, token.id).await?;
+        }
+        Ok(())
+    }
+
+    /// Checks a personal access token. The caller gets the token's scopes,
+    /// minus any permission the user has lost since it was created.
+    pub async fn verify_access_token(&self, token: &str) -> Result<AuthenticatedUser, AuthError> {
+        let record = self.access_tokens
+            .find(&secret::hash(token))
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        if record.is_expired() {
+            return Err(AuthError::TokenExpired);
+        }
+        let user = self.users
+            .find_by_id(record.user_id)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        // Tokens are unusable while the account is pending deletion and work
+        // again once the owner logs in and restores it.
+        if user.deletion_due_at.is_some() {
+            return Err(AuthError::InvalidToken);
+        }
+
+        let now = get_current_timestamp();
+        if record.last_used_at.is_none_or(|at| at + LAST_USED_RESOLUTION <= now) {
+            self.access_tokens.touch(record.id, now).await?;
+        }
+
+        let granted = effective_permissions(&user.roles, &user.permissions);
+        Ok(AuthenticatedUser {
+            user_id: user.id,
+            roles: user.roles,
+            permissions: record.scopes.into_iter().filter(|s| granted.contains(s)).collect(),
+            token_id: record.id.to_string(),
+            expires_at: record.expires_at.unwrap_or(u64::MAX),
+            token_kind: TokenKind::PersonalAccessToken,
+            session_id: None,
+            actor_id: None,
+        })
+    }
+
+    /// Verifies an access token, including revocation, and returns its claims.
+    pub async fn verify_token(&self, token: &str) -> Result<Claims, AuthError> {
+        self.tokens.keys().refresh_for(token).await?;
+        let claims = self.tokens.verify(token)?;
+
+        if self.revocations.is_token_revoked(&claims.jti).await? {
+            return Err(AuthError::TokenRevoked);
+        }
+        if let Some(sid) = claims.sid {
+            if self.revocations.is_token_revoked(&session_revocation_key(sid)).await? {
+                return Err(AuthError::TokenRevoked);
+            }
+        }
+        let user_id = claims.user_id()?;
+        if let Some(cutoff) = self.revocations.user_tokens_revoked_at(user_id).await? {
+            if claims.iat <= cutoff {
+                return Err(AuthError::TokenRevoked);
+            }
+        }
+
+        Ok(claims)
+    }
+
+    /// Revokes the presented access token and its session. Tokens from before
+    /// sessions existed are matched to their login through `refresh_token`.
+    /// An impersonation token ends the impersonation session it belongs to.
+    pub async fn logout(
+        &self,
+        user: &AuthenticatedUser,
+        refresh_token: Option<&str>,
+        client: &ClientInfo,
+    ) -> Result<(), AuthError> {
+        let event = NewAuditEvent::new(AuditAction::Logout, client)
+            .actor(user.actor_id.unwrap_or(user.user_id))
+            .target(user.user_id)
+            .detail("session_id", user.session_id.map(|id| id.to_string()));
+        let result: Result<(), AuthError> = async {
+            self.revocations.revoke_token(&user.token_id, user.expires_at).await?;
+            if let Some(session_id) = user.session_id {
+                self.end_session(user.user_id, session_id).await?;
+            }
+
+            // The user's refresh tokens are not the impersonator's to revoke.
+            if let Some(refresh_token) = refresh_token.filter(|_| user.actor_id.is_none()) {
+                if let Some(record) = self.refresh_tokens.find(&secret::hash(refresh_token)).await? {
+                    if record.user_id == user.user_id {
+                        self.refresh_tokens.revoke_family(record.family_id).await?;
+                    }
+                }
+            }
+
+            info!("User {} logged out token {}", user.user_id, user.token_id);
+            Ok(())
+        }.await;
+        self.audit(event.outcome(&result)).await;
+        result
+    }
+
+    /// [`AuthService::logout_all`] at the user's request.
+    pub async fn logout_all_sessions(&self, user: &AuthenticatedUser, client: &ClientInfo) -> Result<(), AuthError> {
+        let result = self.logout_all(user.user_id).await;
+        self.audit(NewAuditEvent::new(AuditAction::LogoutAll, client).by(user.user_id).outcome(&result)).await;
+        result
+    }
+
+    /// Invalidates every access and refresh token of the user.
+    ///
+    /// Tokens are cut off by issue time with one-second resolution, so a login
+    /// completed within the same second is revoked as well.
+    pub async fn logout_all(&self, user_id: i32) -> Result<(), AuthError> {
+        self.revocations.revoke_user_tokens(user_id, get_current_timestamp()).await?;
+        self.refresh_tokens.revoke_user(user_id).await?;
+        self.sessions.delete_user(user_id).await?;
+
+        info!("User {} logged out of all sessions", user_id);
+        Ok(())
+    }
+
+    /// Starts building an archive of the user's data, unless one is already
+    /// being built, in which case that one is returned.
+    pub async fn request_export(self: Arc<Self>, user: &AuthenticatedUser) -> Result<AccountExport, AuthError> {
+        let (export, started) = self.exports.start(user.user_id).await;
+        if started {
+            let user_id = user.user_id;
+            actix_web::rt::spawn(async move {
+                let archive = self.build_export(export.id, user_id).await;
+                match &archive {
+                    Ok(archive) => info!("Built export {} of user {} ({} bytes)", export.id, user_id, archive.size()),
+                    Err(e) => warn!("Failed to build export {} of user {}: {}", export.id, user_id, e),
+                }
+                self.exports.finish(export.id, archive).await;
+            });
+            info!("User {} requested export {}", user_id, export.id);
+        }
+        Ok(export)
+    }
+
+    pub async fn export_status(&self, user: &AuthenticatedUser, id: Uuid) -> Result<AccountExport, AuthError> {
+        self.exports
+            .find(user.user_id, id)
+            .await
+            .map(|(export, _)| export)
+            .ok_or(AuthError::ExportNotFound)
+    }
+
+    /// The ZIP archive of a finished export.
+    pub async fn download_export(&self, user: &AuthenticatedUser, id: Uuid) -> Result<Arc<ExportArchive>, AuthError> {
+        let (_, archive) = self.exports
+            .find(user.user_id, id)
+            .await
+            .ok_or(AuthError::ExportNotFound)?;
+        archive.ok_or(AuthError::ExportNotReady)
+    }
+
+    /// Collects the account data and that of every hook into an archive.
+    async fn build_export(&self, id: Uuid, user_id: i32) -> Result<ExportArchive, AuthError> {
+        let mut sections = vec![("account", self.export_account(user_id).await?)];
+        for hook in &self.data_subject_hooks {
+            let files = hook.export(user_id).await.map_err(|e| {
+                warn!("{} failed to export user {}: {}", hook.name(), user_id, e);
+                AuthError::InternalError
+            })?;
+            sections.push((hook.name(), files));
+        }
+
+        export::write_archive(self.exports.archive_path(id), user_id, sections).await
+    }
+
+    async fn export_account(&self, user_id: i32) -> Result<Vec<ExportedFile>, AuthError> {
+        let user = self.users
+            .find_by_id(user_id)
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        let sessions: Vec<Session> = self.sessions
+            .list(user_id)
+            .await?
+            .into_iter()
+            .map(|s| Session::from_record(s, false))
+            .collect();
+        let access_tokens: Vec<AccessToken> = self.access_tokens
+            .list(user_id)
+            .await?
+            .into_iter()
+            .map(AccessToken::from)
+            .collect();
+        let oauth_clients: Vec<OAuthClient> = self.oauth_clients
+            .list(user_id)
+            .await?
+            .into_iter()
+            .map(OAuthClient::from)
+            .collect();
+        let two_factor_enabled = self.two_factor.find(user_id).await?.is_some_and(|r| r.enabled);
+
+        Ok(vec![
+            json_file("profile.json", &serde_json::json!({
+                "user": user,
+                "two_factor_enabled": two_factor_enabled,
+            }))?,
+            json_file("sessions.json", &sessions)?,
+            json_file("access_tokens.json", &access_tokens)?,
+            json_file("oauth_clients.json", &oauth_clients)?,
+        ])
+    }
+
+    /// Schedules the account for erasure after the grace period and signs it
+    /// out everywhere. Asking again keeps the original date.
+    pub async fn schedule_account_deletion(
+        &self,
+        user: &AuthenticatedUser,
+        password: &str,
+        client: &ClientInfo,
+    ) -> Result<AccountDeletion, AuthError> {
+        let event = NewAuditEvent::new(AuditAction::AccountDeletionRequested, client).by(user.user_id);
+        let result: Result<AccountDeletion, AuthError> = async {
+            let account = self.users
+                .find_by_id(user.user_id)
+                .await?
+                .ok_or(AuthError::UserNotFound)?;
+            if !verify_password(password.to_string(), account.password_hash.clone()).await? {
+                return Err(AuthError::InvalidCredentials);
+            }
+
+            let deletion_due_at = match account.deletion_due_at {
+                Some(due_at) => due_at,
+                None => {
+                    let due_at = get_current_timestamp() + self.config.account_deletion_grace_period;
+                    self.users.schedule_deletion(account.id, Some(due_at)).await?;
+                    due_at
+                }
+            };
+            self.logout_all(account.id).await?;
+
+            info!("User {} scheduled the deletion of their account for {}", account.id, deletion_due_at);
+            Ok(AccountDeletion { deletion_due_at })
+        }.await;
+        self.audit(event.outcome(&result)).await;
+        result
+    }
+
+    /// Keeps an account scheduled for deletion.
+    pub async fn cancel_account_deletion(&self, user: &AuthenticatedUser) -> Result<(), AuthError> {
+        if !self.users.schedule_deletion(user.user_id, None).await? {
+            return Err(AuthError::UserNotFound);
+        }
+        info!("User {} cancelled the deletion of their account", user.user_id);
+        Ok(())
+    }
+
+    /// Runs [`Self::purge_deleted_accounts`] every hour in the background,
+    /// starting right away.
+    pub fn spawn_purge_task(self: Arc<Self>) {
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                match self.purge_deleted_accounts().await {
+                    Ok(0) => {}
+                    Ok(purged) => info!("Erased {} deleted accounts", purged),
+                    Err(e) => warn!("Failed to purge deleted accounts: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Erases the accounts whose grace period is over and returns how many.
+    /// An account a hook fails on is kept and retried on the next run.
+    pub async fn purge_deleted_accounts(&self) -> Result<usize, AuthError> {
+        let mut purged = 0;
+        for user_id in self.users.due_for_deletion(get_current_timestamp()).await? {
+            match self.erase_account(user_id).await {
+                Ok(()) => purged += 1,
+                Err(e) => warn!("Failed to erase user {}: {}", user_id, e),
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn erase_account(&self, user_id: i32) -> Result<(), AuthError> {
+        // The other crates go first: once the user is gone, nothing would retry them.
+        for hook in &self.data_subject_hooks {
+            hook.erase(user_id).await.map_err(|e| {
+                warn!("{} failed to erase user {}: {}", hook.name(), user_id, e);
+                AuthError::InternalError
+            })?;
+        }
+
+        self.logout_all(user_id).await?;
+        self.delete_access_tokens(user_id).await?;
+        for client in self.oauth_clients.list(user_id).await? {
+            self.oauth_clients.delete(user_id, &client.client_id).await?;
+        }
+        for invite in self.invites.list().await?.into_iter().filter(|i| i.created_by == user_id) {
+            self.invites.delete(invite.id).await?;
+        }
+        self.two_factor.delete(user_id).await?;
+        self.exports.remove_user(user_id).await;
+        self.email_tokens.revoke_user(user_id, EmailTokenPurpose::PasswordReset).await?;
+        self.email_tokens.revoke_user(user_id, EmailTokenPurpose::EmailVerification).await?;
+        self.users.delete(user_id).await?;
+
+        info!("Erased user {}", user_id);
+        Ok(())
+    }
+
+    /// Sessions of the user that can still be refreshed, most recent first.
+    pub async fn list_sessions(&self, user: &AuthenticatedUser) -> Result<Vec<Session>, AuthError> {
+        let cutoff = get_current_timestamp().saturating_sub(self.config.refresh_token_expiration);
+        let sessions = self.sessions.list(user.user_id).await?;
+        Ok(sessions
+            .into_iter()
+            .filter(|s| s.last_seen_at > cutoff)
+            .map(|s| {
+                let current = user.session_id == Some(s.id);
+                Session::from_record(s, current)
+            })
+            .collect())
+    }
+
+    /// Signs a session of the user out, e.g. a lost device.
+    pub async fn revoke_session(
+        &self,
+        user: &AuthenticatedUser,
+        session_id: Uuid,
+        client: &ClientInfo,
+    ) -> Result<(), AuthError> {
+        let event = NewAuditEvent::new(AuditAction::SessionRevoked, client)
+            .by(user.user_id)
+            .detail("session_id", session_id.to_string());
+        let result: Result<(), AuthError> = async {
+            if !self.end_session(user.user_id, session_id).await? {
+                return Err(AuthError::SessionNotFound);
+            }
+            info!("User {} revoked session {}", user.user_id, session_id);
+            Ok(())
+        }.await;
+        self.audit(event.outcome(&result)).await;
+        result
+    }
+
+    /// Deletes the session and revokes its refresh and access tokens.
+    /// Returns `false` if the user has no such session.
+    async fn end_session(&self, user_id: i32, session_id: Uuid) -> Result<bool, AuthError> {
+        if !self.sessions.delete(user_id, session_id).await? {
+            return Ok(false);
+        }
+        self.refresh_tokens.revoke_family(session_id).await?;
+        // Access tokens of the session stay valid for at most this long.
+        let expires_at = get_current_timestamp() + self.tokens.expiration();
+        self.revocations.revoke_token(&session_revocation_key(session_id), expires_at).await?;
+        Ok(true)
+    }
+
+    async fn start_session(&self, user: &User, client: &ClientInfo) -> Result<AuthResponse, AuthError> {
+        let now = get_current_timestamp();
+        let session = SessionRecord {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            device_name: client.device_name.clone(),
+            user_agent: client.user_agent.clone(),
+            ip: client.ip.clone(),
+            created_at: now,
+            last_seen_at: now,
+            impersonator_id: None,
+        };
+        let session_id = session.id;
+        self.sessions.insert(session).await?;
+        self.issue_tokens(user, session_id).await
+    }
+
+    /// Appends to the audit log. A failure to do so is logged but does not
+    /// fail the action.
+    async fn audit(&self, event: NewAuditEvent) {
+        let action = event.action;
+        if let Err(e) = self.audit.append(event).await {
+            error!("Failed to record {} audit event: {}", action, e);
+        }
+    }
+
+    /// Records that the session was used, at most once per [`LAST_USED_RESOLUTION`].
+    async fn record_session_activity(&self, session_id: Uuid) -> Result<(), AuthError> {
+        if self.session_activity.get(&session_id).await.is_none() {
+            self.session_activity.set(session_id, ()).await;
+            self.sessions.touch(session_id, get_current_timestamp(), None).await?;
+        }
+        Ok(())
+    }
+
+    async fn issue_tokens(&self, user: &User, family_id: Uuid) -> Result<AuthResponse, AuthError> {
+        self.tokens.keys().refresh_if_due().await?;
+        let (token, _) = self.tokens.issue_for_session(user.id, &user.roles, &user.permissions, family_id)?;
+
+        let refresh_token = secret::generate();
+        self.refresh_tokens.insert(RefreshTokenRecord {
+            token_hash: secret::hash(&refresh_token),
+            family_id,
+            user_id: user.id,
+            expires_at: get_current_timestamp() + self.config.refresh_token_expiration,
+            used: false,
+        }).await?;
+
         Ok(AuthResponse {
-            token: "dummy_token".to_string(),
-            user_id: 1,
+            token,
+            token_type: "Bearer".to_string(),
+            expires_in: self.tokens.expiration(),
+            refresh_token,
+            user_id: user.id,
         })
     }
 
-    pub async fn register(request: &LoginRequest) -> Result<User, String> {
-        // TODO: Implement registration logic
-        Ok(User {
-            id: 1,
-            username: request.username.clone(),
-            email: "dummy@email.com".to_string(),
-            password_hash: "hash".to_string(),
+    async fn send_verification_email(&self, user: &User) -> Result<(), AuthError> {
+        if user.email_verified {
+            return Err(AuthError::EmailAlreadyVerified);
+        }
+
+        self.email_tokens.revoke_user(user.id, EmailTokenPurpose::EmailVerification).await?;
+        let token = self.issue_email_token(
+            user,
+            EmailTokenPurpose::EmailVerification,
+            self.config.email_verification_expiration,
+        ).await?;
+        self.mailer.send(Email {
+            to: user.email.clone(),
+            subject: "Confirm your SocialHub email address".to_string(),
+            body: format!(
+                "Hi {},\n\nPlease confirm that this is your email address by opening this link:\n\n\
+                 {}/verify-email?token={}\n\nThe link expires in {} hours.\n",
+                user.username,
+                self.config.public_url.trim_end_matches('/'),
+                token,
+                self.config.email_verification_expiration / 3600,
+            ),
+        }).await
+    }
+
+    /// Stores a new single-use token for `user` and returns it.
+    async fn issue_email_token(
+        &self,
+        user: &User,
+        purpose: EmailTokenPurpose,
+        lifetime: u64,
+    ) -> Result<String, AuthError> {
+        let token = secret::generate();
+        self.email_tokens.insert(EmailTokenRecord {
+            token_hash: secret::hash(&token),
+            user_id: user.id,
+            purpose,
+            email: user.email.clone(),
+            expires_at: get_current_timestamp() + lifetime,
+        }).await?;
+        Ok(token)
+    }
+
+    async fn hash_password(&self, password: &str) -> Result<String, AuthError> {
+        let password = password.to_string();
+        let cost = self.config.hash_cost;
+        web::block(move || bcrypt::hash(password, cost))
+            .await
+            .map_err(|_| AuthError::InternalError)?
+            .map_err(|_| AuthError::InternalError)
+    }
+
+    /// Checks and uses up `code`: a TOTP code for a step newer than the last
+    /// used one or, if `allow_recovery`, an unused recovery code.
+    async fn check_two_factor_code(
+        &self,
+        record: &TwoFactorRecord,
+        code: &str,
+        allow_recovery: bool,
+    ) -> Result<bool, AuthError> {
+        if totp::is_totp_code(code) {
+            return match totp::verify(&record.secret, code, get_current_timestamp()) {
+                Some(step) => self.two_factor.use_step(record.user_id, step).await,
+                None => Ok(false),
+            };
+        }
+        if !allow_recovery {
+            return Ok(false);
+        }
+
+        let code_hash = secret::hash(&totp::normalize_recovery_code(code));
+        let used = self.two_factor.use_recovery_code(record.user_id, &code_hash).await?;
+        if used {
+            info!("User {} used a two-factor recovery code", record.user_id);
+        }
+        Ok(used)
+    }
+
+    fn dummy_hash(&self) -> &'static str {
+        static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+        DUMMY_HASH.get_or_init(|| {
+            bcrypt::hash("dummy-password", self.config.hash_cost).unwrap_or_default()
         })
     }
 }
+
+impl TokenVerifier for AuthService {
+    fn verify<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<AuthenticatedUser, AuthRejection>> {
+        fn rejection(e: AuthError) -> AuthRejection {
+            match e {
+                AuthError::TokenExpired => AuthRejection::TokenExpired,
+                AuthError::TokenRevoked => AuthRejection::TokenRevoked,
+                AuthError::InternalError => AuthRejection::Unavailable,
+                _ => AuthRejection::InvalidToken,
+            }
+        }
+
+        Box::pin(async move {
+            if token.starts_with(TOKEN_PREFIX) {
+                return self.verify_access_token(token).await.map_err(rejection);
+            }
+
+            let claims = self.verify_token(token).await.map_err(rejection)?;
+            if let Some(sid) = claims.sid {
+                self.record_session_activity(sid).await.map_err(rejection)?;
+            }
+            let actor_id = claims.actor_id().map_err(|_| AuthRejection::InvalidToken)?;
+            let token_kind = match (&claims.client_id, actor_id) {
+                (Some(_), _) => TokenKind::OAuth,
+                (None, Some(_)) => TokenKind::Impersonation,
+                (None, None) => TokenKind::Session,
+            };
+            Ok(AuthenticatedUser {
+                user_id: claims.user_id().map_err(|_| AuthRejection::InvalidToken)?,
+                roles: claims.roles,
+                permissions: claims.perms,
+                token_id: claims.jti,
+                expires_at: claims.exp,
+                token_kind,
+                session_id: claims.sid,
+                actor_id,
+            })
+        })
+    }
+}
+
+/// `redirect_uri` carrying an OAuth error for the app.
+fn error_redirect(redirect_uri: &str, error: OAuthErrorCode, state: Option<&str>) -> Result<String, AuthError> {
+    let error = serde_json::to_value(error)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .ok_or(AuthError::InternalError)?;
+    let mut params = vec![("error", error.as_str())];
+    params.extend(state.map(|state| ("state", state)));
+    oauth::redirect_to(redirect_uri, &params)
+}
+
+fn json_file<T: serde::Serialize>(path: &str, value: &T) -> Result<ExportedFile, AuthError> {
+    ExportedFile::json(path, value).map_err(|_| AuthError::InternalError)
+}
+
+/// Denylist key under which a revoked session's access tokens are blocked.
+fn session_revocation_key(session_id: Uuid) -> String {
+    format!("session:{}", session_id)
+}
+
+async fn verify_password(password: String, hash: String) -> Result<bool, AuthError> {
+    web::block(move || bcrypt::verify(password, &hash).unwrap_or(false))
+        .await
+        .map_err(|_| AuthError::InternalError)
+}
+
+fn validate_registration(request: &RegisterRequest) -> Result<(), AuthError> {
+    let username = request.username.trim();
+    if username.len() < 3 || username.len() > 32 {
+        return Err(AuthError::Validation("Username must be 3-32 characters".to_string()));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') {
+        return Err(AuthError::Validation("Username contains invalid characters".to_string()));
+    }
+
+    let email = request.email.trim();
+    let valid_email = email.len() <= 254
+        && email.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.')
+        });
+    if !valid_email {
+        return Err(AuthError::Validation("Invalid email address".to_string()));
+    }
+
+    validate_password(&request.password)
+}
+
+fn validate_password(password: &str) -> Result<(), AuthError> {
+    // bcrypt only looks at the first 72 bytes.
+    if password.len() < 8 || password.len() > 72 {
+        return Err(AuthError::Validation("Password must be 8-72 characters".to_string()));
+    }
+    Ok(())
+}
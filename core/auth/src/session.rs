@@ -0,0 +1,130 @@
+//! Login sessions, one per signed-in device.
+//!
+//! A session starts with a password login and shares its id with the refresh
+//! token family of that login. Access tokens carry the id as their `sid`
+//! claim, so revoking a session cuts off its access and refresh tokens alike.
+
+use std::{collections::HashMap, sync::Mutex};
+use actix_web::{http::header::USER_AGENT, HttpRequest};
+use async_trait::async_trait;
+use uuid::Uuid;
+use crate::error::AuthError;
+
+const MAX_DEVICE_NAME: usize = 64;
+const MAX_USER_AGENT: usize = 512;
+
+/// What is known about the client starting or refreshing a session.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    /// Name the client gave itself, e.g. "Living room TV".
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn from_request(req: &HttpRequest, device_name: Option<&str>) -> Self {
+        Self {
+            device_name: device_name
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| truncate(name, MAX_DEVICE_NAME)),
+            user_agent: req
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|agent| truncate(agent, MAX_USER_AGENT)),
+            // The socket address, not X-Forwarded-For, which clients can forge.
+            ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        }
+    }
+}
+
+fn truncate(value: &str, max_chars: usize) -> String {
+    value.chars().take(max_chars).collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    /// Also the id of the session's refresh token family.
+    pub id: Uuid,
+    pub user_id: i32,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: u64,
+    pub last_seen_at: u64,
+    /// The admin who opened the session to act as the user.
+    pub impersonator_id: Option<i32>,
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn insert(&self, record: SessionRecord) -> Result<(), AuthError>;
+
+    /// Sessions of the user, most recently seen first.
+    async fn list(&self, user_id: i32) -> Result<Vec<SessionRecord>, AuthError>;
+
+    /// Records activity of the session at `at`, from `ip` if known.
+    async fn touch(&self, id: Uuid, at: u64, ip: Option<String>) -> Result<(), AuthError>;
+
+    /// Deletes a session of the user. Returns `false` if the user has no such session.
+    async fn delete(&self, user_id: i32, id: Uuid) -> Result<bool, AuthError>;
+
+    /// Deletes every session of the user.
+    async fn delete_user(&self, user_id: i32) -> Result<(), AuthError>;
+}
+
+/// Keeps sessions in process memory. Intended for tests and local development.
+#[derive(Default)]
+pub struct InMemorySessionStore {
+    sessions: Mutex<HashMap<Uuid, SessionRecord>>,
+}
+
+impl InMemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for InMemorySessionStore {
+    async fn insert(&self, record: SessionRecord) -> Result<(), AuthError> {
+        let mut sessions = self.sessions.lock().map_err(|_| AuthError::InternalError)?;
+        sessions.insert(record.id, record);
+        Ok(())
+    }
+
+    async fn list(&self, user_id: i32) -> Result<Vec<SessionRecord>, AuthError> {
+        let sessions = self.sessions.lock().map_err(|_| AuthError::InternalError)?;
+        let mut list: Vec<_> = sessions.values().filter(|s| s.user_id == user_id).cloned().collect();
+        list.sort_by_key(|s| std::cmp::Reverse(s.last_seen_at));
+        Ok(list)
+    }
+
+    async fn touch(&self, id: Uuid, at: u64, ip: Option<String>) -> Result<(), AuthError> {
+        let mut sessions = self.sessions.lock().map_err(|_| AuthError::InternalError)?;
+        if let Some(session) = sessions.get_mut(&id) {
+            session.last_seen_at = at;
+            if ip.is_some() {
+                session.ip = ip;
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: i32, id: Uuid) -> Result<bool, AuthError> {
+        let mut sessions = self.sessions.lock().map_err(|_| AuthError::InternalError)?;
+        if sessions.get(&id).is_some_and(|s| s.user_id == user_id) {
+            sessions.remove(&id);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    async fn delete_user(&self, user_id: i32) -> Result<(), AuthError> {
+        let mut sessions = self.sessions.lock().map_err(|_| AuthError::InternalError)?;
+        sessions.retain(|_, s| s.user_id != user_id);
+        Ok(())
+    }
+}
@@ -12,6 +12,7 @@ async fn test_login_success() {
         .set_json(LoginRequest {
             username: "testuser".to_string(),
             password: "password123".to_string(),
+            device_name: None,
         })
         .to_request();
 
@@ -30,6 +31,7 @@ async fn test_login_failure() {
         .set_json(LoginRequest {
             username: "invalid".to_string(),
             password: "wrong".to_string(),
+            device_name: None,
         })
         .to_request();
 
@@ -49,6 +51,7 @@ async fn test_register() {
             username: "newuser".to_string(),
             email: "new@user.com".to_string(),
             password: "password123".to_string(),
+            invite_code: None,
         })
         .to_request();
 
@@ -0,0 +1,196 @@
+//! Brute-force protection for password logins.
+//!
+//! Failed attempts are counted per username and per client IP. Once a key
+//! reaches its limit, it is locked for [`AuthConfig::login_lockout`] seconds,
+//! and every further failure doubles the lockout up to
+//! [`AuthConfig::login_max_lockout`]. Counters are forgotten after
+//! [`AuthConfig::login_failure_window`] seconds without failures.
+
+use jsonwebtoken::get_current_timestamp;
+use serde_json::json;
+use socialhub_core::cache::{CacheConfig, CacheManager};
+use socialhub_core::logging::log_event;
+use crate::{config::AuthConfig, error::AuthError};
+
+/// Upper bound on tracked usernames and addresses.
+const CAPACITY: u64 = 100_000;
+
+#[derive(Debug, Clone, Default)]
+struct FailureRecord {
+    failures: u32,
+    last_failure: u64,
+    locked_until: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Key<'a> {
+    Username(&'a str),
+    Ip(&'a str),
+}
+
+impl Key<'_> {
+    fn cache_key(&self) -> String {
+        match self {
+            Key::Username(username) => format!("user:{}", username.to_lowercase()),
+            Key::Ip(ip) => format!("ip:{}", ip),
+        }
+    }
+}
+
+pub struct LoginThrottle {
+    failures: CacheManager<String, FailureRecord>,
+    max_failures: u32,
+    max_failures_per_ip: u32,
+    lockout: u64,
+    max_lockout: u64,
+    window: u64,
+}
+
+impl LoginThrottle {
+    pub fn new(config: &AuthConfig) -> Self {
+        let retention = config.login_failure_window + config.login_max_lockout;
+        Self {
+            failures: CacheManager::new(CacheConfig {
+                max_capacity: CAPACITY,
+                time_to_live: retention,
+                time_to_idle: retention,
+            }),
+            max_failures: config.login_max_failures,
+            max_failures_per_ip: config.login_max_failures_per_ip,
+            lockout: config.login_lockout,
+            max_lockout: config.login_max_lockout,
+            window: config.login_failure_window,
+        }
+    }
+
+    /// Fails with [`AuthError::TooManyAttempts`] while the username or the
+    /// address is locked.
+    pub async fn check(&self, username: &str, ip: Option<&str>) -> Result<(), AuthError> {
+        let now = get_current_timestamp();
+        let mut retry_after = 0;
+        for key in keys(username, ip) {
+            if let Some(record) = self.failures.get(&key.cache_key()).await {
+                retry_after = retry_after.max(record.locked_until.saturating_sub(now));
+            }
+        }
+
+        if retry_after > 0 {
+            return Err(AuthError::TooManyAttempts { retry_after });
+        }
+        Ok(())
+    }
+
+    /// Counts a failed attempt against the username and the address, locking
+    /// them once they reach their limit.
+    pub async fn record_failure(&self, username: &str, ip: Option<&str>) {
+        for key in keys(username, ip) {
+            let limit = match key {
+                Key::Username(_) => self.max_failures,
+                Key::Ip(_) => self.max_failures_per_ip,
+            };
+
+            let now = get_current_timestamp();
+            let record = self.failures.upsert_with(key.cache_key(), |record| {
+                let mut record = record
+                    .filter(|r| r.locked_until > now || now - r.last_failure < self.window)
+                    .unwrap_or_default();
+                record.failures += 1;
+                record.last_failure = now;
+                if record.failures >= limit {
+                    record.locked_until = now + self.lockout_for(record.failures - limit);
+                }
+                record
+            }).await;
+
+            if record.locked_until > now {
+                let (kind, value) = match key {
+                    Key::Username(username) => ("username", username),
+                    Key::Ip(ip) => ("ip", ip),
+                };
+                log_event("WARN", "auth", "Login locked out", Some(json!({
+                    "kind": kind,
+                    "key": value,
+                    "failures": record.failures,
+                    "retry_after": record.locked_until - now,
+                })));
+            }
+        }
+    }
+
+    /// Clears the failures of the username after a successful login. The
+    /// address keeps its count, so logging into one's own account does not
+    /// reset guesses at others.
+    pub async fn record_success(&self, username: &str) {
+        self.failures.remove(&Key::Username(username).cache_key()).await;
+    }
+
+    /// Lockout after `excess` failures past the limit: the base lockout,
+    /// doubled for each.
+    fn lockout_for(&self, excess: u32) -> u64 {
+        self.lockout
+            .saturating_mul(1u64 << excess.min(32))
+            .min(self.max_lockout)
+    }
+}
+
+fn keys<'a>(username: &'a str, ip: Option<&'a str>) -> impl Iterator<Item = Key<'a>> {
+    std::iter::once(Key::Username(username)).chain(ip.map(Key::Ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(&AuthConfig {
+            login_max_failures: 3,
+            login_max_failures_per_ip: 5,
+            login_lockout: 30,
+            login_max_lockout: 100,
+            ..AuthConfig::default()
+        })
+    }
+
+    fn retry_after(result: Result<(), AuthError>) -> u64 {
+        match result {
+            Err(AuthError::TooManyAttempts { retry_after }) => retry_after,
+            Ok(()) => 0,
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_lockout_grows_exponentially() {
+        let throttle = throttle();
+        for _ in 0..2 {
+            throttle.record_failure("alice", None).await;
+        }
+        assert!(throttle.check("alice", None).await.is_ok());
+
+        throttle.record_failure("alice", None).await;
+        assert!((29..=30).contains(&retry_after(throttle.check("ALICE", None).await)));
+        throttle.record_failure("alice", None).await;
+        assert!((59..=60).contains(&retry_after(throttle.check("alice", None).await)));
+        throttle.record_failure("alice", None).await;
+        assert!((99..=100).contains(&retry_after(throttle.check("alice", None).await)));
+
+        assert!(throttle.check("bob", None).await.is_ok());
+        throttle.record_success("alice").await;
+        assert!(throttle.check("alice", None).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_ip_is_limited_across_usernames() {
+        let throttle = throttle();
+        for name in ["a", "b", "c", "d", "e"] {
+            throttle.record_failure(name, Some("10.0.0.1")).await;
+        }
+
+        assert!(retry_after(throttle.check("f", Some("10.0.0.1")).await) > 0);
+        assert!(throttle.check("f", Some("10.0.0.2")).await.is_ok());
+
+        // Success does not reset the address.
+        throttle.record_success("f").await;
+        assert!(retry_after(throttle.check("f", Some("10.0.0.1")).await) > 0);
+    }
+}
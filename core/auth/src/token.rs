@@ -0,0 +1,340 @@
+use std::sync::Arc;
+use jsonwebtoken::{decode, errors::ErrorKind, get_current_timestamp, Algorithm, Validation};
+use serde::{Deserialize, Serialize};
+use socialhub_core::auth::{effective_permissions, Role};
+use uuid::Uuid;
+use crate::{config::AuthConfig, error::AuthError, keys::KeyStore};
+
+/// What a token may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenUse {
+    /// Bearer token for the API.
+    #[default]
+    Access,
+    /// Proof of a correct password, exchanged at `/auth/login/2fa`.
+    TwoFactorChallenge,
+}
+
+/// Claims carried by every token issued by SocialHub.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    /// User id, as a string per RFC 7519.
+    pub sub: String,
+    pub roles: Vec<Role>,
+    /// Effective permissions at the time of issue.
+    #[serde(default)]
+    pub perms: Vec<String>,
+    pub iss: String,
+    pub iat: u64,
+    pub exp: u64,
+    /// Unique token id.
+    pub jti: String,
+    #[serde(default)]
+    pub token_use: TokenUse,
+    /// Login session the token belongs to, see [`crate::session`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    /// Third-party app the token was issued to through OAuth.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Space-separated OAuth scopes granted to `client_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// The admin acting as `sub` (RFC 8693 section 4.1).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+/// The party acting on behalf of the subject of a token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    /// User id, as a string like `sub`.
+    pub sub: String,
+}
+
+impl Claims {
+    pub fn user_id(&self) -> Result<i32, AuthError> {
+        self.sub.parse().map_err(|_| AuthError::InvalidToken)
+    }
+
+    /// The admin impersonating the user, for tokens from [`TokenService::issue_impersonation`].
+    pub fn actor_id(&self) -> Result<Option<i32>, AuthError> {
+        self.act
+            .as_ref()
+            .map(|act| act.sub.parse().map_err(|_| AuthError::InvalidToken))
+            .transpose()
+    }
+
+    /// OAuth scopes of the token; empty unless issued to a third-party app.
+    pub fn scopes(&self) -> Vec<&str> {
+        self.scope.as_deref().map(|s| s.split(' ').collect()).unwrap_or_default()
+    }
+}
+
+/// Signs and verifies access tokens with the keys of a [`KeyStore`].
+///
+/// Other crates only need [`TokenService::verify`]; issuing is done by the
+/// auth handlers.
+pub struct TokenService {
+    keys: Arc<KeyStore>,
+    validation: Validation,
+    issuer: String,
+    expiration: u64,
+}
+
+impl TokenService {
+    pub fn new(config: &AuthConfig, keys: Arc<KeyStore>) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_issuer(&[&config.issuer]);
+        validation.set_required_spec_claims(&["exp", "iat", "iss", "sub"]);
+
+        Self {
+            keys,
+            validation,
+            issuer: config.issuer.clone(),
+            expiration: config.token_expiration,
+        }
+    }
+
+    pub fn keys(&self) -> &KeyStore {
+        &self.keys
+    }
+
+    /// Lifetime of issued access tokens, in seconds.
+    pub fn expiration(&self) -> u64 {
+        self.expiration
+    }
+
+    /// Issues an access token carrying `roles` and the permissions they grant,
+    /// plus the individually granted `permissions`, outside of any session.
+    pub fn issue(&self, user_id: i32, roles: &[Role], permissions: &[String]) -> Result<(String, Claims), AuthError> {
+        self.issue_access(user_id, roles, permissions, None)
+    }
+
+    /// Like [`TokenService::issue`], for a token of the login session `session_id`.
+    pub fn issue_for_session(
+        &self,
+        user_id: i32,
+        roles: &[Role],
+        permissions: &[String],
+        session_id: Uuid,
+    ) -> Result<(String, Claims), AuthError> {
+        self.issue_access(user_id, roles, permissions, Some(session_id))
+    }
+
+    fn issue_access(
+        &self,
+        user_id: i32,
+        roles: &[Role],
+        permissions: &[String],
+        sid: Option<Uuid>,
+    ) -> Result<(String, Claims), AuthError> {
+        let now = get_current_timestamp();
+        self.sign(Claims {
+            sub: user_id.to_string(),
+            roles: roles.to_vec(),
+            perms: effective_permissions(roles, permissions),
+            iss: self.issuer.clone(),
+            iat: now,
+            exp: now + self.expiration,
+            jti: Uuid::new_v4().to_string(),
+            token_use: TokenUse::Access,
+            sid,
+            client_id: None,
+            scope: None,
+            act: None,
+        })
+    }
+
+    /// Issues an access token for the third-party app `client_id`, carrying
+    /// exactly `permissions` and the granted `scopes`.
+    pub fn issue_for_client(
+        &self,
+        user_id: i32,
+        roles: &[Role],
+        permissions: Vec<String>,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<(String, Claims), AuthError> {
+        let now = get_current_timestamp();
+        self.sign(Claims {
+            sub: user_id.to_string(),
+            roles: roles.to_vec(),
+            perms: permissions,
+            iss: self.issuer.clone(),
+            iat: now,
+            exp: now + self.expiration,
+            jti: Uuid::new_v4().to_string(),
+            token_use: TokenUse::Access,
+            sid: None,
+            client_id: Some(client_id.to_string()),
+            scope: Some(scopes.join(" ")),
+            act: None,
+        })
+    }
+
+    /// Issues an access token of the session `session_id` for `actor_id` to
+    /// act as `user_id`, valid for `lifetime` seconds.
+    pub fn issue_impersonation(
+        &self,
+        user_id: i32,
+        roles: &[Role],
+        permissions: &[String],
+        session_id: Uuid,
+        actor_id: i32,
+        lifetime: u64,
+    ) -> Result<(String, Claims), AuthError> {
+        let now = get_current_timestamp();
+        self.sign(Claims {
+            sub: user_id.to_string(),
+            roles: roles.to_vec(),
+            perms: effective_permissions(roles, permissions),
+            iss: self.issuer.clone(),
+            iat: now,
+            exp: now + lifetime,
+            jti: Uuid::new_v4().to_string(),
+            token_use: TokenUse::Access,
+            sid: Some(session_id),
+            client_id: None,
+            scope: None,
+            act: Some(Actor { sub: actor_id.to_string() }),
+        })
+    }
+
+    /// Issues a token that only proves `user_id` passed the password step of
+    /// a login. It carries no permissions and is rejected by [`TokenService::verify`].
+    pub fn issue_challenge(&self, user_id: i32, lifetime: u64) -> Result<(String, Claims), AuthError> {
+        let now = get_current_timestamp();
+        self.sign(Claims {
+            sub: user_id.to_string(),
+            roles: Vec::new(),
+            perms: Vec::new(),
+            iss: self.issuer.clone(),
+            iat: now,
+            exp: now + lifetime,
+            jti: Uuid::new_v4().to_string(),
+            token_use: TokenUse::TwoFactorChallenge,
+            sid: None,
+            client_id: None,
+            scope: None,
+            act: None,
+        })
+    }
+
+    /// Verifies an access token.
+    pub fn verify(&self, token: &str) -> Result<Claims, AuthError> {
+        self.verify_use(token, TokenUse::Access)
+    }
+
+    /// Verifies a token issued by [`TokenService::issue_challenge`].
+    pub fn verify_challenge(&self, token: &str) -> Result<Claims, AuthError> {
+        self.verify_use(token, TokenUse::TwoFactorChallenge)
+    }
+
+    fn sign(&self, claims: Claims) -> Result<(String, Claims), AuthError> {
+        let token = self.keys.sign(&claims)?;
+        Ok((token, claims))
+    }
+
+    fn verify_use(&self, token: &str, expected: TokenUse) -> Result<Claims, AuthError> {
+        let (key, algorithm) = self.keys.decoding_key(token)?;
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![algorithm];
+        let claims = decode::<Claims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| match e.kind() {
+                ErrorKind::ExpiredSignature => AuthError::TokenExpired,
+                _ => AuthError::InvalidToken,
+            })?;
+
+        if claims.token_use != expected {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use crate::keys::InMemorySigningKeyStore;
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            jwt_secret: "test-secret".to_string(),
+            ..AuthConfig::default()
+        }
+    }
+
+    fn token_service(config: &AuthConfig) -> TokenService {
+        let keys = KeyStore::new(config, Arc::new(InMemorySigningKeyStore::new()));
+        TokenService::new(config, Arc::new(keys))
+    }
+
+    #[test]
+    fn test_issue_and_verify() {
+        let service = token_service(&config());
+        let (token, issued) = service.issue(42, &[Role::Moderator], &["addons:manage".to_string()]).unwrap();
+
+        let claims = service.verify(&token).unwrap();
+        assert_eq!(claims.user_id().unwrap(), 42);
+        assert_eq!(claims.roles, vec![Role::Moderator]);
+        assert!(claims.perms.contains(&"posts:delete_any".to_string()));
+        assert!(claims.perms.contains(&"addons:manage".to_string()));
+        assert!(!claims.perms.contains(&"users:manage".to_string()));
+        assert_eq!(claims.jti, issued.jti);
+        assert_eq!(claims.exp - claims.iat, 86400);
+    }
+
+    #[test]
+    fn test_rejects_foreign_signature() {
+        let service = token_service(&config());
+        let other = token_service(&AuthConfig {
+            jwt_secret: "another-secret".to_string(),
+            ..AuthConfig::default()
+        });
+        let (token, _) = other.issue(1, &[], &[]).unwrap();
+
+        assert!(matches!(service.verify(&token), Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_rejects_expired_token() {
+        let service = token_service(&config());
+        let now = get_current_timestamp();
+        let claims = Claims {
+            sub: "1".to_string(),
+            roles: Vec::new(),
+            perms: Vec::new(),
+            iss: "socialhub".to_string(),
+            iat: now - 7200,
+            exp: now - 3600,
+            jti: Uuid::new_v4().to_string(),
+            token_use: TokenUse::Access,
+            sid: None,
+            client_id: None,
+            scope: None,
+            act: None,
+        };
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"test-secret"),
+        ).unwrap();
+
+        assert!(matches!(service.verify(&token), Err(AuthError::TokenExpired)));
+    }
+
+    #[test]
+    fn test_challenge_is_not_an_access_token() {
+        let service = token_service(&config());
+        let (challenge, _) = service.issue_challenge(7, 300).unwrap();
+        let (access, _) = service.issue(7, &[Role::User], &[]).unwrap();
+
+        assert_eq!(service.verify_challenge(&challenge).unwrap().user_id().unwrap(), 7);
+        assert!(matches!(service.verify(&challenge), Err(AuthError::InvalidToken)));
+        assert!(matches!(service.verify_challenge(&access), Err(AuthError::InvalidToken)));
+    }
+}
@@ -0,0 +1,94 @@
+//! Storage for TOTP two-factor enrollments.
+//!
+//! An enrollment starts out pending and only guards logins once the user
+//! confirmed it with a valid code.
+
+use std::{collections::HashMap, sync::Mutex};
+use async_trait::async_trait;
+use crate::error::AuthError;
+
+#[derive(Debug, Clone)]
+pub struct TwoFactorRecord {
+    pub user_id: i32,
+    /// Raw TOTP shared secret.
+    pub secret: Vec<u8>,
+    /// Set once the enrollment was confirmed.
+    pub enabled: bool,
+    /// Digests of the unused recovery codes, see [`crate::secret::hash`].
+    pub recovery_codes: Vec<String>,
+    /// Last time step a code was accepted for, to reject replays.
+    pub last_used_step: u64,
+}
+
+#[async_trait]
+pub trait TwoFactorStore: Send + Sync {
+    async fn find(&self, user_id: i32) -> Result<Option<TwoFactorRecord>, AuthError>;
+
+    /// Inserts or replaces the record of `record.user_id`.
+    async fn save(&self, record: TwoFactorRecord) -> Result<(), AuthError>;
+
+    async fn delete(&self, user_id: i32) -> Result<(), AuthError>;
+
+    /// Records that a code for `step` was used. Returns `false`, without
+    /// changing anything, if the step is not newer than the last used one.
+    ///
+    /// Must be atomic so that one code cannot be used twice concurrently.
+    async fn use_step(&self, user_id: i32, step: u64) -> Result<bool, AuthError>;
+
+    /// Removes the recovery code with digest `code_hash`. Returns `false` if
+    /// the user has no such code. Must be atomic.
+    async fn use_recovery_code(&self, user_id: i32, code_hash: &str) -> Result<bool, AuthError>;
+}
+
+/// Keeps enrollments in process memory. Intended for tests and local development.
+#[derive(Default)]
+pub struct InMemoryTwoFactorStore {
+    records: Mutex<HashMap<i32, TwoFactorRecord>>,
+}
+
+impl InMemoryTwoFactorStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TwoFactorStore for InMemoryTwoFactorStore {
+    async fn find(&self, user_id: i32) -> Result<Option<TwoFactorRecord>, AuthError> {
+        let records = self.records.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(records.get(&user_id).cloned())
+    }
+
+    async fn save(&self, record: TwoFactorRecord) -> Result<(), AuthError> {
+        let mut records = self.records.lock().map_err(|_| AuthError::InternalError)?;
+        records.insert(record.user_id, record);
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: i32) -> Result<(), AuthError> {
+        let mut records = self.records.lock().map_err(|_| AuthError::InternalError)?;
+        records.remove(&user_id);
+        Ok(())
+    }
+
+    async fn use_step(&self, user_id: i32, step: u64) -> Result<bool, AuthError> {
+        let mut records = self.records.lock().map_err(|_| AuthError::InternalError)?;
+        Ok(match records.get_mut(&user_id) {
+            Some(record) if step > record.last_used_step => {
+                record.last_used_step = step;
+                true
+            }
+            _ => false,
+        })
+    }
+
+    async fn use_recovery_code(&self, user_id: i32, code_hash: &str) -> Result<bool, AuthError> {
+        let mut records = self.records.lock().map_err(|_| AuthError::InternalError)?;
+        let Some(record) = records.get_mut(&user_id) else {
+            return Ok(false);
+        };
+        let before = record.recovery_codes.len();
+        record.recovery_codes.retain(|c| c != code_hash);
+        Ok(record.recovery_codes.len() < before)
+    }
+}
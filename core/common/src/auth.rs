@@ -0,0 +1,438 @@
+//! Request authentication shared by every SocialHub crate.
+//!
+//! Handlers take an [`AuthenticatedUser`] argument to require a valid bearer
+//! token. The token itself is checked by whatever [`TokenVerifier`] the app
+//! registered as `web::Data<dyn TokenVerifier>`; socialhub-auth provides one.
+
+use std::fmt;
+use actix_web::{
+    dev::Payload,
+    http::{header, StatusCode},
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use futures::future::{BoxFuture, LocalBoxFuture};
+use log::info;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+const REALM: &str = "socialhub";
+
+/// Permission names checked by the handlers and route guards.
+pub mod permissions {
+    pub const POSTS_CREATE: &str = "posts:create";
+    /// Delete posts written by other users.
+    pub const POSTS_DELETE_ANY: &str = "posts:delete_any";
+    pub const STREAMS_START: &str = "streams:start";
+    /// Stop streams started by other users.
+    pub const STREAMS_STOP_ANY: &str = "streams:stop_any";
+    pub const MEDIA_UPLOAD: &str = "media:upload";
+    pub const MEDIA_DELETE: &str = "media:delete";
+    pub const ADDONS_MANAGE: &str = "addons:manage";
+    /// Change roles and permissions of other users.
+    pub const USERS_MANAGE: &str = "users:manage";
+    /// Read the security audit log.
+    pub const AUDIT_READ: &str = "audit:read";
+    /// Act as another user, e.g. to reproduce a reported bug.
+    pub const USERS_IMPERSONATE: &str = "users:impersonate";
+
+    pub const ALL: &[&str] = &[
+        POSTS_CREATE, POSTS_DELETE_ANY, STREAMS_START, STREAMS_STOP_ANY,
+        MEDIA_UPLOAD, MEDIA_DELETE, ADDONS_MANAGE, USERS_MANAGE, AUDIT_READ,
+        USERS_IMPERSONATE,
+    ];
+}
+
+/// OAuth scopes third-party apps can request. Besides the OpenID Connect
+/// scopes, there is one per addon-manager `plugin::Permission`.
+pub mod scopes {
+    use super::permissions::*;
+
+    pub const OPENID: &str = "openid";
+    /// The username, as `preferred_username`.
+    pub const PROFILE: &str = "profile";
+    pub const EMAIL: &str = "email";
+    pub const USER_READ: &str = "user:read";
+    pub const USER_WRITE: &str = "user:write";
+    pub const MEDIA_READ: &str = "media:read";
+    pub const MEDIA_WRITE: &str = "media:write";
+    pub const STREAM_READ: &str = "stream:read";
+    pub const STREAM_WRITE: &str = "stream:write";
+
+    pub const ALL: &[&str] = &[
+        OPENID, PROFILE, EMAIL,
+        USER_READ, USER_WRITE, MEDIA_READ, MEDIA_WRITE, STREAM_READ, STREAM_WRITE,
+    ];
+
+    /// Permissions an app granted `scope` may use, as far as the user holds them.
+    /// Read scopes grant none; reading needs no permission. Only what users may
+    /// do with their own content is delegated, never moderation powers such as
+    /// deleting media or stopping other users' streams.
+    pub fn permissions(scope: &str) -> &'static [&'static str] {
+        match scope {
+            USER_WRITE => &[POSTS_CREATE],
+            MEDIA_WRITE => &[MEDIA_UPLOAD],
+            STREAM_WRITE => &[STREAMS_START],
+            _ => &[],
+        }
+    }
+
+    /// Human-readable summary for consent screens.
+    pub fn description(scope: &str) -> &'static str {
+        match scope {
+            OPENID => "Sign you in with your SocialHub account",
+            PROFILE => "See your username",
+            EMAIL => "See your email address",
+            USER_READ => "See your profile and posts",
+            USER_WRITE => "Post on your behalf",
+            MEDIA_READ => "See your media",
+            MEDIA_WRITE => "Upload media",
+            STREAM_READ => "See your streams",
+            STREAM_WRITE => "Start and stop your streams",
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    /// Permissions granted by the role. Higher roles include those of the lower ones.
+    pub fn permissions(&self) -> &'static [&'static str] {
+        use permissions::*;
+
+        const USER: &[&str] = &[POSTS_CREATE, STREAMS_START, MEDIA_UPLOAD];
+        const MODERATOR: &[&str] = &[
+            POSTS_CREATE, STREAMS_START, MEDIA_UPLOAD,
+            POSTS_DELETE_ANY, STREAMS_STOP_ANY, MEDIA_DELETE,
+        ];
+        const ADMIN: &[&str] = &[
+            POSTS_CREATE, STREAMS_START, MEDIA_UPLOAD,
+            POSTS_DELETE_ANY, STREAMS_STOP_ANY, MEDIA_DELETE,
+            ADDONS_MANAGE, USERS_MANAGE, AUDIT_READ, USERS_IMPERSONATE,
+        ];
+
+        match self {
+            Role::User => USER,
+            Role::Moderator => MODERATOR,
+            Role::Admin => ADMIN,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = UnknownRole;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(UnknownRole(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown role: {0}")]
+pub struct UnknownRole(pub String);
+
+/// Union of the permissions granted by `roles` and the individually granted `extra` ones.
+pub fn effective_permissions(roles: &[Role], extra: &[String]) -> Vec<String> {
+    let mut permissions: Vec<String> = roles
+        .iter()
+        .flat_map(|role| role.permissions().iter().map(|p| p.to_string()))
+        .chain(extra.iter().cloned())
+        .collect();
+    permissions.sort();
+    permissions.dedup();
+    permissions
+}
+
+/// The kind of bearer token a request was authenticated with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    /// Access token from an interactive login.
+    Session,
+    /// Personal access token, limited to the scopes it was created with.
+    PersonalAccessToken,
+    /// Access token of a third-party app, limited to the scopes the user
+    /// consented to.
+    #[serde(rename = "oauth")]
+    OAuth,
+    /// Short-lived access token an admin obtained to act as the user; see
+    /// [`AuthenticatedUser::actor_id`].
+    Impersonation,
+}
+
+/// The caller of the current request, as established by its bearer token.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthenticatedUser {
+    pub user_id: i32,
+    pub roles: Vec<Role>,
+    /// Effective permissions, see [`effective_permissions`]. For personal
+    /// access tokens, only those within the token's scopes.
+    pub permissions: Vec<String>,
+    /// Id of the presented token (`jti`).
+    pub token_id: String,
+    /// Expiry of the presented token, as a Unix timestamp.
+    pub expires_at: u64,
+    pub token_kind: TokenKind,
+    /// Login session the token belongs to, if any.
+    pub session_id: Option<Uuid>,
+    /// The admin acting as the user, for [`TokenKind::Impersonation`] tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<i32>,
+}
+
+impl AuthenticatedUser {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+
+    pub fn require_permission(&self, permission: &str) -> Result<(), Forbidden> {
+        if self.has_permission(permission) {
+            Ok(())
+        } else {
+            Err(Forbidden)
+        }
+    }
+
+    /// Rejects callers that are not using a login session, for account
+    /// management that scoped and impersonation tokens must not reach.
+    pub fn require_session(&self) -> Result<(), Forbidden> {
+        if self.token_kind == TokenKind::Session {
+            Ok(())
+        } else {
+            Err(Forbidden)
+        }
+    }
+}
+
+/// The caller is authenticated but lacks a required permission.
+#[derive(Debug, Error)]
+#[error("Not permitted")]
+pub struct Forbidden;
+
+impl ResponseError for Forbidden {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::Forbidden().json("Not permitted")
+    }
+}
+
+/// Checks bearer tokens on behalf of [`AuthenticatedUser`].
+pub trait TokenVerifier: Send + Sync {
+    fn verify<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<AuthenticatedUser, AuthRejection>>;
+}
+
+#[derive(Debug, Error)]
+pub enum AuthRejection {
+    #[error("Missing bearer token")]
+    MissingToken,
+
+    #[error("Invalid token")]
+    InvalidToken,
+
+    #[error("Token expired")]
+    TokenExpired,
+
+    #[error("Token revoked")]
+    TokenRevoked,
+
+    #[error("No token verifier configured")]
+    Unavailable,
+}
+
+impl ResponseError for AuthRejection {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthRejection::Unavailable => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let challenge = match self {
+            // RFC 6750 section 3.1: no error code when the request had no token.
+            AuthRejection::MissingToken => format!("Bearer realm=\"{}\"", REALM),
+            AuthRejection::InvalidToken | AuthRejection::TokenExpired | AuthRejection::TokenRevoked => format!(
+                "Bearer realm=\"{}\", error=\"invalid_token\", error_description=\"{}\"",
+                REALM, self
+            ),
+            AuthRejection::Unavailable => {
+                return HttpResponse::InternalServerError().json("Internal server error");
+            }
+        };
+
+        HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, challenge))
+            .json(self.to_string())
+    }
+}
+
+/// Returns the token from an `Authorization: Bearer <token>` header.
+pub fn bearer_token(req: &HttpRequest) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then(|| token.to_string())
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AuthRejection;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // Already verified earlier in this request, e.g. by a route guard.
+        if let Some(user) = req.extensions().get::<AuthenticatedUser>() {
+            let user = user.clone();
+            return Box::pin(async move { Ok(user) });
+        }
+
+        let req = req.clone();
+        Box::pin(async move {
+            let verifier = req
+                .app_data::<web::Data<dyn TokenVerifier>>()
+                .cloned()
+                .ok_or(AuthRejection::Unavailable)?;
+            let token = bearer_token(&req).ok_or(AuthRejection::MissingToken)?;
+
+            let user = verifier.verify(&token).await?;
+            if let Some(actor_id) = user.actor_id {
+                info!(
+                    target: "impersonation",
+                    "{} {} by user {} impersonating user {}",
+                    req.method(), req.path(), actor_id, user.user_id
+                );
+            }
+            req.extensions_mut().insert(user.clone());
+            Ok(user)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test::{call_and_read_body_json, call_service, init_service, TestRequest}, App};
+    use std::sync::Arc;
+
+    struct StaticVerifier;
+
+    impl TokenVerifier for StaticVerifier {
+        fn verify<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<AuthenticatedUser, AuthRejection>> {
+            Box::pin(async move {
+                match token {
+                    "good" => Ok(AuthenticatedUser {
+                        user_id: 7,
+                        roles: vec![Role::User],
+                        permissions: effective_permissions(&[Role::User], &[]),
+                        token_id: "jti".to_string(),
+                        expires_at: u64::MAX,
+                        token_kind: TokenKind::Session,
+                        session_id: None,
+                        actor_id: None,
+                    }),
+                    "old" => Err(AuthRejection::TokenExpired),
+                    _ => Err(AuthRejection::InvalidToken),
+                }
+            })
+        }
+    }
+
+    async fn whoami(user: AuthenticatedUser) -> HttpResponse {
+        HttpResponse::Ok().json(user.user_id)
+    }
+
+    fn app_config(cfg: &mut web::ServiceConfig) {
+        let verifier: Arc<dyn TokenVerifier> = Arc::new(StaticVerifier);
+        cfg.app_data(web::Data::from(verifier))
+            .route("/whoami", web::get().to(whoami));
+    }
+
+    #[actix_rt::test]
+    async fn test_valid_token() {
+        let app = init_service(App::new().configure(app_config)).await;
+        let req = TestRequest::get()
+            .uri("/whoami")
+            .insert_header(("Authorization", "Bearer good"))
+            .to_request();
+
+        let body: i32 = call_and_read_body_json(&app, req).await;
+        assert_eq!(body, 7);
+    }
+
+    #[actix_rt::test]
+    async fn test_missing_token() {
+        let app = init_service(App::new().configure(app_config)).await;
+        let req = TestRequest::get().uri("/whoami").to_request();
+
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers().get(header::WWW_AUTHENTICATE).unwrap(), "Bearer realm=\"socialhub\"");
+    }
+
+    #[test]
+    fn test_effective_permissions() {
+        let moderator = effective_permissions(&[Role::Moderator], &[]);
+        assert!(moderator.contains(&permissions::POSTS_DELETE_ANY.to_string()));
+        assert!(!moderator.contains(&permissions::ADDONS_MANAGE.to_string()));
+
+        let granted = effective_permissions(&[Role::User], &[permissions::ADDONS_MANAGE.to_string()]);
+        assert!(granted.contains(&permissions::ADDONS_MANAGE.to_string()));
+        assert!(granted.contains(&permissions::POSTS_CREATE.to_string()));
+
+        let admin = effective_permissions(&[Role::Admin, Role::User], &[]);
+        assert_eq!(admin.len(), Role::Admin.permissions().len());
+    }
+
+    #[test]
+    fn test_scopes_grant_no_moderation() {
+        let moderation = [permissions::POSTS_DELETE_ANY, permissions::STREAMS_STOP_ANY, permissions::MEDIA_DELETE];
+        for scope in scopes::ALL {
+            assert!(scopes::permissions(scope).iter().all(|p| !moderation.contains(p)), "{}", scope);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_and_expired_tokens() {
+        let app = init_service(App::new().configure(app_config)).await;
+
+        for header_value in ["Bearer bogus", "Bearer old", "Basic Z29vZA==", "good"] {
+            let req = TestRequest::get()
+                .uri("/whoami")
+                .insert_header(("Authorization", header_value))
+                .to_request();
+
+            let resp = call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{}", header_value);
+            assert!(resp.headers().contains_key(header::WWW_AUTHENTICATE));
+        }
+    }
+}
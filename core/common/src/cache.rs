@@ -97,6 +97,24 @@ where
         value
     }
 
+    /// Replaces the value under `key` with `f(current value)` and returns the
+    /// new value. Concurrent updates of the same key are serialized, so this
+    /// is safe for counters.
+    pub async fn upsert_with<F>(&self, key: K, f: F) -> V
+    where
+        F: FnOnce(Option<V>) -> V,
+    {
+        if let Ok(mut metrics) = self.metrics.write() {
+            metrics.total_insertions += 1;
+        }
+
+        self.cache
+            .entry(key)
+            .and_upsert_with(|entry| std::future::ready(f(entry.map(|e| e.into_value()))))
+            .await
+            .into_value()
+    }
+
     pub fn entry_count(&self) -> u64 {
         self.cache.entry_count()
     }
@@ -130,6 +148,12 @@ where
         self.cache.invalidate(key).await;
     }
 
+    /// Removes the value under `key` and returns it. Of concurrent calls for
+    /// the same key, only one gets the value.
+    pub async fn take(&self, key: &K) -> Option<V> {
+        self.cache.remove(key).await
+    }
+
     pub fn get_metrics(&self) -> CacheMetrics {
         self.metrics.read().unwrap().clone()
     }
@@ -183,6 +207,42 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_concurrent_upserts() {
+        let cache = CacheManager::<String, u32>::new(CacheConfig::default());
+        let handles: Vec<_> = (0..50)
+            .map(|_| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    cache.upsert_with("counter".to_string(), |n| n.unwrap_or(0) + 1).await
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(cache.get(&"counter".to_string()).await, Some(50));
+    }
+
+    #[tokio::test]
+    async fn test_take_hands_out_value_once() {
+        let cache = CacheManager::<String, u32>::new(CacheConfig::default());
+        cache.set("code".to_string(), 7).await;
+        let handles: Vec<_> = (0..20)
+            .map(|_| {
+                let cache = cache.clone();
+                tokio::spawn(async move { cache.take(&"code".to_string()).await })
+            })
+            .collect();
+
+        let mut taken = Vec::new();
+        for handle in handles {
+            taken.extend(handle.await.unwrap());
+        }
+        assert_eq!(taken, vec![7]);
+        assert_eq!(cache.get(&"code".to_string()).await, None);
+    }
+
     #[tokio::test]
     async fn test_cache_capacity_limit() {
         init_test_logger();
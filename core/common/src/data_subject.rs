@@ -0,0 +1,76 @@
+//! Personal data that SocialHub crates hold about a user.
+//!
+//! socialhub-auth owns the account and handles export and deletion requests
+//! (GDPR articles 15, 17 and 20). Every other crate that stores data about
+//! users implements [`DataSubjectHook`] and is registered with the auth
+//! service, which calls it for its part of the archive and to erase the user.
+
+use std::fmt;
+use actix_web::web::Bytes;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use serde::Serialize;
+use crate::error::CommonError;
+
+/// A file in a personal data export.
+#[derive(Debug)]
+pub struct ExportedFile {
+    /// Path below the directory of the hook, e.g. `posts.json`.
+    pub path: String,
+    pub contents: ExportedContents,
+}
+
+/// What an [`ExportedFile`] holds.
+pub enum ExportedContents {
+    /// Records and other small files.
+    Bytes(Vec<u8>),
+    /// Large files, such as media originals, read only while the archive is
+    /// written. The stream should not open anything before it is first polled.
+    Stream(BoxStream<'static, Result<Bytes, CommonError>>),
+}
+
+impl fmt::Debug for ExportedContents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportedContents::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            ExportedContents::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl ExportedFile {
+    pub fn new(path: &str, contents: Vec<u8>) -> Self {
+        Self { path: path.to_string(), contents: ExportedContents::Bytes(contents) }
+    }
+
+    pub fn stream(path: &str, contents: BoxStream<'static, Result<Bytes, CommonError>>) -> Self {
+        Self { path: path.to_string(), contents: ExportedContents::Stream(contents) }
+    }
+
+    /// `value` as pretty-printed JSON.
+    pub fn json<T: Serialize>(path: &str, value: &T) -> Result<Self, CommonError> {
+        let contents = serde_json::to_vec_pretty(value).map_err(|_| CommonError::InternalError)?;
+        Ok(Self::new(path, contents))
+    }
+
+    /// The contents, unless they are streamed.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match &self.contents {
+            ExportedContents::Bytes(bytes) => Some(bytes),
+            ExportedContents::Stream(_) => None,
+        }
+    }
+}
+
+pub trait DataSubjectHook: Send + Sync {
+    /// Directory of the crate's files in the export archive, e.g. `social`.
+    fn name(&self) -> &'static str;
+
+    /// Everything the crate stores about `user_id`.
+    fn export(&self, user_id: i32) -> BoxFuture<'_, Result<Vec<ExportedFile>, CommonError>>;
+
+    /// Deletes everything the crate stores about `user_id`, or anonymizes
+    /// what other users' data depends on. Runs again when a later hook fails,
+    /// so it must be idempotent.
+    fn erase(&self, user_id: i32) -> BoxFuture<'_, Result<(), CommonError>>;
+}
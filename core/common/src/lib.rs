@@ -1,23 +1,20 @@
 pub mod error;
+pub mod auth;
 pub mod cache;
+pub mod data_subject;
 pub mod utils;
 pub mod logging;
+pub mod middleware;
 
+pub use auth::{AuthenticatedUser, Role, TokenVerifier};
+pub use middleware::require_permission;
 pub use cache::{CacheManager, CacheConfig, CacheMetrics};
+pub use data_subject::{DataSubjectHook, ExportedContents, ExportedFile};
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
-    use env_logger::Builder;
-    use log::LevelFilter;
-
-    fn init_test_logger() {
-        Builder::new()
-            .filter_level(LevelFilter::Debug)
-            .is_test(true)
-            .init();
-    }
 
     #[test]
     fn test_structured_logging() {
@@ -0,0 +1,18 @@
+CREATE TABLE media (
+    id UUID PRIMARY KEY,
+    -- No foreign key: the auth tables live in another crate's migrations,
+    -- and media is erased before its user is.
+    user_id INTEGER NOT NULL,
+    file_type TEXT NOT NULL,
+    url TEXT NOT NULL,
+    file_name TEXT,
+    size BIGINT NOT NULL,
+    sha256 TEXT NOT NULL,
+    metadata JSONB NOT NULL,
+    variants JSONB NOT NULL,
+    description TEXT,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX media_user_id_idx ON media (user_id, created_at);
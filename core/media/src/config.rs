@@ -0,0 +1,132 @@
+#[derive(Debug, Clone)]
+pub struct MediaConfig {
+    /// Directory uploads are stored in, unless `s3` is set.
+    pub upload_dir: String,
+    /// Largest accepted upload in bytes.
+    pub max_file_size: usize,
+    /// Largest resumable upload in bytes. Images are still read whole and
+    /// limited to `max_file_size`.
+    pub max_upload_length: u64,
+    /// Bytes a user may have in unfinished resumable uploads at once.
+    pub max_pending_upload_size: u64,
+    /// Seconds an unfinished resumable upload is kept after its last chunk.
+    pub upload_expiration: u64,
+    /// S3-compatible object storage to keep uploads in instead of `upload_dir`.
+    pub s3: Option<S3Config>,
+    /// WebP derivatives generated for every uploaded image.
+    pub image_variants: Vec<ImageVariant>,
+}
+
+impl MediaConfig {
+    pub fn from_env() -> Self {
+        Self {
+            upload_dir: std::env::var("MEDIA_UPLOAD_DIR")
+                .unwrap_or_else(|_| "./uploads".to_string()),
+            max_file_size: std::env::var("MEDIA_MAX_FILE_SIZE")
+                .unwrap_or_else(|_| "10485760".to_string()) // 10MB
+                .parse()
+                .unwrap(),
+            max_upload_length: std::env::var("MEDIA_MAX_UPLOAD_LENGTH")
+                .map(|length| length.parse().expect("MEDIA_MAX_UPLOAD_LENGTH must be a number of bytes"))
+                .unwrap_or(DEFAULT_MAX_UPLOAD_LENGTH),
+            max_pending_upload_size: std::env::var("MEDIA_MAX_PENDING_UPLOAD_SIZE")
+                .map(|size| size.parse().expect("MEDIA_MAX_PENDING_UPLOAD_SIZE must be a number of bytes"))
+                .unwrap_or(DEFAULT_MAX_PENDING_UPLOAD_SIZE),
+            upload_expiration: std::env::var("MEDIA_UPLOAD_EXPIRATION")
+                .map(|seconds| seconds.parse().expect("MEDIA_UPLOAD_EXPIRATION must be a number of seconds"))
+                .unwrap_or(DEFAULT_UPLOAD_EXPIRATION),
+            s3: S3Config::from_env(),
+            image_variants: ImageVariant::parse_list(
+                &std::env::var("MEDIA_IMAGE_VARIANTS").unwrap_or_else(|_| DEFAULT_IMAGE_VARIANTS.to_string()),
+            )
+            .expect("MEDIA_IMAGE_VARIANTS must be a comma-separated list of name:WIDTHxHEIGHT"),
+        }
+    }
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            upload_dir: "./uploads".to_string(),
+            max_file_size: 10 * 1024 * 1024,
+            max_upload_length: DEFAULT_MAX_UPLOAD_LENGTH,
+            max_pending_upload_size: DEFAULT_MAX_PENDING_UPLOAD_SIZE,
+            upload_expiration: DEFAULT_UPLOAD_EXPIRATION,
+            s3: None,
+            image_variants: ImageVariant::parse_list(DEFAULT_IMAGE_VARIANTS).unwrap(),
+        }
+    }
+}
+
+const DEFAULT_MAX_UPLOAD_LENGTH: u64 = 4 * 1024 * 1024 * 1024;
+const DEFAULT_MAX_PENDING_UPLOAD_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+const DEFAULT_UPLOAD_EXPIRATION: u64 = 24 * 60 * 60;
+
+const DEFAULT_IMAGE_VARIANTS: &str = "thumb:320x320,feed:1080x1350,full:2048x2048";
+
+/// A derivative of uploaded images, scaled down to fit in `max_width` by
+/// `max_height` and served as `GET /media/{id}?variant={name}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageVariant {
+    pub name: String,
+    pub max_width: u32,
+    pub max_height: u32,
+}
+
+impl ImageVariant {
+    /// Parses `thumb:320x320,feed:1080x1350`.
+    pub fn parse_list(list: &str) -> Option<Vec<Self>> {
+        let variants: Vec<Self> = list.split(',')
+            .map(str::trim)
+            .filter(|variant| !variant.is_empty())
+            .map(|variant| {
+                let (name, size) = variant.split_once(':')?;
+                let (width, height) = size.split_once('x')?;
+                let valid_name = !name.is_empty()
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                valid_name.then_some(())?;
+                Some(Self {
+                    name: name.to_string(),
+                    max_width: width.parse().ok().filter(|w| *w > 0)?,
+                    max_height: height.parse().ok().filter(|h| *h > 0)?,
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        let unique = variants.iter().enumerate()
+            .all(|(i, variant)| variants[..i].iter().all(|other| other.name != variant.name));
+        unique.then_some(variants)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// Base URL of the service, e.g. `https://s3.eu-west-1.amazonaws.com` or
+    /// `http://localhost:9000` for MinIO. Buckets are addressed path-style.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl S3Config {
+    /// Reads `MEDIA_S3_BUCKET`, `MEDIA_S3_ENDPOINT`, `MEDIA_S3_REGION`,
+    /// `MEDIA_S3_ACCESS_KEY` and `MEDIA_S3_SECRET_KEY`. Returns `None`
+    /// without a bucket.
+    pub fn from_env() -> Option<Self> {
+        let bucket = std::env::var("MEDIA_S3_BUCKET").ok()?;
+        let region = std::env::var("MEDIA_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+
+        Some(Self {
+            endpoint: std::env::var("MEDIA_S3_ENDPOINT")
+                .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region)),
+            bucket,
+            region,
+            access_key: std::env::var("MEDIA_S3_ACCESS_KEY")
+                .expect("MEDIA_S3_ACCESS_KEY must be set along with MEDIA_S3_BUCKET"),
+            secret_key: std::env::var("MEDIA_S3_SECRET_KEY")
+                .expect("MEDIA_S3_SECRET_KEY must be set along with MEDIA_S3_BUCKET"),
+        })
+    }
+}
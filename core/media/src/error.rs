@@ -1,17 +1,26 @@
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum MediaError {
     #[error("Media not found")]
     NotFound,
-    
+
     #[error("Upload error: {0}")]
     UploadError(String),
-    
+
     #[error("Invalid media format")]
     InvalidFormat,
-    
+
+    #[error("File too large")]
+    TooLarge,
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Checksum mismatch")]
+    ChecksumMismatch,
+
     #[error("Internal server error")]
     InternalError,
 }
@@ -20,8 +29,12 @@ impl ResponseError for MediaError {
     fn error_response(&self) -> HttpResponse {
         match self {
             MediaError::NotFound => HttpResponse::NotFound().finish(),
-            MediaError::UploadError(_) => HttpResponse::UnsupportedMediaType().finish(),
+            MediaError::UploadError(message) => HttpResponse::BadRequest().json(serde_json::json!({ "error": message })),
             MediaError::InvalidFormat => HttpResponse::UnsupportedMediaType().finish(),
+            MediaError::TooLarge => HttpResponse::PayloadTooLarge().finish(),
+            MediaError::Conflict(message) => HttpResponse::Conflict().json(serde_json::json!({ "error": message })),
+            // Defined by the checksum extension of tus.
+            MediaError::ChecksumMismatch => HttpResponse::build(StatusCode::from_u16(460).unwrap()).finish(),
             MediaError::InternalError => HttpResponse::InternalServerError().finish(),
         }
     }
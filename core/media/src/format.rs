@@ -0,0 +1,246 @@
+//! File formats, told apart by their signatures rather than by what the
+//! client claims.
+
+use crate::probe::matroska;
+
+/// Bytes read from the start of an upload to detect its format.
+pub const SNIFF_LENGTH: usize = 4096;
+
+const TS_PACKET_LENGTH: usize = 188;
+
+/// ID of the EBML header that Matroska and WebM files start with.
+const EBML_MAGIC: [u8; 4] = [0x1A, 0x45, 0xDF, 0xA3];
+
+/// ISO-BMFF brands of still images, which are not accepted as video.
+const IMAGE_BRANDS: &[&[u8; 4]] = &[b"heic", b"heix", b"mif1", b"msf1", b"avif", b"avis"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaFormat {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    /// ISO base media file format: MP4, M4A, QuickTime MOV.
+    Mp4,
+    Webm,
+    /// Matroska other than WebM: MKV, MKA.
+    Matroska,
+    /// MPEG transport stream.
+    MpegTs,
+    /// MPEG program stream, MPEG-1 and MPEG-2 video.
+    MpegPs,
+    Mp3,
+    Wav,
+    Ogg,
+    Flac,
+}
+
+impl MediaFormat {
+    pub const ALL: &'static [MediaFormat] = &[
+        MediaFormat::Jpeg, MediaFormat::Png, MediaFormat::Gif, MediaFormat::Webp,
+        MediaFormat::Mp4, MediaFormat::Webm, MediaFormat::Matroska, MediaFormat::MpegTs, MediaFormat::MpegPs,
+        MediaFormat::Mp3, MediaFormat::Wav, MediaFormat::Ogg, MediaFormat::Flac,
+    ];
+
+    /// Short name, as in [`MediaMetadata::format`](crate::models::MediaMetadata::format).
+    pub fn name(self) -> &'static str {
+        match self {
+            MediaFormat::Jpeg => "jpeg",
+            MediaFormat::Png => "png",
+            MediaFormat::Gif => "gif",
+            MediaFormat::Webp => "webp",
+            MediaFormat::Mp4 => "mp4",
+            MediaFormat::Webm => "webm",
+            MediaFormat::Matroska => "matroska",
+            MediaFormat::MpegTs => "mpegts",
+            MediaFormat::MpegPs => "mpeg",
+            MediaFormat::Mp3 => "mp3",
+            MediaFormat::Wav => "wav",
+            MediaFormat::Ogg => "ogg",
+            MediaFormat::Flac => "flac",
+        }
+    }
+
+    /// Registered MIME types of the format, the canonical one first.
+    pub fn mime_types(self) -> &'static [&'static str] {
+        match self {
+            MediaFormat::Jpeg => &["image/jpeg"],
+            MediaFormat::Png => &["image/png"],
+            MediaFormat::Gif => &["image/gif"],
+            MediaFormat::Webp => &["image/webp"],
+            MediaFormat::Mp4 => &["video/mp4", "audio/mp4", "video/quicktime"],
+            MediaFormat::Webm => &["video/webm", "audio/webm"],
+            MediaFormat::Matroska => &["video/x-matroska", "audio/x-matroska"],
+            MediaFormat::MpegTs => &["video/mp2t"],
+            MediaFormat::MpegPs => &["video/mpeg"],
+            MediaFormat::Mp3 => &["audio/mpeg"],
+            MediaFormat::Wav => &["audio/wav", "audio/vnd.wave", "audio/x-wav"],
+            MediaFormat::Ogg => &["audio/ogg", "video/ogg", "application/ogg"],
+            MediaFormat::Flac => &["audio/flac", "audio/x-flac"],
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        self.mime_types()[0]
+    }
+
+    /// The format a MIME type stands for, if it is one we accept.
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        Self::ALL.iter().copied()
+            .find(|format| format.mime_types().iter().any(|t| t.eq_ignore_ascii_case(mime_type)))
+    }
+
+    /// Detects the format from the first bytes of a file, up to [`SNIFF_LENGTH`]
+    /// or the whole file if it is shorter.
+    pub fn detect(head: &[u8]) -> Option<Self> {
+        if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            return Some(MediaFormat::Jpeg);
+        }
+        if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return Some(MediaFormat::Png);
+        }
+        if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+            return Some(MediaFormat::Gif);
+        }
+        if head.len() >= 12 && head.starts_with(b"RIFF") {
+            return match &head[8..12] {
+                b"WEBP" => Some(MediaFormat::Webp),
+                b"WAVE" => Some(MediaFormat::Wav),
+                _ => None,
+            };
+        }
+        if head.len() >= 12 && &head[4..8] == b"ftyp" {
+            let brand: &[u8; 4] = head[8..12].try_into().ok()?;
+            return (!IMAGE_BRANDS.contains(&brand)).then_some(MediaFormat::Mp4);
+        }
+        if head.starts_with(&EBML_MAGIC) {
+            // Without a DocType the file is Matroska, the default.
+            return match matroska::doc_type(head) {
+                Ok(Some(doc_type)) if doc_type == "webm" => Some(MediaFormat::Webm),
+                Ok(Some(doc_type)) if doc_type == "matroska" => Some(MediaFormat::Matroska),
+                Ok(None) => Some(MediaFormat::Matroska),
+                _ => None,
+            };
+        }
+        if head.starts_with(b"OggS") {
+            return Some(MediaFormat::Ogg);
+        }
+        if head.starts_with(b"fLaC") {
+            return Some(MediaFormat::Flac);
+        }
+        if head.starts_with(&[0x00, 0x00, 0x01, 0xBA]) {
+            return Some(MediaFormat::MpegPs);
+        }
+        if is_transport_stream(head) {
+            return Some(MediaFormat::MpegTs);
+        }
+        if head.starts_with(b"ID3") {
+            return detect_after_id3(head);
+        }
+        is_mp3_frame(head).then_some(MediaFormat::Mp3)
+    }
+}
+
+/// Sync bytes at the start of at least two consecutive packets.
+fn is_transport_stream(head: &[u8]) -> bool {
+    head.len() > TS_PACKET_LENGTH
+        && (0..head.len()).step_by(TS_PACKET_LENGTH).all(|offset| head[offset] == 0x47)
+}
+
+/// Skips an ID3v2 tag, which MP3 files start with, but FLAC files can as well.
+fn detect_after_id3(head: &[u8]) -> Option<MediaFormat> {
+    if head.len() < 10 {
+        return None;
+    }
+    // Sizes are "syncsafe": seven bits per byte.
+    let size = head[6..10].iter().fold(0usize, |size, &b| (size << 7) | (b & 0x7F) as usize);
+    let footer = if head[5] & 0x10 != 0 { 10 } else { 0 };
+    let audio = &head[(10 + size + footer).min(head.len())..];
+
+    if audio.len() < 4 {
+        // Tags with cover art run past what was read; trust the tag.
+        return Some(MediaFormat::Mp3);
+    }
+    if audio.starts_with(b"fLaC") {
+        return Some(MediaFormat::Flac);
+    }
+    is_mp3_frame(audio).then_some(MediaFormat::Mp3)
+}
+
+/// An MPEG audio layer III frame header.
+fn is_mp3_frame(head: &[u8]) -> bool {
+    if head.len() < 4 || head[0] != 0xFF || head[1] & 0xE0 != 0xE0 {
+        return false;
+    }
+    let version = (head[1] >> 3) & 0b11;
+    let layer = (head[1] >> 1) & 0b11;
+    let bitrate = head[2] >> 4;
+    let sample_rate = (head[2] >> 2) & 0b11;
+    version != 0b01 && layer == 0b01 && bitrate != 0 && bitrate != 0xF && sample_rate != 0b11
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(packets: usize) -> Vec<u8> {
+        let mut stream = vec![0u8; packets * TS_PACKET_LENGTH];
+        for packet in stream.chunks_mut(TS_PACKET_LENGTH) {
+            packet[0] = 0x47;
+        }
+        stream
+    }
+
+    #[test]
+    fn test_detect_signatures() {
+        let cases: &[(&[u8], Option<MediaFormat>)] = &[
+            (b"\xFF\xD8\xFF\xE0\x00\x10JFIF\x00", Some(MediaFormat::Jpeg)),
+            (b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR", Some(MediaFormat::Png)),
+            (b"GIF89a\x01\x00\x01\x00", Some(MediaFormat::Gif)),
+            (b"RIFF\x24\x00\x00\x00WEBPVP8 ", Some(MediaFormat::Webp)),
+            (b"RIFF\x24\x00\x00\x00WAVEfmt ", Some(MediaFormat::Wav)),
+            (b"RIFF\x24\x00\x00\x00AVI LIST", None),
+            (b"\x00\x00\x00\x18ftypmp42\x00\x00\x00\x00", Some(MediaFormat::Mp4)),
+            (b"\x00\x00\x00\x18ftypheic\x00\x00\x00\x00", None),
+            (b"\x00\x00\x00\x14ftypqt  \x00\x00\x00\x00", Some(MediaFormat::Mp4)),
+            (b"\x1A\x45\xDF\xA3\x8B\x42\x82\x84webm\x42\x87\x81\x04", Some(MediaFormat::Webm)),
+            (b"\x1A\x45\xDF\xA3\x8B\x42\x82\x88matroska", Some(MediaFormat::Matroska)),
+            (b"\x1A\x45\xDF\xA3\x84\x42\x87\x81\x04", Some(MediaFormat::Matroska)),
+            (b"\x1A\x45\xDF\xA3\x87\x42\x82\x84avi ", None),
+            (b"OggS\x00\x02\x00\x00", Some(MediaFormat::Ogg)),
+            (b"fLaC\x00\x00\x00\x22", Some(MediaFormat::Flac)),
+            (b"\x00\x00\x01\xBA\x44\x00", Some(MediaFormat::MpegPs)),
+            // MPEG-1 layer III, 128 kbit/s, 44.1 kHz
+            (b"\xFF\xFB\x90\x64\x00\x00", Some(MediaFormat::Mp3)),
+            // ADTS AAC shares the sync word, but is layer 0
+            (b"\xFF\xF1\x50\x80\x00\x1F", None),
+            (b"ID3\x04\x00\x00\x00\x00\x00\x00\xFF\xFB\x90\x64", Some(MediaFormat::Mp3)),
+            (b"ID3\x04\x00\x00\x00\x00\x00\x00fLaC", Some(MediaFormat::Flac)),
+            (b"ID3\x04\x00\x00\x00\x00\x00\x00MZ\x90\x00", None),
+            (b"MZ\x90\x00\x03\x00\x00\x00", None),
+            (b"\x7fELF\x02\x01\x01\x00", None),
+            (b"", None),
+        ];
+
+        for (head, expected) in cases {
+            assert_eq!(MediaFormat::detect(head), *expected, "{:?}", String::from_utf8_lossy(head));
+        }
+        assert_eq!(MediaFormat::detect(&ts(3)), Some(MediaFormat::MpegTs));
+        assert_eq!(MediaFormat::detect(&ts(1)), None);
+        let mut broken = ts(3);
+        broken[2 * TS_PACKET_LENGTH] = 0;
+        assert_eq!(MediaFormat::detect(&broken), None);
+    }
+
+    #[test]
+    fn test_mime_types() {
+        assert_eq!(MediaFormat::from_mime_type("IMAGE/JPEG"), Some(MediaFormat::Jpeg));
+        assert_eq!(MediaFormat::from_mime_type("audio/mpeg"), Some(MediaFormat::Mp3));
+        assert_eq!(MediaFormat::from_mime_type("video/quicktime"), Some(MediaFormat::Mp4));
+        assert_eq!(MediaFormat::from_mime_type("audio/mp3"), None);
+        assert_eq!(MediaFormat::from_mime_type("text/plain"), None);
+        for format in MediaFormat::ALL {
+            assert_eq!(MediaFormat::from_mime_type(format.mime_type()), Some(*format));
+        }
+    }
+}
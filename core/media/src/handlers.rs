@@ -1,18 +1,32 @@
-use actix_web::{web, Error, HttpResponse};
+use std::time::SystemTime;
+use actix_web::{http::{header::{self, EntityTag}, StatusCode}, web, Error, HttpRequest, HttpResponse};
 use actix_multipart::{Field, Multipart};
 use uuid::Uuid;
 use crate::error::MediaError;
-use futures::StreamExt;
-use log::{debug, info, warn};
+use crate::range::{self, ByteRanges, Selection};
+use crate::service::{MediaService, NewUpload};
+use futures::{stream, StreamExt, TryStreamExt};
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use socialhub_core::auth::AuthenticatedUser;
+use utoipa::{IntoParams, ToSchema};
 
-const MAX_FILE_SIZE: usize = 10 * 1024 * 1024; // 10MB
+/// Longest accepted `description` form field.
+pub(crate) const MAX_DESCRIPTION_LENGTH: usize = 2000;
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UploadRequest {
     pub file_type: String,
-    pub description: Option<String>
+    pub description: Option<String>,
+    /// `true` to keep the camera make, model and capture time of a photo in
+    /// its metadata. Locations and other EXIF data are always removed.
+    pub keep_camera_info: Option<bool>
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct MediaQuery {
+    /// A variant of an image, e.g. `thumb`, instead of the original.
+    pub variant: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -23,111 +37,191 @@ pub struct MetadataUpdate {
 }
 
 /// Handles file upload with multipart/form-data
-/// 
-/// # Arguments
-/// * `payload` - Multipart form data containing the file
-/// 
+///
+/// The form carries the file in a `file` field and, before it, optional
+/// `description` and `keep_camera_info` fields. The file is streamed to the
+/// storage backend as it arrives.
+///
 /// # Returns
-/// * `Ok(HttpResponse)` - 201 Created on success
-/// * `Ok(HttpResponse)` - 415 Unsupported Media Type for invalid content types
-/// * `Ok(HttpResponse)` - 413 Payload Too Large for files > 10MB
-/// 
-/// # Example
-/// ```no_run
-/// use actix_web::{web, Error, HttpResponse};
-/// use actix_multipart::Multipart;
-/// 
-/// async fn example(mut payload: Multipart) -> Result<HttpResponse, Error> {
-///     // Supondo que temos uma instância de Multipart...
-///     let response = HttpResponse::Ok().finish();
-///     Ok(response)
-/// }
-/// ```
+/// * `Ok(HttpResponse)` - 201 Created with the new [`Media`](crate::models::Media)
+/// * `Err(MediaError::InvalidFormat)` - 415 Unsupported Media Type for invalid content types,
+///   and for audio and video files that are truncated or corrupt
+/// * `Err(MediaError::TooLarge)` - 413 Payload Too Large for files above the configured limit
 #[utoipa::path(
     post,
     path = "/media/upload",
-    request_body = UploadRequest,
+    request_body(content = UploadRequest, content_type = "multipart/form-data"),
     responses(
-        (status = 201, description = "Media uploaded successfully"),
+        (status = 201, description = "Media uploaded successfully", body = Media),
         (status = 400, description = "Invalid request"),
-        (status = 413, description = "File too large")
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Missing the media:upload permission"),
+        (status = 413, description = "File too large"),
+        (status = 415, description = "Unsupported media type")
     ),
     security(("bearer_token" = [])),
     tag = "media"
 )]
-pub async fn upload(mut payload: Multipart) -> Result<HttpResponse, Error> {
+pub async fn upload(
+    service: web::Data<MediaService>,
+    user: AuthenticatedUser,
+    mut payload: Multipart,
+) -> Result<HttpResponse, Error> {
     debug!("Starting file upload");
+    let mut description = None;
+    let mut keep_camera_info = false;
 
-    if let Some(field_result) = payload.next().await {
-        let field = field_result?;
-        match process_field(field).await {
-            Ok(_) => {
-                info!("Upload successful");
-                Ok(HttpResponse::Created().finish())
-            },
+    while let Some(field) = payload.next().await {
+        let field = field?;
+        match field.name() {
+            "description" => {
+                description = Some(read_text(field, MAX_DESCRIPTION_LENGTH).await?);
+                continue;
+            }
+            "keep_camera_info" => {
+                keep_camera_info = match read_text(field, 5).await?.as_str() {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(MediaError::UploadError("keep_camera_info must be true or false".to_string()).into()),
+                };
+                continue;
+            }
+            _ => {}
+        }
+
+        let upload = NewUpload {
+            content_type: field.content_type().map(|ct| ct.essence_str().to_string()).unwrap_or_default(),
+            file_name: field.content_disposition().get_filename().map(str::to_string),
+            description,
+            keep_camera_info,
+        };
+        debug!("Content type: {}", upload.content_type);
+
+        let body = field.map_err(|e| MediaError::UploadError(e.to_string()));
+        return match service.upload(user.user_id, upload, body).await {
+            Ok(media) => Ok(HttpResponse::Created().json(media)),
             Err(e) => {
                 warn!("Upload failed: {:?}", e);
-                Ok(e)
+                Err(e.into())
             }
-        }
-    } else {
-        warn!("No file provided");
-        Ok(HttpResponse::BadRequest().finish())
+        };
     }
-}
 
-async fn process_field(mut field: Field) -> Result<(), HttpResponse> {
-    // Validate content type
-    let content_type = field.content_type();
-    debug!("Content type: {:?}", content_type);
-    
-    if !content_type.map_or(false, |ct| is_valid_media_type(ct.to_string().as_str())) {
-        return Err(HttpResponse::UnsupportedMediaType().finish());
-    }
+    warn!("No file provided");
+    Err(MediaError::UploadError("No file provided".to_string()).into())
+}
 
-    // Validate file size
-    let mut size = 0;
+async fn read_text(mut field: Field, max_length: usize) -> Result<String, MediaError> {
+    let mut text = Vec::new();
     while let Some(chunk) = field.next().await {
-        size += chunk.map_err(|_| HttpResponse::BadRequest().finish())?.len();
-        if size > MAX_FILE_SIZE {
-            return Err(HttpResponse::PayloadTooLarge().finish());
+        text.extend_from_slice(&chunk.map_err(|e| MediaError::UploadError(e.to_string()))?);
+        if text.len() > max_length {
+            return Err(MediaError::UploadError(format!("{} is too long", field.name())));
         }
     }
-
-    Ok(())
-}
-
-fn is_valid_media_type(content_type: &str) -> bool {
-    let valid_types = [
-        "image/jpeg", "image/png", "image/gif",
-        "video/mp4", "video/mpeg",
-        "audio/mp3", "audio/mpeg", "audio/wav"
-    ];
-    
-    debug!("Checking content type: {}", content_type);
-    valid_types.iter().any(|&t| content_type.eq_ignore_ascii_case(t))
+    String::from_utf8(text).map_err(|_| MediaError::UploadError(format!("{} is not UTF-8", field.name())))
 }
 
 /// Retrieves media by ID
-/// 
+///
+/// Streams the stored file, or with `?variant=` one of the image variants
+/// listed in [`Media::variants`](crate::models::Media::variants). Supports `Range` requests with one or more
+/// byte ranges, validated by `If-Range`, and conditional requests with
+/// `If-None-Match` and `If-Modified-Since` against the file's `ETag` and
+/// `Last-Modified`.
+///
 /// # Arguments
 /// * `id` - UUID of the media to retrieve
-/// 
+///
 /// # Returns
 /// * `Ok(HttpResponse)` - 200 OK with media content
-/// * `Err(MediaError::NotFound)` - 404 Not Found
+/// * `Ok(HttpResponse)` - 206 Partial Content with the requested ranges,
+///   as `multipart/byteranges` when there are several
+/// * `Ok(HttpResponse)` - 304 Not Modified when the client's copy is current
+/// * `Ok(HttpResponse)` - 416 Range Not Satisfiable when no range lies within the file
+/// * `Err(MediaError::NotFound)` - 404 Not Found, also for unknown variants
 #[utoipa::path(
     get,
     path = "/media/{id}",
+    params(
+        MediaQuery,
+        ("Range" = Option<String>, Header, description = "Byte ranges, e.g. `bytes=0-1023`"),
+        ("If-Range" = Option<String>, Header, description = "Serve the ranges only if the file still has this ETag or date"),
+        ("If-None-Match" = Option<String>, Header, description = "ETags the client has")
+    ),
     responses(
         (status = 200, description = "Media found"),
-        (status = 404, description = "Media not found")
+        (status = 206, description = "Requested ranges of the media"),
+        (status = 304, description = "Not modified"),
+        (status = 404, description = "Media or variant not found"),
+        (status = 416, description = "Range not satisfiable")
     ),
     tag = "media"
 )]
-pub async fn get_media(_id: web::Path<Uuid>) -> Result<HttpResponse, Error> {
-    // Simulando que o media não foi encontrado para o teste
-    Err(MediaError::NotFound.into())
+pub async fn get_media(
+    req: HttpRequest,
+    service: web::Data<MediaService>,
+    id: web::Path<Uuid>,
+    query: web::Query<MediaQuery>,
+) -> Result<HttpResponse, Error> {
+    let media = service.get_media(id.into_inner()).await?;
+    let file = service.stored_file(&media, query.variant.as_deref())?;
+    let etag = EntityTag::new_strong(file.sha256.clone());
+    let last_modified = SystemTime::from(media.created_at);
+    let selection = range::select(&req, &etag, last_modified, file.size);
+
+    let status = match &selection {
+        Selection::Full => StatusCode::OK,
+        Selection::NotModified => StatusCode::NOT_MODIFIED,
+        Selection::Partial(_) => StatusCode::PARTIAL_CONTENT,
+        Selection::Unsatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+    };
+    let mut response = HttpResponse::build(status);
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header(header::LastModified(last_modified.into()))
+        .insert_header((header::ACCEPT_RANGES, "bytes"));
+
+    match selection {
+        Selection::NotModified => Ok(response.finish()),
+        Selection::Unsatisfiable => Ok(response
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{}", file.size)))
+            .finish()),
+        Selection::Full => {
+            let body = service.open(&file).await?;
+            Ok(response
+                .content_type(file.content_type.as_str())
+                .no_chunking(file.size)
+                .streaming(body))
+        }
+        Selection::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0].clone();
+            let body = service.open_range(&file, range.clone()).await?;
+            Ok(response
+                .content_type(file.content_type.as_str())
+                .insert_header((header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, range.end - 1, file.size)))
+                .no_chunking(range.end - range.start)
+                .streaming(body))
+        }
+        Selection::Partial(ranges) => {
+            let byte_ranges = ByteRanges::new(ranges, &file.content_type, file.size);
+            response
+                .content_type(byte_ranges.content_type())
+                .no_chunking(byte_ranges.content_length());
+
+            // Each range is opened only once the previous one has been sent.
+            let end = byte_ranges.end;
+            let parts = stream::iter(byte_ranges.parts).then(move |(head, range)| {
+                let service = service.clone();
+                let file = file.clone();
+                async move {
+                    let body = service.open_range(&file, range).await?;
+                    Ok::<_, MediaError>(stream::once(async { Ok(head) }).chain(body))
+                }
+            });
+            Ok(response.streaming(parts.try_flatten().chain(stream::once(async { Ok(end) }))))
+        }
+    }
 }
 
 #[utoipa::path(
@@ -149,12 +243,37 @@ pub async fn update_metadata(
     Ok(HttpResponse::Ok().finish())
 }
 
-pub async fn get_metadata(_id: web::Path<Uuid>) -> Result<HttpResponse, Error> {
-    // Simulando que o metadata não foi encontrado
-    Err(MediaError::NotFound.into())
+/// What was found out about the file on upload: its format and, depending on
+/// it, dimensions, duration, codecs, bitrate and audio parameters.
+#[utoipa::path(
+    get,
+    path = "/media/{id}/metadata",
+    responses(
+        (status = 200, description = "Format, dimensions, duration and codecs of the media", body = MediaMetadata),
+        (status = 404, description = "Media not found")
+    ),
+    tag = "media"
+)]
+pub async fn get_metadata(service: web::Data<MediaService>, id: web::Path<Uuid>) -> Result<HttpResponse, Error> {
+    let media = service.get_media(id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(media.metadata))
 }
 
-pub async fn delete_media(_id: web::Path<Uuid>) -> Result<HttpResponse, Error> {
-    // TODO: Implement media deletion
-    Ok(HttpResponse::Ok().finish())
+#[utoipa::path(
+    delete,
+    path = "/media/{id}",
+    responses(
+        (status = 204, description = "Media deleted"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Missing the media:delete permission")
+    ),
+    security(("bearer_token" = [])),
+    tag = "media"
+)]
+pub async fn delete_media(
+    service: web::Data<MediaService>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    service.delete_media(id.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
 }
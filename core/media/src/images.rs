@@ -0,0 +1,224 @@
+//! Uploaded images: turned upright, stripped of metadata, and scaled down
+//! into WebP derivatives for each configured [`ImageVariant`].
+//!
+//! Decoding and encoding are CPU-bound; callers run [`process`] on a
+//! blocking thread.
+
+use std::io::Cursor;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use log::{error, info};
+use crate::config::ImageVariant;
+use crate::error::MediaError;
+use crate::format::MediaFormat;
+use crate::models::CameraInfo;
+use crate::sanitize;
+
+/// Quality of JPEGs re-encoded to apply their orientation.
+const JPEG_QUALITY: u8 = 90;
+
+/// Larger images are rejected rather than decoded.
+const MAX_DIMENSION: u32 = 16_384;
+
+/// An image ready to be stored, with its derivatives.
+pub(crate) struct ProcessedImage {
+    /// The uploaded file without metadata, upright.
+    pub original: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub camera: Option<CameraInfo>,
+    pub derivatives: Vec<Derivative>,
+}
+
+pub(crate) struct Derivative {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub webp: Vec<u8>,
+}
+
+/// The decoder for `format`, or `None` if it is not an image.
+pub(crate) fn image_format(format: MediaFormat) -> Option<ImageFormat> {
+    match format {
+        MediaFormat::Jpeg => Some(ImageFormat::Jpeg),
+        MediaFormat::Png => Some(ImageFormat::Png),
+        MediaFormat::Gif => Some(ImageFormat::Gif),
+        MediaFormat::Webp => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// Decodes `original`, removes its EXIF, XMP and IPTC metadata and renders
+/// every variant. Images that do not decode are [`MediaError::InvalidFormat`].
+///
+/// An EXIF orientation is applied to the pixels first, re-encoding the
+/// original; otherwise its image data is kept as uploaded. Camera make, model
+/// and capture time are only read with `keep_camera_info`.
+///
+/// Variants are never larger than the original; animated images keep only
+/// their first frame.
+pub(crate) fn process(
+    original: &[u8],
+    format: MediaFormat,
+    variants: &[ImageVariant],
+    keep_camera_info: bool,
+) -> Result<ProcessedImage, MediaError> {
+    let image_format = image_format(format).ok_or(MediaError::InvalidFormat)?;
+    let (image, orientation, exif) = decode(original, image_format)?;
+
+    let stripped = if orientation == Orientation::NoTransforms {
+        sanitize::strip_metadata(original, format).ok_or_else(|| {
+            info!("Malformed {} image", format.name());
+            MediaError::InvalidFormat
+        })?
+    } else {
+        encode(&image, image_format)?
+    };
+    let camera = if keep_camera_info { exif.as_deref().and_then(sanitize::camera_info) } else { None };
+
+    let derivatives = variants.iter()
+        .map(|variant| {
+            let scaled = if image.width() > variant.max_width || image.height() > variant.max_height {
+                image.resize(variant.max_width, variant.max_height, FilterType::CatmullRom)
+            } else {
+                image.clone()
+            };
+            Ok(Derivative {
+                name: variant.name.clone(),
+                width: scaled.width(),
+                height: scaled.height(),
+                webp: encode_webp(scaled)?,
+            })
+        })
+        .collect::<Result<_, MediaError>>()?;
+
+    Ok(ProcessedImage { original: stripped, width: image.width(), height: image.height(), camera, derivatives })
+}
+
+/// The image turned upright, the orientation that took, and the raw EXIF chunk.
+fn decode(original: &[u8], format: ImageFormat) -> Result<(DynamicImage, Orientation, Option<Vec<u8>>), MediaError> {
+    let invalid = |e: image::ImageError| {
+        info!("Failed to decode {:?} image: {}", format, e);
+        MediaError::InvalidFormat
+    };
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+
+    let mut reader = ImageReader::with_format(Cursor::new(original), format);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder().map_err(invalid)?;
+    let exif = decoder.exif_metadata().unwrap_or(None);
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    image.apply_orientation(orientation);
+    Ok((image, orientation, exif))
+}
+
+/// Re-encodes `image` in its own format, which writes no metadata.
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, MediaError> {
+    let mut encoded = Vec::new();
+    let written = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY)),
+        ImageFormat::Png => image.write_with_encoder(PngEncoder::new(&mut encoded)),
+        _ => return encode_webp(image.clone()),
+    };
+    written.map_err(|e| {
+        error!("Failed to encode {:?}: {}", format, e);
+        MediaError::InternalError
+    })?;
+    Ok(encoded)
+}
+
+/// Lossless, the only WebP encoding available without libwebp.
+fn encode_webp(image: DynamicImage) -> Result<Vec<u8>, MediaError> {
+    let image = if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(image.into_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(image.into_rgb8())
+    };
+    let mut webp = Vec::new();
+    image.write_with_encoder(WebPEncoder::new_lossless(&mut webp))
+        .map_err(|e| {
+            error!("Failed to encode WebP: {}", e);
+            MediaError::InternalError
+        })?;
+    Ok(webp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sanitize::tests::{assert_no_location, test_image, jpeg_with_metadata, png_with_metadata, webp_with_metadata};
+
+    fn variant(name: &str, max_width: u32, max_height: u32) -> ImageVariant {
+        ImageVariant { name: name.to_string(), max_width, max_height }
+    }
+
+    #[test]
+    fn test_process_scales_to_fit_without_upscaling() {
+        let variants = [variant("thumb", 100, 100), variant("feed", 400, 100), variant("full", 1000, 1000)];
+        let processed = process(&test_image(ImageFormat::Png, 400, 200), MediaFormat::Png, &variants, false).unwrap();
+
+        assert_eq!((processed.width, processed.height), (400, 200));
+        let sizes: Vec<_> = processed.derivatives.iter().map(|d| (d.name.as_str(), d.width, d.height)).collect();
+        assert_eq!(sizes, [("thumb", 100, 50), ("feed", 200, 100), ("full", 400, 200)]);
+
+        for derivative in &processed.derivatives {
+            assert_eq!(MediaFormat::detect(&derivative.webp), Some(MediaFormat::Webp));
+            let decoded = image::load_from_memory_with_format(&derivative.webp, ImageFormat::WebP).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (derivative.width, derivative.height));
+        }
+    }
+
+    #[test]
+    fn test_process_rejects_undecodable_images() {
+        let mut truncated = test_image(ImageFormat::Png, 64, 64);
+        truncated.truncate(40);
+        assert!(matches!(process(&truncated, MediaFormat::Png, &[], false), Err(MediaError::InvalidFormat)));
+        assert!(matches!(process(b"\xFF\xD8\xFF\xE0junk", MediaFormat::Jpeg, &[], false), Err(MediaError::InvalidFormat)));
+    }
+
+    #[test]
+    fn test_process_removes_metadata() {
+        let variants = [variant("thumb", 4, 4)];
+        for (format, file) in [
+            (MediaFormat::Jpeg, jpeg_with_metadata(16, 8, 1)),
+            (MediaFormat::Png, png_with_metadata(16, 8, 1)),
+            (MediaFormat::Webp, webp_with_metadata(16, 8, 1)),
+        ] {
+            let processed = process(&file, format, &variants, false).unwrap();
+            assert_no_location(&processed.original);
+            assert_no_location(&processed.derivatives[0].webp);
+            assert_eq!(MediaFormat::detect(&processed.original), Some(format));
+            assert_eq!(processed.camera, None);
+        }
+    }
+
+    #[test]
+    fn test_process_applies_orientation() {
+        // 6: the camera was turned clockwise, the stored pixels need turning back
+        for (format, image_format, file) in [
+            (MediaFormat::Jpeg, ImageFormat::Jpeg, jpeg_with_metadata(16, 8, 6)),
+            (MediaFormat::Png, ImageFormat::Png, png_with_metadata(16, 8, 6)),
+            (MediaFormat::Webp, ImageFormat::WebP, webp_with_metadata(16, 8, 6)),
+        ] {
+            let processed = process(&file, format, &[variant("thumb", 100, 100)], true).unwrap();
+            assert_eq!((processed.width, processed.height), (8, 16), "{:?}", format);
+            assert_eq!((processed.derivatives[0].width, processed.derivatives[0].height), (8, 16));
+            assert_no_location(&processed.original);
+
+            let stored = image::load_from_memory_with_format(&processed.original, image_format).unwrap();
+            assert_eq!((stored.width(), stored.height()), (8, 16), "{:?}", format);
+
+            let camera = processed.camera.unwrap();
+            assert_eq!(camera.make.as_deref(), Some("Acme"));
+            assert_eq!(camera.captured_at.as_deref(), Some("2024-05-01T12:34:56"));
+        }
+    }
+}
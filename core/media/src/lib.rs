@@ -11,20 +11,46 @@
 //! }
 //! ```
 
-use actix_web::web;
+use actix_web::{http::Method, web};
+use socialhub_core::{auth::permissions::{MEDIA_DELETE, MEDIA_UPLOAD}, require_permission};
 
+mod config;
 mod error;
+pub mod format;
 pub mod models;
 pub mod handlers;  // Alterado para público
+mod range;
+pub mod repository;
+mod s3;
+mod schema;
+mod sanitize;
 mod service;
-
+pub mod storage;
+mod images;
+mod probe;
+pub mod tus;
+
+pub use config::{ImageVariant, MediaConfig, S3Config};
+pub use error::MediaError;
+pub use repository::{InMemoryMediaRepository, MediaRepository, PgMediaRepository};
+pub use service::{MediaService, NewUpload, StoredFile};
+
+/// Registers the media routes. The handlers expect a shared
+/// `web::Data<MediaService>` registered as app data.
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/media")
-            .service(web::resource("/upload").route(web::post().to(handlers::upload)))
+            .service(web::resource("/upload").route(web::post().to(handlers::upload).wrap(require_permission(MEDIA_UPLOAD))))
+            .service(web::resource("/uploads")
+                .route(web::method(Method::OPTIONS).to(tus::options))
+                .route(web::post().to(tus::create_upload).wrap(require_permission(MEDIA_UPLOAD))))
+            .service(web::resource("/uploads/{id}")
+                .route(web::head().to(tus::upload_offset).wrap(require_permission(MEDIA_UPLOAD)))
+                .route(web::patch().to(tus::append_upload).wrap(require_permission(MEDIA_UPLOAD)))
+                .route(web::delete().to(tus::terminate_upload).wrap(require_permission(MEDIA_UPLOAD))))
             .service(web::resource("/{id}")
                 .route(web::get().to(handlers::get_media))
-                .route(web::delete().to(handlers::delete_media)))
+                .route(web::delete().to(handlers::delete_media).wrap(require_permission(MEDIA_DELETE))))
             .service(web::resource("/{id}/metadata")
                 .route(web::get().to(handlers::get_metadata))
                 .route(web::put().to(handlers::update_metadata)))
@@ -34,47 +60,132 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use std::sync::Arc;
     use actix_web::{test, App, http::header};
-    use bytes::Bytes;
+    use socialhub_auth::{AuthConfig, AuthService, InMemoryUserRepository, Role};
+    use socialhub_core::{DataSubjectHook, ExportedContents};
+    use futures::TryStreamExt;
+    use tempfile::TempDir;
     use uuid::Uuid;
     use serde_json::json;
     use log::info;
     use std::env;
+    use crate::models::{Media, MediaMetadata};
+    use crate::storage::{hex, LocalStorage};
+    use crate::probe::tests::{flac, mp3, mp4, wav, webm};
+    use crate::sanitize::tests::{assert_no_location, jpeg_with_metadata, png_with_metadata, test_image, webp_with_metadata};
+    use image::ImageFormat;
+    use sha2::{Digest, Sha256};
+
+    const BOUNDARY: &str = "abbc761f78ff4d7cb7573b5a23f96ef0";
+
+    /// Start of a JFIF file.
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0\x00\x10JFIF\x00";
+    /// Pack header of an MPEG program stream, 12 bytes. Such streams are
+    /// not probed, so any content will do.
+    const MPEG_PS: &[u8] = b"\x00\x00\x01\xBA\x44\x00\x04\x00\x04\x01\x01\x89";
+
+    fn file(signature: &[u8], content: &[u8]) -> Vec<u8> {
+        [signature, content].concat()
+    }
 
     fn init() {
         env::set_var("RUST_LOG", "debug");
         let _ = env_logger::try_init();
     }
 
+    fn auth_service() -> web::Data<AuthService> {
+        web::Data::new(AuthService::new(AuthConfig::default(), Arc::new(InMemoryUserRepository::new())))
+    }
+
+    /// Media service storing files in a fresh temporary directory.
+    fn media_service() -> (web::Data<MediaService>, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = MediaConfig { upload_dir: dir.path().to_string_lossy().to_string(), ..MediaConfig::default() };
+        let storage = Arc::new(LocalStorage::new(dir.path()));
+        (web::Data::new(MediaService::new(config, storage, Arc::new(InMemoryMediaRepository::new()))), dir)
+    }
+
+    fn bearer(auth: &AuthService, user_id: i32, role: Role) -> String {
+        let (token, _) = auth.tokens().issue(user_id, &[role], &[]).unwrap();
+        format!("Bearer {}", token)
+    }
+
+    fn upload_request(auth: &AuthService, content_type: &str, content: &[u8]) -> test::TestRequest {
+        upload_form(auth, &[("description", "A test file")], content_type, content)
+    }
+
+    /// An upload with these text fields before the file.
+    fn upload_form(auth: &AuthService, fields: &[(&str, &str)], content_type: &str, content: &[u8]) -> test::TestRequest {
+        let mut payload = Vec::new();
+        for (name, value) in fields {
+            payload.extend_from_slice(format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                BOUNDARY, name, value
+            ).as_bytes());
+        }
+        payload.extend_from_slice(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"test.jpg\"\r\n\
+            Content-Type: {}\r\n\r\n",
+            BOUNDARY, content_type
+        ).as_bytes());
+        payload.extend_from_slice(content);
+        payload.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+        test::TestRequest::post()
+            .uri("/media/upload")
+            .insert_header((header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY)))
+            .insert_header((header::AUTHORIZATION, bearer(auth, 1, Role::User)))
+            .set_payload(payload)
+    }
+
+    fn stored_files(dir: &TempDir) -> usize {
+        std::fs::read_dir(dir.path()).unwrap()
+            .map(|entry| std::fs::read_dir(entry.unwrap().path()).unwrap().count())
+            .sum()
+    }
+
     #[actix_rt::test]
     async fn test_upload_media() {
         init();
         info!("Running test_upload_media");
 
+        let auth = auth_service();
+        let (media, dir) = media_service();
         let app = test::init_service(
-            App::new().service(web::scope("/media").route("/upload", web::post().to(handlers::upload)))
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media.clone())
+                .configure(configure)
         ).await;
 
-        let payload = concat!(
-            "--abbc761f78ff4d7cb7573b5a23f96ef0\r\n",
-            "Content-Disposition: form-data; name=\"file\"; filename=\"test.jpg\"\r\n",
-            "Content-Type: image/jpeg\r\n\r\n",
-            "test file content",
-            "\r\n--abbc761f78ff4d7cb7573b5a23f96ef0--\r\n"
-        );
-
-        let req = test::TestRequest::post()
-            .uri("/media/upload")
-            .insert_header((
-                header::CONTENT_TYPE,
-                "multipart/form-data; boundary=abbc761f78ff4d7cb7573b5a23f96ef0"
-            ))
-            .set_payload(payload)
-            .to_request();
-
+        let content = test_image(ImageFormat::Jpeg, 64, 48);
+        let req = upload_request(&auth, "image/jpeg", &content).to_request();
         let resp = test::call_service(&app, req).await;
         info!("Upload response status: {}", resp.status());
-        assert!(resp.status().is_success());
+        assert_eq!(resp.status().as_u16(), 201);
+
+        let uploaded: Media = test::read_body_json(resp).await;
+        assert_eq!(uploaded.user_id, 1);
+        assert_eq!(uploaded.file_type, "image/jpeg");
+        assert_eq!(uploaded.file_name.as_deref(), Some("test.jpg"));
+        assert_eq!(uploaded.description.as_deref(), Some("A test file"));
+        assert_eq!(uploaded.size, content.len() as u64);
+        assert_eq!(uploaded.metadata.format, "jpeg");
+        assert_eq!((uploaded.metadata.width, uploaded.metadata.height), (Some(64), Some(48)));
+        assert_eq!(uploaded.url, format!("/media/{}", uploaded.id));
+        assert_eq!(std::fs::read(dir.path().join(uploaded.id.to_string()).join("original")).unwrap(), content);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&uploaded.url).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "image/jpeg");
+        assert_eq!(test::read_body(resp).await, content);
+
+        // Uploading takes an account
+        let req = upload_request(&auth, "image/jpeg", &content)
+            .insert_header((header::AUTHORIZATION, "Bearer invalid"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 401);
     }
 
     #[actix_rt::test]
@@ -82,8 +193,11 @@ pub mod tests {
         init();
         info!("Running test_get_media_not_found");
 
+        let (media, _dir) = media_service();
         let app = test::init_service(
-            App::new().service(web::scope("/media").route("/{id}", web::get().to(handlers::get_media)))
+            App::new()
+                .app_data(media)
+                .service(web::scope("/media").route("/{id}", web::get().to(handlers::get_media)))
         ).await;
 
         let req = test::TestRequest::get()
@@ -96,34 +210,263 @@ pub mod tests {
     }
 
     #[actix_rt::test]
-    async fn test_upload_invalid_media_type() {
+    async fn test_get_media_ranges() {
         init();
-        info!("Running test_upload_invalid_media_type");
 
+        let auth = auth_service();
+        let (media, _dir) = media_service();
         let app = test::init_service(
-            App::new().service(web::scope("/media").route("/upload", web::post().to(handlers::upload)))
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
         ).await;
+        let resp = test::call_service(&app, upload_request(&auth, "video/mpeg", &file(MPEG_PS, b"0123456789abcdef")).to_request()).await;
+        let uploaded: Media = test::read_body_json(resp).await;
+        assert!(uploaded.variants.is_empty());
+        assert_eq!(uploaded.metadata.width, None);
+        let get = |range: &str| test::TestRequest::get().uri(&uploaded.url).insert_header((header::RANGE, range.to_string())).to_request();
+
+        let resp = test::call_service(&app, get("bytes=14-17")).await;
+        assert_eq!(resp.status().as_u16(), 206);
+        assert_eq!(resp.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 14-17/28");
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "video/mpeg");
+        assert_eq!(test::read_body(resp).await, "2345");
+
+        // Suffix and open-ended ranges
+        assert_eq!(test::read_body(test::call_service(&app, get("bytes=-3")).await).await, "def");
+        assert_eq!(test::read_body(test::call_service(&app, get("bytes=24-")).await).await, "cdef");
+
+        // Overlapping ranges are merged into one
+        let resp = test::call_service(&app, get("bytes=16-19,12-17")).await;
+        assert_eq!(resp.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 12-19/28");
+        assert_eq!(test::read_body(resp).await, "01234567");
+
+        let resp = test::call_service(&app, get("bytes=12-13, 22-23")).await;
+        assert_eq!(resp.status().as_u16(), 206);
+        let content_type = resp.headers().get(header::CONTENT_TYPE).unwrap().to_str().unwrap().to_string();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap().to_string();
+        let content_length: usize = resp.headers().get(header::CONTENT_LENGTH).unwrap().to_str().unwrap().parse().unwrap();
+        let body = test::read_body(resp).await;
+        assert_eq!(body.len(), content_length);
+        assert_eq!(body, format!(
+            "\r\n--{b}\r\nContent-Type: video/mpeg\r\nContent-Range: bytes 12-13/28\r\n\r\n01\
+            \r\n--{b}\r\nContent-Type: video/mpeg\r\nContent-Range: bytes 22-23/28\r\n\r\nab\
+            \r\n--{b}--\r\n",
+            b = boundary
+        ));
+
+        let resp = test::call_service(&app, get("bytes=28-30")).await;
+        assert_eq!(resp.status().as_u16(), 416);
+        assert_eq!(resp.headers().get(header::CONTENT_RANGE).unwrap(), "bytes */28");
+
+        // Ranges in other units or malformed ones are ignored
+        for range in ["items=0-1", "bytes=5-2", "bytes"] {
+            let resp = test::call_service(&app, get(range)).await;
+            assert_eq!(resp.status().as_u16(), 200, "{}", range);
+            assert_eq!(test::read_body(resp).await, file(MPEG_PS, b"0123456789abcdef"));
+        }
+    }
 
-        let payload = concat!(
-            "--abbc761f78ff4d7cb7573b5a23f96ef0\r\n",
-            "Content-Disposition: form-data; name=\"file\"; filename=\"test.txt\"\r\n",
-            "Content-Type: text/plain\r\n\r\n",
-            "invalid content",
-            "\r\n--abbc761f78ff4d7cb7573b5a23f96ef0--\r\n"
-        );
+    #[actix_rt::test]
+    async fn test_get_media_conditional() {
+        init();
 
-        let req = test::TestRequest::post()
-            .uri("/media/upload")
-            .insert_header((
-                header::CONTENT_TYPE,
-                "multipart/form-data; boundary=abbc761f78ff4d7cb7573b5a23f96ef0"
-            ))
-            .set_payload(payload)
-            .to_request();
+        let auth = auth_service();
+        let (media, _dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+        let content = test_image(ImageFormat::Png, 16, 16);
+        let resp = test::call_service(&app, upload_request(&auth, "image/png", &content).to_request()).await;
+        let uploaded: Media = test::read_body_json(resp).await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&uploaded.url).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let etag = resp.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+        let last_modified = resp.headers().get(header::LAST_MODIFIED).unwrap().to_str().unwrap().to_string();
+        assert_eq!(etag, format!("\"{}\"", uploaded.sha256));
+        assert_eq!(uploaded.sha256, hex(&Sha256::digest(&content)));
+        assert_eq!(resp.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+
+        let get = |name: header::HeaderName, value: &str| {
+            test::TestRequest::get().uri(&uploaded.url).insert_header((name, value.to_string()))
+        };
+
+        for value in [etag.clone(), format!("W/{}", etag), format!("\"other\", {}", etag), "*".to_string()] {
+            let resp = test::call_service(&app, get(header::IF_NONE_MATCH, &value).to_request()).await;
+            assert_eq!(resp.status().as_u16(), 304, "{}", value);
+            assert_eq!(resp.headers().get(header::ETAG).unwrap(), etag.as_str());
+            assert!(test::read_body(resp).await.is_empty());
+        }
+        let resp = test::call_service(&app, get(header::IF_NONE_MATCH, "\"other\"").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let resp = test::call_service(&app, get(header::IF_MODIFIED_SINCE, &last_modified).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 304);
+        let resp = test::call_service(&app, get(header::IF_MODIFIED_SINCE, "Sat, 01 Jan 2000 00:00:00 GMT").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        // If-Range resumes only a download of the same file
+        for (value, status) in [(etag.as_str(), 206), (last_modified.as_str(), 206), ("\"other\"", 200), (&*format!("W/{}", etag), 200)] {
+            let req = get(header::IF_RANGE, value).insert_header((header::RANGE, "bytes=4-")).to_request();
+            assert_eq!(test::call_service(&app, req).await.status().as_u16(), status, "{}", value);
+        }
+    }
 
+    #[actix_rt::test]
+    async fn test_get_media_variants() {
+        init();
+
+        let auth = auth_service();
+        let (media, dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+        let resp = test::call_service(&app, upload_request(&auth, "image/png", &test_image(ImageFormat::Png, 1600, 1200)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let uploaded: Media = test::read_body_json(resp).await;
+        assert_eq!((uploaded.metadata.width, uploaded.metadata.height), (Some(1600), Some(1200)));
+
+        // thumb:320x320, feed:1080x1350 and full:2048x2048, never upscaled
+        let sizes: Vec<_> = uploaded.variants.iter().map(|v| (v.name.as_str(), v.width, v.height)).collect();
+        assert_eq!(sizes, [("thumb", 320, 240), ("feed", 1080, 810), ("full", 1600, 1200)]);
+
+        let thumb = &uploaded.variants[0];
+        assert_eq!(thumb.url, format!("/media/{}?variant=thumb", uploaded.id));
+        let stored = std::fs::read(dir.path().join(uploaded.id.to_string()).join("thumb.webp")).unwrap();
+        assert_eq!(thumb.size, stored.len() as u64);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&thumb.url).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "image/webp");
+        assert_eq!(resp.headers().get(header::ETAG).unwrap().to_str().unwrap(), format!("\"{}\"", thumb.sha256));
+        let body = test::read_body(resp).await;
+        assert_eq!(body, stored);
+        let decoded = image::load_from_memory_with_format(&body, ImageFormat::WebP).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (320, 240));
+
+        let req = test::TestRequest::get().uri(&thumb.url).insert_header((header::RANGE, "bytes=0-3")).to_request();
+        assert_eq!(test::read_body(test::call_service(&app, req).await).await, "RIFF");
+
+        let url = format!("{}?variant=huge", uploaded.url);
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&url).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+
+    #[actix_rt::test]
+    async fn test_upload_strips_image_metadata() {
+        init();
+
+        let auth = auth_service();
+        let (media, dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+
+        for (content_type, content) in [
+            ("image/jpeg", jpeg_with_metadata(40, 20, 6)),
+            ("image/png", png_with_metadata(40, 20, 6)),
+            ("image/webp", webp_with_metadata(40, 20, 1)),
+        ] {
+            let resp = test::call_service(&app, upload_request(&auth, content_type, &content).to_request()).await;
+            assert_eq!(resp.status().as_u16(), 201, "{}", content_type);
+            let uploaded: Media = test::read_body_json(resp).await;
+            assert!(uploaded.metadata.camera.is_none());
+
+            // Stored and served without GPS tags, as are the variants
+            let stored = std::fs::read(dir.path().join(uploaded.id.to_string()).join("original")).unwrap();
+            assert_no_location(&stored);
+            assert_eq!(uploaded.sha256, hex(&Sha256::digest(&stored)));
+            assert_eq!(uploaded.size, stored.len() as u64);
+            for url in std::iter::once(&uploaded.url).chain(uploaded.variants.iter().map(|v| &v.url)) {
+                let resp = test::call_service(&app, test::TestRequest::get().uri(url).to_request()).await;
+                assert_no_location(&test::read_body(resp).await);
+            }
+        }
+
+        // Turned upright, and the camera kept on request
+        let fields = [("keep_camera_info", "true")];
+        let req = upload_form(&auth, &fields, "image/jpeg", &jpeg_with_metadata(40, 20, 6)).to_request();
+        let uploaded: Media = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!((uploaded.metadata.width, uploaded.metadata.height), (Some(20), Some(40)));
+        let camera = uploaded.metadata.camera.unwrap();
+        assert_eq!(camera.make.as_deref(), Some("Acme"));
+        assert_eq!(camera.model.as_deref(), Some("Phone 1"));
+        assert_eq!(camera.captured_at.as_deref(), Some("2024-05-01T12:34:56"));
+        let stored = std::fs::read(dir.path().join(uploaded.id.to_string()).join("original")).unwrap();
+        assert_no_location(&stored);
+        let upright = image::load_from_memory_with_format(&stored, ImageFormat::Jpeg).unwrap();
+        assert_eq!((upright.width(), upright.height()), (20, 40));
+
+        let fields = [("keep_camera_info", "maybe")];
+        let req = upload_form(&auth, &fields, "image/jpeg", &jpeg_with_metadata(40, 20, 6)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 400);
+    }
+
+    #[actix_rt::test]
+    async fn test_upload_checks_file_signature() {
+        init();
+
+        let auth = auth_service();
+        let (media, dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+        let mp3 = mp3(4);
+
+        // An executable, a PNG and an MP3 claiming to be something they are
+        // not, and a JPEG signature in front of something else
+        for (content_type, content) in [
+            ("image/jpeg", file(b"MZ\x90\x00", b"not an image")),
+            ("image/jpeg", test_image(ImageFormat::Png, 8, 8)),
+            ("image/jpeg", file(JPEG, b"not an image")),
+            ("video/mp4", mp3.clone()),
+            ("audio/mp3", mp3.clone()),
+        ] {
+            let resp = test::call_service(&app, upload_request(&auth, content_type, &content).to_request()).await;
+            assert_eq!(resp.status().as_u16(), 415, "{}", content_type);
+        }
+        assert_eq!(stored_files(&dir), 0);
+
+        let resp = test::call_service(&app, upload_request(&auth, "audio/MPEG", &mp3).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let uploaded: Media = test::read_body_json(resp).await;
+        assert_eq!(uploaded.file_type, "audio/mpeg");
+        assert_eq!(uploaded.metadata.format, "mp3");
+    }
+
+    #[actix_rt::test]
+    async fn test_upload_invalid_media_type() {
+        init();
+        info!("Running test_upload_invalid_media_type");
+
+        let auth = auth_service();
+        let (media, dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+
+        let req = upload_request(&auth, "text/plain", b"invalid content").to_request();
         let resp = test::call_service(&app, req).await;
         info!("Invalid media type response status: {}", resp.status());
         assert_eq!(resp.status().as_u16(), 415); // Unsupported Media Type
+        assert_eq!(stored_files(&dir), 0);
     }
 
     #[actix_rt::test]
@@ -131,8 +474,11 @@ pub mod tests {
         init();
         info!("Running test_get_media_metadata_not_found");
 
+        let (media, _dir) = media_service();
         let app = test::init_service(
-            App::new().service(web::scope("/media").route("/{id}/metadata", web::get().to(handlers::get_metadata)))
+            App::new()
+                .app_data(media)
+                .service(web::scope("/media").route("/{id}/metadata", web::get().to(handlers::get_metadata)))
         ).await;
 
         let req = test::TestRequest::get()
@@ -145,36 +491,309 @@ pub mod tests {
     }
 
     #[actix_rt::test]
-    async fn test_upload_large_file() {
+    async fn test_upload_probes_audio_and_video() {
         init();
-        info!("Running test_upload_large_file");
 
+        let auth = auth_service();
+        let (media, _dir) = media_service();
         let app = test::init_service(
-            App::new().service(web::scope("/media").route("/upload", web::post().to(handlers::upload)))
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
         ).await;
 
-        let large_content = vec![0u8; 11 * 1024 * 1024]; // 11MB
-        let payload = format!(
-            "--abbc761f78ff4d7cb7573b5a23f96ef0\r\n\
-            Content-Disposition: form-data; name=\"file\"; filename=\"large.jpg\"\r\n\
-            Content-Type: image/jpeg\r\n\r\n\
-            {}\r\n\
-            --abbc761f78ff4d7cb7573b5a23f96ef0--\r\n",
-            String::from_utf8_lossy(&large_content)
-        );
+        let resp = test::call_service(&app, upload_request(&auth, "video/mp4", &mp4(50_000)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let uploaded: Media = test::read_body_json(resp).await;
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&format!("{}/metadata", uploaded.url)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let metadata: MediaMetadata = test::read_body_json(resp).await;
+        assert_eq!(metadata.format, "mp4");
+        assert_eq!(metadata.duration, Some(10.0));
+        assert_eq!((metadata.width, metadata.height), (Some(640), Some(360)));
+        assert_eq!(metadata.video_codec.as_deref(), Some("h264"));
+        assert_eq!(metadata.audio_codec.as_deref(), Some("aac"));
+        assert_eq!(metadata.bitrate, Some(uploaded.size * 8 / 10));
+        assert_eq!((metadata.sample_rate, metadata.channels), (Some(44100), Some(2)));
+
+        for (content_type, content, codec, duration) in [
+            ("video/webm", webm(1000), "opus", 5.0),
+            ("audio/mpeg", mp3(10), "mp3", 11520.0 / 44100.0),
+            ("audio/wav", wav(1), "pcm_s16le", 1.0),
+            ("audio/flac", flac(10), "flac", 2560.0 / 44100.0),
+        ] {
+            let resp = test::call_service(&app, upload_request(&auth, content_type, &content).to_request()).await;
+            assert_eq!(resp.status().as_u16(), 201, "{}", content_type);
+            let uploaded: Media = test::read_body_json(resp).await;
+            assert_eq!(uploaded.metadata.audio_codec.as_deref(), Some(codec));
+            // JSON numbers do not round-trip every last bit of a float.
+            assert!((uploaded.metadata.duration.unwrap() - duration).abs() < 1e-9, "{}", content_type);
+            assert!(uploaded.metadata.bitrate.is_some());
+        }
+    }
 
-        let req = test::TestRequest::post()
-            .uri("/media/upload")
-            .insert_header((
-                header::CONTENT_TYPE,
-                "multipart/form-data; boundary=abbc761f78ff4d7cb7573b5a23f96ef0"
-            ))
-            .set_payload(payload)
-            .to_request();
+    #[actix_rt::test]
+    async fn test_upload_rejects_truncated_media() {
+        init();
 
+        let auth = auth_service();
+        let (media, dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+
+        for (content_type, content) in [
+            ("video/mp4", mp4(50_000)),
+            ("video/webm", webm(1000)),
+            ("audio/mpeg", mp3(10)),
+            ("audio/wav", wav(1)),
+            ("audio/flac", flac(10)),
+        ] {
+            let truncated = &content[..content.len() - 100];
+            let resp = test::call_service(&app, upload_request(&auth, content_type, truncated).to_request()).await;
+            assert_eq!(resp.status().as_u16(), 415, "{}", content_type);
+        }
+        // Nothing of the rejected uploads is kept
+        assert_eq!(stored_files(&dir), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_upload_large_file() {
+        init();
+        info!("Running test_upload_large_file");
+
+        let auth = auth_service();
+        let (media, dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+
+        let large_content = file(JPEG, &vec![0u8; 11 * 1024 * 1024]); // 11MB
+        let req = upload_request(&auth, "image/jpeg", &large_content).to_request();
         let resp = test::call_service(&app, req).await;
         info!("Upload large file response status: {}", resp.status());
         assert_eq!(resp.status().as_u16(), 413); // Payload Too Large
+        // Nothing of the partial upload is kept
+        assert_eq!(stored_files(&dir), 0);
+    }
+
+    /// A tus request to `uri` from user 1.
+    fn tus_request(auth: &AuthService, method: Method, uri: &str) -> test::TestRequest {
+        test::TestRequest::default()
+            .method(method)
+            .uri(uri)
+            .insert_header(("Tus-Resumable", "1.0.0"))
+            .insert_header((header::AUTHORIZATION, bearer(auth, 1, Role::User)))
+    }
+
+    fn create_tus_upload(auth: &AuthService, length: usize, filetype: &str) -> test::TestRequest {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        tus_request(auth, Method::POST, "/media/uploads")
+            .insert_header(("Upload-Length", length.to_string()))
+            .insert_header(("Upload-Metadata", format!("filename {},filetype {}", STANDARD.encode("clip.mp4"), STANDARD.encode(filetype))))
+    }
+
+    fn patch_tus_upload(auth: &AuthService, location: &str, offset: usize, chunk: &[u8]) -> test::TestRequest {
+        tus_request(auth, Method::PATCH, location)
+            .insert_header((header::CONTENT_TYPE, "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", offset.to_string()))
+            .set_payload(chunk.to_vec())
+    }
+
+    fn header_value<B>(resp: &actix_web::dev::ServiceResponse<B>, name: &str) -> String {
+        resp.headers().get(name).unwrap().to_str().unwrap().to_string()
+    }
+
+    #[actix_rt::test]
+    async fn test_resumable_upload() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        init();
+
+        let auth = auth_service();
+        let (media, dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+
+        let resp = test::call_service(&app, test::TestRequest::default().method(Method::OPTIONS).uri("/media/uploads").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        assert_eq!(header_value(&resp, "Tus-Version"), "1.0.0");
+        assert_eq!(header_value(&resp, "Tus-Extension"), "creation,expiration,termination,checksum");
+        assert_eq!(header_value(&resp, "Tus-Checksum-Algorithm"), "sha1,sha256");
+        assert_eq!(header_value(&resp, "Tus-Max-Size"), MediaConfig::default().max_upload_length.to_string());
+
+        let content = mp4(50_000);
+        let resp = test::call_service(&app, create_tus_upload(&auth, content.len(), "video/mp4").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        assert_eq!(header_value(&resp, "Tus-Resumable"), "1.0.0");
+        assert!(resp.headers().contains_key("Upload-Expires"));
+        let location = header_value(&resp, "Location");
+
+        let third = content.len() / 3;
+        let checksum = format!("sha1 {}", STANDARD.encode(sha1::Sha1::digest(&content[..third])));
+        let resp = test::call_service(&app, patch_tus_upload(&auth, &location, 0, &content[..third])
+            .insert_header(("Upload-Checksum", checksum)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        assert_eq!(header_value(&resp, "Upload-Offset"), third.to_string());
+
+        // Resending the same chunk
+        let resp = test::call_service(&app, patch_tus_upload(&auth, &location, 0, &content[..third]).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 409);
+
+        // A damaged chunk is dropped
+        let checksum = format!("sha256 {}", STANDARD.encode(Sha256::digest(&content[third..2 * third])));
+        let mut damaged = content[third..2 * third].to_vec();
+        damaged[10] ^= 1;
+        let resp = test::call_service(&app, patch_tus_upload(&auth, &location, third, &damaged)
+            .insert_header(("Upload-Checksum", checksum.clone())).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 460);
+        let resp = test::call_service(&app, tus_request(&auth, Method::HEAD, &location).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(header_value(&resp, "Upload-Offset"), third.to_string());
+        assert_eq!(header_value(&resp, "Upload-Length"), content.len().to_string());
+        assert_eq!(header_value(&resp, "Cache-Control"), "no-store");
+        assert!(resp.headers().get(header::CONTENT_LOCATION).is_none());
+
+        let resp = test::call_service(&app, patch_tus_upload(&auth, &location, third, &content[third..2 * third])
+            .insert_header(("Upload-Checksum", checksum)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        let resp = test::call_service(&app, patch_tus_upload(&auth, &location, 2 * third, &content[2 * third..]).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        assert_eq!(header_value(&resp, "Upload-Offset"), content.len().to_string());
+        assert!(resp.headers().get("Upload-Expires").is_none());
+        let media_url = header_value(&resp, "Content-Location");
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&format!("{}/metadata", media_url)).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let metadata: MediaMetadata = test::read_body_json(resp).await;
+        assert_eq!(metadata.format, "mp4");
+        assert_eq!(metadata.duration, Some(10.0));
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&media_url).to_request()).await;
+        assert_eq!(test::read_body(resp).await, content);
+
+        // The chunks and the upload are gone, the media stays
+        assert_eq!(stored_files(&dir), 1);
+        for method in [Method::HEAD, Method::DELETE] {
+            let resp = test::call_service(&app, tus_request(&auth, method, &location).to_request()).await;
+            assert_eq!(resp.status().as_u16(), 404);
+        }
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&media_url).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    #[actix_rt::test]
+    async fn test_resumable_upload_limits() {
+        init();
+
+        let auth = auth_service();
+        let dir = tempfile::tempdir().unwrap();
+        let config = MediaConfig { max_upload_length: 1000, max_pending_upload_size: 1500, ..MediaConfig::default() };
+        let media = web::Data::new(MediaService::new(
+            config,
+            Arc::new(LocalStorage::new(dir.path())),
+            Arc::new(InMemoryMediaRepository::new()),
+        ));
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+
+        let resp = test::call_service(&app, create_tus_upload(&auth, 1001, "audio/wav").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 413);
+        let resp = test::call_service(&app, create_tus_upload(&auth, 100, "application/x-unknown").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 415);
+        let resp = test::call_service(&app, create_tus_upload(&auth, 1000, "audio/wav")
+            .insert_header(("Tus-Resumable", "0.2.2")).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 412);
+        assert_eq!(header_value(&resp, "Tus-Version"), "1.0.0");
+
+        let resp = test::call_service(&app, create_tus_upload(&auth, 1000, "audio/wav").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let location = header_value(&resp, "Location");
+        // The user's unfinished uploads would exceed 1500 bytes
+        let resp = test::call_service(&app, create_tus_upload(&auth, 600, "audio/wav").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 413);
+
+        // Other users cannot see or touch the upload
+        let other = bearer(&auth, 2, Role::User);
+        for method in [Method::HEAD, Method::DELETE] {
+            let req = tus_request(&auth, method, &location).insert_header((header::AUTHORIZATION, other.clone()));
+            assert_eq!(test::call_service(&app, req.to_request()).await.status().as_u16(), 404);
+        }
+        let req = patch_tus_upload(&auth, &location, 0, b"RIFF").insert_header((header::AUTHORIZATION, other));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status().as_u16(), 404);
+
+        let resp = test::call_service(&app, patch_tus_upload(&auth, &location, 0, &[0; 1001]).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 413);
+        let resp = test::call_service(&app, patch_tus_upload(&auth, &location, 0, b"RIFF")
+            .insert_header((header::CONTENT_TYPE, "application/octet-stream")).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 415);
+        let resp = test::call_service(&app, tus_request(&auth, Method::HEAD, &location).to_request()).await;
+        assert_eq!(header_value(&resp, "Upload-Offset"), "0");
+
+        let resp = test::call_service(&app, tus_request(&auth, Method::DELETE, &location).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        let resp = test::call_service(&app, create_tus_upload(&auth, 1000, "audio/wav").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        assert_eq!(stored_files(&dir), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_resumable_upload_expiry() {
+        init();
+
+        let auth = auth_service();
+        let dir = tempfile::tempdir().unwrap();
+        let config = MediaConfig { max_pending_upload_size: 1500, upload_expiration: 1, ..MediaConfig::default() };
+        let media = web::Data::new(MediaService::new(
+            config,
+            Arc::new(LocalStorage::new(dir.path())),
+            Arc::new(InMemoryMediaRepository::new()),
+        ));
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media.clone())
+                .configure(configure)
+        ).await;
+
+        let resp = test::call_service(&app, create_tus_upload(&auth, 1000, "audio/wav").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+        let location = header_value(&resp, "Location");
+        let resp = test::call_service(&app, patch_tus_upload(&auth, &location, 0, b"RIFF").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        let expires: header::HttpDate = header_value(&resp, "Upload-Expires").parse().unwrap();
+        assert!(std::time::SystemTime::from(expires) > std::time::SystemTime::now());
+        assert_eq!(stored_files(&dir), 1);
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        // Gone for the client, and no longer counted against the user
+        let resp = test::call_service(&app, tus_request(&auth, Method::HEAD, &location).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+        let resp = test::call_service(&app, patch_tus_upload(&auth, &location, 4, b"WAVE").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+        let resp = test::call_service(&app, create_tus_upload(&auth, 1000, "audio/wav").to_request()).await;
+        assert_eq!(resp.status().as_u16(), 201);
+
+        // Purging deletes its chunks and leaves the new upload alone
+        assert_eq!(media.purge_expired_uploads().await.unwrap(), 1);
+        assert_eq!(stored_files(&dir), 0);
+        assert_eq!(media.purge_expired_uploads().await.unwrap(), 0);
+        let resp = test::call_service(&app, tus_request(&auth, Method::HEAD, &header_value(&resp, "Location")).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 200);
     }
 
     #[actix_rt::test]
@@ -199,4 +818,124 @@ pub mod tests {
         info!("Update metadata response status: {}", resp.status());
         assert!(resp.status().is_success());
     }
+
+    #[actix_rt::test]
+    async fn test_delete_media_requires_permission() {
+        init();
+        info!("Running test_delete_media_requires_permission");
+
+        let auth = auth_service();
+        let (media, _dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+        let delete = |role: Option<Role>| {
+            let mut req = test::TestRequest::delete().uri(&format!("/media/{}", Uuid::new_v4()));
+            if let Some(role) = role {
+                req = req.insert_header((header::AUTHORIZATION, bearer(&auth, 1, role)));
+            }
+            req.to_request()
+        };
+
+        assert_eq!(test::call_service(&app, delete(None)).await.status().as_u16(), 401);
+        assert_eq!(test::call_service(&app, delete(Some(Role::User))).await.status().as_u16(), 403);
+        assert!(test::call_service(&app, delete(Some(Role::Moderator))).await.status().is_success());
+
+        // A moderator's app granted media:write may upload, not delete.
+        let permissions = vec![socialhub_core::auth::permissions::MEDIA_UPLOAD.to_string()];
+        let (token, _) = auth.tokens().issue_for_client(1, &[Role::Moderator], permissions, "app", &["media:write".to_string()]).unwrap();
+        let req = test::TestRequest::delete()
+            .uri(&format!("/media/{}", Uuid::new_v4()))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status().as_u16(), 403);
+    }
+
+    #[actix_rt::test]
+    async fn test_delete_media_removes_file() {
+        init();
+
+        let auth = auth_service();
+        let (media, dir) = media_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(media)
+                .configure(configure)
+        ).await;
+
+        let resp = test::call_service(&app, upload_request(&auth, "image/png", &test_image(ImageFormat::Png, 8, 8)).to_request()).await;
+        let uploaded: Media = test::read_body_json(resp).await;
+        // The original and its variants
+        assert_eq!(stored_files(&dir), 4);
+
+        let req = test::TestRequest::delete()
+            .uri(&uploaded.url)
+            .insert_header((header::AUTHORIZATION, bearer(&auth, 2, Role::Moderator)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 204);
+        assert_eq!(stored_files(&dir), 0);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&uploaded.url).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&uploaded.variants[0].url).to_request()).await;
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+
+    #[actix_rt::test]
+    async fn test_media_data_subject_hook() {
+        init();
+
+        let (media, dir) = media_service();
+        let upload = |user_id, name: &str| {
+            let body = futures::stream::iter(vec![Ok(bytes::Bytes::from(test_image(ImageFormat::Png, 4, 4)))]);
+            let upload = NewUpload { content_type: "image/png".to_string(), file_name: Some(format!("../{}.png", name)), description: None, keep_camera_info: false };
+            let media = media.clone();
+            async move { media.upload(user_id, upload, body).await.unwrap() }
+        };
+        let own = upload(1, "mine").await;
+        let other = upload(2, "theirs").await;
+        assert_eq!(own.file_name.as_deref(), Some("mine.png"));
+
+        let mut files = media.export(1).await.unwrap();
+        assert_eq!(files.len(), 2);
+        let records: Vec<Media> = serde_json::from_slice(files[0].bytes().unwrap()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, own.id);
+        assert_eq!(files[1].path, format!("files/{}-mine.png", own.id));
+        // Originals are streamed rather than read into memory.
+        let ExportedContents::Stream(original) = files.remove(1).contents else {
+            panic!("the original is not streamed");
+        };
+        let original: Vec<bytes::Bytes> = original.try_collect().await.unwrap();
+        assert_eq!(original.concat(), test_image(ImageFormat::Png, 4, 4));
+
+        media.erase(1).await.unwrap();
+        media.erase(1).await.unwrap();
+        assert!(media.get_media(own.id).await.is_err());
+        assert!(media.get_media(other.id).await.is_ok());
+        assert_eq!(stored_files(&dir), 1 + other.variants.len());
+    }
+
+    #[actix_rt::test]
+    async fn test_media_records_outlive_the_service() {
+        init();
+
+        let dir = tempfile::tempdir().unwrap();
+        let records = Arc::new(InMemoryMediaRepository::new());
+        let service = || MediaService::new(MediaConfig::default(), Arc::new(LocalStorage::new(dir.path())), records.clone());
+        let body = futures::stream::iter(vec![Ok(bytes::Bytes::from(test_image(ImageFormat::Png, 4, 4)))]);
+        let upload = NewUpload { content_type: "image/png".to_string(), file_name: None, description: None, keep_camera_info: false };
+        let uploaded = service().upload(1, upload, body).await.unwrap();
+
+        // As after a restart.
+        let restarted = service();
+        let found = restarted.get_media(uploaded.id).await.unwrap();
+        assert_eq!(found.sha256, uploaded.sha256);
+        assert_eq!(records.list_by_user(1).await.unwrap().len(), 1);
+        restarted.delete_media(uploaded.id).await.unwrap();
+        assert!(records.find(uploaded.id).await.unwrap().is_none());
+    }
 }
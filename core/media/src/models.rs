@@ -3,21 +3,70 @@ use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Media {
     pub id: Uuid,
     pub user_id: i32,
     pub file_type: String,
+    /// Where the file is served, `/media/{id}`.
     pub url: String,
+    /// Name of the file on the uploader's device.
+    pub file_name: Option<String>,
+    /// Size in bytes.
+    pub size: u64,
+    /// Hex SHA-256 of the file, also its `ETag`.
+    pub sha256: String,
+    pub metadata: MediaMetadata,
+    /// Scaled-down WebP copies of images, empty for other media.
+    pub variants: Vec<MediaVariant>,
     pub description: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A derivative of an image, generated on upload.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MediaVariant {
+    /// Name of the configured variant, e.g. `thumb`.
+    pub name: String,
+    /// Where the variant is served, `/media/{id}?variant={name}`.
+    pub url: String,
+    pub file_type: String,
+    pub width: u32,
+    pub height: u32,
+    /// Size in bytes.
+    pub size: u64,
+    /// Hex SHA-256 of the file, also its `ETag`.
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct MediaMetadata {
+    /// Dimensions of images, as decoded, and of the first video track.
     pub width: Option<u32>,
     pub height: Option<u32>,
+    /// Seconds, for audio and video whose container records it.
     pub duration: Option<f64>,
+    /// Format detected from the file's signature, e.g. `jpeg` or `mp4`.
     pub format: String,
+    /// From the photo's EXIF data, only when the uploader asked to keep it.
+    pub camera: Option<CameraInfo>,
+    /// Codec of the first video track, e.g. `h264` or `vp9`.
+    pub video_codec: Option<String>,
+    /// Codec of the first audio track, e.g. `aac`, `opus` or `pcm_s16le`.
+    pub audio_codec: Option<String>,
+    /// Average over the whole file, in bits per second.
+    pub bitrate: Option<u64>,
+    /// Of the first audio track, in Hz.
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CameraInfo {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// Local time the photo was taken, as `2024-05-01T12:34:56`. Cameras
+    /// do not record the time zone.
+    pub captured_at: Option<String>,
 }
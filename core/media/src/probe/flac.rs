@@ -0,0 +1,265 @@
+//! FLAC: metadata blocks, then frames.
+//!
+//! `STREAMINFO` gives the audio parameters and the number of samples. The
+//! frames are not decoded; instead the last one is found at the end of the
+//! file by its checksums, and must end at the last sample.
+
+use bytes::Bytes;
+use futures::Stream;
+use super::{be_u16, skip_id3v2, Probe, ProbeError, Reader};
+
+const STREAMINFO: u8 = 0;
+const STREAMINFO_SIZE: usize = 34;
+const INVALID_BLOCK: u8 = 127;
+
+/// Kept from the end of the file when `STREAMINFO` does not give the largest frame.
+const DEFAULT_TAIL: usize = 1024 * 1024;
+/// Frame headers are at most 16 bytes.
+const MAX_HEADER_SIZE: usize = 16;
+/// Candidate headers checked for the last frame before giving up.
+const MAX_CANDIDATES: usize = 64;
+
+struct StreamInfo {
+    /// Block size of fixed-blocksize streams, in which frames are numbered.
+    max_block_size: u64,
+    max_frame_size: usize,
+    sample_rate: u32,
+    channels: u16,
+    /// Zero when unknown.
+    total_samples: u64,
+}
+
+impl StreamInfo {
+    fn parse(block: &[u8]) -> Self {
+        let packed = u64::from_be_bytes(block[10..18].try_into().unwrap());
+        Self {
+            max_block_size: be_u16(&block[2..4]) as u64,
+            max_frame_size: (block[7] as usize) << 16 | (block[8] as usize) << 8 | block[9] as usize,
+            sample_rate: (packed >> 44) as u32,
+            channels: ((packed >> 41) & 0b111) as u16 + 1,
+            total_samples: packed & 0xF_FFFF_FFFF,
+        }
+    }
+}
+
+pub(super) async fn probe<S>(reader: &mut Reader<S>) -> Result<Probe, ProbeError>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    let mut magic = reader.read(4).await?;
+    if magic.starts_with(b"ID3") {
+        skip_id3v2(reader, &magic).await?;
+        magic = reader.read(4).await?;
+    }
+    if magic != b"fLaC" {
+        return Err(ProbeError::Corrupt("no fLaC marker"));
+    }
+
+    let mut info = None;
+    loop {
+        let header = reader.read(4).await?;
+        let last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let length = (header[1] as usize) << 16 | (header[2] as usize) << 8 | header[3] as usize;
+        match (block_type, &info) {
+            (STREAMINFO, None) if length == STREAMINFO_SIZE => {
+                info = Some(StreamInfo::parse(&reader.read(length).await?));
+            }
+            (_, None) => return Err(ProbeError::Corrupt("STREAMINFO is not the first block")),
+            (STREAMINFO, Some(_)) => return Err(ProbeError::Corrupt("several STREAMINFO blocks")),
+            (INVALID_BLOCK, _) => return Err(ProbeError::Corrupt("invalid metadata block")),
+            _ => reader.skip(length as u64).await?,
+        }
+        if last {
+            break;
+        }
+    }
+    let info = info.expect("the loop stops at anything but STREAMINFO first");
+    if info.sample_rate == 0 {
+        return Err(ProbeError::Corrupt("sample rate of zero"));
+    }
+
+    let sync = reader.read(2).await?;
+    if sync[0] != 0xFF || sync[1] & 0xFE != 0xF8 {
+        return Err(ProbeError::Corrupt("no frame after the metadata"));
+    }
+    let limit = match info.max_frame_size {
+        0 => DEFAULT_TAIL,
+        size => size + MAX_HEADER_SIZE,
+    };
+    let tail = reader.tail(sync, limit).await;
+    let end_sample = last_frame_end(&tail, &info).ok_or(ProbeError::Truncated)?;
+    if info.total_samples != 0 && end_sample != info.total_samples {
+        return Err(ProbeError::Truncated);
+    }
+
+    Ok(Probe {
+        duration: Some(end_sample as f64 / info.sample_rate as f64),
+        audio_codec: Some("flac".to_string()),
+        sample_rate: Some(info.sample_rate),
+        channels: Some(info.channels),
+        ..Probe::default()
+    })
+}
+
+/// The sample after the last one of the complete frame that ends `tail`.
+fn last_frame_end(tail: &[u8], info: &StreamInfo) -> Option<u64> {
+    if tail.len() < 2 {
+        return None;
+    }
+    let stored_crc = be_u16(&tail[tail.len() - 2..]);
+    (0..tail.len() - 1).rev()
+        .filter(|&start| tail[start] == 0xFF && tail[start + 1] & 0xFE == 0xF8)
+        .filter_map(|start| frame_header(&tail[start..tail.len() - 2], info).map(|end_sample| (start, end_sample)))
+        .take(MAX_CANDIDATES)
+        .find(|&(start, _)| crc16(&tail[start..tail.len() - 2]) == stored_crc)
+        .map(|(_, end_sample)| end_sample)
+}
+
+/// Parses a frame header with a valid CRC-8 at the start of `frame`,
+/// returning the sample after the frame's last.
+fn frame_header(frame: &[u8], info: &StreamInfo) -> Option<u64> {
+    let variable_blocksize = *frame.get(1)? & 1 != 0;
+    let block_size_code = *frame.get(2)? >> 4;
+    let sample_rate_code = frame[2] & 0x0F;
+    if block_size_code == 0 || sample_rate_code == 0x0F || *frame.get(3)? & 1 != 0 {
+        return None;
+    }
+
+    // The frame or sample number, UTF-8 style.
+    let first = *frame.get(4)?;
+    let extra = match first.leading_ones() {
+        0 => 0,
+        n @ 2..=7 => n as usize - 1,
+        _ => return None,
+    };
+    let mut number = (first & (0x7F >> extra)) as u64;
+    for &byte in frame.get(5..5 + extra)? {
+        if byte & 0xC0 != 0x80 {
+            return None;
+        }
+        number = (number << 6) | (byte & 0x3F) as u64;
+    }
+
+    let mut pos = 5 + extra;
+    let block_size = match block_size_code {
+        1 => 192,
+        2..=5 => 576 << (block_size_code - 2),
+        6 => {
+            pos += 1;
+            *frame.get(pos - 1)? as u64 + 1
+        }
+        7 => {
+            pos += 2;
+            be_u16(frame.get(pos - 2..pos)?) as u64 + 1
+        }
+        _ => 256 << (block_size_code - 8),
+    };
+    pos += match sample_rate_code {
+        12 => 1,
+        13 | 14 => 2,
+        _ => 0,
+    };
+    if crc8(frame.get(..pos)?) != *frame.get(pos)? {
+        return None;
+    }
+
+    let first_sample = if variable_blocksize { number } else { number * info.max_block_size };
+    Some(first_sample + block_size)
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::format::MediaFormat;
+    use crate::probe::tests::{assert_rejects_truncated, probe_bytes};
+
+    const BLOCK_SIZE: u64 = 256;
+    const FRAME_PAYLOAD: usize = 300;
+
+    fn frame(number: u8) -> Vec<u8> {
+        // 256 samples at 44.1 kHz, two independent 16-bit channels
+        let mut frame = vec![0xFF, 0xF8, 0x89, 0x18, number];
+        frame.push(crc8(&frame));
+        frame.extend((0..FRAME_PAYLOAD).map(|i| (i % 200) as u8 ^ number));
+        frame.extend_from_slice(&crc16(&frame).to_be_bytes());
+        frame
+    }
+
+    /// `frames` frames of 256 samples of stereo 44.1 kHz audio, after a
+    /// padding block.
+    pub(crate) fn flac(frames: u8) -> Vec<u8> {
+        let frame_size = frame(0).len() as u32;
+        let mut info = Vec::new();
+        info.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+        info.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+        info.extend_from_slice(&frame_size.to_be_bytes()[1..]);
+        info.extend_from_slice(&frame_size.to_be_bytes()[1..]);
+        let packed = 44100u64 << 44 | 1 << 41 | 15 << 36 | (frames as u64 * BLOCK_SIZE);
+        info.extend_from_slice(&packed.to_be_bytes());
+        info.extend_from_slice(&[0; 16]);
+
+        let mut file = b"fLaC\x00\x00\x00\x22".to_vec();
+        file.extend_from_slice(&info);
+        file.extend_from_slice(&[0x81, 0x00, 0x00, 0x10]);
+        file.extend_from_slice(&[0; 16]);
+        for number in 0..frames {
+            file.extend(frame(number));
+        }
+        file
+    }
+
+    #[test]
+    fn test_probe_flac() {
+        let file = flac(20);
+        let probe = probe_bytes(MediaFormat::Flac, &file).unwrap().unwrap();
+        assert_eq!(probe.duration, Some(5120.0 / 44100.0));
+        assert_eq!(probe.audio_codec.as_deref(), Some("flac"));
+        assert_eq!(probe.sample_rate, Some(44100));
+        assert_eq!(probe.channels, Some(2));
+
+        // Behind an ID3 tag
+        let tagged = [&b"ID3\x04\x00\x00\x00\x00\x00\x04TAGS"[..], &file].concat();
+        assert_eq!(probe_bytes(MediaFormat::Flac, &tagged).unwrap().unwrap().duration, probe.duration);
+    }
+
+    #[test]
+    fn test_probe_flac_rejects_broken_files() {
+        let file = flac(5);
+        let frames = file.len() - 5 * frame(0).len();
+        assert_rejects_truncated(MediaFormat::Flac, &file, &[3, 30, frames, frames + 1, frames + 100, file.len() - frame(0).len(), file.len() - 1]);
+
+        let mut damaged = file.clone();
+        let last = damaged.len() - 10;
+        damaged[last] ^= 1;
+        assert_eq!(probe_bytes(MediaFormat::Flac, &damaged).unwrap(), Err(ProbeError::Truncated));
+
+        let mut no_streaminfo = file.clone();
+        no_streaminfo[4] = 1;
+        assert_eq!(probe_bytes(MediaFormat::Flac, &no_streaminfo).unwrap(), Err(ProbeError::Corrupt("STREAMINFO is not the first block")));
+
+        let mut invalid = file.clone();
+        invalid[42] = 0x80 | INVALID_BLOCK;
+        assert_eq!(probe_bytes(MediaFormat::Flac, &invalid).unwrap(), Err(ProbeError::Corrupt("invalid metadata block")));
+    }
+}
@@ -0,0 +1,417 @@
+//! Matroska and WebM, both EBML documents.
+//!
+//! The segment's `Info` and `Tracks` elements are read, clusters and the
+//! other elements skipped. Files written live, by browsers recording with
+//! `MediaRecorder` for one, leave the segment and its clusters with an
+//! unknown size and carry no duration.
+
+use bytes::Bytes;
+use futures::Stream;
+use super::{Probe, ProbeError, Reader};
+
+const EBML: u32 = 0x1A45DFA3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+
+const CLUSTER: u32 = 0x1F43B675;
+
+/// Elements found directly in a segment, which end a cluster of unknown size.
+const TOP_LEVEL: &[u32] = &[
+    0x114D9B74, INFO, TRACKS, CLUSTER, 0x1C53BB6B, 0x1043A770, 0x1254C367, 0x1941A469,
+];
+
+/// Largest EBML header, `Info` or `Tracks` element read.
+const MAX_HEADER_SIZE: u64 = 4096;
+const MAX_ELEMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Nanoseconds per timestamp unit, unless the file says otherwise.
+const DEFAULT_TIMESTAMP_SCALE: u64 = 1_000_000;
+
+/// The DocType of the EBML header at the start of `head`, `webm` or
+/// `matroska` for the files we accept.
+pub(crate) fn doc_type(head: &[u8]) -> Result<Option<String>, ProbeError> {
+    let (id, size, header_length) = element_header(head)?;
+    if id != EBML {
+        return Err(ProbeError::Corrupt("no EBML header"));
+    }
+    let size = size.ok_or(ProbeError::Corrupt("EBML header of unknown size"))?;
+    let body = head.get(header_length..header_length + size as usize).ok_or(ProbeError::Truncated)?;
+    header_doc_type(body)
+}
+
+fn header_doc_type(header: &[u8]) -> Result<Option<String>, ProbeError> {
+    Ok(elements(header)?.into_iter()
+        .find(|(id, _)| *id == DOC_TYPE)
+        .map(|(_, value)| string(value)))
+}
+
+pub(super) async fn probe<S>(reader: &mut Reader<S>) -> Result<Probe, ProbeError>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    let (id, size) = read_header(reader).await?;
+    match size {
+        Some(size) if id == EBML && size <= MAX_HEADER_SIZE => {
+            header_doc_type(&reader.read(size as usize).await?)?;
+        }
+        _ => return Err(ProbeError::Corrupt("invalid EBML header")),
+    }
+
+    let (id, size) = read_header(reader).await?;
+    if id != SEGMENT {
+        return Err(ProbeError::Corrupt("no segment"));
+    }
+    let end = size.map(|size| reader.position() + size);
+
+    let mut probe = Probe::default();
+    let mut timestamp_scale = DEFAULT_TIMESTAMP_SCALE;
+    let mut duration = None;
+    let mut has_tracks = false;
+    // The element following a cluster of unknown size, read to find its end.
+    let mut next = None;
+
+    loop {
+        let (id, size) = match next.take() {
+            Some(header) => header,
+            None => {
+                let done = match end {
+                    Some(end) => reader.position() >= end,
+                    None => reader.at_end().await,
+                };
+                if done {
+                    break;
+                }
+                read_header(reader).await?
+            }
+        };
+        if let (Some(end), Some(size)) = (end, size) {
+            if reader.position() + size > end {
+                return Err(ProbeError::Corrupt("element runs past its segment"));
+            }
+        }
+
+        match (id, size) {
+            (INFO, Some(size)) if size <= MAX_ELEMENT_SIZE => {
+                for (id, value) in elements(&reader.read(size as usize).await?)? {
+                    match id {
+                        TIMESTAMP_SCALE => timestamp_scale = uint(value)?,
+                        DURATION => duration = Some(float(value)?),
+                        _ => {}
+                    }
+                }
+            }
+            (TRACKS, Some(size)) if size <= MAX_ELEMENT_SIZE => {
+                for (id, entry) in elements(&reader.read(size as usize).await?)? {
+                    if id == TRACK_ENTRY {
+                        read_track(entry, &mut probe)?;
+                        has_tracks = true;
+                    }
+                }
+            }
+            (INFO | TRACKS, Some(_)) => return Err(ProbeError::Corrupt("header element too large")),
+            (CLUSTER, None) => next = skip_cluster(reader, end).await?,
+            (_, Some(size)) => reader.skip(size).await?,
+            (_, None) => return Err(ProbeError::Corrupt("element of unknown size")),
+        }
+    }
+
+    if !has_tracks {
+        return Err(ProbeError::Corrupt("no tracks"));
+    }
+    if timestamp_scale == 0 {
+        return Err(ProbeError::Corrupt("timestamp scale of zero"));
+    }
+    probe.duration = duration.filter(|d| *d > 0.0).map(|d| d * timestamp_scale as f64 / 1e9);
+    Ok(probe)
+}
+
+/// Skips the children of a cluster of unknown size, up to the end of the
+/// segment or the next top-level element, whose header is returned.
+async fn skip_cluster<S>(reader: &mut Reader<S>, end: Option<u64>) -> Result<Option<(u32, Option<u64>)>, ProbeError>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    loop {
+        let done = match end {
+            Some(end) => reader.position() >= end,
+            None => reader.at_end().await,
+        };
+        if done {
+            return Ok(None);
+        }
+        let (id, size) = read_header(reader).await?;
+        if TOP_LEVEL.contains(&id) {
+            return Ok(Some((id, size)));
+        }
+        match size {
+            Some(size) => reader.skip(size).await?,
+            None => return Err(ProbeError::Corrupt("cluster child of unknown size")),
+        }
+    }
+}
+
+fn read_track(entry: &[u8], probe: &mut Probe) -> Result<(), ProbeError> {
+    let mut track_type = 0;
+    let mut codec_id = None;
+    let mut video = None;
+    let mut audio = None;
+    for (id, value) in elements(entry)? {
+        match id {
+            TRACK_TYPE => track_type = uint(value)?,
+            CODEC_ID => codec_id = Some(string(value)),
+            VIDEO => video = Some(value),
+            AUDIO => audio = Some(value),
+            _ => {}
+        }
+    }
+    let codec = codec_id.as_deref().map(codec);
+
+    match track_type {
+        1 if probe.video_codec.is_none() => {
+            probe.video_codec = codec;
+            for (id, value) in elements(video.unwrap_or_default())? {
+                match id {
+                    PIXEL_WIDTH => probe.width = Some(uint(value)?.try_into().map_err(|_| ProbeError::Corrupt("width out of range"))?),
+                    PIXEL_HEIGHT => probe.height = Some(uint(value)?.try_into().map_err(|_| ProbeError::Corrupt("height out of range"))?),
+                    _ => {}
+                }
+            }
+        }
+        2 if probe.audio_codec.is_none() => {
+            probe.audio_codec = codec;
+            probe.sample_rate = Some(8000);
+            probe.channels = Some(1);
+            for (id, value) in elements(audio.unwrap_or_default())? {
+                match id {
+                    SAMPLING_FREQUENCY => probe.sample_rate = Some(float(value)?.round() as u32),
+                    CHANNELS => probe.channels = Some(uint(value)?.min(u16::MAX as u64) as u16),
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Codec name of a Matroska codec ID, e.g. `vp9` for `V_VP9`.
+fn codec(codec_id: &str) -> String {
+    let name = match codec_id {
+        "V_VP8" => "vp8",
+        "V_VP9" => "vp9",
+        "V_AV1" => "av1",
+        "V_MPEG4/ISO/AVC" => "h264",
+        "V_MPEGH/ISO/HEVC" => "hevc",
+        "A_OPUS" => "opus",
+        "A_VORBIS" => "vorbis",
+        "A_MPEG/L3" => "mp3",
+        "A_FLAC" => "flac",
+        "A_AC3" => "ac3",
+        "A_EAC3" => "eac3",
+        id if id.starts_with("A_AAC") => "aac",
+        id if id.starts_with("A_PCM") => "pcm",
+        id => return id.to_ascii_lowercase(),
+    };
+    name.to_string()
+}
+
+async fn read_header<S>(reader: &mut Reader<S>) -> Result<(u32, Option<u64>), ProbeError>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    let mut header = reader.read(1).await?;
+    let id_length = id_length(header[0]).ok_or(ProbeError::Corrupt("invalid element ID"))?;
+    header.extend(reader.read(id_length - 1).await?);
+    let first = reader.read(1).await?[0];
+    let size_length = size_length(first).ok_or(ProbeError::Corrupt("invalid element size"))?;
+    header.push(first);
+    header.extend(reader.read(size_length - 1).await?);
+    let (id, size, _) = element_header(&header)?;
+    Ok((id, size))
+}
+
+fn id_length(first: u8) -> Option<usize> {
+    let length = first.leading_zeros() as usize + 1;
+    (length <= 4).then_some(length)
+}
+
+fn size_length(first: u8) -> Option<usize> {
+    let length = first.leading_zeros() as usize + 1;
+    (length <= 8).then_some(length)
+}
+
+/// ID, size (`None` when unknown) and header length of the element at the
+/// start of `data`.
+fn element_header(data: &[u8]) -> Result<(u32, Option<u64>, usize), ProbeError> {
+    let first = *data.first().ok_or(ProbeError::Truncated)?;
+    let id_length = id_length(first).ok_or(ProbeError::Corrupt("invalid element ID"))?;
+    let id = data.get(..id_length).ok_or(ProbeError::Truncated)?
+        .iter().fold(0u32, |id, &b| (id << 8) | b as u32);
+
+    let first = *data.get(id_length).ok_or(ProbeError::Truncated)?;
+    let size_length = size_length(first).ok_or(ProbeError::Corrupt("invalid element size"))?;
+    let bytes = data.get(id_length..id_length + size_length).ok_or(ProbeError::Truncated)?;
+    let marker_mask = (0xFFu16 >> size_length) as u8;
+    let size = bytes[1..].iter().fold((first & marker_mask) as u64, |size, &b| (size << 8) | b as u64);
+    // All ones means the size is unknown.
+    let unknown = size == (1u64 << (7 * size_length)) - 1;
+    Ok((id, (!unknown).then_some(size), id_length + size_length))
+}
+
+/// The child elements of a fully read element.
+fn elements(mut data: &[u8]) -> Result<Vec<(u32, &[u8])>, ProbeError> {
+    let mut children = Vec::new();
+    while !data.is_empty() {
+        let (id, size, header_length) = element_header(data)
+            .map_err(|_| ProbeError::Corrupt("invalid child element"))?;
+        let size = size.ok_or(ProbeError::Corrupt("child element of unknown size"))?;
+        let end = (header_length as u64).checked_add(size)
+            .filter(|end| *end <= data.len() as u64)
+            .ok_or(ProbeError::Corrupt("child element runs past its parent"))? as usize;
+        children.push((id, &data[header_length..end]));
+        data = &data[end..];
+    }
+    Ok(children)
+}
+
+fn uint(value: &[u8]) -> Result<u64, ProbeError> {
+    if value.len() > 8 {
+        return Err(ProbeError::Corrupt("integer too long"));
+    }
+    Ok(value.iter().fold(0, |n, &b| (n << 8) | b as u64))
+}
+
+fn float(value: &[u8]) -> Result<f64, ProbeError> {
+    match value.len() {
+        0 => Ok(0.0),
+        4 => Ok(f32::from_be_bytes(value.try_into().unwrap()) as f64),
+        8 => Ok(f64::from_be_bytes(value.try_into().unwrap())),
+        _ => Err(ProbeError::Corrupt("invalid float")),
+    }
+}
+
+/// Strings may be padded with zeros.
+fn string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).trim_end_matches('\0').to_string()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::format::MediaFormat;
+    use crate::probe::tests::{assert_rejects_truncated, probe_bytes};
+
+    const UNKNOWN: Option<usize> = None;
+
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        sized_element(id, Some(body.len()), body)
+    }
+
+    /// Sizes are written with eight bytes, as some muxers do.
+    fn sized_element(id: u32, size: Option<usize>, body: &[u8]) -> Vec<u8> {
+        let id = id.to_be_bytes();
+        let mut e = id[id.iter().position(|&b| b != 0).unwrap()..].to_vec();
+        match size {
+            Some(size) => e.extend_from_slice(&(size as u64 | 1 << 56).to_be_bytes()),
+            None => e.extend_from_slice(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+        }
+        e.extend_from_slice(body);
+        e
+    }
+
+    fn tracks() -> Vec<u8> {
+        let video = [
+            element(TRACK_TYPE, &[1]),
+            element(CODEC_ID, b"V_VP9"),
+            element(VIDEO, &[element(PIXEL_WIDTH, &1280u16.to_be_bytes()), element(PIXEL_HEIGHT, &720u16.to_be_bytes())].concat()),
+        ].concat();
+        let audio = [
+            element(TRACK_TYPE, &[2]),
+            element(CODEC_ID, b"A_OPUS"),
+            element(AUDIO, &[element(SAMPLING_FREQUENCY, &48000f64.to_be_bytes()), element(CHANNELS, &[2])].concat()),
+        ].concat();
+        element(TRACKS, &[element(TRACK_ENTRY, &video), element(TRACK_ENTRY, &audio)].concat())
+    }
+
+    /// A WebM file lasting 5 s, with a 1280x720 VP9 track, a stereo 48 kHz
+    /// Opus track and `media_size` bytes of blocks in one cluster.
+    pub(crate) fn webm(media_size: usize) -> Vec<u8> {
+        let header = element(EBML, &[element(0x4286, &[1]), element(DOC_TYPE, b"webm")].concat());
+        let info = element(INFO, &[element(TIMESTAMP_SCALE, &[0x0F, 0x42, 0x40]), element(DURATION, &5000f64.to_be_bytes())].concat());
+        let cluster = element(CLUSTER, &[element(0xE7, &[0]), element(0xA3, &vec![0; media_size])].concat());
+        let segment = element(SEGMENT, &[info, tracks(), cluster].concat());
+        [header, segment].concat()
+    }
+
+    /// As written live: segment and clusters of unknown size, no duration.
+    fn live_webm() -> Vec<u8> {
+        let header = element(EBML, &element(DOC_TYPE, b"webm"));
+        let cluster = |blocks: usize| sized_element(CLUSTER, UNKNOWN, &[element(0xE7, &[0]), element(0xA3, &vec![0; blocks])].concat());
+        let segment = sized_element(SEGMENT, UNKNOWN, &[tracks(), cluster(100), cluster(50), element(0x1C53BB6B, &[])].concat());
+        [header, segment].concat()
+    }
+
+    #[test]
+    fn test_doc_type() {
+        let file = webm(10);
+        assert_eq!(doc_type(&file), Ok(Some("webm".to_string())));
+        assert_eq!(doc_type(&file[..20]), Err(ProbeError::Truncated));
+        assert_eq!(doc_type(b"\x1A\x45\xDF\xA3\x85\x42\x82\x83ab"), Err(ProbeError::Corrupt("child element runs past its parent")));
+    }
+
+    #[test]
+    fn test_probe_webm() {
+        let file = webm(20_000);
+        let probe = probe_bytes(MediaFormat::Webm, &file).unwrap().unwrap();
+        assert_eq!(probe.duration, Some(5.0));
+        assert_eq!((probe.width, probe.height), (Some(1280), Some(720)));
+        assert_eq!(probe.video_codec.as_deref(), Some("vp9"));
+        assert_eq!(probe.audio_codec.as_deref(), Some("opus"));
+        assert_eq!(probe.sample_rate, Some(48000));
+        assert_eq!(probe.channels, Some(2));
+        assert_eq!(probe.bitrate, Some(file.len() as u64 * 8 / 5));
+
+        let probe = probe_bytes(MediaFormat::Webm, &live_webm()).unwrap().unwrap();
+        assert_eq!(probe.duration, None);
+        assert_eq!(probe.bitrate, None);
+        assert_eq!(probe.video_codec.as_deref(), Some("vp9"));
+    }
+
+    #[test]
+    fn test_probe_webm_rejects_broken_files() {
+        let file = webm(1000);
+        assert_rejects_truncated(MediaFormat::Webm, &file, &[3, 10, 40, 100, file.len() - 1000, file.len() - 1]);
+        let live = live_webm();
+        assert_rejects_truncated(MediaFormat::Webm, &live, &[live.len() - 1, live.len() - 100]);
+
+        // Tracks are required.
+        let header = element(EBML, &element(DOC_TYPE, b"webm"));
+        let without_tracks = [header.clone(), element(SEGMENT, &element(INFO, &[]))].concat();
+        assert_eq!(probe_bytes(MediaFormat::Webm, &without_tracks).unwrap(), Err(ProbeError::Corrupt("no tracks")));
+
+        // An element of unknown size other than a cluster
+        let unknown = [header.clone(), element(SEGMENT, &[tracks(), sized_element(INFO, UNKNOWN, &[])].concat())].concat();
+        assert!(matches!(probe_bytes(MediaFormat::Webm, &unknown).unwrap(), Err(ProbeError::Corrupt(_))));
+
+        // A child running past the end of its segment
+        let mut broken = [header, element(SEGMENT, &tracks())].concat();
+        let len = broken.len();
+        broken[len - tracks().len() + 11] += 1;
+        assert!(matches!(probe_bytes(MediaFormat::Webm, &broken).unwrap(), Err(ProbeError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,243 @@
+//! Container probing: duration, resolution, codecs and audio parameters of
+//! uploaded audio and video, read while the upload streams to storage.
+//!
+//! Probers read a file front to back through a [`Reader`], keeping only the
+//! headers they need and skipping the rest. Every structure must be complete:
+//! a file that ends early is [`ProbeError::Truncated`], one whose structure
+//! does not add up [`ProbeError::Corrupt`].
+
+mod flac;
+pub(crate) mod matroska;
+mod mp3;
+mod mp4;
+mod wav;
+
+use std::fmt;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use crate::format::MediaFormat;
+
+/// What a prober found. Unknown or absent properties are `None`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Probe {
+    /// Seconds.
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    /// Average over the whole file, in bits per second.
+    pub bitrate: Option<u64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ProbeError {
+    /// The file ends inside a structure.
+    Truncated,
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::Truncated => write!(f, "truncated"),
+            ProbeError::Corrupt(reason) => write!(f, "corrupt: {}", reason),
+        }
+    }
+}
+
+/// Probes a file of `format` arriving as `chunks`, or returns `None` straight
+/// away for formats without a prober.
+pub(crate) async fn probe<S>(format: MediaFormat, chunks: S) -> Option<Result<Probe, ProbeError>>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    let mut reader = Reader::new(chunks);
+    let probed = match format {
+        MediaFormat::Mp4 => mp4::probe(&mut reader).await,
+        MediaFormat::Webm | MediaFormat::Matroska => matroska::probe(&mut reader).await,
+        MediaFormat::Mp3 => mp3::probe(&mut reader).await,
+        MediaFormat::Wav => wav::probe(&mut reader).await,
+        MediaFormat::Flac => flac::probe(&mut reader).await,
+        _ => return None,
+    };
+
+    let mut probe = match probed {
+        Ok(probe) => probe,
+        Err(e) => return Some(Err(e)),
+    };
+    // Probers may stop at the end of the container; anything after it counts too.
+    reader.skip_to_end().await;
+    if let Some(duration) = probe.duration.filter(|d| *d > 0.0) {
+        probe.bitrate = Some((reader.position() as f64 * 8.0 / duration).round() as u64);
+    }
+    Some(Ok(probe))
+}
+
+/// Reads a file sequentially from its chunks.
+pub(crate) struct Reader<S> {
+    chunks: S,
+    current: Bytes,
+    position: u64,
+}
+
+impl<S: Stream<Item = Bytes> + Unpin> Reader<S> {
+    fn new(chunks: S) -> Self {
+        Self { chunks, current: Bytes::new(), position: 0 }
+    }
+
+    /// Bytes read or skipped so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Makes `current` non-empty, unless the file has ended.
+    async fn fill(&mut self) -> bool {
+        while self.current.is_empty() {
+            match self.chunks.next().await {
+                Some(chunk) => self.current = chunk,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    pub async fn at_end(&mut self) -> bool {
+        !self.fill().await
+    }
+
+    pub async fn read(&mut self, length: usize) -> Result<Vec<u8>, ProbeError> {
+        // Lengths come from the file; memory is only committed as data arrives.
+        let mut read = Vec::with_capacity(length.min(64 * 1024));
+        while read.len() < length {
+            if !self.fill().await {
+                return Err(ProbeError::Truncated);
+            }
+            let take = (length - read.len()).min(self.current.len());
+            read.extend_from_slice(&self.current.split_to(take));
+            self.position += take as u64;
+        }
+        Ok(read)
+    }
+
+    pub async fn skip(&mut self, mut length: u64) -> Result<(), ProbeError> {
+        while length > 0 {
+            if !self.fill().await {
+                return Err(ProbeError::Truncated);
+            }
+            let take = length.min(self.current.len() as u64) as usize;
+            let _ = self.current.split_to(take);
+            self.position += take as u64;
+            length -= take as u64;
+        }
+        Ok(())
+    }
+
+    pub async fn skip_to_end(&mut self) {
+        while self.fill().await {
+            self.position += self.current.len() as u64;
+            self.current.clear();
+        }
+    }
+
+    /// Reads to the end of the file, keeping the last `limit` bytes after `start`.
+    pub async fn tail(&mut self, start: Vec<u8>, limit: usize) -> Vec<u8> {
+        let mut tail = start;
+        while self.fill().await {
+            let chunk = std::mem::take(&mut self.current);
+            self.position += chunk.len() as u64;
+            tail.extend_from_slice(&chunk);
+            if tail.len() > 2 * limit {
+                tail.drain(..tail.len() - limit);
+            }
+        }
+        if tail.len() > limit {
+            tail.drain(..tail.len() - limit);
+        }
+        tail
+    }
+}
+
+/// Skips an ID3v2 tag, given its first four bytes. MP3 files start with one,
+/// FLAC files can as well.
+async fn skip_id3v2<S>(reader: &mut Reader<S>, start: &[u8]) -> Result<(), ProbeError>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    let rest = reader.read(6).await?;
+    let header = [start, &rest].concat();
+    if header[6..10].iter().any(|b| b & 0x80 != 0) {
+        return Err(ProbeError::Corrupt("invalid ID3 tag size"));
+    }
+    // Sizes are "syncsafe": seven bits per byte.
+    let size = header[6..10].iter().fold(0u64, |size, &b| (size << 7) | b as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    reader.skip(size + footer).await
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use futures::stream;
+
+    pub(crate) use super::flac::tests::flac;
+    pub(crate) use super::matroska::tests::webm;
+    pub(crate) use super::mp3::tests::mp3;
+    pub(crate) use super::mp4::tests::mp4;
+    pub(crate) use super::wav::tests::wav;
+
+    /// Probes `file` fed in small chunks, which splits every structure
+    /// across chunk boundaries somewhere.
+    pub(crate) fn probe_bytes(format: MediaFormat, file: &[u8]) -> Option<Result<Probe, ProbeError>> {
+        let chunks: Vec<Bytes> = file.chunks(7).map(Bytes::copy_from_slice).collect();
+        futures::executor::block_on(probe(format, stream::iter(chunks)))
+    }
+
+    /// Every cut of `file` that ends inside a structure is rejected.
+    pub(crate) fn assert_rejects_truncated(format: MediaFormat, file: &[u8], cuts: &[usize]) {
+        for &cut in cuts {
+            let probed = probe_bytes(format, &file[..cut]).unwrap();
+            assert!(probed.is_err(), "{:?} cut at {} of {}: {:?}", format, cut, file.len(), probed);
+        }
+    }
+
+    #[test]
+    fn test_reader() {
+        let chunks = stream::iter([Bytes::from_static(b"abc"), Bytes::new(), Bytes::from_static(b"defgh")]);
+        let mut reader = Reader::new(chunks);
+        futures::executor::block_on(async {
+            assert_eq!(reader.read(4).await.unwrap(), b"abcd");
+            reader.skip(2).await.unwrap();
+            assert_eq!(reader.position(), 6);
+            assert_eq!(reader.tail(b"xy".to_vec(), 3).await, b"ygh");
+            assert_eq!(reader.position(), 8);
+            assert!(reader.at_end().await);
+            assert_eq!(reader.read(1).await, Err(ProbeError::Truncated));
+            assert_eq!(reader.skip(1).await, Err(ProbeError::Truncated));
+        });
+    }
+
+    #[test]
+    fn test_formats_without_prober() {
+        assert!(probe_bytes(MediaFormat::Ogg, b"OggS").is_none());
+        assert!(probe_bytes(MediaFormat::Jpeg, b"\xFF\xD8\xFF").is_none());
+    }
+}
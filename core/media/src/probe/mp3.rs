@@ -0,0 +1,212 @@
+//! MPEG audio layer III, frame by frame.
+//!
+//! MP3 has no container: the file is a run of frames, optionally preceded by
+//! an ID3v2 tag and followed by an ID3v1 one. The duration is counted from
+//! the frames rather than estimated from the first one, so variable bitrate
+//! files come out right as well.
+
+use bytes::Bytes;
+use futures::Stream;
+use super::{be_u32, skip_id3v2, Probe, ProbeError, Reader};
+
+/// Kilobits per second by bitrate index, for MPEG-1 and for MPEG-2 and 2.5.
+const MPEG1_BITRATES: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const MPEG2_BITRATES: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+const MPEG1_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// Size of the ID3v1 tag at the end of a file, `TAG` included.
+const ID3V1_SIZE: u64 = 128;
+
+#[derive(Debug, PartialEq, Eq)]
+struct FrameHeader {
+    mpeg1: bool,
+    sample_rate: u32,
+    mono: bool,
+    length: usize,
+}
+
+impl FrameHeader {
+    fn parse(header: &[u8]) -> Option<Self> {
+        if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        let version = (header[1] >> 3) & 0b11;
+        let layer = (header[1] >> 1) & 0b11;
+        let bitrate_index = (header[2] >> 4) as usize;
+        let sample_rate_index = ((header[2] >> 2) & 0b11) as usize;
+        if version == 0b01 || layer != 0b01 || bitrate_index == 0 || bitrate_index == 0xF || sample_rate_index == 3 {
+            return None;
+        }
+
+        let mpeg1 = version == 0b11;
+        let sample_rate = match version {
+            0b11 => MPEG1_SAMPLE_RATES[sample_rate_index],
+            0b10 => MPEG1_SAMPLE_RATES[sample_rate_index] / 2,
+            _ => MPEG1_SAMPLE_RATES[sample_rate_index] / 4,
+        };
+        let padding = ((header[2] >> 1) & 1) as usize;
+        let length = if mpeg1 {
+            144_000 * MPEG1_BITRATES[bitrate_index] / sample_rate
+        } else {
+            72_000 * MPEG2_BITRATES[bitrate_index] / sample_rate
+        } as usize + padding;
+        Some(Self { mpeg1, sample_rate, mono: header[3] >> 6 == 0b11, length })
+    }
+
+    fn samples(&self) -> u64 {
+        if self.mpeg1 { 1152 } else { 576 }
+    }
+
+    /// Frames the first frame's Xing, Info or VBRI header says the file has,
+    /// if it is one. Such a frame carries no audio.
+    fn vbr_header_frames(&self, frame: &[u8]) -> Option<Option<u32>> {
+        let side_info = match (self.mpeg1, self.mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9,
+        };
+        let xing = frame.get(4 + side_info..)?;
+        if xing.starts_with(b"Xing") || xing.starts_with(b"Info") {
+            let flags = be_u32(xing.get(4..8)?);
+            return Some((flags & 1 != 0).then(|| xing.get(8..12).map(be_u32)).flatten());
+        }
+        let vbri = frame.get(36..)?;
+        if vbri.starts_with(b"VBRI") {
+            return Some(vbri.get(14..18).map(be_u32));
+        }
+        None
+    }
+}
+
+pub(super) async fn probe<S>(reader: &mut Reader<S>) -> Result<Probe, ProbeError>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    let mut first: Option<FrameHeader> = None;
+    let mut frames = 0u64;
+    let mut announced_frames = None;
+
+    while !reader.at_end().await {
+        let header = reader.read(4).await?;
+        if header.starts_with(b"ID3") && first.is_none() {
+            skip_id3v2(reader, &header).await?;
+            continue;
+        }
+        if header.starts_with(b"TAG") {
+            reader.skip(ID3V1_SIZE - 4).await?;
+            if !reader.at_end().await {
+                return Err(ProbeError::Corrupt("data after the ID3v1 tag"));
+            }
+            break;
+        }
+
+        let frame = FrameHeader::parse(&header).ok_or(ProbeError::Corrupt("lost frame sync"))?;
+        if frame.length < 4 {
+            return Err(ProbeError::Corrupt("invalid frame length"));
+        }
+        match &first {
+            None => {
+                let body = reader.read(frame.length - 4).await?;
+                match frame.vbr_header_frames(&[&header[..], &body].concat()) {
+                    Some(announced) => announced_frames = announced,
+                    None => frames += 1,
+                }
+                first = Some(frame);
+            }
+            Some(first) => {
+                if frame.sample_rate != first.sample_rate || frame.mpeg1 != first.mpeg1 {
+                    return Err(ProbeError::Corrupt("sample rate changes"));
+                }
+                reader.skip(frame.length as u64 - 4).await?;
+                frames += 1;
+            }
+        }
+    }
+
+    let first = first.ok_or(ProbeError::Corrupt("no frames"))?;
+    if frames == 0 {
+        return Err(ProbeError::Corrupt("no frames"));
+    }
+    // Encoders disagree on whether the count includes the Xing frame itself.
+    if announced_frames.is_some_and(|announced| frames + 1 < announced as u64) {
+        return Err(ProbeError::Truncated);
+    }
+    Ok(Probe {
+        duration: Some((frames * first.samples()) as f64 / first.sample_rate as f64),
+        audio_codec: Some("mp3".to_string()),
+        sample_rate: Some(first.sample_rate),
+        channels: Some(if first.mono { 1 } else { 2 }),
+        ..Probe::default()
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::format::MediaFormat;
+    use crate::probe::tests::{assert_rejects_truncated, probe_bytes};
+
+    /// MPEG-1 layer III, 128 kbit/s, 44.1 kHz, joint stereo, no padding.
+    const HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x64];
+    const FRAME_LENGTH: usize = 417;
+
+    /// An ID3v2 tag, a Xing header announcing `frames`, the frames and an
+    /// ID3v1 tag.
+    pub(crate) fn mp3(frames: usize) -> Vec<u8> {
+        let mut file = b"ID3\x04\x00\x00\x00\x00\x00\x0A".to_vec();
+        file.extend_from_slice(&[0; 10]);
+
+        let mut xing = HEADER.to_vec();
+        xing.extend_from_slice(&[0; 32]);
+        xing.extend_from_slice(b"Xing");
+        xing.extend_from_slice(&1u32.to_be_bytes());
+        xing.extend_from_slice(&(frames as u32).to_be_bytes());
+        xing.resize(FRAME_LENGTH, 0);
+        file.extend_from_slice(&xing);
+
+        for _ in 0..frames {
+            file.extend_from_slice(&HEADER);
+            file.extend_from_slice(&[0x55; FRAME_LENGTH - 4]);
+        }
+        let mut tag = b"TAG".to_vec();
+        tag.resize(ID3V1_SIZE as usize, b' ');
+        file.extend_from_slice(&tag);
+        file
+    }
+
+    #[test]
+    fn test_probe_mp3() {
+        let file = mp3(100);
+        let probe = probe_bytes(MediaFormat::Mp3, &file).unwrap().unwrap();
+        assert_eq!(probe.duration, Some(115_200.0 / 44100.0));
+        assert_eq!(probe.audio_codec.as_deref(), Some("mp3"));
+        assert_eq!(probe.sample_rate, Some(44100));
+        assert_eq!(probe.channels, Some(2));
+        assert_eq!(probe.video_codec, None);
+        assert_eq!(probe.bitrate, Some((file.len() as f64 * 8.0 / (115_200.0 / 44100.0)).round() as u64));
+
+        // Without tags or a Xing header
+        let bare = &file[20 + FRAME_LENGTH..file.len() - ID3V1_SIZE as usize];
+        assert_eq!(probe_bytes(MediaFormat::Mp3, bare).unwrap().unwrap().duration, probe.duration);
+    }
+
+    #[test]
+    fn test_probe_mp3_rejects_broken_files() {
+        let file = mp3(10);
+        let audio_end = file.len() - ID3V1_SIZE as usize;
+        // Cutting between frames is noticed thanks to the Xing header.
+        assert_rejects_truncated(MediaFormat::Mp3, &file, &[5, 15, 30, 500, audio_end - 2 * FRAME_LENGTH, audio_end - 1, audio_end + 2, file.len() - 1]);
+
+        let mut lost_sync = file.clone();
+        lost_sync[20 + 3 * FRAME_LENGTH] = 0;
+        assert_eq!(probe_bytes(MediaFormat::Mp3, &lost_sync).unwrap(), Err(ProbeError::Corrupt("lost frame sync")));
+
+        let mut trailing = file.clone();
+        trailing.extend_from_slice(b"junk");
+        assert_eq!(probe_bytes(MediaFormat::Mp3, &trailing).unwrap(), Err(ProbeError::Corrupt("data after the ID3v1 tag")));
+
+        let only_xing = &file[..20 + FRAME_LENGTH];
+        assert_eq!(probe_bytes(MediaFormat::Mp3, only_xing).unwrap(), Err(ProbeError::Corrupt("no frames")));
+    }
+}
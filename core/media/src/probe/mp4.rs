@@ -0,0 +1,314 @@
+//! ISO base media files: MP4, M4A and QuickTime MOV.
+//!
+//! Everything of interest is in the `moov` box, which is read into memory;
+//! the other top-level boxes, `mdat` above all, are skipped.
+
+use bytes::Bytes;
+use futures::Stream;
+use super::{be_u16, be_u32, Probe, ProbeError, Reader};
+
+/// Largest `moov` box read. Hours of video with many tracks stay well below.
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+pub(super) async fn probe<S>(reader: &mut Reader<S>) -> Result<Probe, ProbeError>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    let mut moov = None;
+    let mut has_media_data = false;
+
+    while !reader.at_end().await {
+        let header = reader.read(8).await?;
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+        let (size, header_size) = match be_u32(&header) {
+            1 => (u64::from_be_bytes(reader.read(8).await?.try_into().unwrap()), 16),
+            size => (size as u64, 8),
+        };
+
+        if size == 0 {
+            // The last box runs to the end of the file.
+            if &kind == b"moov" {
+                let body = reader.tail(Vec::new(), MAX_MOOV_SIZE as usize + 1).await;
+                if body.len() as u64 > MAX_MOOV_SIZE {
+                    return Err(ProbeError::Corrupt("moov box too large"));
+                }
+                moov = Some(body);
+            } else {
+                has_media_data |= &kind == b"mdat";
+                reader.skip_to_end().await;
+            }
+            break;
+        }
+        if size < header_size {
+            return Err(ProbeError::Corrupt("box smaller than its header"));
+        }
+        let body_size = size - header_size;
+        match &kind {
+            b"moov" if moov.is_some() => return Err(ProbeError::Corrupt("several moov boxes")),
+            b"moov" if body_size > MAX_MOOV_SIZE => return Err(ProbeError::Corrupt("moov box too large")),
+            b"moov" => moov = Some(reader.read(body_size as usize).await?),
+            _ => {
+                has_media_data |= &kind == b"mdat";
+                reader.skip(body_size).await?;
+            }
+        }
+    }
+
+    let moov = moov.ok_or(ProbeError::Corrupt("no moov box"))?;
+    if !has_media_data {
+        return Err(ProbeError::Truncated);
+    }
+    let probe = probe_moov(&moov, reader.position())?;
+    Ok(probe)
+}
+
+/// Reads the movie header and the tracks of a `moov` box, checking that the
+/// samples lie within the `file_size` bytes received.
+fn probe_moov(moov: &[u8], file_size: u64) -> Result<Probe, ProbeError> {
+    let mut probe = Probe::default();
+
+    let mvhd = child(moov, b"mvhd")?.ok_or(ProbeError::Corrupt("no mvhd box"))?;
+    let (timescale, duration) = match mvhd.first() {
+        Some(0) if mvhd.len() >= 20 => (be_u32(&mvhd[12..16]) as u64, be_u32(&mvhd[16..20]) as u64),
+        Some(1) if mvhd.len() >= 32 => (
+            be_u32(&mvhd[20..24]) as u64,
+            u64::from_be_bytes(mvhd[24..32].try_into().unwrap()),
+        ),
+        _ => return Err(ProbeError::Corrupt("invalid mvhd box")),
+    };
+    if timescale == 0 {
+        return Err(ProbeError::Corrupt("mvhd timescale of zero"));
+    }
+    // Fragmented files leave the movie duration at zero and give it in mehd.
+    let duration = match fragment_duration(moov)? {
+        Some(fragments) if duration == 0 => fragments,
+        _ => duration,
+    };
+    if duration != 0 && duration != u32::MAX as u64 && duration != u64::MAX {
+        probe.duration = Some(duration as f64 / timescale as f64);
+    }
+
+    let mut tracks = 0;
+    for (kind, trak) in children(moov)? {
+        if kind != b"trak" {
+            continue;
+        }
+        tracks += 1;
+        let mdia = child(trak, b"mdia")?.ok_or(ProbeError::Corrupt("trak without mdia"))?;
+        let hdlr = child(mdia, b"hdlr")?.ok_or(ProbeError::Corrupt("mdia without hdlr"))?;
+        let handler = hdlr.get(8..12).ok_or(ProbeError::Corrupt("invalid hdlr box"))?;
+        let Some(stbl) = child(mdia, b"minf")?.map(|minf| child(minf, b"stbl")).transpose()?.flatten() else {
+            return Err(ProbeError::Corrupt("mdia without stbl"));
+        };
+        check_chunk_offsets(stbl, file_size)?;
+
+        let stsd = child(stbl, b"stsd")?.ok_or(ProbeError::Corrupt("stbl without stsd"))?;
+        let Some((format, entry)) = stsd.get(8..).map(children).transpose()?.and_then(|e| e.into_iter().next()) else {
+            continue;
+        };
+        match handler {
+            b"vide" if probe.video_codec.is_none() => {
+                let size = entry.get(24..28).ok_or(ProbeError::Corrupt("invalid video sample entry"))?;
+                probe.video_codec = Some(codec(format));
+                probe.width = Some(be_u16(&size[0..2]) as u32);
+                probe.height = Some(be_u16(&size[2..4]) as u32);
+            }
+            b"soun" if probe.audio_codec.is_none() => {
+                let fields = entry.get(..28).ok_or(ProbeError::Corrupt("invalid audio sample entry"))?;
+                probe.audio_codec = Some(codec(format));
+                // QuickTime sound descriptions of version 2 move both to a later field.
+                if be_u16(&fields[8..10]) == 2 {
+                    let v2 = entry.get(28..44).ok_or(ProbeError::Corrupt("invalid audio sample entry"))?;
+                    probe.sample_rate = Some(f64::from_be_bytes(v2[4..12].try_into().unwrap()).round() as u32);
+                    probe.channels = Some(be_u32(&v2[12..16]).min(u16::MAX as u32) as u16);
+                } else {
+                    probe.channels = Some(be_u16(&fields[16..18]));
+                    probe.sample_rate = Some(be_u32(&fields[24..28]) >> 16);
+                }
+            }
+            _ => {}
+        }
+    }
+    if tracks == 0 {
+        return Err(ProbeError::Corrupt("no tracks"));
+    }
+    Ok(probe)
+}
+
+/// Duration of a fragmented movie, in `mvex/mehd`.
+fn fragment_duration(moov: &[u8]) -> Result<Option<u64>, ProbeError> {
+    let Some(mehd) = child(moov, b"mvex")?.map(|mvex| child(mvex, b"mehd")).transpose()?.flatten() else {
+        return Ok(None);
+    };
+    match mehd.first() {
+        Some(0) if mehd.len() >= 8 => Ok(Some(be_u32(&mehd[4..8]) as u64)),
+        Some(1) if mehd.len() >= 12 => Ok(Some(u64::from_be_bytes(mehd[4..12].try_into().unwrap()))),
+        _ => Err(ProbeError::Corrupt("invalid mehd box")),
+    }
+}
+
+/// Chunks of samples pointing past the end of the file mean it was cut short.
+fn check_chunk_offsets(stbl: &[u8], file_size: u64) -> Result<(), ProbeError> {
+    let (table, width) = match (child(stbl, b"stco")?, child(stbl, b"co64")?) {
+        (Some(stco), _) => (stco, 4),
+        (None, Some(co64)) => (co64, 8),
+        // Fragmented files keep their samples in moof boxes.
+        (None, None) => return Ok(()),
+    };
+    let count = table.get(4..8).map(be_u32).ok_or(ProbeError::Corrupt("invalid chunk offset box"))? as usize;
+    let offsets = table.get(8..8 + count * width).ok_or(ProbeError::Corrupt("invalid chunk offset box"))?;
+    for offset in offsets.chunks(width) {
+        let offset = if width == 4 { be_u32(offset) as u64 } else { u64::from_be_bytes(offset.try_into().unwrap()) };
+        if offset >= file_size {
+            return Err(ProbeError::Truncated);
+        }
+    }
+    Ok(())
+}
+
+/// Type and body of each box.
+type Boxes<'a> = Vec<(&'a [u8; 4], &'a [u8])>;
+
+/// The boxes in `data`, which must be exactly filled by them.
+fn children(mut data: &[u8]) -> Result<Boxes<'_>, ProbeError> {
+    let mut boxes = Vec::new();
+    while !data.is_empty() {
+        let header = data.get(..8).ok_or(ProbeError::Corrupt("box header cut short"))?;
+        let kind: &[u8; 4] = header[4..8].try_into().unwrap();
+        let (size, header_size) = match be_u32(header) {
+            0 => (data.len() as u64, 8),
+            1 => {
+                let large = data.get(8..16).ok_or(ProbeError::Corrupt("box header cut short"))?;
+                (u64::from_be_bytes(large.try_into().unwrap()), 16)
+            }
+            size => (size as u64, 8),
+        };
+        if size < header_size || size > data.len() as u64 {
+            return Err(ProbeError::Corrupt("box size out of bounds"));
+        }
+        boxes.push((kind, &data[header_size as usize..size as usize]));
+        data = &data[size as usize..];
+    }
+    Ok(boxes)
+}
+
+/// Body of the first `kind` box in `data`.
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Result<Option<&'a [u8]>, ProbeError> {
+    Ok(children(data)?.into_iter().find(|(k, _)| *k == kind).map(|(_, body)| body))
+}
+
+/// Codec name of a sample entry, e.g. `h264` for `avc1`.
+fn codec(format: &[u8; 4]) -> String {
+    let name = match format {
+        b"avc1" | b"avc3" => "h264",
+        b"hvc1" | b"hev1" => "hevc",
+        b"av01" => "av1",
+        b"vp08" => "vp8",
+        b"vp09" => "vp9",
+        b"mp4v" => "mpeg4",
+        b"mp4a" => "aac",
+        b"Opus" => "opus",
+        b"fLaC" => "flac",
+        b"ac-3" => "ac3",
+        b"ec-3" => "eac3",
+        b"lpcm" | b"sowt" | b"twos" | b"raw " | b"in24" | b"in32" | b"fl32" | b"fl64" => "pcm",
+        _ => return String::from_utf8_lossy(format).trim().to_ascii_lowercase(),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::format::MediaFormat;
+    use crate::probe::tests::{assert_rejects_truncated, probe_bytes};
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut b = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(body);
+        b
+    }
+
+    fn full_box(kind: &[u8; 4], version: u8, body: &[u8]) -> Vec<u8> {
+        mp4_box(kind, &[&[version, 0, 0, 0], body].concat())
+    }
+
+    fn track(handler: &[u8; 4], entry: Vec<u8>, chunk_offset: u32) -> Vec<u8> {
+        let hdlr = full_box(b"hdlr", 0, &[&[0; 4], &handler[..], &[0; 13]].concat());
+        let stsd = full_box(b"stsd", 0, &[&1u32.to_be_bytes()[..], &entry].concat());
+        let stco = full_box(b"stco", 0, &[1u32.to_be_bytes(), chunk_offset.to_be_bytes()].concat());
+        let stbl = mp4_box(b"stbl", &[stsd, stco].concat());
+        let minf = mp4_box(b"minf", &stbl);
+        let mdia = mp4_box(b"mdia", &[hdlr, minf].concat());
+        mp4_box(b"trak", &mdia)
+    }
+
+    fn video_entry(width: u16, height: u16) -> Vec<u8> {
+        let mut body = vec![0; 78];
+        body[24..26].copy_from_slice(&width.to_be_bytes());
+        body[26..28].copy_from_slice(&height.to_be_bytes());
+        mp4_box(b"avc1", &body)
+    }
+
+    fn audio_entry(channels: u16, sample_rate: u16) -> Vec<u8> {
+        let mut body = vec![0; 28];
+        body[16..18].copy_from_slice(&channels.to_be_bytes());
+        body[18..20].copy_from_slice(&16u16.to_be_bytes());
+        body[24..26].copy_from_slice(&sample_rate.to_be_bytes());
+        mp4_box(b"mp4a", &body)
+    }
+
+    /// A 10 s movie with a 640x360 H.264 track and a stereo 44.1 kHz AAC
+    /// track, whose samples are `media_size` bytes of zeros.
+    pub(crate) fn mp4(media_size: usize) -> Vec<u8> {
+        let ftyp = mp4_box(b"ftyp", b"isom\x00\x00\x02\x00isomiso2avc1mp41");
+        let moov = |offset: u32| {
+            let mut mvhd = vec![0; 96];
+            mvhd[8..12].copy_from_slice(&1000u32.to_be_bytes());
+            mvhd[12..16].copy_from_slice(&10_000u32.to_be_bytes());
+            mp4_box(b"moov", &[
+                full_box(b"mvhd", 0, &mvhd),
+                track(b"vide", video_entry(640, 360), offset),
+                track(b"soun", audio_entry(2, 44100), offset),
+            ].concat())
+        };
+        // Samples start right after the mdat header.
+        let offset = (ftyp.len() + moov(0).len() + 8) as u32;
+        [ftyp, moov(offset), mp4_box(b"mdat", &vec![0; media_size])].concat()
+    }
+
+    #[test]
+    fn test_probe_mp4() {
+        let file = mp4(12_000);
+        let probe = probe_bytes(MediaFormat::Mp4, &file).unwrap().unwrap();
+        assert_eq!(probe.duration, Some(10.0));
+        assert_eq!((probe.width, probe.height), (Some(640), Some(360)));
+        assert_eq!(probe.video_codec.as_deref(), Some("h264"));
+        assert_eq!(probe.audio_codec.as_deref(), Some("aac"));
+        assert_eq!(probe.sample_rate, Some(44100));
+        assert_eq!(probe.channels, Some(2));
+        assert_eq!(probe.bitrate, Some(file.len() as u64 * 8 / 10));
+    }
+
+    #[test]
+    fn test_probe_mp4_rejects_broken_files() {
+        let file = mp4(1000);
+        let mdat = file.len() - 1008;
+        assert_rejects_truncated(MediaFormat::Mp4, &file, &[4, 20, 100, mdat, mdat + 4, mdat + 8, file.len() - 1]);
+
+        // Without the moov box
+        let without_moov = [&file[..32], &file[mdat..]].concat();
+        assert_eq!(probe_bytes(MediaFormat::Mp4, &without_moov).unwrap(), Err(ProbeError::Corrupt("no moov box")));
+
+        // A box smaller than its header
+        let mut broken = file.clone();
+        broken[32..36].copy_from_slice(&4u32.to_be_bytes());
+        assert!(matches!(probe_bytes(MediaFormat::Mp4, &broken).unwrap(), Err(ProbeError::Corrupt(_))));
+
+        // A child box running past the end of moov
+        let mut broken = file.clone();
+        broken[40..44].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(probe_bytes(MediaFormat::Mp4, &broken).unwrap(), Err(ProbeError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,164 @@
+//! RIFF WAVE files: a `fmt ` chunk describing the samples and a `data`
+//! chunk holding them.
+
+use bytes::Bytes;
+use futures::Stream;
+use super::{le_u16, le_u32, Probe, ProbeError, Reader};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_ALAW: u16 = 0x0006;
+const WAVE_FORMAT_MULAW: u16 = 0x0007;
+/// The actual format is the first two bytes of the sub-format GUID.
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+const MAX_FMT_SIZE: u32 = 1024;
+
+pub(super) async fn probe<S>(reader: &mut Reader<S>) -> Result<Probe, ProbeError>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    let header = reader.read(12).await?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(ProbeError::Corrupt("not a RIFF WAVE file"));
+    }
+    let end = 8 + le_u32(&header[4..8]) as u64;
+
+    let mut fmt = None;
+    let mut data_size = None;
+    while reader.position() < end {
+        let chunk = reader.read(8).await?;
+        let size = le_u32(&chunk[4..8]);
+        if reader.position() + size as u64 > end {
+            return Err(ProbeError::Corrupt("chunk runs past the RIFF chunk"));
+        }
+        match &chunk[0..4] {
+            b"fmt " if !(16..=MAX_FMT_SIZE).contains(&size) => return Err(ProbeError::Corrupt("invalid fmt chunk")),
+            b"fmt " => fmt = Some(reader.read(size as usize).await?),
+            b"data" => {
+                reader.skip(size as u64).await?;
+                data_size = Some(size as u64);
+            }
+            _ => reader.skip(size as u64).await?,
+        }
+        // Chunks are padded to an even size; writers often omit the last pad byte.
+        if size % 2 == 1 {
+            if reader.at_end().await {
+                break;
+            }
+            reader.skip(1).await?;
+        }
+    }
+
+    let fmt = fmt.ok_or(ProbeError::Corrupt("no fmt chunk"))?;
+    let data_size = data_size.ok_or(ProbeError::Corrupt("no data chunk"))?;
+    let mut format_tag = le_u16(&fmt[0..2]);
+    if format_tag == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 40 {
+        format_tag = le_u16(&fmt[24..26]);
+    }
+    let channels = le_u16(&fmt[2..4]);
+    let sample_rate = le_u32(&fmt[4..8]);
+    let byte_rate = le_u32(&fmt[8..12]);
+    let bits = le_u16(&fmt[14..16]);
+    if byte_rate == 0 || channels == 0 || sample_rate == 0 {
+        return Err(ProbeError::Corrupt("invalid fmt chunk"));
+    }
+
+    let codec = match format_tag {
+        WAVE_FORMAT_PCM if bits == 8 => Some("pcm_u8".to_string()),
+        WAVE_FORMAT_PCM => Some(format!("pcm_s{}le", bits)),
+        WAVE_FORMAT_IEEE_FLOAT => Some(format!("pcm_f{}le", bits)),
+        WAVE_FORMAT_ALAW => Some("pcm_alaw".to_string()),
+        WAVE_FORMAT_MULAW => Some("pcm_mulaw".to_string()),
+        _ => None,
+    };
+    Ok(Probe {
+        duration: Some(data_size as f64 / byte_rate as f64),
+        audio_codec: codec,
+        sample_rate: Some(sample_rate),
+        channels: Some(channels),
+        ..Probe::default()
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::format::MediaFormat;
+    use crate::probe::tests::{assert_rejects_truncated, probe_bytes};
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut c = id.to_vec();
+        c.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        c.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            c.push(0);
+        }
+        c
+    }
+
+    fn fmt(format_tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        [
+            &format_tag.to_le_bytes()[..],
+            &channels.to_le_bytes(),
+            &sample_rate.to_le_bytes(),
+            &(sample_rate * block_align as u32).to_le_bytes(),
+            &block_align.to_le_bytes(),
+            &bits.to_le_bytes(),
+        ].concat()
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = [&b"WAVE"[..], &chunks.concat()].concat();
+        [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat()
+    }
+
+    /// `seconds` of 16-bit stereo PCM at 8 kHz, after a `LIST` chunk.
+    pub(crate) fn wav(seconds: usize) -> Vec<u8> {
+        riff(&[
+            chunk(b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 8000, 16)),
+            chunk(b"LIST", b"INFOISFT\x05\x00\x00\x00test\x00"),
+            chunk(b"data", &vec![0; seconds * 8000 * 4]),
+        ])
+    }
+
+    #[test]
+    fn test_probe_wav() {
+        let file = wav(2);
+        let probe = probe_bytes(MediaFormat::Wav, &file).unwrap().unwrap();
+        assert_eq!(probe.duration, Some(2.0));
+        assert_eq!(probe.audio_codec.as_deref(), Some("pcm_s16le"));
+        assert_eq!(probe.sample_rate, Some(8000));
+        assert_eq!(probe.channels, Some(2));
+        assert_eq!(probe.bitrate, Some((file.len() as u64 * 8).div_ceil(2)));
+
+        let float = riff(&[chunk(b"fmt ", &fmt(WAVE_FORMAT_IEEE_FLOAT, 1, 48000, 32)), chunk(b"data", &[0; 48])]);
+        let probe = probe_bytes(MediaFormat::Wav, &float).unwrap().unwrap();
+        assert_eq!(probe.audio_codec.as_deref(), Some("pcm_f32le"));
+        assert_eq!(probe.duration, Some(0.00025));
+
+        // Without the pad byte of an odd-sized last chunk
+        let mut unpadded = riff(&[chunk(b"fmt ", &fmt(WAVE_FORMAT_PCM, 1, 8000, 8)), chunk(b"data", &[0x80; 3])]);
+        unpadded.pop();
+        assert!(probe_bytes(MediaFormat::Wav, &unpadded).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_probe_wav_rejects_broken_files() {
+        let file = wav(1);
+        assert_rejects_truncated(MediaFormat::Wav, &file, &[6, 20, 40, 70, file.len() - 1000, file.len() - 1]);
+
+        let without_fmt = riff(&[chunk(b"data", &[0; 16])]);
+        assert_eq!(probe_bytes(MediaFormat::Wav, &without_fmt).unwrap(), Err(ProbeError::Corrupt("no fmt chunk")));
+        let without_data = riff(&[chunk(b"fmt ", &fmt(WAVE_FORMAT_PCM, 2, 8000, 16))]);
+        assert_eq!(probe_bytes(MediaFormat::Wav, &without_data).unwrap(), Err(ProbeError::Corrupt("no data chunk")));
+        let silent = riff(&[chunk(b"fmt ", &fmt(WAVE_FORMAT_PCM, 0, 8000, 16)), chunk(b"data", &[])]);
+        assert_eq!(probe_bytes(MediaFormat::Wav, &silent).unwrap(), Err(ProbeError::Corrupt("invalid fmt chunk")));
+
+        let mut overlong = file.clone();
+        let data = overlong.len() - 8000 * 4 - 4;
+        overlong[data..data + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(probe_bytes(MediaFormat::Wav, &overlong).unwrap(), Err(ProbeError::Corrupt("chunk runs past the RIFF chunk")));
+    }
+}
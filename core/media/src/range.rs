@@ -0,0 +1,127 @@
+//! Conditional and range requests for stored files (RFC 9110, sections 13 and 14).
+//!
+//! Stored files never change, so their SHA-256 is a strong validator and the
+//! upload time their `Last-Modified`.
+
+use std::ops::Range as ByteRange;
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::http::header::{self, EntityTag, Header, IfModifiedSince, IfNoneMatch, IfRange, Range};
+use actix_web::HttpRequest;
+use bytes::Bytes;
+use uuid::Uuid;
+
+/// Requests for more ranges than this get the whole file.
+const MAX_RANGES: usize = 16;
+
+/// What to answer a `GET` with.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Selection {
+    /// 200 with the whole file.
+    Full,
+    /// 304, the client's copy is current.
+    NotModified,
+    /// 206 with these ranges, sorted and merged where they overlap or touch.
+    Partial(Vec<ByteRange<u64>>),
+    /// 416, no requested range lies within the file.
+    Unsatisfiable,
+}
+
+pub(crate) fn select(req: &HttpRequest, etag: &EntityTag, last_modified: SystemTime, size: u64) -> Selection {
+    if not_modified(req, etag, last_modified) {
+        return Selection::NotModified;
+    }
+
+    // Malformed ranges and other units are ignored, as RFC 9110 asks.
+    let specs = match Range::parse(req) {
+        Ok(Range::Bytes(specs)) if !specs.is_empty() && specs.len() <= MAX_RANGES => specs,
+        _ => return Selection::Full,
+    };
+    if !if_range_matches(req, etag, last_modified) {
+        return Selection::Full;
+    }
+
+    let mut ranges: Vec<ByteRange<u64>> = specs.iter()
+        .filter_map(|spec| spec.to_satisfiable_range(size))
+        .map(|(first, last)| first..last + 1)
+        .collect();
+    if ranges.is_empty() {
+        return Selection::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    Selection::Partial(merged)
+}
+
+/// `If-None-Match`, or `If-Modified-Since` without it.
+fn not_modified(req: &HttpRequest, etag: &EntityTag, last_modified: SystemTime) -> bool {
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+            Err(_) => false,
+        };
+    }
+
+    match IfModifiedSince::parse(req) {
+        Ok(IfModifiedSince(since)) => unix_seconds(last_modified) <= unix_seconds(since.into()),
+        Err(_) => false,
+    }
+}
+
+/// Whether the client's partial copy is of this file. Without `If-Range` it is assumed to be.
+fn if_range_matches(req: &HttpRequest, etag: &EntityTag, last_modified: SystemTime) -> bool {
+    if !req.headers().contains_key(header::IF_RANGE) {
+        return true;
+    }
+
+    match IfRange::parse(req) {
+        Ok(IfRange::EntityTag(tag)) => tag.strong_eq(etag),
+        Ok(IfRange::Date(date)) => unix_seconds(date.into()) == unix_seconds(last_modified),
+        Err(_) => false,
+    }
+}
+
+/// HTTP dates have a resolution of one second.
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// A `multipart/byteranges` body: every range preceded by its part header.
+pub(crate) struct ByteRanges {
+    boundary: String,
+    pub parts: Vec<(Bytes, ByteRange<u64>)>,
+    pub end: Bytes,
+}
+
+impl ByteRanges {
+    pub fn new(ranges: Vec<ByteRange<u64>>, content_type: &str, size: u64) -> Self {
+        let boundary = Uuid::new_v4().simple().to_string();
+        let parts = ranges.into_iter()
+            .map(|range| {
+                let head = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, content_type, range.start, range.end - 1, size
+                );
+                (Bytes::from(head), range)
+            })
+            .collect();
+        let end = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+        Self { boundary, parts, end }
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    pub fn content_length(&self) -> u64 {
+        let parts: u64 = self.parts.iter().map(|(head, range)| head.len() as u64 + range.end - range.start).sum();
+        parts + self.end.len() as u64
+    }
+}
@@ -0,0 +1,43 @@
+use std::{collections::HashMap, sync::RwLock};
+use async_trait::async_trait;
+use uuid::Uuid;
+use super::MediaRepository;
+use crate::{error::MediaError, models::Media};
+
+/// Keeps media records in process memory. Intended for tests and local development.
+#[derive(Default)]
+pub struct InMemoryMediaRepository {
+    media: RwLock<HashMap<Uuid, Media>>,
+}
+
+impl InMemoryMediaRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl MediaRepository for InMemoryMediaRepository {
+    async fn insert(&self, media: Media) -> Result<(), MediaError> {
+        let mut records = self.media.write().map_err(|_| MediaError::InternalError)?;
+        records.insert(media.id, media);
+        Ok(())
+    }
+
+    async fn find(&self, id: Uuid) -> Result<Option<Media>, MediaError> {
+        let records = self.media.read().map_err(|_| MediaError::InternalError)?;
+        Ok(records.get(&id).cloned())
+    }
+
+    async fn list_by_user(&self, user_id: i32) -> Result<Vec<Media>, MediaError> {
+        let records = self.media.read().map_err(|_| MediaError::InternalError)?;
+        let mut own: Vec<Media> = records.values().filter(|m| m.user_id == user_id).cloned().collect();
+        own.sort_by_key(|m| (m.created_at, m.id));
+        Ok(own)
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool, MediaError> {
+        let mut records = self.media.write().map_err(|_| MediaError::InternalError)?;
+        Ok(records.remove(&id).is_some())
+    }
+}
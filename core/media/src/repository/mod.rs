@@ -0,0 +1,29 @@
+//! Media record storage.
+//!
+//! [`crate::MediaService`] only talks to [`MediaRepository`]; production uses
+//! [`PgMediaRepository`] and tests use [`InMemoryMediaRepository`]. The files
+//! themselves go to a [`crate::storage::MediaStorage`].
+
+use async_trait::async_trait;
+use uuid::Uuid;
+use crate::{error::MediaError, models::Media};
+
+mod memory;
+mod postgres;
+
+pub use memory::InMemoryMediaRepository;
+pub use postgres::PgMediaRepository;
+
+#[async_trait]
+pub trait MediaRepository: Send + Sync {
+    /// Stores a new record.
+    async fn insert(&self, media: Media) -> Result<(), MediaError>;
+
+    async fn find(&self, id: Uuid) -> Result<Option<Media>, MediaError>;
+
+    /// Records of the user's media, oldest first.
+    async fn list_by_user(&self, user_id: i32) -> Result<Vec<Media>, MediaError>;
+
+    /// Removes a record. Returns `false` if there is no such record.
+    async fn delete(&self, id: Uuid) -> Result<bool, MediaError>;
+}
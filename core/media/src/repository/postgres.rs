@@ -0,0 +1,167 @@
+use actix_web::web;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+    result::Error as DieselError,
+};
+use log::error;
+use uuid::Uuid;
+use super::MediaRepository;
+use crate::{error::MediaError, models::Media, schema::media};
+
+type PgPool = Pool<ConnectionManager<PgConnection>>;
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = media)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct MediaRow {
+    id: Uuid,
+    user_id: i32,
+    file_type: String,
+    url: String,
+    file_name: Option<String>,
+    size: i64,
+    sha256: String,
+    metadata: serde_json::Value,
+    variants: serde_json::Value,
+    description: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<MediaRow> for Media {
+    type Error = MediaError;
+
+    fn try_from(row: MediaRow) -> Result<Self, Self::Error> {
+        let invalid = |e: serde_json::Error| {
+            error!("Invalid media record {}: {}", row.id, e);
+            MediaError::InternalError
+        };
+        Ok(Self {
+            metadata: serde_json::from_value(row.metadata.clone()).map_err(invalid)?,
+            variants: serde_json::from_value(row.variants.clone()).map_err(invalid)?,
+            id: row.id,
+            user_id: row.user_id,
+            file_type: row.file_type,
+            url: row.url,
+            file_name: row.file_name,
+            size: row.size as u64,
+            sha256: row.sha256,
+            description: row.description,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+impl TryFrom<Media> for MediaRow {
+    type Error = MediaError;
+
+    fn try_from(media: Media) -> Result<Self, Self::Error> {
+        let invalid = |e: serde_json::Error| {
+            error!("Failed to serialize media record {}: {}", media.id, e);
+            MediaError::InternalError
+        };
+        Ok(Self {
+            metadata: serde_json::to_value(&media.metadata).map_err(invalid)?,
+            variants: serde_json::to_value(&media.variants).map_err(invalid)?,
+            id: media.id,
+            user_id: media.user_id,
+            file_type: media.file_type,
+            url: media.url,
+            file_name: media.file_name,
+            size: media.size as i64,
+            sha256: media.sha256,
+            description: media.description,
+            created_at: media.created_at,
+            updated_at: media.updated_at,
+        })
+    }
+}
+
+/// Postgres-backed media records. Queries run on actix's blocking thread pool.
+#[derive(Clone)]
+pub struct PgMediaRepository {
+    pool: PgPool,
+}
+
+impl PgMediaRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Opens a connection pool against `database_url`.
+    pub fn connect(database_url: &str, max_connections: u32) -> Result<Self, MediaError> {
+        let manager = ConnectionManager::<PgConnection>::new(database_url);
+        let pool = Pool::builder()
+            .max_size(max_connections)
+            .build(manager)
+            .map_err(|e| {
+                error!("Failed to create Postgres pool: {}", e);
+                MediaError::InternalError
+            })?;
+        Ok(Self::new(pool))
+    }
+
+    async fn run<F, T>(&self, query: F) -> Result<T, MediaError>
+    where
+        F: FnOnce(&mut PgConnection) -> Result<T, DieselError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        web::block(move || {
+            let mut conn = pool.get().map_err(|e| {
+                error!("Failed to get Postgres connection: {}", e);
+                MediaError::InternalError
+            })?;
+            query(&mut conn).map_err(|e| {
+                error!("Postgres query failed: {}", e);
+                MediaError::InternalError
+            })
+        })
+        .await
+        .map_err(|_| MediaError::InternalError)?
+    }
+}
+
+#[async_trait]
+impl MediaRepository for PgMediaRepository {
+    async fn insert(&self, media: Media) -> Result<(), MediaError> {
+        let row = MediaRow::try_from(media)?;
+        self.run(move |conn| diesel::insert_into(media::table).values(&row).execute(conn))
+            .await
+            .map(|_| ())
+    }
+
+    async fn find(&self, id: Uuid) -> Result<Option<Media>, MediaError> {
+        let row = self.run(move |conn| {
+            media::table
+                .find(id)
+                .select(MediaRow::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await?;
+        row.map(Media::try_from).transpose()
+    }
+
+    async fn list_by_user(&self, user_id: i32) -> Result<Vec<Media>, MediaError> {
+        let rows = self.run(move |conn| {
+            media::table
+                .filter(media::user_id.eq(user_id))
+                .order((media::created_at.asc(), media::id.asc()))
+                .select(MediaRow::as_select())
+                .load(conn)
+        })
+        .await?;
+        rows.into_iter().map(Media::try_from).collect()
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<bool, MediaError> {
+        self.run(move |conn| diesel::delete(media::table.find(id)).execute(conn))
+            .await
+            .map(|deleted| deleted > 0)
+    }
+}
@@ -0,0 +1,356 @@
+//! Removal of EXIF, XMP and IPTC metadata from image files, which would
+//! otherwise publish where and with what a photo was taken.
+//!
+//! Files are rewritten without the segments or chunks that carry metadata;
+//! the compressed image data is copied as is. Anything after the end of the
+//! image, such as the extra images of multi-picture JPEGs, is dropped.
+
+use chrono::NaiveDateTime;
+use exif::{In, Tag, Value};
+use crate::format::MediaFormat;
+use crate::models::CameraInfo;
+
+/// PNG chunks with metadata: EXIF, text (which holds XMP, and raw EXIF or
+/// IPTC profiles written by some tools) and the modification time.
+const PNG_METADATA_CHUNKS: &[&[u8; 4]] = &[b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// Flags in the `VP8X` chunk of WebP files announcing `EXIF` and `XMP ` chunks.
+const VP8X_EXIF_AND_XMP: u8 = 0x08 | 0x04;
+
+/// `original` without metadata, or `None` if it is not a well-formed file of
+/// `format`. GIFs carry no EXIF and are returned unchanged.
+pub(crate) fn strip_metadata(original: &[u8], format: MediaFormat) -> Option<Vec<u8>> {
+    match format {
+        MediaFormat::Jpeg => strip_jpeg(original),
+        MediaFormat::Png => strip_png(original),
+        MediaFormat::Webp => strip_webp(original),
+        _ => Some(original.to_vec()),
+    }
+}
+
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = vec![0xFF, 0xD8];
+    let mut pos = 2;
+
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        // Markers may be padded with any number of 0xFF.
+        while *data.get(pos)? == 0xFF {
+            pos += 1;
+        }
+        let marker = data[pos];
+        pos += 1;
+
+        match marker {
+            // End of image
+            0xD9 => {
+                out.extend_from_slice(&[0xFF, 0xD9]);
+                return Some(out);
+            }
+            // Restart markers and TEM stand alone.
+            0xD0..=0xD7 | 0x01 => {
+                out.extend_from_slice(&[0xFF, marker]);
+                continue;
+            }
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize;
+        if length < 2 {
+            return None;
+        }
+        let segment = data.get(pos..pos + length)?;
+        pos += length;
+        if keep_jpeg_segment(marker, &segment[2..]) {
+            out.extend_from_slice(&[0xFF, marker]);
+            out.extend_from_slice(segment);
+        }
+
+        // Start of scan: entropy-coded data follows, up to the next marker
+        // that is neither a stuffed 0xFF00 nor a restart marker.
+        if marker == 0xDA {
+            let start = pos;
+            loop {
+                match *data.get(pos)? {
+                    0xFF => match *data.get(pos + 1)? {
+                        0x00 | 0xD0..=0xD7 => pos += 2,
+                        _ => break,
+                    },
+                    _ => pos += 1,
+                }
+            }
+            out.extend_from_slice(&data[start..pos]);
+        }
+    }
+}
+
+/// Of the application segments and comments only JFIF, ICC profiles and
+/// Adobe's colour transform are kept; they are needed to show the image.
+fn keep_jpeg_segment(marker: u8, payload: &[u8]) -> bool {
+    match marker {
+        0xE0 => payload.starts_with(b"JFIF\0"),
+        0xE2 => payload.starts_with(b"ICC_PROFILE\0"),
+        0xEE => payload.starts_with(b"Adobe"),
+        0xE1..=0xEF | 0xFE => false,
+        _ => true,
+    }
+}
+
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    let signature = data.get(..8)?;
+    let mut out = signature.to_vec();
+    let mut pos = 8;
+
+    loop {
+        let length = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let chunk_type: &[u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+        // Length, type, data and CRC
+        let end = pos.checked_add(length)?.checked_add(12)?;
+        let chunk = data.get(pos..end)?;
+        if !PNG_METADATA_CHUNKS.contains(&chunk_type) {
+            out.extend_from_slice(chunk);
+        }
+        pos = end;
+        if chunk_type == b"IEND" {
+            return Some(out);
+        }
+    }
+}
+
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    if data.get(..4)? != b"RIFF" || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let riff_size = u32::from_le_bytes(data[4..8].try_into().ok()?) as usize;
+    let data = data.get(..riff_size.checked_add(8)?)?;
+    let mut out = b"RIFF\0\0\0\0WEBP".to_vec();
+    let mut pos = 12;
+
+    while pos < data.len() {
+        let fourcc = data.get(pos..pos + 4)?;
+        let size = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        // Chunks are padded to an even size.
+        let end = pos.checked_add(8)?.checked_add(size + (size & 1))?;
+        let chunk = data.get(pos..end)?;
+        match fourcc {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let mut header = chunk.to_vec();
+                *header.get_mut(8)? &= !VP8X_EXIF_AND_XMP;
+                out.extend_from_slice(&header);
+            }
+            _ => out.extend_from_slice(chunk),
+        }
+        pos = end;
+    }
+
+    let riff_size = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(out)
+}
+
+/// Camera make, model and capture time from a raw EXIF chunk, if it has any of them.
+pub(crate) fn camera_info(exif: &[u8]) -> Option<CameraInfo> {
+    let exif = exif::Reader::new().read_raw(exif.to_vec()).ok()?;
+    let text = |tag: Tag| {
+        let field = exif.get_field(tag, In::PRIMARY)?;
+        match &field.value {
+            Value::Ascii(values) => values.first()
+                .map(|value| String::from_utf8_lossy(value).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string())
+                .filter(|value| !value.is_empty()),
+            _ => None,
+        }
+    };
+
+    let captured_at = text(Tag::DateTimeOriginal)
+        .and_then(|time| NaiveDateTime::parse_from_str(&time, "%Y:%m:%d %H:%M:%S").ok())
+        .map(|time| time.format("%Y-%m-%dT%H:%M:%S").to_string());
+    let camera = CameraInfo { make: text(Tag::Make), model: text(Tag::Model), captured_at };
+    (camera.make.is_some() || camera.model.is_some() || camera.captured_at.is_some()).then_some(camera)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use exif::{experimental::Writer, Context, Field, Rational};
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    /// Spelled out in the XMP and IPTC fixtures.
+    pub(crate) const PLACE: &str = "Springfield";
+
+    /// EXIF of a phone photo with the given orientation and where it was taken.
+    pub(crate) fn exif(orientation: u16) -> Vec<u8> {
+        let ascii = |tag, text: &str| Field { tag, ifd_num: In::PRIMARY, value: Value::Ascii(vec![text.as_bytes().to_vec()]) };
+        let rational = |tag, values: &[(u32, u32)]| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(values.iter().map(|&(num, denom)| Rational { num, denom }).collect()),
+        };
+        let fields = [
+            ascii(Tag::Make, "Acme"),
+            ascii(Tag::Model, "Phone 1"),
+            Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![orientation]) },
+            ascii(Tag::DateTimeOriginal, "2024:05:01 12:34:56"),
+            ascii(Tag::GPSLatitudeRef, "N"),
+            rational(Tag::GPSLatitude, &[(37, 1), (46, 1), (2940, 100)]),
+            ascii(Tag::GPSLongitudeRef, "W"),
+            rational(Tag::GPSLongitude, &[(122, 1), (25, 1), (960, 100)]),
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        tiff.into_inner()
+    }
+
+    fn xmp() -> String {
+        format!(
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><exif:GPSLatitude>37,46.49N</exif:GPSLatitude>\
+            <photoshop:City>{}</photoshop:City></x:xmpmeta>",
+            PLACE
+        )
+    }
+
+    pub(crate) fn test_image(format: ImageFormat, width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 64]));
+        let mut encoded = Vec::new();
+        image.write_to(&mut Cursor::new(&mut encoded), format).unwrap();
+        encoded
+    }
+
+    fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let length = (payload.len() + 2) as u16;
+        [&[0xFF, marker][..], &length.to_be_bytes(), payload].concat()
+    }
+
+    /// A JPEG with EXIF, XMP, IPTC and a comment, followed by a second
+    /// image with EXIF of its own, as in multi-picture files.
+    pub(crate) fn jpeg_with_metadata(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+        let image = test_image(ImageFormat::Jpeg, width, height);
+        let iptc = [&b"Photoshop 3.0\08BIM\x04\x04\0\0\0\0\0\x10\x1c\x02\x5a\0\x0b"[..], PLACE.as_bytes()].concat();
+        let metadata = [
+            jpeg_segment(0xE1, &[&b"Exif\0\0"[..], &exif(orientation)].concat()),
+            jpeg_segment(0xE1, &[&b"http://ns.adobe.com/xap/1.0/\0"[..], xmp().as_bytes()].concat()),
+            jpeg_segment(0xED, &iptc),
+            jpeg_segment(0xFE, PLACE.as_bytes()),
+        ].concat();
+        let trailer = [&image[..2], &jpeg_segment(0xE1, &[&b"Exif\0\0"[..], &exif(1)].concat()), &image[2..]].concat();
+        [&image[..2], &metadata, &image[2..], &trailer].concat()
+    }
+
+    fn png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let crc = crc32fast::hash(&[&chunk_type[..], data].concat());
+        [&(data.len() as u32).to_be_bytes()[..], chunk_type, data, &crc.to_be_bytes()].concat()
+    }
+
+    /// A PNG with EXIF, XMP and a text chunk.
+    pub(crate) fn png_with_metadata(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+        let image = test_image(ImageFormat::Png, width, height);
+        // Signature and IHDR
+        let (head, rest) = image.split_at(8 + 25);
+        let metadata = [
+            png_chunk(b"eXIf", &exif(orientation)),
+            png_chunk(b"iTXt", &[&b"XML:com.adobe.xmp\0\0\0\0\0"[..], xmp().as_bytes()].concat()),
+            png_chunk(b"tEXt", &[&b"Location\0"[..], PLACE.as_bytes()].concat()),
+        ].concat();
+        [head, &metadata, rest].concat()
+    }
+
+    fn riff_chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let padding: &[u8] = if data.len() % 2 == 1 { &[0] } else { &[] };
+        [&fourcc[..], &(data.len() as u32).to_le_bytes(), data, padding].concat()
+    }
+
+    /// An extended WebP with EXIF and XMP.
+    pub(crate) fn webp_with_metadata(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+        let image = test_image(ImageFormat::WebP, width, height);
+        let mut vp8x = vec![VP8X_EXIF_AND_XMP, 0, 0, 0];
+        vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        let chunks = [
+            riff_chunk(b"VP8X", &vp8x),
+            image[12..].to_vec(),
+            riff_chunk(b"EXIF", &exif(orientation)),
+            riff_chunk(b"XMP ", xmp().as_bytes()),
+        ].concat();
+        [&b"RIFF"[..], &(chunks.len() as u32 + 4).to_le_bytes(), b"WEBP", &chunks].concat()
+    }
+
+    /// No EXIF GPS tags, and no location in XMP, IPTC or text.
+    pub(crate) fn assert_no_location(file: &[u8]) {
+        if let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(file)) {
+            let gps: Vec<_> = exif.fields().filter(|f| f.tag.context() == Context::Gps).collect();
+            assert!(gps.is_empty(), "GPS tags survived: {:?}", gps);
+        }
+        for needle in ["GPS", PLACE] {
+            assert!(!file.windows(needle.len()).any(|w| w == needle.as_bytes()), "{} survived", needle);
+        }
+    }
+
+    #[test]
+    fn test_fixtures_carry_location() {
+        for (format, file) in [
+            (ImageFormat::Jpeg, jpeg_with_metadata(8, 4, 1)),
+            (ImageFormat::Png, png_with_metadata(8, 4, 1)),
+            (ImageFormat::WebP, webp_with_metadata(8, 4, 1)),
+        ] {
+            let exif = exif::Reader::new().read_from_container(&mut Cursor::new(&file)).unwrap();
+            assert!(exif.get_field(Tag::GPSLatitude, In::PRIMARY).is_some(), "{:?}", format);
+            assert!(file.windows(PLACE.len()).any(|w| w == PLACE.as_bytes()), "{:?}", format);
+            image::load_from_memory_with_format(&file, format).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_strip_metadata() {
+        for (format, image_format, file) in [
+            (MediaFormat::Jpeg, ImageFormat::Jpeg, jpeg_with_metadata(8, 4, 1)),
+            (MediaFormat::Png, ImageFormat::Png, png_with_metadata(8, 4, 1)),
+            (MediaFormat::Webp, ImageFormat::WebP, webp_with_metadata(8, 4, 1)),
+        ] {
+            let stripped = strip_metadata(&file, format).unwrap();
+            assert_no_location(&stripped);
+            assert!(exif::Reader::new().read_from_container(&mut Cursor::new(&stripped)).is_err(), "{:?}", format);
+
+            // The image itself is untouched
+            let before = image::load_from_memory_with_format(&file, image_format).unwrap();
+            let after = image::load_from_memory_with_format(&stripped, image_format).unwrap();
+            assert_eq!(before, after, "{:?}", format);
+        }
+
+        // Files without metadata come out as they went in
+        for (format, image_format) in [(MediaFormat::Png, ImageFormat::Png), (MediaFormat::Webp, ImageFormat::WebP)] {
+            let plain = test_image(image_format, 8, 4);
+            assert_eq!(strip_metadata(&plain, format).unwrap(), plain);
+        }
+    }
+
+    #[test]
+    fn test_strip_metadata_rejects_truncated_files() {
+        for (format, file) in [
+            (MediaFormat::Jpeg, test_image(ImageFormat::Jpeg, 8, 4)),
+            (MediaFormat::Png, test_image(ImageFormat::Png, 8, 4)),
+            (MediaFormat::Webp, test_image(ImageFormat::WebP, 8, 4)),
+        ] {
+            assert!(strip_metadata(&file[..file.len() - 3], format).is_none(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_camera_info() {
+        assert_eq!(camera_info(&exif(1)), Some(CameraInfo {
+            make: Some("Acme".to_string()),
+            model: Some("Phone 1".to_string()),
+            captured_at: Some("2024-05-01T12:34:56".to_string()),
+        }));
+        assert_eq!(camera_info(b"not exif"), None);
+    }
+}
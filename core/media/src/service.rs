@@ -1,22 +1,765 @@
-use actix_multipart::Multipart;
-use chrono::Utc;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::{stream, SinkExt, Stream, StreamExt, TryStreamExt};
+use log::{error, info};
+use sha2::{Digest, Sha256};
+use socialhub_core::{
+    data_subject::{DataSubjectHook, ExportedFile},
+    error::CommonError,
+};
 use uuid::Uuid;
-use crate::models::Media;
+use crate::config::MediaConfig;
+use crate::error::MediaError;
+use crate::format::{MediaFormat, SNIFF_LENGTH};
+use crate::models::{Media, MediaMetadata, MediaVariant};
+use crate::repository::MediaRepository;
+use crate::storage::{hex, ByteStream, MediaStorage};
+use crate::images;
+use crate::probe;
 
-pub struct MediaService;
+/// Chunks buffered between the request and the storage backend.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// A file about to be uploaded.
+#[derive(Debug, Clone)]
+pub struct NewUpload {
+    pub content_type: String,
+    pub file_name: Option<String>,
+    pub description: Option<String>,
+    /// Record the camera make, model and capture time of photos. Their
+    /// other metadata, the location above all, is never kept.
+    pub keep_camera_info: bool,
+}
+
+/// A stored file: the original of some media or one of its variants.
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    key: String,
+    pub content_type: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// A file sent in any number of requests, through the tus protocol.
+#[derive(Debug, Clone)]
+pub struct ResumableUpload {
+    pub id: Uuid,
+    pub user_id: i32,
+    /// Size of the whole file, announced when the upload was created.
+    pub length: u64,
+    /// Bytes received so far.
+    pub offset: u64,
+    pub upload: NewUpload,
+    /// `Upload-Metadata` as the client sent it, returned as is.
+    pub metadata: Option<String>,
+    /// The media the upload turned into, once complete. The upload itself
+    /// is gone by then.
+    pub media_id: Option<Uuid>,
+    /// When the upload is dropped unless another chunk arrives.
+    pub expires_at: DateTime<Utc>,
+    /// Offsets at which the stored chunks start.
+    chunks: Vec<u64>,
+    /// A chunk is being received.
+    busy: bool,
+}
+
+impl ResumableUpload {
+    /// Expired uploads are gone for their user, and removed with their chunks
+    /// by [`MediaService::purge_expired_uploads`]. One receiving a chunk is not.
+    fn is_expired(&self) -> bool {
+        !self.busy && self.expires_at <= Utc::now()
+    }
+}
+
+/// Digest a chunk of a resumable upload must have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub digest: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: &'static [ChecksumAlgorithm] = &[ChecksumAlgorithm::Sha1, ChecksumAlgorithm::Sha256];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|algorithm| algorithm.name() == name)
+    }
+
+    fn hasher(self) -> Box<dyn sha2::digest::DynDigest> {
+        match self {
+            ChecksumAlgorithm::Sha1 => Box::new(sha1::Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Box::new(Sha256::new()),
+        }
+    }
+}
+
+/// Uploaded media. Files are kept in a [`MediaStorage`], the records in a
+/// [`MediaRepository`].
+pub struct MediaService {
+    config: MediaConfig,
+    storage: Arc<dyn MediaStorage>,
+    media: Arc<dyn MediaRepository>,
+    uploads: RwLock<HashMap<Uuid, ResumableUpload>>,
+}
 
 impl MediaService {
-    pub async fn upload_media(_payload: Multipart) -> Result<Media, String> {
-        // Simulação de upload bem-sucedido
+    pub fn new(config: MediaConfig, storage: Arc<dyn MediaStorage>, media: Arc<dyn MediaRepository>) -> Self {
+        Self { config, storage, media, uploads: RwLock::new(HashMap::new()) }
+    }
+
+    pub fn config(&self) -> &MediaConfig {
+        &self.config
+    }
+
+    /// Streams `body` to the storage backend and records it. Fails with
+    /// [`MediaError::TooLarge`] as soon as it exceeds
+    /// [`MediaConfig::max_file_size`], in which case nothing is stored.
+    ///
+    /// The format is detected from the file's signature and must be the one
+    /// `upload.content_type` names, or the upload fails with
+    /// [`MediaError::InvalidFormat`].
+    ///
+    /// Images are read whole and must decode. They are stored upright and
+    /// without EXIF, XMP and IPTC metadata, with a WebP copy for each of
+    /// [`MediaConfig::image_variants`]; see [`NewUpload::keep_camera_info`].
+    /// Audio and video files are probed as they stream to storage, and
+    /// removed again if they turn out truncated or corrupt.
+    ///
+    /// `body` need not be `Send`, which request payloads are not.
+    pub async fn upload<S>(&self, user_id: i32, upload: NewUpload, body: S) -> Result<Media, MediaError>
+    where
+        S: Stream<Item = Result<Bytes, MediaError>> + Unpin,
+    {
+        self.ingest(user_id, upload, body, self.config.max_file_size as u64).await
+    }
+
+    /// [`upload`](Self::upload) with files other than images limited to
+    /// `max_size` bytes rather than [`MediaConfig::max_file_size`].
+    async fn ingest<S>(&self, user_id: i32, upload: NewUpload, body: S, max_size: u64) -> Result<Media, MediaError>
+    where
+        S: Stream<Item = Result<Bytes, MediaError>> + Unpin,
+    {
+        let content_type = upload.content_type.to_ascii_lowercase();
+        let declared = MediaFormat::from_mime_type(&content_type).ok_or(MediaError::InvalidFormat)?;
+
+        // Multipart fields must not be polled again once they have ended.
+        let mut body = body.fuse();
+        let mut head = BytesMut::new();
+        while head.len() < SNIFF_LENGTH {
+            match body.next().await {
+                Some(chunk) => head.extend_from_slice(&chunk?),
+                None => break,
+            }
+        }
+        let format = MediaFormat::detect(&head[..head.len().min(SNIFF_LENGTH)]);
+        if format != Some(declared) {
+            info!(
+                "Rejected upload of user {}: declared {}, detected {}",
+                user_id, content_type, format.map_or("unknown", MediaFormat::name)
+            );
+            return Err(MediaError::InvalidFormat);
+        }
+
+        let id = Uuid::new_v4();
+        let mut metadata = MediaMetadata {
+            width: None,
+            height: None,
+            duration: None,
+            format: declared.name().to_string(),
+            camera: None,
+            video_codec: None,
+            audio_codec: None,
+            bitrate: None,
+            sample_rate: None,
+            channels: None,
+        };
+        let mut media_variants = Vec::new();
+        let (size, sha256) = if images::image_format(declared).is_some() {
+            // Images are rewritten before anything is stored.
+            let original = self.read_whole(head, body).await?;
+            let configured = self.config.image_variants.clone();
+            let keep_camera_info = upload.keep_camera_info;
+            let processed = tokio::task::spawn_blocking(move || images::process(&original, declared, &configured, keep_camera_info))
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Image processing of media {} panicked: {}", id, e);
+                    Err(MediaError::InternalError)
+                })?;
+            metadata.width = Some(processed.width);
+            metadata.height = Some(processed.height);
+            metadata.camera = processed.camera.clone();
+            match self.store_image(id, &content_type, processed, &mut media_variants).await {
+                Ok(stored) => stored,
+                Err(e) => {
+                    self.delete_files(id, &media_variants).await;
+                    return Err(e);
+                }
+            }
+        } else {
+            let (size, sha256, probed) = self.store_stream(&original_key(id), &content_type, declared, head, body, max_size).await?;
+            match probed {
+                Some(Ok(probe)) => {
+                    metadata.width = probe.width;
+                    metadata.height = probe.height;
+                    metadata.duration = probe.duration;
+                    metadata.video_codec = probe.video_codec;
+                    metadata.audio_codec = probe.audio_codec;
+                    metadata.bitrate = probe.bitrate;
+                    metadata.sample_rate = probe.sample_rate;
+                    metadata.channels = probe.channels;
+                }
+                Some(Err(e)) => {
+                    info!("Rejected upload of user {}: {} file is {}", user_id, declared.name(), e);
+                    self.delete_files(id, &[]).await;
+                    return Err(MediaError::InvalidFormat);
+                }
+                None => {}
+            }
+            (size, sha256)
+        };
+
         let now = Utc::now();
-        Ok(Media {
-            id: Uuid::new_v4(),
-            user_id: 1,
-            file_type: "image/jpeg".to_string(),
-            url: "https://example.com/media/test.jpg".to_string(),
-            description: Some("Test image".to_string()),
+        let media = Media {
+            id,
+            user_id,
+            file_type: content_type,
+            url: format!("/media/{}", id),
+            file_name: upload.file_name.as_deref().map(base_name),
+            size,
+            sha256,
+            metadata,
+            variants: media_variants,
+            description: upload.description,
             created_at: now,
             updated_at: now,
+        };
+
+        if let Err(e) = self.media.insert(media.clone()).await {
+            self.delete_files(id, &media.variants).await;
+            return Err(e);
+        }
+        info!("User {} uploaded media {} ({} bytes)", user_id, id, size);
+        Ok(media)
+    }
+
+    /// `head` and the rest of `body`, up to [`MediaConfig::max_file_size`].
+    async fn read_whole<S>(&self, head: BytesMut, mut body: S) -> Result<Vec<u8>, MediaError>
+    where
+        S: Stream<Item = Result<Bytes, MediaError>> + Unpin,
+    {
+        let mut contents = head.to_vec();
+        loop {
+            if contents.len() > self.config.max_file_size {
+                return Err(MediaError::TooLarge);
+            }
+            match body.next().await {
+                Some(chunk) => contents.extend_from_slice(&chunk?),
+                None => return Ok(contents),
+            }
+        }
+    }
+
+    /// Streams `head` and the rest of `body` to `key`, returning the size,
+    /// the SHA-256 and what probing it as `format` found, if it has a prober.
+    async fn store_stream<S>(
+        &self,
+        key: &str,
+        content_type: &str,
+        format: MediaFormat,
+        head: BytesMut,
+        body: S,
+        max_size: u64,
+    ) -> Result<(u64, String, Option<Result<probe::Probe, probe::ProbeError>>), MediaError>
+    where
+        S: Stream<Item = Result<Bytes, MediaError>> + Unpin,
+    {
+        let (mut tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+        let (mut probe_tx, probe_rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+
+        let forward = async move {
+            let mut size = 0u64;
+            let mut hasher = Sha256::new();
+            let head = stream::iter([Ok(head.freeze())]);
+            let mut body = head.chain(body);
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.and_then(|chunk| {
+                    size += chunk.len() as u64;
+                    hasher.update(&chunk);
+                    if size > max_size { Err(MediaError::TooLarge) } else { Ok(chunk) }
+                });
+                let failed = chunk.is_err();
+                if let Ok(chunk) = &chunk {
+                    // The prober stops reading once it has failed, or straight away for other formats.
+                    let _ = probe_tx.send(chunk.clone()).await;
+                }
+                // The backend dropped the receiver when it gave up; its error is returned below.
+                if tx.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+            hasher
+        };
+        let (hasher, stored, probed) = futures::join!(
+            forward,
+            self.storage.put(key, content_type, rx.boxed()),
+            probe::probe(format, probe_rx),
+        );
+        Ok((stored?, hex(&hasher.finalize()), probed))
+    }
+
+    /// Stores the processed original and its derivatives, adding each
+    /// derivative to `stored` once it is. Returns the original's size and SHA-256.
+    async fn store_image(
+        &self,
+        id: Uuid,
+        content_type: &str,
+        image: images::ProcessedImage,
+        stored: &mut Vec<MediaVariant>,
+    ) -> Result<(u64, String), MediaError> {
+        let sha256 = hex(&Sha256::digest(&image.original));
+        let body = stream::iter([Ok(Bytes::from(image.original))]).boxed();
+        let size = self.storage.put(&original_key(id), content_type, body).await?;
+        self.store_variants(id, image.derivatives, stored).await?;
+        Ok((size, sha256))
+    }
+
+    /// Stores the derivatives, adding each to `stored` once it is.
+    async fn store_variants(
+        &self,
+        id: Uuid,
+        derivatives: Vec<images::Derivative>,
+        stored: &mut Vec<MediaVariant>,
+    ) -> Result<(), MediaError> {
+        for derivative in derivatives {
+            let sha256 = hex(&Sha256::digest(&derivative.webp));
+            let body = stream::iter([Ok(Bytes::from(derivative.webp))]).boxed();
+            let size = self.storage.put(&variant_key(id, &derivative.name), WEBP, body).await?;
+            stored.push(MediaVariant {
+                url: format!("/media/{}?variant={}", id, derivative.name),
+                name: derivative.name,
+                file_type: WEBP.to_string(),
+                width: derivative.width,
+                height: derivative.height,
+                size,
+                sha256,
+            });
+        }
+        Ok(())
+    }
+
+    /// Best effort, for uploads that failed half-way.
+    async fn delete_files(&self, id: Uuid, stored: &[MediaVariant]) {
+        for key in stored.iter().map(|v| variant_key(id, &v.name)).chain([original_key(id)]) {
+            self.delete_key(&key).await;
+        }
+    }
+
+    pub async fn get_media(&self, id: Uuid) -> Result<Media, MediaError> {
+        self.media.find(id).await?.ok_or(MediaError::NotFound)
+    }
+
+    /// The original of `media`, or the variant of that name.
+    /// [`MediaError::NotFound`] for variants it does not have.
+    pub fn stored_file(&self, media: &Media, variant: Option<&str>) -> Result<StoredFile, MediaError> {
+        match variant {
+            None => Ok(StoredFile {
+                key: original_key(media.id),
+                content_type: media.file_type.clone(),
+                size: media.size,
+                sha256: media.sha256.clone(),
+            }),
+            Some(name) => {
+                let variant = media.variants.iter().find(|v| v.name == name).ok_or(MediaError::NotFound)?;
+                Ok(StoredFile {
+                    key: variant_key(media.id, &variant.name),
+                    content_type: variant.file_type.clone(),
+                    size: variant.size,
+                    sha256: variant.sha256.clone(),
+                })
+            }
+        }
+    }
+
+    pub async fn open(&self, file: &StoredFile) -> Result<ByteStream, MediaError> {
+        self.storage.get(&file.key).await
+    }
+
+    /// `range` of `file`, which must lie within it.
+    pub async fn open_range(&self, file: &StoredFile, range: Range<u64>) -> Result<ByteStream, MediaError> {
+        self.storage.get_range(&file.key, range).await
+    }
+
+    /// Deletes the record, the file and its variants. Deleting missing media succeeds.
+    pub async fn delete_media(&self, id: Uuid) -> Result<(), MediaError> {
+        let stored_variants = match self.get_media(id).await {
+            Ok(media) => media.variants,
+            Err(MediaError::NotFound) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        for variant in &stored_variants {
+            self.storage.delete(&variant_key(id, &variant.name)).await?;
+        }
+        self.storage.delete(&original_key(id)).await?;
+        self.media.delete(id).await?;
+        Ok(())
+    }
+
+    /// Starts a resumable upload of a `length` byte file. Fails with
+    /// [`MediaError::TooLarge`] above [`MediaConfig::max_upload_length`], or
+    /// when the user's unfinished uploads would exceed
+    /// [`MediaConfig::max_pending_upload_size`].
+    pub fn create_upload(
+        &self,
+        user_id: i32,
+        length: u64,
+        upload: NewUpload,
+        metadata: Option<String>,
+    ) -> Result<ResumableUpload, MediaError> {
+        if length == 0 || MediaFormat::from_mime_type(&upload.content_type).is_none() {
+            return Err(MediaError::InvalidFormat);
+        }
+        if length > self.config.max_upload_length {
+            return Err(MediaError::TooLarge);
+        }
+
+        let mut uploads = self.uploads.write().map_err(|_| MediaError::InternalError)?;
+        let pending: u64 = uploads.values()
+            .filter(|u| u.user_id == user_id && !u.is_expired())
+            .map(|u| u.length)
+            .sum();
+        if pending + length > self.config.max_pending_upload_size {
+            info!("Refused resumable upload of user {}: {} bytes pending", user_id, pending);
+            return Err(MediaError::TooLarge);
+        }
+
+        let resumable = ResumableUpload {
+            id: Uuid::new_v4(),
+            user_id,
+            length,
+            offset: 0,
+            upload,
+            metadata,
+            media_id: None,
+            expires_at: self.upload_expiry(),
+            chunks: Vec::new(),
+            busy: false,
+        };
+        uploads.insert(resumable.id, resumable.clone());
+        info!("User {} started resumable upload {} of {} bytes", user_id, resumable.id, length);
+        Ok(resumable)
+    }
+
+    /// The user's upload. [`MediaError::NotFound`] for anyone else's.
+    pub fn resumable_upload(&self, user_id: i32, id: Uuid) -> Result<ResumableUpload, MediaError> {
+        let uploads = self.uploads.read().map_err(|_| MediaError::InternalError)?;
+        uploads.get(&id).filter(|u| u.user_id == user_id && !u.is_expired()).cloned().ok_or(MediaError::NotFound)
+    }
+
+    /// Stores `body` as the next chunk of the upload, which must have received
+    /// `offset` bytes so far, and turns the upload into media once complete.
+    ///
+    /// When `body` fails half-way, what arrived is kept and the client resumes
+    /// after it, unless the chunk has a `checksum`, which is then verified and
+    /// the chunk dropped with [`MediaError::ChecksumMismatch`] if it does not
+    /// match. Chunks running past the announced length are dropped with
+    /// [`MediaError::TooLarge`]. Completing the upload fails as
+    /// [`upload`](Self::upload) does, except that files other than images may
+    /// be up to [`MediaConfig::max_upload_length`]; the upload is gone then.
+    pub async fn append_upload<S>(
+        &self,
+        user_id: i32,
+        id: Uuid,
+        offset: u64,
+        checksum: Option<Checksum>,
+        mut body: S,
+    ) -> Result<ResumableUpload, MediaError>
+    where
+        S: Stream<Item = Result<Bytes, MediaError>> + Unpin,
+    {
+        let remaining = {
+            let mut uploads = self.uploads.write().map_err(|_| MediaError::InternalError)?;
+            let upload = uploads.get_mut(&id)
+                .filter(|u| u.user_id == user_id && !u.is_expired())
+                .ok_or(MediaError::NotFound)?;
+            if upload.busy {
+                return Err(MediaError::Conflict("Another request is uploading to this upload".to_string()));
+            }
+            if upload.offset != offset {
+                return Err(MediaError::Conflict(format!("Upload-Offset must be {}", upload.offset)));
+            }
+            upload.busy = true;
+            upload.length - upload.offset
+        };
+        let _busy = BusyUpload { uploads: &self.uploads, id };
+
+        let key = chunk_key(id, offset);
+        let (mut tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+        let algorithm = checksum.as_ref().map(|c| c.algorithm);
+        let forward = async move {
+            let mut size = 0u64;
+            let mut hasher = algorithm.map(ChecksumAlgorithm::hasher);
+            let mut failure = None;
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.and_then(|chunk| {
+                    size += chunk.len() as u64;
+                    if size > remaining { Err(MediaError::TooLarge) } else { Ok(chunk) }
+                });
+                match chunk {
+                    Ok(chunk) => {
+                        if let Some(hasher) = hasher.as_mut() {
+                            hasher.update(&chunk);
+                        }
+                        // The backend dropped the receiver when it gave up; its error is returned below.
+                        if tx.send(Ok(chunk)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        // Ending the stream cleanly keeps what arrived; an error drops it.
+                        if hasher.is_some() || matches!(e, MediaError::TooLarge) {
+                            let _ = tx.send(Err(MediaError::UploadError("Chunk dropped".to_string()))).await;
+                        }
+                        failure = Some(e);
+                        break;
+                    }
+                }
+            }
+            (hasher.map(|hasher| hasher.finalize()), failure)
+        };
+        let ((digest, failure), stored) = futures::join!(
+            forward,
+            self.storage.put(&key, CHUNK_CONTENT_TYPE, rx.boxed()),
+        );
+        let size = match (stored, failure) {
+            (Err(_), Some(e)) | (Err(e), None) => return Err(e),
+            (Ok(size), Some(e)) => {
+                info!("Resumable upload {} interrupted after {} more bytes: {}", id, size, e);
+                if size > 0 {
+                    self.record_chunk(id, offset, size)?;
+                } else {
+                    self.delete_key(&key).await;
+                }
+                return Err(e);
+            }
+            (Ok(size), None) => size,
+        };
+
+        if let (Some(checksum), Some(digest)) = (checksum, digest) {
+            if *digest != *checksum.digest {
+                self.delete_key(&key).await;
+                return Err(MediaError::ChecksumMismatch);
+            }
+        }
+        if size == 0 {
+            self.delete_key(&key).await;
+        }
+        let upload = self.record_chunk(id, offset, size)?;
+        if upload.offset < upload.length {
+            return Ok(upload);
+        }
+        // Still busy, so that nothing else touches the chunks meanwhile.
+        self.finish_upload(upload).await
+    }
+
+    /// Adds a stored chunk of `size` bytes at `offset` to the upload, which
+    /// then expires later.
+    fn record_chunk(&self, id: Uuid, offset: u64, size: u64) -> Result<ResumableUpload, MediaError> {
+        let mut uploads = self.uploads.write().map_err(|_| MediaError::InternalError)?;
+        let upload = uploads.get_mut(&id).ok_or(MediaError::InternalError)?;
+        if size > 0 {
+            upload.chunks.push(offset);
+            upload.offset += size;
+            upload.expires_at = self.upload_expiry();
+        }
+        Ok(upload.clone())
+    }
+
+    fn upload_expiry(&self) -> DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(self.config.upload_expiration as i64)
+    }
+
+    /// Turns a complete upload into media and forgets the upload, deleting
+    /// its chunks either way.
+    async fn finish_upload(&self, upload: ResumableUpload) -> Result<ResumableUpload, MediaError> {
+        let keys: Vec<String> = upload.chunks.iter().map(|&offset| chunk_key(upload.id, offset)).collect();
+        let storage = self.storage.clone();
+        let body = stream::iter(keys.clone())
+            .then(move |key| {
+                let storage = storage.clone();
+                async move { storage.get(&key).await }
+            })
+            .try_flatten()
+            .boxed();
+        let ingested = self.ingest(upload.user_id, upload.upload.clone(), body, upload.length).await;
+        for key in &keys {
+            self.delete_key(key).await;
+        }
+
+        let mut uploads = self.uploads.write().map_err(|_| MediaError::InternalError)?;
+        let mut finished = uploads.remove(&upload.id).ok_or(MediaError::InternalError)?;
+        let media = ingested?;
+        finished.media_id = Some(media.id);
+        finished.chunks.clear();
+        info!("Resumable upload {} became media {}", upload.id, media.id);
+        Ok(finished)
+    }
+
+    /// Cancels the user's unfinished upload and deletes what it received.
+    pub async fn terminate_upload(&self, user_id: i32, id: Uuid) -> Result<(), MediaError> {
+        let upload = {
+            let mut uploads = self.uploads.write().map_err(|_| MediaError::InternalError)?;
+            match uploads.get(&id) {
+                Some(upload) if upload.user_id == user_id && upload.busy => {
+                    return Err(MediaError::Conflict("The upload is receiving a request".to_string()));
+                }
+                Some(upload) if upload.user_id == user_id => {}
+                _ => return Err(MediaError::NotFound),
+            }
+            uploads.remove(&id).ok_or(MediaError::NotFound)?
+        };
+        self.delete_chunks(&upload).await;
+        Ok(())
+    }
+
+    /// Deletes the uploads that expired, with what they received, and
+    /// returns how many.
+    pub async fn purge_expired_uploads(&self) -> Result<usize, MediaError> {
+        let expired: Vec<ResumableUpload> = {
+            let mut uploads = self.uploads.write().map_err(|_| MediaError::InternalError)?;
+            let ids: Vec<Uuid> = uploads.values().filter(|u| u.is_expired()).map(|u| u.id).collect();
+            ids.iter().filter_map(|id| uploads.remove(id)).collect()
+        };
+        for upload in &expired {
+            info!("Resumable upload {} of user {} expired at offset {}", upload.id, upload.user_id, upload.offset);
+            self.delete_chunks(upload).await;
+        }
+        Ok(expired.len())
+    }
+
+    /// Runs [`Self::purge_expired_uploads`] every hour in the background.
+    pub fn spawn_upload_purge_task(self: Arc<Self>) {
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.purge_expired_uploads().await {
+                    error!("Failed to purge expired uploads: {}", e);
+                }
+            }
+        });
+    }
+
+    async fn delete_chunks(&self, upload: &ResumableUpload) {
+        for &offset in &upload.chunks {
+            self.delete_key(&chunk_key(upload.id, offset)).await;
+        }
+    }
+
+    /// Best effort, for data that is no longer needed.
+    async fn delete_key(&self, key: &str) {
+        if let Err(e) = self.storage.delete(key).await {
+            error!("Failed to clean up {}: {}", key, e);
+        }
+    }
+}
+
+/// Drops any directories a client put in front of the file name.
+fn base_name(file_name: &str) -> String {
+    file_name.rsplit(['/', '\\']).next().unwrap_or_default().to_string()
+}
+
+fn original_key(id: Uuid) -> String {
+    format!("{}/original", id)
+}
+
+fn variant_key(id: Uuid, name: &str) -> String {
+    format!("{}/{}.webp", id, name)
+}
+
+/// Chunks sort by the offset they start at.
+fn chunk_key(upload_id: Uuid, offset: u64) -> String {
+    format!("{}/chunk-{:020}", upload_id, offset)
+}
+
+const WEBP: &str = "image/webp";
+const CHUNK_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// Clears [`ResumableUpload::busy`] once a chunk has been handled, or its
+/// request dropped.
+struct BusyUpload<'a> {
+    uploads: &'a RwLock<HashMap<Uuid, ResumableUpload>>,
+    id: Uuid,
+}
+
+impl Drop for BusyUpload<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut uploads) = self.uploads.write() {
+            if let Some(upload) = uploads.get_mut(&self.id) {
+                upload.busy = false;
+            }
+        }
+    }
+}
+
+/// Records go into `media/media.json`, the original files into `media/files/`.
+impl DataSubjectHook for MediaService {
+    fn name(&self) -> &'static str {
+        "media"
+    }
+
+    fn export(&self, user_id: i32) -> BoxFuture<'_, Result<Vec<ExportedFile>, CommonError>> {
+        Box::pin(async move {
+            let own = self.media.list_by_user(user_id).await.map_err(|_| CommonError::InternalError)?;
+            let mut files = vec![ExportedFile::json("media.json", &own)?];
+            for media in &own {
+                let original = self.stored_file(media, None).map_err(|_| CommonError::InternalError)?;
+                let storage = self.storage.clone();
+                let media_id = media.id;
+                // Opened only once the archive gets to it.
+                let contents = stream::once(async move { storage.get(&original.key).await })
+                    .try_flatten()
+                    .map_err(move |e| {
+                        error!("Failed to export media {}: {}", media_id, e);
+                        CommonError::InternalError
+                    })
+                    .boxed();
+                let name = media.file_name.as_deref().unwrap_or("file");
+                files.push(ExportedFile::stream(&format!("files/{}-{}", media.id, name), contents));
+            }
+            Ok(files)
+        })
+    }
+
+    fn erase(&self, user_id: i32) -> BoxFuture<'_, Result<(), CommonError>> {
+        Box::pin(async move {
+            let own = self.media.list_by_user(user_id).await.map_err(|_| CommonError::InternalError)?;
+            for media in own {
+                self.delete_media(media.id).await.map_err(|_| CommonError::InternalError)?;
+            }
+            let uploads: Vec<Uuid> = self.uploads.read().map_err(|_| CommonError::InternalError)?
+                .values().filter(|u| u.user_id == user_id).map(|u| u.id).collect();
+            for id in uploads {
+                self.terminate_upload(user_id, id).await.map_err(|_| CommonError::InternalError)?;
+            }
+            Ok(())
         })
     }
 }
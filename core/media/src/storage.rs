@@ -0,0 +1,229 @@
+//! Where uploaded files are kept.
+//!
+//! [`MediaStorage`] stores opaque objects under keys such as
+//! `5b0f…/original`. [`LocalStorage`] writes them below
+//! [`MediaConfig::upload_dir`], [`S3Storage`] into an S3-compatible bucket.
+//! [`from_config`] picks one.
+
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::stream::{self, BoxStream, StreamExt};
+use log::error;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+use crate::config::MediaConfig;
+use crate::error::MediaError;
+
+pub use crate::s3::S3Storage;
+
+/// Contents of a stored object, read or written chunk by chunk.
+pub type ByteStream = BoxStream<'static, Result<Bytes, MediaError>>;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+#[async_trait]
+pub trait MediaStorage: Send + Sync {
+    /// Stores `body` under `key`, replacing what was there, and returns its size.
+    /// When `body` yields an error nothing is stored and that error is returned.
+    async fn put(&self, key: &str, content_type: &str, body: ByteStream) -> Result<u64, MediaError>;
+
+    /// Reads the object under `key`. [`MediaError::NotFound`] when there is none.
+    async fn get(&self, key: &str) -> Result<ByteStream, MediaError>;
+
+    /// Reads `range` of the object under `key`. The range must lie within the object.
+    async fn get_range(&self, key: &str, range: Range<u64>) -> Result<ByteStream, MediaError>;
+
+    /// Deletes the object under `key`. Deleting a missing object succeeds.
+    async fn delete(&self, key: &str) -> Result<(), MediaError>;
+}
+
+/// S3 when `config.s3` is set, the local file system otherwise.
+pub fn from_config(config: &MediaConfig) -> Result<Arc<dyn MediaStorage>, MediaError> {
+    match &config.s3 {
+        Some(s3) => Ok(Arc::new(S3Storage::new(s3.clone())?)),
+        None => Ok(Arc::new(LocalStorage::new(&config.upload_dir))),
+    }
+}
+
+/// Keys are relative paths of `[A-Za-z0-9._-]` segments, which keeps them
+/// inside the upload directory and the bucket.
+pub(crate) fn validate_key(key: &str) -> Result<(), MediaError> {
+    let valid = !key.is_empty()
+        && key.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(MediaError::UploadError(format!("Invalid storage key: {}", key)))
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn io_error(context: &str, key: &str, err: std::io::Error) -> MediaError {
+    error!("Failed to {} {}: {}", context, key, err);
+    MediaError::InternalError
+}
+
+/// Files below a directory, one per key.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, MediaError> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+
+    async fn open(&self, key: &str) -> Result<File, MediaError> {
+        match File::open(self.path(key)?).await {
+            Ok(file) => Ok(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(MediaError::NotFound),
+            Err(e) => Err(io_error("open", key, e)),
+        }
+    }
+
+    async fn write(file: &mut File, mut body: ByteStream) -> Result<u64, MediaError> {
+        let mut size = 0;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await.map_err(|e| io_error("write", "upload", e))?;
+            size += chunk.len() as u64;
+        }
+        file.sync_all().await.map_err(|e| io_error("sync", "upload", e))?;
+        Ok(size)
+    }
+}
+
+#[async_trait]
+impl MediaStorage for LocalStorage {
+    async fn put(&self, key: &str, _content_type: &str, body: ByteStream) -> Result<u64, MediaError> {
+        let path = self.path(key)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir).await.map_err(|e| io_error("create directory for", key, e))?;
+
+        // Written next to the target and renamed into place, so readers never
+        // see a partial file and a failed upload leaves nothing behind.
+        let partial = dir.join(format!(".{}.part", Uuid::new_v4()));
+        let mut file = File::create(&partial).await.map_err(|e| io_error("create", key, e))?;
+        let written = Self::write(&mut file, body).await;
+        drop(file);
+
+        let result = match written {
+            Ok(size) => fs::rename(&partial, &path).await
+                .map(|_| size)
+                .map_err(|e| io_error("move into place", key, e)),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&partial).await;
+        }
+        result
+    }
+
+    async fn get(&self, key: &str) -> Result<ByteStream, MediaError> {
+        let file = self.open(key).await?;
+        Ok(read_chunks(file, key))
+    }
+
+    async fn get_range(&self, key: &str, range: Range<u64>) -> Result<ByteStream, MediaError> {
+        let mut file = self.open(key).await?;
+        file.seek(SeekFrom::Start(range.start)).await.map_err(|e| io_error("seek in", key, e))?;
+        Ok(read_chunks(file.take(range.end - range.start), key))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), MediaError> {
+        let path = self.path(key)?;
+        match fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(io_error("delete", key, e)),
+        }
+    }
+}
+
+fn read_chunks<R: AsyncRead + Unpin + Send + 'static>(reader: R, key: &str) -> ByteStream {
+    let key = key.to_string();
+    stream::try_unfold((reader, key), |(mut reader, key)| async move {
+        let mut buf = BytesMut::zeroed(READ_CHUNK_SIZE);
+        let read = reader.read(&mut buf).await.map_err(|e| io_error("read", &key, e))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buf.truncate(read);
+        Ok(Some((buf.freeze(), (reader, key))))
+    }).boxed()
+}
+
+/// Reads a whole object into memory.
+pub async fn read_all(mut body: ByteStream) -> Result<Vec<u8>, MediaError> {
+    let mut contents = Vec::new();
+    while let Some(chunk) = body.next().await {
+        contents.extend_from_slice(&chunk?);
+    }
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(chunks: Vec<Result<&'static [u8], MediaError>>) -> ByteStream {
+        stream::iter(chunks.into_iter().map(|c| c.map(Bytes::from_static))).boxed()
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path());
+
+        let size = storage.put("a/original", "image/png", body(vec![Ok(b"hello "), Ok(b"world")])).await.unwrap();
+        assert_eq!(size, 11);
+        assert_eq!(read_all(storage.get("a/original").await.unwrap()).await.unwrap(), b"hello world");
+        assert_eq!(read_all(storage.get_range("a/original", 3..8).await.unwrap()).await.unwrap(), b"lo wo");
+
+        storage.delete("a/original").await.unwrap();
+        assert!(matches!(storage.get("a/original").await, Err(MediaError::NotFound)));
+        // Deleting twice is fine
+        storage.delete("a/original").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_discards_failed_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path());
+
+        let result = storage.put("a/original", "image/png", body(vec![Ok(b"partial"), Err(MediaError::TooLarge)])).await;
+        assert!(matches!(result, Err(MediaError::TooLarge)));
+        assert!(matches!(storage.get("a/original").await, Err(MediaError::NotFound)));
+        assert_eq!(std::fs::read_dir(dir.path().join("a")).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_rejects_keys_outside_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path().join("uploads"));
+
+        for key in ["../escape", "/etc/passwd", "a//b", "a/./b", "", "a b"] {
+            assert!(storage.put(key, "image/png", body(vec![Ok(b"x")])).await.is_err(), "{}", key);
+        }
+        assert!(!dir.path().join("escape").exists());
+    }
+}
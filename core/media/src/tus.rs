@@ -0,0 +1,345 @@
+//! Resumable uploads through the tus protocol, version 1.0.0, with the
+//! creation, expiration, termination and checksum extensions
+//! (<https://tus.io/protocols/resumable-upload>).
+//!
+//! A client creates an upload with `POST /media/uploads`, announcing the
+//! file's size in `Upload-Length` and its type in `Upload-Metadata`, and sends
+//! it in any number of `PATCH` requests to the returned `Location`. After an
+//! interruption, `HEAD` tells it where to resume. Once all bytes have
+//! arrived, the upload becomes [`Media`](crate::models::Media), linked from
+//! `Content-Location`, and the upload is gone. Unfinished uploads expire
+//! [`MediaConfig::upload_expiration`](crate::config::MediaConfig::upload_expiration)
+//! seconds after their last chunk, as `Upload-Expires` tells.
+
+use std::time::SystemTime;
+use actix_web::{http::{header::{self, HttpDate}, StatusCode}, web, HttpRequest, HttpResponse, HttpResponseBuilder, ResponseError};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::TryStreamExt;
+use log::warn;
+use socialhub_core::auth::AuthenticatedUser;
+use uuid::Uuid;
+use crate::error::MediaError;
+use crate::handlers::MAX_DESCRIPTION_LENGTH;
+use crate::service::{Checksum, ChecksumAlgorithm, MediaService, NewUpload, ResumableUpload};
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination,checksum";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: &str = "Tus-Resumable";
+const TUS_VERSION_HEADER: &str = "Tus-Version";
+const TUS_EXTENSION: &str = "Tus-Extension";
+const TUS_MAX_SIZE: &str = "Tus-Max-Size";
+const TUS_CHECKSUM_ALGORITHM: &str = "Tus-Checksum-Algorithm";
+const UPLOAD_LENGTH: &str = "Upload-Length";
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+const UPLOAD_METADATA: &str = "Upload-Metadata";
+const UPLOAD_CHECKSUM: &str = "Upload-Checksum";
+const UPLOAD_EXPIRES: &str = "Upload-Expires";
+
+/// Describes the server's tus support
+#[utoipa::path(
+    options,
+    path = "/media/uploads",
+    responses(
+        (status = 204, description = "Supported versions, extensions, checksum algorithms and the largest upload")
+    ),
+    tag = "media"
+)]
+pub async fn options(service: web::Data<MediaService>) -> HttpResponse {
+    let algorithms: Vec<&str> = ChecksumAlgorithm::ALL.iter().map(|algorithm| algorithm.name()).collect();
+    tus_response(StatusCode::NO_CONTENT)
+        .insert_header((TUS_VERSION_HEADER, TUS_VERSION))
+        .insert_header((TUS_EXTENSION, TUS_EXTENSIONS))
+        .insert_header((TUS_MAX_SIZE, service.config().max_upload_length))
+        .insert_header((TUS_CHECKSUM_ALGORITHM, algorithms.join(",")))
+        .finish()
+}
+
+/// Creates a resumable upload
+///
+/// `Upload-Metadata` must carry the MIME type as `filetype`, and may carry
+/// `filename`, `description` and `keep_camera_info`, as the fields of
+/// [`upload`](crate::handlers::upload).
+///
+/// # Returns
+/// * 201 Created with the upload's URL in `Location`, and when it expires in
+///   `Upload-Expires`
+/// * 413 Payload Too Large above `Tus-Max-Size`, or when the user's
+///   unfinished uploads would exceed their limit
+/// * 415 Unsupported Media Type for types that cannot be uploaded
+#[utoipa::path(
+    post,
+    path = "/media/uploads",
+    params(
+        ("Tus-Resumable" = String, Header, description = "1.0.0"),
+        ("Upload-Length" = u64, Header, description = "Size of the file in bytes"),
+        ("Upload-Metadata" = String, Header, description = "Comma-separated keys and base64 values, `filetype` required")
+    ),
+    responses(
+        (status = 201, description = "Upload created"),
+        (status = 400, description = "Invalid request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Missing the media:upload permission"),
+        (status = 412, description = "Unsupported tus version"),
+        (status = 413, description = "File too large"),
+        (status = 415, description = "Unsupported media type")
+    ),
+    security(("bearer_token" = [])),
+    tag = "media"
+)]
+pub async fn create_upload(req: HttpRequest, service: web::Data<MediaService>, user: AuthenticatedUser) -> HttpResponse {
+    respond(async {
+        let length = number_header(&req, UPLOAD_LENGTH)?;
+        let raw_metadata = text_header(&req, UPLOAD_METADATA)?;
+        let metadata = parse_metadata(raw_metadata.unwrap_or_default())?;
+        let field = |key: &str| metadata.iter().find(|(k, _)| k == key).and_then(|(_, value)| value.clone());
+
+        let description = field("description");
+        if description.as_ref().is_some_and(|d| d.len() > MAX_DESCRIPTION_LENGTH) {
+            return Err(MediaError::UploadError("description is too long".to_string()));
+        }
+        let upload = NewUpload {
+            content_type: field("filetype").ok_or_else(|| MediaError::UploadError("Upload-Metadata lacks filetype".to_string()))?,
+            file_name: field("filename"),
+            description,
+            keep_camera_info: match field("keep_camera_info").as_deref() {
+                None | Some("false") => false,
+                Some("true") => true,
+                Some(_) => return Err(MediaError::UploadError("keep_camera_info must be true or false".to_string())),
+            },
+        };
+
+        let created = service.create_upload(user.user_id, length, upload, raw_metadata.map(str::to_string))?;
+        Ok(tus_response(StatusCode::CREATED)
+            .insert_header((header::LOCATION, format!("/media/uploads/{}", created.id)))
+            .insert_header(expires(&created))
+            .finish())
+    }, &req).await
+}
+
+/// Tells how much of an upload has arrived
+#[utoipa::path(
+    head,
+    path = "/media/uploads/{id}",
+    params(("Tus-Resumable" = String, Header, description = "1.0.0")),
+    responses(
+        (status = 200, description = "`Upload-Offset`, `Upload-Length` and `Upload-Expires`"),
+        (status = 404, description = "Upload not found, finished or expired"),
+        (status = 412, description = "Unsupported tus version")
+    ),
+    security(("bearer_token" = [])),
+    tag = "media"
+)]
+pub async fn upload_offset(
+    req: HttpRequest,
+    service: web::Data<MediaService>,
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+) -> HttpResponse {
+    respond(async {
+        let upload = service.resumable_upload(user.user_id, id.into_inner())?;
+        let mut response = progress(StatusCode::OK, &upload);
+        response
+            .insert_header((UPLOAD_LENGTH, upload.length))
+            .insert_header((header::CACHE_CONTROL, "no-store"));
+        if let Some(metadata) = &upload.metadata {
+            response.insert_header((UPLOAD_METADATA, metadata.as_str()));
+        }
+        Ok(response.finish())
+    }, &req).await
+}
+
+/// Sends the next chunk of an upload
+///
+/// The body is stored from `Upload-Offset`, which must be what `HEAD` last
+/// answered. With `Upload-Checksum`, the chunk is dropped unless its digest
+/// matches; without, an interrupted chunk is kept as far as it arrived.
+///
+/// # Returns
+/// * 204 No Content with the new `Upload-Offset` and `Upload-Expires`, or
+///   `Content-Location` once the upload is complete and has become media
+/// * 409 Conflict when `Upload-Offset` is not the upload's
+/// * 460 when the chunk does not match `Upload-Checksum`
+#[utoipa::path(
+    patch,
+    path = "/media/uploads/{id}",
+    params(
+        ("Tus-Resumable" = String, Header, description = "1.0.0"),
+        ("Upload-Offset" = u64, Header, description = "Bytes of the file before this chunk"),
+        ("Upload-Checksum" = Option<String>, Header, description = "Algorithm and base64 digest of the chunk, e.g. `sha1 Kq5sNclPz7QV2+lfQIuc6R7oRu0=`")
+    ),
+    request_body(content = Vec<u8>, content_type = "application/offset+octet-stream"),
+    responses(
+        (status = 204, description = "Chunk stored"),
+        (status = 400, description = "Invalid request"),
+        (status = 404, description = "Upload not found, finished or expired"),
+        (status = 409, description = "Wrong offset, or another chunk is being sent"),
+        (status = 412, description = "Unsupported tus version"),
+        (status = 413, description = "Chunk runs past Upload-Length"),
+        (status = 415, description = "Wrong content type, or the file is not valid media"),
+        (status = 460, description = "Checksum mismatch")
+    ),
+    security(("bearer_token" = [])),
+    tag = "media"
+)]
+pub async fn append_upload(
+    req: HttpRequest,
+    service: web::Data<MediaService>,
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+    body: web::Payload,
+) -> HttpResponse {
+    respond(async {
+        if req.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) != Some(OFFSET_CONTENT_TYPE) {
+            return Err(MediaError::InvalidFormat);
+        }
+        let offset = number_header(&req, UPLOAD_OFFSET)?;
+        let checksum = text_header(&req, UPLOAD_CHECKSUM)?.map(parse_checksum).transpose()?;
+
+        let body = body.map_err(|e| MediaError::UploadError(e.to_string()));
+        let upload = service.append_upload(user.user_id, id.into_inner(), offset, checksum, body).await?;
+        Ok(progress(StatusCode::NO_CONTENT, &upload).finish())
+    }, &req).await
+}
+
+/// Cancels an upload
+#[utoipa::path(
+    delete,
+    path = "/media/uploads/{id}",
+    params(("Tus-Resumable" = String, Header, description = "1.0.0")),
+    responses(
+        (status = 204, description = "Upload cancelled"),
+        (status = 404, description = "Upload not found or finished"),
+        (status = 409, description = "A chunk is being sent"),
+        (status = 412, description = "Unsupported tus version")
+    ),
+    security(("bearer_token" = [])),
+    tag = "media"
+)]
+pub async fn terminate_upload(
+    req: HttpRequest,
+    service: web::Data<MediaService>,
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>,
+) -> HttpResponse {
+    respond(async {
+        service.terminate_upload(user.user_id, id.into_inner()).await?;
+        Ok(tus_response(StatusCode::NO_CONTENT).finish())
+    }, &req).await
+}
+
+fn tus_response(status: StatusCode) -> HttpResponseBuilder {
+    let mut response = HttpResponse::build(status);
+    response.insert_header((TUS_RESUMABLE, TUS_VERSION));
+    response
+}
+
+fn progress(status: StatusCode, upload: &ResumableUpload) -> HttpResponseBuilder {
+    let mut response = tus_response(status);
+    response.insert_header((UPLOAD_OFFSET, upload.offset));
+    match upload.media_id {
+        Some(media_id) => response.insert_header((header::CONTENT_LOCATION, format!("/media/{}", media_id))),
+        None => response.insert_header(expires(upload)),
+    };
+    response
+}
+
+fn expires(upload: &ResumableUpload) -> (&'static str, HttpDate) {
+    (UPLOAD_EXPIRES, HttpDate::from(SystemTime::from(upload.expires_at)))
+}
+
+/// Checks the client's tus version, then answers with `handler`'s response,
+/// or its error, carrying `Tus-Resumable` either way.
+async fn respond<F>(handler: F, req: &HttpRequest) -> HttpResponse
+where
+    F: std::future::Future<Output = Result<HttpResponse, MediaError>>,
+{
+    if req.headers().get(TUS_RESUMABLE).and_then(|v| v.to_str().ok()) != Some(TUS_VERSION) {
+        return tus_response(StatusCode::PRECONDITION_FAILED)
+            .insert_header((TUS_VERSION_HEADER, TUS_VERSION))
+            .finish();
+    }
+    match handler.await {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Resumable upload request failed: {:?}", e);
+            let mut response = e.error_response();
+            response.headers_mut().insert(
+                header::HeaderName::from_static("tus-resumable"),
+                header::HeaderValue::from_static(TUS_VERSION),
+            );
+            response
+        }
+    }
+}
+
+fn text_header<'a>(req: &'a HttpRequest, name: &str) -> Result<Option<&'a str>, MediaError> {
+    req.headers().get(name)
+        .map(|value| value.to_str().map_err(|_| MediaError::UploadError(format!("{} is not ASCII", name))))
+        .transpose()
+}
+
+fn number_header(req: &HttpRequest, name: &str) -> Result<u64, MediaError> {
+    text_header(req, name)?
+        .ok_or_else(|| MediaError::UploadError(format!("{} is required", name)))?
+        .parse()
+        .map_err(|_| MediaError::UploadError(format!("{} must be a number of bytes", name)))
+}
+
+/// Parses `Upload-Metadata`: comma-separated keys, each with a base64 value
+/// after a space, or none.
+fn parse_metadata(metadata: &str) -> Result<Vec<(String, Option<String>)>, MediaError> {
+    let invalid = || MediaError::UploadError("Invalid Upload-Metadata".to_string());
+    metadata.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = match pair.split_once(' ') {
+                Some((key, value)) => (key, Some(value)),
+                None => (pair, None),
+            };
+            let value = value
+                .map(|value| STANDARD.decode(value).ok().and_then(|value| String::from_utf8(value).ok()).ok_or_else(invalid))
+                .transpose()?;
+            Ok((key.to_string(), value))
+        })
+        .collect()
+}
+
+/// Parses `Upload-Checksum`, an algorithm and a base64 digest.
+fn parse_checksum(checksum: &str) -> Result<Checksum, MediaError> {
+    let (name, digest) = checksum.split_once(' ')
+        .ok_or_else(|| MediaError::UploadError("Invalid Upload-Checksum".to_string()))?;
+    let algorithm = ChecksumAlgorithm::from_name(name)
+        .ok_or_else(|| MediaError::UploadError(format!("Unsupported checksum algorithm {}", name)))?;
+    let digest = STANDARD.decode(digest)
+        .map_err(|_| MediaError::UploadError("Invalid Upload-Checksum".to_string()))?;
+    Ok(Checksum { algorithm, digest })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata = parse_metadata("filename dGVzdC5tcDQ=,is_confidential, filetype dmlkZW8vbXA0").unwrap();
+        assert_eq!(metadata, vec![
+            ("filename".to_string(), Some("test.mp4".to_string())),
+            ("is_confidential".to_string(), None),
+            ("filetype".to_string(), Some("video/mp4".to_string())),
+        ]);
+        assert!(parse_metadata("").unwrap().is_empty());
+        assert!(parse_metadata("filename not*base64").is_err());
+    }
+
+    #[test]
+    fn test_parse_checksum() {
+        let checksum = parse_checksum("sha1 Kq5sNclPz7QV2+lfQIuc6R7oRu0=").unwrap();
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Sha1);
+        assert_eq!(checksum.digest.len(), 20);
+        assert!(parse_checksum("md5 Kq5sNclPz7QV2+lfQIuc6R7oRu0=").is_err());
+        assert!(parse_checksum("sha1").is_err());
+    }
+}
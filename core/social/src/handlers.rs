@@ -1,13 +1,15 @@
 use actix_web::{web, HttpResponse, Error};
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use socialhub_core::auth::AuthenticatedUser;
 use utoipa::ToSchema;
+use crate::service::SocialService;
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreatePostRequest {
     pub content: String,
-    pub media_ids: Vec<String>,
+    #[serde(default)]
+    pub media_ids: Vec<Uuid>,
 }
 
 #[utoipa::path(
@@ -15,41 +17,58 @@ pub struct CreatePostRequest {
     path = "/social/posts",
     request_body = CreatePostRequest,
     responses(
-        (status = 201, description = "Post created"),
+        (status = 201, description = "Post created", body = Post),
         (status = 401, description = "Unauthorized")
     ),
     security(("bearer_token" = [])),
     tag = "social"
 )]
 pub async fn create_post(
-    req: actix_web::HttpRequest,
-    post_data: web::Json<serde_json::Value>
+    service: web::Data<SocialService>,
+    user: AuthenticatedUser,
+    post_data: web::Json<CreatePostRequest>
 ) -> Result<HttpResponse, Error> {
-    // Verificar autenticação
-    if req.headers().get("Authorization").is_none() {
-        return Ok(HttpResponse::Unauthorized().finish());
-    }
-
-    // Criar post e retornar sucesso
-    Ok(HttpResponse::Created().json(json!({
-        "id": Uuid::new_v4(),
-        "content": post_data.get("content").unwrap_or(&json!("")).to_string(),
-        "created_at": "2025-02-18T19:00:00Z"
-    })))
+    let post_data = post_data.into_inner();
+    let post = service.create_post(user.user_id, post_data.content, post_data.media_ids)?;
+    Ok(HttpResponse::Created().json(post))
 }
 
 #[utoipa::path(
     get,
     path = "/social/posts/{id}",
     responses(
-        (status = 200, description = "Post found"),
+        (status = 200, description = "Post found", body = Post),
         (status = 404, description = "Post not found")
     ),
     tag = "social"
 )]
-pub async fn get_post(_id: web::Path<Uuid>) -> Result<HttpResponse, Error> {
-    // Simular post não encontrado
-    Ok(HttpResponse::NotFound().finish())
+pub async fn get_post(
+    service: web::Data<SocialService>,
+    id: web::Path<Uuid>
+) -> Result<HttpResponse, Error> {
+    let post = service.get_post(id.into_inner())?;
+    Ok(HttpResponse::Ok().json(post))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/social/posts/{id}",
+    responses(
+        (status = 204, description = "Post deleted"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not the author and missing posts:delete_any"),
+        (status = 404, description = "Post not found")
+    ),
+    security(("bearer_token" = [])),
+    tag = "social"
+)]
+pub async fn delete_post(
+    service: web::Data<SocialService>,
+    user: AuthenticatedUser,
+    id: web::Path<Uuid>
+) -> Result<HttpResponse, Error> {
+    service.delete_post(&user, id.into_inner())?;
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[utoipa::path(
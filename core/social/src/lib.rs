@@ -1,15 +1,25 @@
 use actix_web::web;
+use socialhub_core::{auth::permissions::POSTS_CREATE, require_permission};
 
 pub mod handlers;
 pub mod models;
 mod service;
 mod error;
 
+pub use error::SocialError;
+pub use service::SocialService;
+
+/// Registers the social routes. The handlers expect a shared
+/// `web::Data<SocialService>` registered as app data.
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/social")
-            .service(web::resource("/posts").route(web::post().to(handlers::create_post)))
-            .service(web::resource("/posts/{id}").route(web::get().to(handlers::get_post)))
+            .service(web::resource("/posts").route(web::post().to(handlers::create_post).wrap(require_permission(POSTS_CREATE))))
+            .service(
+                web::resource("/posts/{id}")
+                    .route(web::get().to(handlers::get_post))
+                    .route(web::delete().to(handlers::delete_post))
+            )
             .service(web::resource("/posts/{id}/like").route(web::post().to(handlers::like_post)))
             .service(web::resource("/users/{id}/follow").route(web::post().to(handlers::follow_user)))
     );
@@ -18,19 +28,59 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use std::sync::Arc;
     use actix_web::{test, App};
     use serde_json::json;
+    use socialhub_auth::{AuthConfig, AuthService, InMemoryUserRepository, Role};
     use uuid::Uuid;
+    use crate::models::Post;
+
+    fn auth_service() -> web::Data<AuthService> {
+        web::Data::new(AuthService::new(AuthConfig::default(), Arc::new(InMemoryUserRepository::new())))
+    }
+
+    fn bearer(service: &AuthService, user_id: i32) -> String {
+        bearer_with_role(service, user_id, Role::User)
+    }
+
+    fn bearer_with_role(service: &AuthService, user_id: i32, role: Role) -> String {
+        let (token, _) = service.tokens().issue(user_id, &[role], &[]).unwrap();
+        format!("Bearer {}", token)
+    }
+
+    /// Token of a third-party app granted `scopes` by a user with `role`.
+    fn scoped_bearer(service: &AuthService, user_id: i32, role: Role, scopes: &[&str]) -> String {
+        let permissions = scopes.iter()
+            .flat_map(|scope| socialhub_core::auth::scopes::permissions(scope))
+            .filter(|p| role.permissions().contains(p))
+            .map(|p| p.to_string())
+            .collect();
+        let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+        let (token, _) = service.tokens().issue_for_client(user_id, &[role], permissions, "app", &scopes).unwrap();
+        format!("Bearer {}", token)
+    }
+
+    /// Token of a third-party app holding exactly `permissions`.
+    fn client_bearer(service: &AuthService, user_id: i32, permissions: &[&str]) -> String {
+        let permissions = permissions.iter().map(|p| p.to_string()).collect();
+        let (token, _) = service.tokens().issue_for_client(user_id, &[Role::User], permissions, "app", &[]).unwrap();
+        format!("Bearer {}", token)
+    }
 
     #[actix_rt::test]
     async fn test_create_post() {
+        let auth = auth_service();
+        let service = web::Data::new(SocialService::new());
         let app = test::init_service(
-            App::new().service(web::scope("/social").route("/posts", web::post().to(handlers::create_post)))
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(service.clone())
+                .service(web::scope("/social").route("/posts", web::post().to(handlers::create_post)))
         ).await;
 
         let req = test::TestRequest::post()
             .uri("/social/posts")
-            .insert_header(("Authorization", "Bearer test-token"))
+            .insert_header(("Authorization", bearer(&auth, 42)))
             .set_json(json!({
                 "content": "Test post content",
                 "media_ids": [],
@@ -40,6 +90,59 @@ pub mod tests {
 
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
+
+        let body: Post = test::read_body_json(resp).await;
+        assert_eq!(body.user_id, 42);
+        assert_eq!(body.content, "Test post content");
+        assert_eq!(service.get_post(body.id).unwrap().user_id, 42);
+    }
+
+    #[actix_rt::test]
+    async fn test_create_post_requires_permission() {
+        let auth = auth_service();
+        let service = web::Data::new(SocialService::new());
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(service.clone())
+                .configure(configure)
+        ).await;
+
+        let create = |token: String| test::TestRequest::post()
+            .uri("/social/posts")
+            .insert_header(("Authorization", token))
+            .set_json(json!({ "content": "Test content" }))
+            .to_request();
+
+        let resp = test::call_service(&app, create(client_bearer(&auth, 1, &[]))).await;
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let resp = test::call_service(&app, create(client_bearer(&auth, 1, &["posts:create"]))).await;
+        assert_eq!(resp.status().as_u16(), 201);
+    }
+
+    #[actix_rt::test]
+    async fn test_create_post_with_scoped_token() {
+        let auth = auth_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(SocialService::new()))
+                .configure(configure)
+        ).await;
+
+        let create = |token: String| test::TestRequest::post()
+            .uri("/social/posts")
+            .insert_header(("Authorization", token))
+            .set_json(json!({ "content": "Test content" }))
+            .to_request();
+
+        for scopes in [&["openid", "profile"][..], &["media:write", "stream:write", "user:read"]] {
+            let resp = test::call_service(&app, create(scoped_bearer(&auth, 1, Role::User, scopes))).await;
+            assert_eq!(resp.status().as_u16(), 403, "{:?}", scopes);
+        }
+        let resp = test::call_service(&app, create(scoped_bearer(&auth, 1, Role::User, &["user:write"]))).await;
+        assert_eq!(resp.status().as_u16(), 201);
     }
 
     #[actix_rt::test]
@@ -60,7 +163,10 @@ pub mod tests {
     #[actix_rt::test]
     async fn test_create_post_without_auth() {
         let app = test::init_service(
-            App::new().service(web::scope("/social").route("/posts", web::post().to(handlers::create_post)))
+            App::new()
+                .configure(socialhub_auth::configure_state(auth_service()))
+                .app_data(web::Data::new(SocialService::new()))
+                .service(web::scope("/social").route("/posts", web::post().to(handlers::create_post)))
         ).await;
 
         let req = test::TestRequest::post()
@@ -74,6 +180,28 @@ pub mod tests {
         assert_eq!(resp.status().as_u16(), 401);
     }
 
+    #[actix_rt::test]
+    async fn test_create_post_with_invalid_token() {
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth_service()))
+                .app_data(web::Data::new(SocialService::new()))
+                .service(web::scope("/social").route("/posts", web::post().to(handlers::create_post)))
+        ).await;
+
+        let req = test::TestRequest::post()
+            .uri("/social/posts")
+            .insert_header(("Authorization", "Bearer test-token"))
+            .set_json(json!({
+                "content": "Test content"
+            }))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 401);
+        assert!(resp.headers().contains_key("WWW-Authenticate"));
+    }
+
     #[actix_rt::test]
     async fn test_like_own_post() {
         let post_id = Uuid::new_v4();
@@ -94,7 +222,9 @@ pub mod tests {
     async fn test_get_post_not_found() {
         let post_id = Uuid::new_v4();
         let app = test::init_service(
-            App::new().service(web::scope("/social").route("/posts/{id}", web::get().to(handlers::get_post)))
+            App::new()
+                .app_data(web::Data::new(SocialService::new()))
+                .service(web::scope("/social").route("/posts/{id}", web::get().to(handlers::get_post)))
         ).await;
 
         let req = test::TestRequest::get()
@@ -120,4 +250,65 @@ pub mod tests {
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status().as_u16(), 404);
     }
+
+    #[actix_rt::test]
+    async fn test_delete_post_permissions() {
+        let auth = auth_service();
+        let service = web::Data::new(SocialService::new());
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(service.clone())
+                .configure(configure)
+        ).await;
+
+        let first = service.create_post(1, "first".to_string(), Vec::new()).unwrap();
+        let second = service.create_post(1, "second".to_string(), Vec::new()).unwrap();
+
+        let delete = |id: Uuid, token: String| test::TestRequest::delete()
+            .uri(&format!("/social/posts/{}", id))
+            .insert_header(("Authorization", token))
+            .to_request();
+
+        // Another plain user may not remove the post...
+        let resp = test::call_service(&app, delete(first.id, bearer(&auth, 2))).await;
+        assert_eq!(resp.status().as_u16(), 403);
+
+        // ...but its author and moderators may.
+        let resp = test::call_service(&app, delete(first.id, bearer(&auth, 1))).await;
+        assert_eq!(resp.status().as_u16(), 204);
+        let resp = test::call_service(&app, delete(second.id, bearer_with_role(&auth, 3, Role::Moderator))).await;
+        assert_eq!(resp.status().as_u16(), 204);
+
+        // A moderator's app may not act as moderator.
+        let third = service.create_post(1, "third".to_string(), Vec::new()).unwrap();
+        let scoped = scoped_bearer(&auth, 3, Role::Moderator, &["user:write", "media:write", "stream:write"]);
+        let resp = test::call_service(&app, delete(third.id, scoped)).await;
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let resp = test::call_service(&app, delete(second.id, bearer(&auth, 1))).await;
+        assert_eq!(resp.status().as_u16(), 404);
+        let req = test::TestRequest::get().uri(&format!("/social/posts/{}", first.id)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 404);
+    }
+
+    #[actix_rt::test]
+    async fn test_data_subject_export_and_erase() {
+        use socialhub_core::DataSubjectHook;
+
+        let service = SocialService::new();
+        let post = service.create_post(1, "mine".to_string(), Vec::new()).unwrap();
+        let other = service.create_post(2, "theirs".to_string(), Vec::new()).unwrap();
+
+        let files = service.export(1).await.unwrap();
+        assert_eq!(files[0].path, "posts.json");
+        let exported: Vec<Post> = serde_json::from_slice(files[0].bytes().unwrap()).unwrap();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].id, post.id);
+
+        service.erase(1).await.unwrap();
+        service.erase(1).await.unwrap();
+        assert!(matches!(service.get_post(post.id), Err(SocialError::PostNotFound)));
+        assert!(service.get_post(other.id).is_ok());
+    }
 }
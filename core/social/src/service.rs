@@ -1,25 +1,85 @@
-use crate::models::Post;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use crate::{error::SocialError, models::Post};
+use futures::future::BoxFuture;
+use log::info;
+use socialhub_core::{
+    auth::{permissions, AuthenticatedUser},
+    data_subject::{DataSubjectHook, ExportedFile},
+    error::CommonError,
+};
 use uuid::Uuid;
 use chrono::Utc;
 
+/// Posts, kept in process memory until the social tables land.
+#[derive(Default)]
 pub struct SocialService {
-    // Add fields later as needed
+    posts: RwLock<HashMap<Uuid, Post>>,
 }
 
 impl SocialService {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
     }
 
-    pub async fn create_post(content: String, user_id: i32) -> Result<Post, String> {
+    pub fn create_post(&self, user_id: i32, content: String, media_ids: Vec<Uuid>) -> Result<Post, SocialError> {
         let now = Utc::now();
-        Ok(Post {
+        let post = Post {
             id: Uuid::new_v4(),
             content,
             user_id,
-            media_ids: Vec::new(),  // Empty vector for new posts
+            media_ids,
             created_at: now,
             updated_at: now,
+        };
+
+        let mut posts = self.posts.write().map_err(|_| SocialError::InternalError)?;
+        posts.insert(post.id, post.clone());
+        Ok(post)
+    }
+
+    pub fn get_post(&self, id: Uuid) -> Result<Post, SocialError> {
+        let posts = self.posts.read().map_err(|_| SocialError::InternalError)?;
+        posts.get(&id).cloned().ok_or(SocialError::PostNotFound)
+    }
+
+    /// Deletes a post. Only its author and holders of `posts:delete_any` may do so.
+    pub fn delete_post(&self, user: &AuthenticatedUser, id: Uuid) -> Result<(), SocialError> {
+        let mut posts = self.posts.write().map_err(|_| SocialError::InternalError)?;
+        let post = posts.get(&id).ok_or(SocialError::PostNotFound)?;
+
+        if post.user_id != user.user_id {
+            if !user.has_permission(permissions::POSTS_DELETE_ANY) {
+                return Err(SocialError::NotPermitted);
+            }
+            info!("User {} removed post {} of user {}", user.user_id, id, post.user_id);
+        }
+
+        posts.remove(&id);
+        Ok(())
+    }
+}
+
+/// Posts go into `social/posts.json`. Likes and follows are not stored yet.
+impl DataSubjectHook for SocialService {
+    fn name(&self) -> &'static str {
+        "social"
+    }
+
+    fn export(&self, user_id: i32) -> BoxFuture<'_, Result<Vec<ExportedFile>, CommonError>> {
+        Box::pin(async move {
+            let posts = self.posts.read().map_err(|_| CommonError::InternalError)?;
+            let mut own: Vec<&Post> = posts.values().filter(|p| p.user_id == user_id).collect();
+            own.sort_by_key(|p| p.created_at);
+            Ok(vec![ExportedFile::json("posts.json", &own)?])
+        })
+    }
+
+    fn erase(&self, user_id: i32) -> BoxFuture<'_, Result<(), CommonError>> {
+        Box::pin(async move {
+            let mut posts = self.posts.write().map_err(|_| CommonError::InternalError)?;
+            posts.retain(|_, p| p.user_id != user_id);
+            Ok(())
         })
     }
 }
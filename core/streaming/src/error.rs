@@ -12,6 +12,9 @@ pub enum StreamingError {
     
     #[error("Invalid stream format")]
     InvalidFormat,
+
+    #[error("Operation not permitted")]
+    NotPermitted,
     
     #[error("Internal server error")]
     InternalError,
@@ -23,6 +26,7 @@ impl ResponseError for StreamingError {
             Self::NotFound => HttpResponse::NotFound().finish(),
             Self::StreamError(msg) => HttpResponse::BadRequest().json(msg),
             Self::InvalidFormat => HttpResponse::UnsupportedMediaType().finish(),
+            Self::NotPermitted => HttpResponse::Forbidden().json("Not permitted"),
             Self::InternalError => HttpResponse::InternalServerError().finish(),
         }
     }
@@ -2,6 +2,7 @@ use actix_web::{web, HttpResponse, Error as ActixError};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use log::warn;
+use socialhub_core::auth::AuthenticatedUser;
 use crate::service::StreamingService;
 use crate::models::StreamType;
 use utoipa::ToSchema;
@@ -56,13 +57,9 @@ pub async fn stream_audio(_id: web::Path<Uuid>) -> Result<HttpResponse, ActixErr
 )]
 pub async fn start_live(
     service: web::Data<StreamingService>,
-    req: actix_web::HttpRequest,
+    user: AuthenticatedUser,
     stream_req: web::Json<StreamRequest>
 ) -> Result<HttpResponse, ActixError> {
-    if req.headers().get("Authorization").is_none() {
-        return Ok(HttpResponse::Unauthorized().finish());
-    }
-
     let stream_type = match stream_req.stream_type.as_str() {
         "video" => StreamType::Video,
         "audio" => StreamType::Audio,
@@ -72,7 +69,7 @@ pub async fn start_live(
         }
     };
 
-    service.start_stream(1, stream_type)
+    service.start_stream(user.user_id, stream_type)
         .await
         .map(|stream| HttpResponse::Ok().json(stream))
         .map_err(ActixError::from)
@@ -84,15 +81,19 @@ pub async fn start_live(
     params(("id" = Uuid, Path, description = "Stream ID to stop")),
     responses(
         (status = 200, description = "Stream stopped successfully"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not the owner and missing streams:stop_any"),
         (status = 404, description = "Stream not found")
     ),
+    security(("bearer_token" = [])),
     tag = "streaming"
 )]
 pub async fn stop_stream(
     service: web::Data<StreamingService>,
+    user: AuthenticatedUser,
     stream_id: web::Path<Uuid>
 ) -> Result<HttpResponse, ActixError> {
-    service.stop_stream(stream_id.into_inner())
+    service.stop_stream(&user, stream_id.into_inner())
         .await
         .map(|_| HttpResponse::Ok().finish())
         .map_err(|e| e.into())
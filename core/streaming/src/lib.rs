@@ -1,18 +1,22 @@
 use actix_web::web;
+use socialhub_core::{auth::permissions::STREAMS_START, require_permission};
 
 pub mod models;  // tornar público
 pub mod handlers;  // tornar público
 mod service;
 mod error;
 
+pub use error::StreamingError;
+pub use service::StreamingService;
+
+/// Registers the streaming routes. The handlers expect a shared
+/// `web::Data<StreamingService>` registered as app data.
 pub fn configure(cfg: &mut web::ServiceConfig) {
-    let streaming_service = web::Data::new(service::StreamingService::default());
-    cfg.app_data(streaming_service.clone())
-        .service(
+    cfg.service(
             web::scope("/stream")
                 .route("/video/{id}", web::get().to(handlers::stream_video))
                 .route("/audio/{id}", web::get().to(handlers::stream_audio))
-                .route("/live", web::post().to(handlers::start_live))
+                .route("/live", web::post().to(handlers::start_live).wrap(require_permission(STREAMS_START)))
                 .route("/{id}/stop", web::post().to(handlers::stop_stream))
                 .route("/{id}/info", web::get().to(handlers::get_stream_info))
         );
@@ -21,14 +25,52 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Arc;
     use actix_web::{test, App};  // Removed unused dev::Service import
     use serde_json::json;
+    use socialhub_auth::{AuthConfig, AuthService, InMemoryUserRepository, Role};
     use uuid::Uuid;
+    use crate::models::{StreamStatus, StreamType};
+
+    fn auth_service() -> web::Data<AuthService> {
+        web::Data::new(AuthService::new(AuthConfig::default(), Arc::new(InMemoryUserRepository::new())))
+    }
+
+    fn bearer(service: &AuthService, user_id: i32) -> String {
+        bearer_with_role(service, user_id, Role::User)
+    }
+
+    fn bearer_with_role(service: &AuthService, user_id: i32, role: Role) -> String {
+        let (token, _) = service.tokens().issue(user_id, &[role], &[]).unwrap();
+        format!("Bearer {}", token)
+    }
+
+    /// Token of a third-party app granted `scopes` by a user with `role`.
+    fn scoped_bearer(service: &AuthService, user_id: i32, role: Role, scopes: &[&str]) -> String {
+        let permissions = scopes.iter()
+            .flat_map(|scope| socialhub_core::auth::scopes::permissions(scope))
+            .filter(|p| role.permissions().contains(p))
+            .map(|p| p.to_string())
+            .collect();
+        let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+        let (token, _) = service.tokens().issue_for_client(user_id, &[role], permissions, "app", &scopes).unwrap();
+        format!("Bearer {}", token)
+    }
+
+    /// Token of a third-party app holding exactly `permissions`.
+    fn client_bearer(service: &AuthService, user_id: i32, permissions: &[&str]) -> String {
+        let permissions = permissions.iter().map(|p| p.to_string()).collect();
+        let (token, _) = service.tokens().issue_for_client(user_id, &[Role::User], permissions, "app", &[]).unwrap();
+        format!("Bearer {}", token)
+    }
 
     #[actix_rt::test]
     async fn test_stream_video_endpoint() {
+        let auth = auth_service();
         let app = test::init_service(
             App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(StreamingService::new()))
                 .configure(configure)
         ).await;
         
@@ -43,14 +85,17 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_start_live_stream() {
+        let auth = auth_service();
         let app = test::init_service(
             App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(StreamingService::new()))
                 .configure(configure)
         ).await;
         
         let req = test::TestRequest::post()
             .uri("/stream/live")
-            .insert_header(("Authorization", "test-token"))
+            .insert_header(("Authorization", bearer(&auth, 7)))
             .set_json(json!({
                 "title": "Test Stream",
                 "stream_type": "video"
@@ -59,12 +104,18 @@ mod tests {
         
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["user_id"], 7);
     }
 
     #[actix_rt::test]
     async fn test_start_live_stream_unauthorized() {
+        let auth = auth_service();
         let app = test::init_service(
             App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(StreamingService::new()))
                 .configure(configure)
         ).await;
         
@@ -80,16 +131,65 @@ mod tests {
         assert_eq!(resp.status(), 401);
     }
 
+    #[actix_rt::test]
+    async fn test_start_live_stream_requires_permission() {
+        let auth = auth_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(StreamingService::new()))
+                .configure(configure)
+        ).await;
+
+        let start = |token: String| test::TestRequest::post()
+            .uri("/stream/live")
+            .insert_header(("Authorization", token))
+            .set_json(json!({ "title": "Test Stream", "stream_type": "video" }))
+            .to_request();
+
+        let resp = test::call_service(&app, start(client_bearer(&auth, 7, &[]))).await;
+        assert_eq!(resp.status(), 403);
+        let resp = test::call_service(&app, start(client_bearer(&auth, 7, &["streams:start"]))).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_rt::test]
+    async fn test_start_live_stream_with_scoped_token() {
+        let auth = auth_service();
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(StreamingService::new()))
+                .configure(configure)
+        ).await;
+
+        let start = |token: String| test::TestRequest::post()
+            .uri("/stream/live")
+            .insert_header(("Authorization", token))
+            .set_json(json!({ "title": "Test Stream", "stream_type": "video" }))
+            .to_request();
+
+        for scopes in [&["openid", "profile"][..], &["user:write", "media:write", "stream:read"]] {
+            let resp = test::call_service(&app, start(scoped_bearer(&auth, 7, Role::User, scopes))).await;
+            assert_eq!(resp.status(), 403, "{:?}", scopes);
+        }
+        let resp = test::call_service(&app, start(scoped_bearer(&auth, 7, Role::User, &["stream:write"]))).await;
+        assert!(resp.status().is_success());
+    }
+
     #[actix_rt::test]
     async fn test_invalid_stream_type() {
+        let auth = auth_service();
         let app = test::init_service(
             App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(StreamingService::new()))
                 .configure(configure)
         ).await;
         
         let req = test::TestRequest::post()
             .uri("/stream/live")
-            .insert_header(("Authorization", "test-token"))
+            .insert_header(("Authorization", bearer(&auth, 7)))
             .set_json(json!({
                 "title": "Test Stream",
                 "stream_type": "invalid_type"
@@ -102,24 +202,71 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_stop_stream_endpoint() {
+        let auth = auth_service();
+        let service = web::Data::new(StreamingService::new());
         let app = test::init_service(
             App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(service.clone())
                 .configure(configure)
         ).await;
-        
-        let stream_id = Uuid::new_v4();
+
+        let stream = service.start_stream(7, StreamType::Video).await.unwrap();
         let req = test::TestRequest::post()
-            .uri(&format!("/stream/{}/stop", stream_id))
+            .uri(&format!("/stream/{}/stop", stream.id))
+            .insert_header(("Authorization", bearer(&auth, 7)))
             .to_request();
-        
+
         let resp = test::call_service(&app, req).await;
         assert!(resp.status().is_success());
+        assert_eq!(service.get_stream(stream.id).await.unwrap().status, StreamStatus::Inactive);
+    }
+
+    #[actix_rt::test]
+    async fn test_stop_stream_permissions() {
+        let auth = auth_service();
+        let service = web::Data::new(StreamingService::new());
+        let app = test::init_service(
+            App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(service.clone())
+                .configure(configure)
+        ).await;
+
+        let stream = service.start_stream(7, StreamType::Audio).await.unwrap();
+        let stop = |token: Option<String>| {
+            let mut req = test::TestRequest::post().uri(&format!("/stream/{}/stop", stream.id));
+            if let Some(token) = token {
+                req = req.insert_header(("Authorization", token));
+            }
+            req.to_request()
+        };
+
+        assert_eq!(test::call_service(&app, stop(None)).await.status(), 401);
+        assert_eq!(test::call_service(&app, stop(Some(bearer(&auth, 8)))).await.status(), 403);
+        // A moderator's app may not act as moderator.
+        let scoped = scoped_bearer(&auth, 9, Role::Moderator, &["stream:write"]);
+        assert_eq!(test::call_service(&app, stop(Some(scoped))).await.status(), 403);
+        assert_eq!(service.get_stream(stream.id).await.unwrap().status, StreamStatus::Active);
+
+        let resp = test::call_service(&app, stop(Some(bearer_with_role(&auth, 9, Role::Moderator)))).await;
+        assert!(resp.status().is_success());
+        assert_eq!(service.get_stream(stream.id).await.unwrap().status, StreamStatus::Inactive);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/stream/{}/stop", Uuid::new_v4()))
+            .insert_header(("Authorization", bearer(&auth, 7)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
     }
 
     #[actix_rt::test]
     async fn test_stream_audio_endpoint() {
+        let auth = auth_service();
         let app = test::init_service(
             App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(StreamingService::new()))
                 .configure(configure)
         ).await;
         
@@ -134,8 +281,11 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_get_nonexistent_stream() {
+        let auth = auth_service();
         let app = test::init_service(
             App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(StreamingService::new()))
                 .configure(configure)
         ).await;
         
@@ -150,14 +300,17 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_malformed_stream_request() {
+        let auth = auth_service();
         let app = test::init_service(
             App::new()
+                .configure(socialhub_auth::configure_state(auth.clone()))
+                .app_data(web::Data::new(StreamingService::new()))
                 .configure(configure)
         ).await;
         
         let req = test::TestRequest::post()
             .uri("/stream/live")
-            .insert_header(("Authorization", "test-token"))
+            .insert_header(("Authorization", bearer(&auth, 7)))
             .set_json(json!({
                 "stream_type": "video"
                 // missing title field
@@ -167,4 +320,23 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), 400);
     }
+
+    #[actix_rt::test]
+    async fn test_data_subject_export_and_erase() {
+        use socialhub_core::DataSubjectHook;
+
+        let service = StreamingService::new();
+        let own = service.start_stream(1, StreamType::Video).await.unwrap();
+        let other = service.start_stream(2, StreamType::Audio).await.unwrap();
+
+        let files = service.export(1).await.unwrap();
+        assert_eq!(files[0].path, "streams.json");
+        let exported: Vec<serde_json::Value> = serde_json::from_slice(files[0].bytes().unwrap()).unwrap();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0]["id"], own.id.to_string());
+
+        service.erase(1).await.unwrap();
+        assert!(service.get_stream(own.id).await.is_err());
+        assert!(service.get_stream(other.id).await.is_ok());
+    }
 }
@@ -1,34 +1,82 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use futures::future::BoxFuture;
+use log::info;
+use socialhub_core::{
+    auth::{permissions, AuthenticatedUser},
+    data_subject::{DataSubjectHook, ExportedFile},
+    error::CommonError,
+};
 use uuid::Uuid;
 use crate::models::{Stream, StreamType, StreamStatus};
 use crate::error::StreamingError;
 
 #[derive(Default)]
-pub struct StreamingService;
+pub struct StreamingService {
+    streams: RwLock<HashMap<Uuid, Stream>>,
+}
 
 impl StreamingService {
-    #[allow(dead_code)]
     pub fn new() -> Self {
         Self::default()
     }
 
     pub async fn start_stream(&self, user_id: i32, stream_type: StreamType) -> Result<Stream, StreamingError> {
         let id = Uuid::new_v4();
-        Ok(Stream {
+        let stream = Stream {
             id,
             user_id,
             stream_type,
             status: StreamStatus::Active,
             url: format!("/stream/{}", id),
-        })
+        };
+
+        let mut streams = self.streams.write().map_err(|_| StreamingError::InternalError)?;
+        streams.insert(id, stream.clone());
+        Ok(stream)
     }
 
-    pub async fn stop_stream(&self, _stream_id: Uuid) -> Result<(), StreamingError> {
-        // Simulated stream stopping
+    /// Ends a stream. Only its owner and holders of `streams:stop_any` may do so.
+    pub async fn stop_stream(&self, user: &AuthenticatedUser, stream_id: Uuid) -> Result<(), StreamingError> {
+        let mut streams = self.streams.write().map_err(|_| StreamingError::InternalError)?;
+        let stream = streams.get_mut(&stream_id).ok_or(StreamingError::NotFound)?;
+
+        if stream.user_id != user.user_id {
+            if !user.has_permission(permissions::STREAMS_STOP_ANY) {
+                return Err(StreamingError::NotPermitted);
+            }
+            info!("User {} stopped stream {} of user {}", user.user_id, stream_id, stream.user_id);
+        }
+
+        stream.status = StreamStatus::Inactive;
         Ok(())
     }
 
-    pub async fn get_stream(&self, _id: Uuid) -> Result<Stream, StreamingError> {
-        // Simulação - em produção buscaria do banco de dados
-        Err(StreamingError::NotFound)
+    pub async fn get_stream(&self, id: Uuid) -> Result<Stream, StreamingError> {
+        let streams = self.streams.read().map_err(|_| StreamingError::InternalError)?;
+        streams.get(&id).cloned().ok_or(StreamingError::NotFound)
+    }
+}
+
+/// The user's streams, live and ended, go into `streaming/streams.json`.
+impl DataSubjectHook for StreamingService {
+    fn name(&self) -> &'static str {
+        "streaming"
+    }
+
+    fn export(&self, user_id: i32) -> BoxFuture<'_, Result<Vec<ExportedFile>, CommonError>> {
+        Box::pin(async move {
+            let streams = self.streams.read().map_err(|_| CommonError::InternalError)?;
+            let own: Vec<&Stream> = streams.values().filter(|s| s.user_id == user_id).collect();
+            Ok(vec![ExportedFile::json("streams.json", &own)?])
+        })
+    }
+
+    fn erase(&self, user_id: i32) -> BoxFuture<'_, Result<(), CommonError>> {
+        Box::pin(async move {
+            let mut streams = self.streams.write().map_err(|_| CommonError::InternalError)?;
+            streams.retain(|_, s| s.user_id != user_id);
+            Ok(())
+        })
     }
 }
@@ -11,19 +11,65 @@ use utoipa::OpenApi;
         
         // Auth routes
         socialhub_auth::handlers::login,
+        socialhub_auth::handlers::login_two_factor,
+        socialhub_auth::handlers::refresh,
+        socialhub_auth::handlers::device_code,
+        socialhub_auth::handlers::verify_device,
+        socialhub_auth::handlers::token,
         socialhub_auth::handlers::register,
+        socialhub_auth::handlers::forgot_password,
+        socialhub_auth::handlers::reset_password,
+        socialhub_auth::handlers::verify_email,
+        socialhub_auth::handlers::send_verification_email,
         socialhub_auth::handlers::logout,
+        socialhub_auth::handlers::logout_all,
+        socialhub_auth::handlers::update_roles,
+        socialhub_auth::handlers::impersonate,
+        socialhub_auth::handlers::create_invite,
+        socialhub_auth::handlers::list_invites,
+        socialhub_auth::handlers::delete_invite,
+        socialhub_auth::handlers::list_audit_events,
+        socialhub_auth::handlers::export_audit_events,
+        socialhub_auth::handlers::create_access_token,
+        socialhub_auth::handlers::list_access_tokens,
+        socialhub_auth::handlers::delete_access_token,
+        socialhub_auth::handlers::list_sessions,
+        socialhub_auth::handlers::revoke_session,
+        socialhub_auth::handlers::request_export,
+        socialhub_auth::handlers::export_status,
+        socialhub_auth::handlers::download_export,
+        socialhub_auth::handlers::delete_account,
+        socialhub_auth::handlers::restore_account,
+        socialhub_auth::handlers::enroll_two_factor,
+        socialhub_auth::handlers::confirm_two_factor,
+        socialhub_auth::handlers::disable_two_factor,
+        socialhub_auth::handlers::create_oauth_client,
+        socialhub_auth::handlers::list_oauth_clients,
+        socialhub_auth::handlers::delete_oauth_client,
+        socialhub_auth::handlers::consent_prompt,
+        socialhub_auth::handlers::authorize,
+        socialhub_auth::handlers::userinfo,
+        socialhub_auth::handlers::jwks,
+        socialhub_auth::handlers::openid_configuration,
         
         // Social routes
         socialhub_social::handlers::create_post,
         socialhub_social::handlers::get_post,
+        socialhub_social::handlers::delete_post,
         socialhub_social::handlers::like_post,
         socialhub_social::handlers::follow_user,
         
         // Media routes
         socialhub_media::handlers::upload,  // Changed from upload_media to upload
         socialhub_media::handlers::get_media,
+        socialhub_media::handlers::get_metadata,
         socialhub_media::handlers::update_metadata,
+        socialhub_media::handlers::delete_media,
+        socialhub_media::tus::options,
+        socialhub_media::tus::create_upload,
+        socialhub_media::tus::upload_offset,
+        socialhub_media::tus::append_upload,
+        socialhub_media::tus::terminate_upload,
         
         // Addon routes
         addon_manager::web::configure_addon,
@@ -39,6 +85,51 @@ use utoipa::OpenApi;
             socialhub_auth::models::LoginRequest,
             socialhub_auth::models::RegisterRequest,
             socialhub_auth::models::AuthResponse,
+            socialhub_auth::models::LoginResponse,
+            socialhub_auth::models::TwoFactorChallenge,
+            socialhub_auth::models::TwoFactorLoginRequest,
+            socialhub_auth::models::TwoFactorEnrollment,
+            socialhub_auth::models::TwoFactorCodeRequest,
+            socialhub_auth::models::RefreshRequest,
+            socialhub_auth::models::DeviceCodeRequest,
+            socialhub_auth::models::DeviceCodeResponse,
+            socialhub_auth::models::DeviceVerifyRequest,
+            socialhub_auth::models::TokenRequest,
+            socialhub_auth::models::LogoutRequest,
+            socialhub_auth::models::ForgotPasswordRequest,
+            socialhub_auth::models::ResetPasswordRequest,
+            socialhub_auth::models::VerifyEmailRequest,
+            socialhub_auth::models::User,
+            socialhub_auth::models::UpdateRolesRequest,
+            socialhub_auth::models::ImpersonateRequest,
+            socialhub_auth::models::Impersonation,
+            socialhub_auth::models::CreateInviteRequest,
+            socialhub_auth::models::Invite,
+            socialhub_auth::models::CreatedInvite,
+            socialhub_auth::models::AuditPage,
+            socialhub_auth::audit::AuditEvent,
+            socialhub_auth::audit::AuditAction,
+            socialhub_auth::audit::AuditOutcome,
+            socialhub_auth::models::CreateAccessTokenRequest,
+            socialhub_auth::models::AccessToken,
+            socialhub_auth::models::CreatedAccessToken,
+            socialhub_auth::models::Session,
+            socialhub_auth::models::ExportStatus,
+            socialhub_auth::models::AccountExport,
+            socialhub_auth::models::DeleteAccountRequest,
+            socialhub_auth::models::AccountDeletion,
+            socialhub_auth::models::TokenResponse,
+            socialhub_auth::models::OAuthTokenResponse,
+            socialhub_auth::models::CreateOAuthClientRequest,
+            socialhub_auth::models::OAuthClient,
+            socialhub_auth::models::CreatedOAuthClient,
+            socialhub_auth::models::AuthorizeRequest,
+            socialhub_auth::models::ConsentPrompt,
+            socialhub_auth::models::ScopeDescription,
+            socialhub_auth::models::ConsentDecision,
+            socialhub_auth::models::AuthorizationRedirect,
+            socialhub_auth::models::UserInfo,
+            socialhub_auth::models::OpenIdConfiguration,
             
             // Social schemas
             socialhub_social::models::Post,
@@ -47,6 +138,9 @@ use utoipa::OpenApi;
             
             // Media schemas
             socialhub_media::models::Media,
+            socialhub_media::models::MediaMetadata,
+            socialhub_media::models::CameraInfo,
+            socialhub_media::models::MediaVariant,
             socialhub_media::handlers::UploadRequest,
             socialhub_media::handlers::MetadataUpdate,
             
@@ -57,6 +151,7 @@ use utoipa::OpenApi;
     tags(
         (name = "streaming", description = "Live streaming endpoints"),
         (name = "auth", description = "Authentication endpoints"),
+        (name = "oauth", description = "OAuth 2.0 and OpenID Connect provider for third-party apps"),
         (name = "social", description = "Social features endpoints"),
         (name = "media", description = "Media management endpoints"),
         (name = "addons", description = "Addon management endpoints")
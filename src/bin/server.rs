@@ -1,17 +1,67 @@
-use actix_web::{App, HttpServer};
+use std::sync::Arc;
+use actix_web::{web, App, HttpServer};
 use log::info;
+use socialhub::config::Config;
+use addon_manager::AddonManager;
+use socialhub_auth::{AuthService, PgUserRepository};
+use socialhub_media::{MediaService, PgMediaRepository};
+use socialhub_social::SocialService;
+use socialhub_streaming::StreamingService;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
     info!("Starting SocialHub server...");
 
-    HttpServer::new(|| {
+    let config = Config::from_env();
+    let users = Arc::new(
+        PgUserRepository::connect(&config.database.url, config.database.max_connections)
+            .map_err(std::io::Error::other)?
+    );
+    let mailer = socialhub_auth::mailer::from_env().map_err(std::io::Error::other)?;
+    let social_service = web::Data::new(SocialService::new());
+    let streaming_service = web::Data::new(StreamingService::new());
+    let media_storage = socialhub_media::storage::from_config(&config.media).map_err(std::io::Error::other)?;
+    let media_records = Arc::new(
+        PgMediaRepository::connect(&config.database.url, config.database.max_connections)
+            .map_err(std::io::Error::other)?
+    );
+    let media_service = web::Data::new(MediaService::new(config.media, media_storage, media_records));
+    let addons = web::Data::new(AddonManager::new());
+    let auth_service = web::Data::new(
+        AuthService::new(config.auth, users.clone())
+            .with_two_factor(users.clone())
+            .with_email_tokens(users.clone())
+            .with_access_tokens(users.clone())
+            .with_sessions(users.clone())
+            .with_oauth_clients(users.clone())
+            .with_invites(users.clone())
+            .with_audit_log(users.clone())
+            .with_signing_keys(users)
+            .with_mailer(mailer)
+            .with_data_subject_hook(social_service.clone().into_inner())
+            .with_data_subject_hook(streaming_service.clone().into_inner())
+            .with_data_subject_hook(media_service.clone().into_inner())
+            .with_data_subject_hook(addons.clone().into_inner())
+    );
+
+    // Erase accounts whose deletion grace period is over.
+    auth_service.clone().into_inner().spawn_purge_task();
+    // Drop resumable uploads that were abandoned half-way.
+    media_service.clone().into_inner().spawn_upload_purge_task();
+
+    HttpServer::new(move || {
         App::new()
+            .configure(socialhub_auth::configure_state(auth_service.clone()))
+            .app_data(social_service.clone())
+            .app_data(streaming_service.clone())
+            .app_data(media_service.clone())
+            .app_data(addons.clone())
             .configure(socialhub_streaming::configure)
             .configure(socialhub_auth::configure)
             .configure(socialhub_social::configure)
             .configure(socialhub_media::configure)
+            .configure(addon_manager::configure)
     })
     .bind(("127.0.0.1", 8080))?
     .run()
@@ -1,5 +1,7 @@
 pub mod types;
 pub use types::CacheConfig;
+pub use socialhub_auth::AuthConfig;
+pub use socialhub_media::MediaConfig;
 
 pub struct Config {
     pub server: ServerConfig,
@@ -20,16 +22,6 @@ pub struct DatabaseConfig {
     pub max_connections: u32,
 }
 
-pub struct MediaConfig {
-    pub upload_dir: String,
-    pub max_file_size: usize,
-}
-
-pub struct AuthConfig {
-    pub token_expiration: u64,
-    pub refresh_token_expiration: u64,
-}
-
 impl Config {
     pub fn from_env() -> Self {
         Self {
@@ -67,24 +59,8 @@ impl Config {
                     .parse()
                     .unwrap(),
             },
-            media: MediaConfig {
-                upload_dir: std::env::var("MEDIA_UPLOAD_DIR")
-                    .unwrap_or_else(|_| "./uploads".to_string()),
-                max_file_size: std::env::var("MEDIA_MAX_FILE_SIZE")
-                    .unwrap_or_else(|_| "10485760".to_string()) // 10MB
-                    .parse()
-                    .unwrap(),
-            },
-            auth: AuthConfig {
-                token_expiration: std::env::var("AUTH_TOKEN_EXPIRATION")
-                    .unwrap_or_else(|_| "86400".to_string()) // 24 hours
-                    .parse()
-                    .unwrap(),
-                refresh_token_expiration: std::env::var("AUTH_REFRESH_TOKEN_EXPIRATION")
-                    .unwrap_or_else(|_| "604800".to_string()) // 7 days
-                    .parse()
-                    .unwrap(),
-            },
+            media: MediaConfig::from_env(),
+            auth: AuthConfig::from_env(),
         }
     }
 }
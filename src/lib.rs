@@ -23,17 +23,34 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 
 #[cfg(test)]
 mod tests {
-    use actix_web::{test, App};
+    use std::sync::Arc;
+    use actix_web::{test, web, App};
+    use socialhub_auth::{AuthConfig, AuthService, InMemoryUserRepository};
 
     #[actix_rt::test]
     async fn test_auth_flow() {
+        let config = AuthConfig { hash_cost: 4, ..AuthConfig::default() };
+        let auth = AuthService::new(config, Arc::new(InMemoryUserRepository::new()));
         let app = test::init_service(
             App::new()
+                .configure(socialhub_auth::configure_state(web::Data::new(auth)))
                 .configure(|cfg| {
                     socialhub_auth::configure(cfg);
                 })
         ).await;
 
+        let register_resp = test::call_service(&app,
+            test::TestRequest::post()
+                .uri("/auth/register")
+                .set_json(serde_json::json!({
+                    "username": "testuser",
+                    "email": "testuser@example.com",
+                    "password": "password123"
+                }))
+                .to_request()
+        ).await;
+        assert_eq!(register_resp.status().as_u16(), 201);
+
         let login_resp = test::call_service(&app, 
             test::TestRequest::post()
                 .uri("/auth/login")
@@ -1,14 +1,19 @@
-use actix_web::{test, App, http::header};
-use socialhub_auth;
-use socialhub_media;
-use socialhub_social;
-use socialhub_streaming;
-use socialhub;
+use std::sync::Arc;
+use actix_web::{test, web, App, http::header};
+use socialhub_auth::{self, AuthConfig, AuthService, InMemoryUserRepository};
+use socialhub_auth::models::AuthResponse;
+use socialhub_media::{storage::LocalStorage, InMemoryMediaRepository, MediaConfig, MediaService};
+
+fn auth_service() -> web::Data<AuthService> {
+    let config = AuthConfig { hash_cost: 4, ..AuthConfig::default() };
+    web::Data::new(AuthService::new(config, Arc::new(InMemoryUserRepository::new())))
+}
 
 #[actix_rt::test]
 async fn test_complete_flow() {
-    let app = test::init_service(
+    let _app = test::init_service(
         App::new()
+            .configure(socialhub_auth::configure_state(auth_service()))
             .configure(|cfg| {
                 socialhub_auth::configure(cfg);
                 socialhub_media::configure(cfg);
@@ -19,17 +24,36 @@ async fn test_complete_flow() {
     ).await;
 
     // TODO: Implementar testes de fluxo completo
-    assert!(true);
 }
 
 #[actix_rt::test]
 async fn test_auth_with_media_upload() {
+    let upload_dir = std::env::temp_dir().join(format!("socialhub-test-{}", std::process::id()));
+    let media_service = web::Data::new(MediaService::new(
+        MediaConfig::default(),
+        Arc::new(LocalStorage::new(&upload_dir)),
+        Arc::new(InMemoryMediaRepository::new()),
+    ));
     let app = test::init_service(
         App::new()
+            .configure(socialhub_auth::configure_state(auth_service()))
+            .app_data(media_service)
             .configure(socialhub_auth::configure)
             .configure(socialhub_media::configure)
     ).await;
 
+    let register_req = test::TestRequest::post()
+        .uri("/auth/register")
+        .set_json(serde_json::json!({
+            "username": "testuser",
+            "email": "testuser@example.com",
+            "password": "password123"
+        }))
+        .to_request();
+
+    let register_resp = test::call_service(&app, register_req).await;
+    assert_eq!(register_resp.status().as_u16(), 201);
+
     // Primeiro faz login
     let login_req = test::TestRequest::post()
         .uri("/auth/login")
@@ -41,19 +65,18 @@ async fn test_auth_with_media_upload() {
 
     let login_resp = test::call_service(&app, login_req).await;
     assert!(login_resp.status().is_success());
+    let login: AuthResponse = test::read_body_json(login_resp).await;
 
     // Depois tenta upload usando multipart
-    let payload = concat!(
-        "--abbc761f78ff4d7cb7573b5a23f96ef0\r\n",
-        "Content-Disposition: form-data; name=\"file\"; filename=\"test.jpg\"\r\n",
-        "Content-Type: image/jpeg\r\n\r\n",
-        "test file content",
-        "\r\n--abbc761f78ff4d7cb7573b5a23f96ef0--\r\n"
-    );
+    let payload: &[u8] = b"--abbc761f78ff4d7cb7573b5a23f96ef0\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"pixel.gif\"\r\n\
+        Content-Type: image/gif\r\n\r\n\
+        GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xFF\xFF\xFF!\xF9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x01D\x00;\
+        \r\n--abbc761f78ff4d7cb7573b5a23f96ef0--\r\n";
 
     let upload_req = test::TestRequest::post()
         .uri("/media/upload")
-        .insert_header(("Authorization", "Bearer test-token"))
+        .insert_header(("Authorization", format!("Bearer {}", login.token)))
         .insert_header((
             header::CONTENT_TYPE,
             "multipart/form-data; boundary=abbc761f78ff4d7cb7573b5a23f96ef0"
@@ -63,6 +86,7 @@ async fn test_auth_with_media_upload() {
 
     let upload_resp = test::call_service(&app, upload_req).await;
     assert!(upload_resp.status().is_success());
+    let _ = std::fs::remove_dir_all(upload_dir);
 }
 
 #[actix_rt::test]
@@ -95,7 +119,7 @@ async fn test_addon_integration() {
 
     // Verificar o conteúdo da resposta
     let body: serde_json::Value = test::read_body_json(list_resp).await;
-    assert!(body.as_array().unwrap().len() > 0, "Addon list should not be empty");
+    assert!(!body.as_array().unwrap().is_empty(), "Addon list should not be empty");
 }
 
 #[actix_rt::test]